@@ -0,0 +1,10 @@
+CREATE TABLE user_sessions
+(
+    id         UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id    INTEGER     NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX user_sessions_user_id ON user_sessions (user_id) WHERE revoked_at IS NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE roles DROP COLUMN require_mfa;
+
+DROP TABLE totp_credentials;
@@ -0,0 +1,10 @@
+CREATE TABLE totp_credentials
+(
+    user_id        INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    secret         VARCHAR(128) NOT NULL,
+    confirmed_at   TIMESTAMPTZ,
+    last_used_step BIGINT,
+    created_at     TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE roles ADD COLUMN require_mfa BOOLEAN NOT NULL DEFAULT FALSE;
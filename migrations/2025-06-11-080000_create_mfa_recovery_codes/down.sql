@@ -0,0 +1,3 @@
+DROP TABLE audit_log;
+
+DROP TABLE mfa_recovery_codes;
@@ -0,0 +1,21 @@
+CREATE TABLE mfa_recovery_codes
+(
+    id         SERIAL PRIMARY KEY,
+    user_id    INTEGER      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash  VARCHAR(255) NOT NULL,
+    used_at    TIMESTAMPTZ,
+    created_at TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX mfa_recovery_codes_user_id ON mfa_recovery_codes (user_id) WHERE used_at IS NULL;
+
+CREATE TABLE audit_log
+(
+    id         SERIAL PRIMARY KEY,
+    user_id    INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    event      VARCHAR(100) NOT NULL,
+    detail     TEXT,
+    created_at TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_log_user_id ON audit_log (user_id);
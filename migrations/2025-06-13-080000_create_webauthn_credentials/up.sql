@@ -0,0 +1,23 @@
+CREATE TABLE webauthn_credentials
+(
+    id            SERIAL PRIMARY KEY,
+    user_id       INTEGER       NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    credential_id VARCHAR(1366) NOT NULL UNIQUE,
+    public_key    BYTEA         NOT NULL,
+    sign_count    BIGINT        NOT NULL DEFAULT 0,
+    transports    TEXT[]        NOT NULL DEFAULT '{}',
+    name          VARCHAR(100)  NOT NULL,
+    created_at    TIMESTAMPTZ   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at  TIMESTAMPTZ
+);
+
+CREATE INDEX webauthn_credentials_user_id ON webauthn_credentials (user_id);
+
+CREATE TABLE webauthn_challenges
+(
+    id         UUID PRIMARY KEY,
+    user_id    INTEGER REFERENCES users (id) ON DELETE CASCADE,
+    ceremony   VARCHAR(20) NOT NULL CHECK (ceremony IN ('registration', 'authentication')),
+    challenge  VARCHAR(64) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,4 @@
+DROP TABLE failed_logins;
+
+ALTER TABLE users
+    DROP COLUMN locked_until;
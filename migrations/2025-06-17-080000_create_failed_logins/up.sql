@@ -0,0 +1,13 @@
+ALTER TABLE users
+    ADD COLUMN locked_until TIMESTAMPTZ;
+
+CREATE TABLE failed_logins
+(
+    id           SERIAL PRIMARY KEY,
+    user_id      INTEGER REFERENCES users (id) ON DELETE CASCADE,
+    ip_address   VARCHAR(45) NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX failed_logins_user_id_attempted_at ON failed_logins (user_id, attempted_at);
+CREATE INDEX failed_logins_ip_address_attempted_at ON failed_logins (ip_address, attempted_at);
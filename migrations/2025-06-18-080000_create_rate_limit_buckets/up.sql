@@ -0,0 +1,7 @@
+-- Token buckets for RATE_LIMIT_STORE=postgres, shared by every instance of the API.
+CREATE TABLE rate_limit_buckets
+(
+    key        VARCHAR(255)     PRIMARY KEY,
+    tokens     DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMPTZ      NOT NULL
+);
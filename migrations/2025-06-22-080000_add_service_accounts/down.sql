@@ -0,0 +1,21 @@
+UPDATE roles
+SET permission = permission & ~(1::BIGINT << 21)
+WHERE name = 'owner';
+
+UPDATE users
+SET permissions = permissions & ~(1::BIGINT << 21);
+
+DELETE FROM permissions
+WHERE name = 'can_manage_service_accounts';
+
+DELETE FROM users
+WHERE is_service_account;
+
+ALTER TABLE users
+    DROP CONSTRAINT users_service_account_without_password,
+    DROP CONSTRAINT users_human_fields,
+    ALTER COLUMN last_name SET NOT NULL,
+    ALTER COLUMN first_name SET NOT NULL,
+    ALTER COLUMN password_hash SET NOT NULL,
+    DROP COLUMN description,
+    DROP COLUMN is_service_account;
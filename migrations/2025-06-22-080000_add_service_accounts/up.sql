@@ -0,0 +1,20 @@
+ALTER TABLE users
+    ADD COLUMN is_service_account BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN description VARCHAR(255),
+    ALTER COLUMN password_hash DROP NOT NULL,
+    ALTER COLUMN first_name DROP NOT NULL,
+    ALTER COLUMN last_name DROP NOT NULL,
+    ADD CONSTRAINT users_human_fields CHECK (
+        is_service_account
+        OR (password_hash IS NOT NULL AND first_name IS NOT NULL AND last_name IS NOT NULL)
+    ),
+    ADD CONSTRAINT users_service_account_without_password CHECK (
+        NOT is_service_account OR password_hash IS NULL
+    );
+
+INSERT INTO permissions (name, description)
+VALUES ('can_manage_service_accounts', 'Create service accounts and issue their credentials.'); -- bitmask: 1 << (21) = 2097152
+
+UPDATE roles
+SET permission = permission | (1::BIGINT << 21)
+WHERE name = 'owner';
@@ -0,0 +1,17 @@
+UPDATE roles
+SET permission = permission & ~(1::BIGINT << 22)
+WHERE name = 'owner';
+
+UPDATE users
+SET permissions = permissions & ~(1::BIGINT << 22);
+
+DELETE FROM permissions
+WHERE name = 'can_manage_oauth_clients';
+
+DROP TABLE oauth_refresh_tokens;
+
+ALTER TABLE user_sessions DROP COLUMN oauth_client_id;
+
+DROP TABLE oauth_consents;
+DROP TABLE oauth_authorization_codes;
+DROP TABLE oauth_clients;
@@ -0,0 +1,60 @@
+CREATE TABLE oauth_clients
+(
+    id                 SERIAL PRIMARY KEY,
+    client_id          VARCHAR(64)  NOT NULL UNIQUE,
+    -- NULL for public clients, which authenticate with PKCE instead of a secret.
+    client_secret_hash VARCHAR(64),
+    name               VARCHAR(100) NOT NULL,
+    redirect_uris      TEXT[]       NOT NULL,
+    -- Permission bitmask the client may request as scopes.
+    scopes             BIGINT       NOT NULL DEFAULT 0,
+    created_by         INTEGER      REFERENCES users (id) ON DELETE SET NULL,
+    created_at         TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE oauth_authorization_codes
+(
+    code_hash       VARCHAR(64) PRIMARY KEY,
+    oauth_client_id INTEGER     NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    user_id         INTEGER     NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- The redirect URI sent to /oauth/authorize, which the token request must repeat.
+    redirect_uri    TEXT,
+    scope           BIGINT      NOT NULL,
+    code_challenge  VARCHAR(128),
+    expires_at      TIMESTAMPTZ NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE oauth_consents
+(
+    user_id         INTEGER     NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    oauth_client_id INTEGER     NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    scope           BIGINT      NOT NULL,
+    granted_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, oauth_client_id)
+);
+
+-- Sessions started for an OAuth client end when the client is deleted.
+ALTER TABLE user_sessions
+    ADD COLUMN oauth_client_id INTEGER REFERENCES oauth_clients (id) ON DELETE CASCADE;
+
+CREATE TABLE oauth_refresh_tokens
+(
+    id         SERIAL PRIMARY KEY,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    session_id UUID        NOT NULL REFERENCES user_sessions (id) ON DELETE CASCADE,
+    scope      BIGINT      NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- Set once the token was exchanged; using it again revokes the whole session.
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX oauth_refresh_tokens_session_id ON oauth_refresh_tokens (session_id);
+
+INSERT INTO permissions (name, description)
+VALUES ('can_manage_oauth_clients', 'Register and delete OAuth clients.'); -- bitmask: 1 << (22) = 4194304
+
+UPDATE roles
+SET permission = permission | (1::BIGINT << 22)
+WHERE name = 'owner';
@@ -0,0 +1,13 @@
+ALTER TABLE user_sessions
+    DROP COLUMN auth_time;
+
+ALTER TABLE oauth_refresh_tokens
+    DROP COLUMN identity_scope;
+
+ALTER TABLE oauth_consents
+    DROP COLUMN identity_scope;
+
+ALTER TABLE oauth_authorization_codes
+    DROP COLUMN auth_time,
+    DROP COLUMN nonce,
+    DROP COLUMN identity_scope;
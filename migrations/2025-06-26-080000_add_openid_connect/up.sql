@@ -0,0 +1,15 @@
+-- OpenID Connect scopes granted alongside permissions: 1 = openid, 2 = profile, 4 = email.
+ALTER TABLE oauth_authorization_codes
+    ADD COLUMN identity_scope SMALLINT    NOT NULL DEFAULT 0,
+    ADD COLUMN nonce          VARCHAR(255),
+    ADD COLUMN auth_time      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+ALTER TABLE oauth_consents
+    ADD COLUMN identity_scope SMALLINT NOT NULL DEFAULT 0;
+
+ALTER TABLE oauth_refresh_tokens
+    ADD COLUMN identity_scope SMALLINT NOT NULL DEFAULT 0;
+
+-- When the user authenticated, for sessions started by an OAuth client.
+ALTER TABLE user_sessions
+    ADD COLUMN auth_time TIMESTAMPTZ;
@@ -0,0 +1,5 @@
+DROP TABLE oauth_client_assertions;
+
+ALTER TABLE oauth_clients
+    DROP COLUMN jwt_public_key,
+    DROP COLUMN service_account_id;
@@ -0,0 +1,14 @@
+ALTER TABLE oauth_clients
+    -- Service account the client acts as in the client_credentials grant.
+    ADD COLUMN service_account_id INTEGER REFERENCES users (id) ON DELETE CASCADE,
+    -- PEM public key of clients authenticating with private_key_jwt instead of a secret.
+    ADD COLUMN jwt_public_key     TEXT;
+
+-- `jti` of client assertions until they expire, so each can only be used once.
+CREATE TABLE oauth_client_assertions
+(
+    oauth_client_id INTEGER      NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    jti             VARCHAR(255) NOT NULL,
+    expires_at      TIMESTAMPTZ  NOT NULL,
+    PRIMARY KEY (oauth_client_id, jti)
+);
@@ -0,0 +1,19 @@
+-- Pending device authorization requests (RFC 8628), until the device polls for its tokens.
+CREATE TABLE oauth_device_codes
+(
+    device_code_hash VARCHAR(64) PRIMARY KEY,
+    -- Normalized to eight letters without the separator shown to the user.
+    user_code        VARCHAR(8)  NOT NULL UNIQUE,
+    oauth_client_id  INTEGER     NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    scope            BIGINT      NOT NULL,
+    identity_scope   SMALLINT    NOT NULL,
+    -- Set once a user approved the request.
+    user_id          INTEGER REFERENCES users (id) ON DELETE CASCADE,
+    auth_time        TIMESTAMPTZ,
+    denied           BOOLEAN     NOT NULL DEFAULT FALSE,
+    -- Seconds the device must wait between polls; raised on every slow_down.
+    poll_interval    INTEGER     NOT NULL,
+    last_polled_at   TIMESTAMPTZ,
+    expires_at       TIMESTAMPTZ NOT NULL,
+    created_at       TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
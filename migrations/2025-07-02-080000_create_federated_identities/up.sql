@@ -0,0 +1,29 @@
+-- Accounts at upstream OpenID Connect providers that log in as a local user.
+CREATE TABLE federated_identities
+(
+    id            SERIAL PRIMARY KEY,
+    user_id       INTEGER      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- Provider id from FEDERATED_PROVIDERS.
+    provider      VARCHAR(50)  NOT NULL,
+    -- `sub` of the upstream ID token, stable per provider.
+    subject       VARCHAR(255) NOT NULL,
+    -- Email reported by the provider when the identity was linked, for display only.
+    email         VARCHAR(255),
+    created_at    TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_login_at TIMESTAMPTZ,
+    UNIQUE (provider, subject),
+    UNIQUE (user_id, provider)
+);
+
+-- Federated logins and links that were started but not finished yet.
+CREATE TABLE federated_login_states
+(
+    state_hash    VARCHAR(64) PRIMARY KEY,
+    provider      VARCHAR(50) NOT NULL,
+    nonce         VARCHAR(64) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    -- Set when a logged-in user links the provider instead of logging in.
+    link_user_id  INTEGER REFERENCES users (id) ON DELETE CASCADE,
+    expires_at    TIMESTAMPTZ NOT NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
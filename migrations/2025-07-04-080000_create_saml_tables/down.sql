@@ -0,0 +1,5 @@
+DROP TABLE saml_login_codes;
+
+DROP TABLE saml_assertions;
+
+DROP TABLE saml_requests;
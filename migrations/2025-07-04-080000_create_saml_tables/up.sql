@@ -0,0 +1,28 @@
+-- SAML logins that were sent to an identity provider and not answered yet.
+CREATE TABLE saml_requests
+(
+    -- ID of the AuthnRequest, echoed as InResponseTo.
+    id         VARCHAR(80) PRIMARY KEY,
+    -- Provider id from SAML_PROVIDERS.
+    provider   VARCHAR(50) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Assertions that were used to log in, kept until they expire to reject replays.
+CREATE TABLE saml_assertions
+(
+    provider     VARCHAR(50)  NOT NULL,
+    assertion_id VARCHAR(255) NOT NULL,
+    expires_at   TIMESTAMPTZ  NOT NULL,
+    PRIMARY KEY (provider, assertion_id)
+);
+
+-- One-time codes handing a finished SAML login over to the login frontend.
+CREATE TABLE saml_login_codes
+(
+    code_hash  VARCHAR(64) PRIMARY KEY,
+    user_id    INTEGER     NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,6 @@
+-- Existing users without a password or names are kept; only new rows are checked.
+ALTER TABLE users
+    ADD CONSTRAINT users_human_fields CHECK (
+        is_service_account
+        OR (password_hash IS NOT NULL AND first_name IS NOT NULL AND last_name IS NOT NULL)
+    ) NOT VALID;
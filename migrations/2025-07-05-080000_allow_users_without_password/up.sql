@@ -0,0 +1,4 @@
+-- Users created through federated, SAML or LDAP logins have no password, and providers do not
+-- always share their names.
+ALTER TABLE users
+    DROP CONSTRAINT users_human_fields;
@@ -0,0 +1,9 @@
+UPDATE roles
+SET permission = permission & ~(1::BIGINT << 23)
+WHERE name = 'owner';
+
+UPDATE users
+SET permissions = permissions & ~(1::BIGINT << 23);
+
+DELETE FROM permissions
+WHERE name = 'can_provision_users';
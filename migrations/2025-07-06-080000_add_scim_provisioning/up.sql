@@ -0,0 +1,6 @@
+INSERT INTO permissions (name, description)
+VALUES ('can_provision_users', 'Create, update and delete users and role memberships through SCIM.'); -- bitmask: 1 << (23) = 8388608
+
+UPDATE roles
+SET permission = permission | (1::BIGINT << 23)
+WHERE name = 'owner';
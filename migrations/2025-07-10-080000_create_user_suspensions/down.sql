@@ -0,0 +1,11 @@
+UPDATE roles
+SET permission = permission & ~(1::BIGINT << 24)
+WHERE name = 'owner';
+
+UPDATE users
+SET permissions = permissions & ~(1::BIGINT << 24);
+
+DELETE FROM permissions
+WHERE name = 'can_edit_user';
+
+DROP TABLE user_suspensions;
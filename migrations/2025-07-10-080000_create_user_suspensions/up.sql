@@ -0,0 +1,20 @@
+CREATE TABLE user_suspensions
+(
+    id           SERIAL PRIMARY KEY,
+    user_id      INTEGER      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    reason       TEXT         NOT NULL,
+    suspended_by VARCHAR(255) NOT NULL,
+    ends_at      TIMESTAMPTZ,
+    lifted_at    TIMESTAMPTZ,
+    created_at   TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- A user has at most one suspension in force.
+CREATE UNIQUE INDEX unique_open_suspension ON user_suspensions (user_id) WHERE lifted_at IS NULL;
+
+INSERT INTO permissions (name, description)
+VALUES ('can_edit_user', 'Change the username, names and email address of other users.'); -- bitmask: 1 << (24) = 16777216
+
+UPDATE roles
+SET permission = permission | (1::BIGINT << 24)
+WHERE name = 'owner';
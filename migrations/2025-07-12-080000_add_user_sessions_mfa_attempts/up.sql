@@ -0,0 +1 @@
+ALTER TABLE user_sessions ADD COLUMN mfa_attempts INTEGER NOT NULL DEFAULT 0;
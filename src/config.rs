@@ -0,0 +1,116 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Runtime settings read from the environment at startup.
+pub struct AppConfig {
+    /// Public base URL of this API, used to build links sent by email.
+    pub public_url: String,
+    /// Minutes a password reset token stays valid.
+    pub password_reset_ttl_minutes: i64,
+    /// Failed password logins within `login_failure_window_minutes` that lock an account.
+    pub login_lockout_threshold: i64,
+    /// Minutes an account stays locked after too many failed logins.
+    pub login_lockout_minutes: i64,
+    /// Failed logins from one client IP within the window before it is refused with `429`.
+    pub login_ip_max_failures: i64,
+    /// Minutes failed logins are counted for.
+    pub login_failure_window_minutes: i64,
+    /// Take the client IP from `X-Forwarded-For`; only enable behind a trusted proxy.
+    pub trust_proxy_headers: bool,
+    /// Minutes a magic login link stays valid.
+    pub magic_link_ttl_minutes: i64,
+    /// Maximum magic login links emailed to one account per hour.
+    pub magic_link_max_per_hour: i64,
+    /// Hours an email verification token stays valid.
+    pub email_verification_ttl_hours: i64,
+    /// Minimum seconds between two verification emails for the same account.
+    pub email_verification_resend_seconds: i64,
+    /// Reject `login` until the email address is verified.
+    pub require_email_verification: bool,
+    /// Directory with per-locale email templates overriding the built-in ones.
+    pub mail_template_dir: Option<PathBuf>,
+    /// Issuer shown in authenticator apps.
+    pub mfa_issuer: String,
+    /// WebAuthn relying party id, the domain passkeys are bound to.
+    pub webauthn_rp_id: String,
+    /// WebAuthn relying party name shown by authenticators.
+    pub webauthn_rp_name: String,
+    /// Origin the browser reports during WebAuthn ceremonies.
+    pub webauthn_origin: String,
+    /// Minutes an OAuth access token stays valid.
+    pub oauth_access_token_ttl_minutes: i64,
+    /// Days an unused OAuth refresh token stays valid; every refresh starts the period again.
+    pub oauth_refresh_token_ttl_days: i64,
+    /// Page of the login frontend that handles authorization requests, published as the
+    /// authorization endpoint in the OpenID Connect discovery document.
+    pub oauth_authorize_url: String,
+    /// Page of the login frontend where users enter device codes, sent to devices as
+    /// `verification_uri`.
+    pub oauth_device_verification_url: String,
+    /// Page of the login frontend that upstream identity providers redirect back to; it must
+    /// be registered as redirect URI at every provider.
+    pub federated_redirect_url: String,
+    /// Entity id we use as SAML service provider.
+    pub saml_sp_entity_id: String,
+    /// Where identity providers post SAML responses; always `PUBLIC_URL/saml/acs`.
+    pub saml_acs_url: String,
+    /// Page of the login frontend that finished SAML logins redirect to with a `code`.
+    pub saml_login_redirect_url: String,
+}
+
+impl AppConfig {
+    pub fn from_env() -> Self {
+        let public_url = env::var("PUBLIC_URL")
+            .unwrap_or_else(|_| "http://localhost:3000".into())
+            .trim_end_matches('/')
+            .to_string();
+        let webauthn_origin =
+            env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| public_url.clone());
+        let oauth_authorize_url = env::var("OAUTH_AUTHORIZE_URL")
+            .unwrap_or_else(|_| format!("{}/oauth/authorize", public_url));
+        let oauth_device_verification_url = env::var("OAUTH_DEVICE_VERIFICATION_URL")
+            .unwrap_or_else(|_| format!("{}/device", public_url));
+        let federated_redirect_url = env::var("FEDERATED_REDIRECT_URL")
+            .unwrap_or_else(|_| format!("{}/auth/federated/callback", public_url));
+        let saml_sp_entity_id = env::var("SAML_SP_ENTITY_ID")
+            .unwrap_or_else(|_| format!("{}/saml/metadata", public_url));
+        let saml_acs_url = format!("{}/saml/acs", public_url);
+        let saml_login_redirect_url = env::var("SAML_LOGIN_REDIRECT_URL")
+            .unwrap_or_else(|_| format!("{}/auth/saml/callback", public_url));
+
+        Self {
+            public_url,
+            password_reset_ttl_minutes: env_or("PASSWORD_RESET_TTL_MINUTES", 30),
+            login_lockout_threshold: env_or("LOGIN_LOCKOUT_THRESHOLD", 5),
+            login_lockout_minutes: env_or("LOGIN_LOCKOUT_MINUTES", 15),
+            login_ip_max_failures: env_or("LOGIN_IP_MAX_FAILURES", 20),
+            login_failure_window_minutes: env_or("LOGIN_FAILURE_WINDOW_MINUTES", 15),
+            trust_proxy_headers: env_or("TRUST_PROXY_HEADERS", false),
+            magic_link_ttl_minutes: env_or("MAGIC_LINK_TTL_MINUTES", 15),
+            magic_link_max_per_hour: env_or("MAGIC_LINK_MAX_PER_HOUR", 5),
+            email_verification_ttl_hours: env_or("EMAIL_VERIFICATION_TTL_HOURS", 24),
+            email_verification_resend_seconds: env_or("EMAIL_VERIFICATION_RESEND_SECONDS", 60),
+            require_email_verification: env_or("REQUIRE_EMAIL_VERIFICATION", false),
+            mail_template_dir: env::var("MAIL_TEMPLATE_DIR").ok().map(PathBuf::from),
+            mfa_issuer: env::var("MFA_ISSUER").unwrap_or_else(|_| "user_auth".into()),
+            webauthn_rp_id: env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".into()),
+            webauthn_rp_name: env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "user_auth".into()),
+            webauthn_origin,
+            oauth_access_token_ttl_minutes: env_or("OAUTH_ACCESS_TOKEN_TTL_MINUTES", 60),
+            oauth_refresh_token_ttl_days: env_or("OAUTH_REFRESH_TOKEN_TTL_DAYS", 30),
+            oauth_authorize_url,
+            oauth_device_verification_url,
+            federated_redirect_url,
+            saml_sp_entity_id,
+            saml_acs_url,
+            saml_login_redirect_url,
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse::<T>().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,142 @@
+use crate::handlers::mfa::load_user;
+use crate::models::{ApiKey, ApiKeyView, CreatedApiKeyView, NewApiKeyInput};
+use crate::services::api_keys::{create_api_key, list_api_keys, revoke_api_key};
+use crate::services::jwt::{ensure_login_token, extract_user_from_jwt};
+use crate::services::permissions::{resolve_permissions, PermissionCache, ResolvedPermissions};
+use crate::{db::Pool, utils::error::internal_error};
+use axum::extract::Path;
+use axum::{http::HeaderMap, http::StatusCode, Extension, Json};
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+const MAX_API_KEY_NAME_LENGTH: usize = 100;
+
+pub(crate) fn to_view(api_key: ApiKey, resolved: &ResolvedPermissions) -> ApiKeyView {
+    ApiKeyView {
+        id: api_key.id,
+        name: api_key.name,
+        prefix: api_key.prefix,
+        permissions: resolved.names_of(api_key.permissions),
+        expires_at: api_key.expires_at,
+        last_used_at: api_key.last_used_at,
+        created_at: api_key.created_at,
+    }
+}
+
+/// Creates an API key for the logged-in `user`.
+///
+/// **Authentication:** Logged-in user. API keys cannot create further keys.
+///
+/// The key can only use the listed permissions, and only while the owner still holds them.
+/// The full key is returned once; only its hash is stored.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the key as JSON on success.
+/// - `400 BAD_REQUEST` if the name is empty or too long, or a permission is not held.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `NewApiKeyInput` JSON Payload Example
+/// ```json
+/// {
+///   "name": "CI deploy",
+///   "permissions": ["can_view_user_table"],
+///   "expires_in_days": 90
+/// }
+/// ```
+pub async fn create_own_api_key(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Json(payload): Json<NewApiKeyInput>,
+) -> Result<(StatusCode, Json<CreatedApiKeyView>), (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_API_KEY_NAME_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Name must be between 1 and 100 characters".into(),
+        ));
+    }
+
+    let user = load_user(&mut conn, &claims)?;
+    let resolved = resolve_permissions(&permission_cache, &claims, &mut conn).await?;
+    let scope = resolved.bits_of(&payload.permissions)?;
+
+    let expires_at = payload
+        .expires_in_days
+        .map(|days| Utc::now() + Duration::days(days.into()));
+
+    let (api_key, key) = create_api_key(&mut conn, user.id, name, scope, expires_at)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKeyView {
+            key,
+            api_key: to_view(api_key, &resolved),
+        }),
+    ))
+}
+
+/// Returns the active API keys of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user or API key.
+/// ___
+/// # Returns
+/// - `200 OK` with a list of API keys as JSON on success. The keys themselves are not
+///   included.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_own_api_keys(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ApiKeyView>>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    let user = load_user(&mut conn, &claims)?;
+    let resolved = resolve_permissions(&permission_cache, &claims, &mut conn).await?;
+
+    let keys = list_api_keys(&mut conn, user.id)?;
+
+    Ok(Json(
+        keys.into_iter()
+            .map(|api_key| to_view(api_key, &resolved))
+            .collect(),
+    ))
+}
+
+/// Revokes an API key of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user or API key.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `404 NOT_FOUND` if the user has no active key with this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn revoke_own_api_key(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+    Path(key_id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    let user = load_user(&mut conn, &claims)?;
+
+    if !revoke_api_key(&mut conn, user.id, key_id)? {
+        return Err((StatusCode::NOT_FOUND, "API key not found".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
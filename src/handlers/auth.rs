@@ -1,7 +1,59 @@
-use axum::{Extension, Json, http::StatusCode};
+use axum::{Extension, Json, extract::ConnectInfo, http::HeaderMap, http::StatusCode};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use diesel::prelude::*;
-use crate::{models::{Login, User}, schema::users::dsl::*, db::Pool, services::jwt::create_jwt, utils::{hash::verify_password, error::internal_error}};
+use crate::{
+    config::AppConfig,
+    db::Pool,
+    models::{
+        Login, LoginResponse, MagicLinkConsume, MagicLinkRequest, PasswordResetConfirm,
+        PasswordResetRequest, ResendVerificationInput, User, VerifyEmailInput,
+    },
+    schema::users::dsl::*,
+    services::{
+        audit::record_event,
+        email_change::change_email_with_token,
+        email_verification::{resend_verification_email, verify_email_token},
+        federation::accounts::resolve_federated_user,
+        ldap::{DirectoryLogin, LdapDirectories},
+        login_guard::{
+            clear_failed_logins, ensure_ip_allowed, is_locked, record_failed_login,
+        },
+        magic_link::{consume_magic_link, send_magic_link},
+        mailer::Mailer,
+        mfa::begin_login,
+        password_reset::{reset_password_with_token, send_reset_link},
+        permissions::PermissionCache,
+    },
+    utils::{
+        client_ip::client_ip,
+        error::internal_error,
+        hash::{verify_dummy_password, verify_password},
+    },
+};
+
+/// Account checks shared by every way of logging in once the user has been identified.
+pub(crate) fn ensure_login_allowed(
+    config: &AppConfig,
+    user: &User,
+) -> Result<(), (StatusCode, String)> {
+    if user.is_service_account {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Service accounts cannot log in interactively".into(),
+        ));
+    }
+
+    if !user.is_active {
+        return Err((StatusCode::FORBIDDEN, "Account is inactive".into()));
+    }
+
+    if config.require_email_verification && user.email_verified_at.is_none() {
+        return Err((StatusCode::FORBIDDEN, "Email address is not verified".into()));
+    }
+
+    Ok(())
+}
 
 /// Returns a list of all `permissions` from the database table.
 ///
@@ -9,12 +61,36 @@ use crate::{models::{Login, User}, schema::users::dsl::*, db::Pool, services::jw
 ///
 /// Accepts a JSON payload based on the `Login` struct containing user credentials
 /// to receive a JWT.
+///
+/// Emails in a domain served by an LDAP directory (`LDAP_DIRECTORIES`) are checked by binding
+/// to the directory as the user; the user is linked or created like a federated login and
+/// gets the roles mapped from their directory groups. The local password only applies if no
+/// directory has an entry for the email.
+///
+/// Failed attempts are counted per account and per client IP. Every failure delays the
+/// response a little longer; after `LOGIN_LOCKOUT_THRESHOLD` failures the account is locked
+/// for `LOGIN_LOCKOUT_MINUTES` and its owner is notified by email. While locked, the password
+/// is not tried and every login is answered like a wrong password.
+///
+/// Whether an account is inactive is only revealed once its password was verified.
+///
+/// The returned token may be restricted to a further step:
+/// - `mfa` if the user has an authenticator; exchange it at `POST /auth/mfa/verify`.
+/// - `mfa_enrollment` if a role of the user requires MFA but none is set up.
+/// - `password_change` if the password was reset by an administrator; it is only accepted
+///   by `PUT /users/profile/password`.
 /// ___
 /// # Returns
-/// - `200 OK` with JWT token as JSON on successful login.
-/// - `401 UNAUTHORIZED` if credentials are invalid.
-/// - `403 FORBIDDEN` if the user account is inactive.
+/// - `200 OK` with a `LoginResponse` as JSON on successful login.
+/// - `401 UNAUTHORIZED` if credentials are invalid or the account is temporarily locked.
+/// - `403 FORBIDDEN` if the user account is inactive, the email address is not verified
+///   while `REQUIRE_EMAIL_VERIFICATION` is enabled, or no user may be created for a
+///   directory user.
+/// - `409 CONFLICT` if a directory user's email belongs to an account that cannot be linked
+///   automatically.
+/// - `429 TOO_MANY_REQUESTS` if the client IP failed too many logins recently.
 /// - `500 INTERNAL_SERVER_ERROR` on database or token generation error.
+/// - `502 BAD_GATEWAY` if a directory serving the email cannot be reached.
 /// ---
 /// ## `Login` JSON Payload Example
 /// ```json
@@ -26,34 +102,394 @@ use crate::{models::{Login, User}, schema::users::dsl::*, db::Pool, services::jw
 pub async fn login(
     Extension(pool): Extension<Arc<Pool>>,
     Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(directories): Extension<Arc<LdapDirectories>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<Login>,
-) -> Result<Json<String>, (StatusCode, String)> {
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
     let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
 
+    let ip = client_ip(&headers, peer, config.trust_proxy_headers);
+    ensure_ip_allowed(&mut conn, &config, ip)?;
+
+    let login_email = payload.email.to_lowercase();
+    // Suspended users keep their address, which an active user may have taken meanwhile;
+    // that user is the one logging in. Otherwise the latest inactive one gets its 403.
     let user = users
-        .filter(email.eq(&payload.email.to_lowercase()))
+        .filter(email.eq(&login_email))
+        .order((is_active.desc(), id.desc()))
         .first::<User>(&mut conn)
         .optional()
         .map_err(|e| internal_error("DB query error", e))?;
 
-    let user = match user {
+    if directories.serves(&login_email) {
+        // Locked accounts do not get their password tried against the directory either.
+        if user.as_ref().is_some_and(is_locked) {
+            let delay = record_failed_login(&mut conn, mailer.as_ref(), &config, None, ip)?;
+            drop(conn);
+            tokio::time::sleep(delay).await;
+            return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into()));
+        }
+
+        // No connection is held while waiting for the directory.
+        drop(conn);
+        let directory_login = directories.authenticate(&login_email, &payload.password).await?;
+        conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+        match directory_login {
+            DirectoryLogin::Authenticated(directory, identity) => {
+                let user = resolve_federated_user(
+                    &mut conn,
+                    &directory.id,
+                    &directory.accounts,
+                    &identity,
+                )?;
+                if identity.roles.is_some() {
+                    permission_cache.invalidate_user(user.temp_id);
+                }
+
+                ensure_login_allowed(&config, &user)?;
+                clear_failed_logins(&mut conn, user.id)?;
+
+                record_event(
+                    &mut conn,
+                    Some(user.id),
+                    "ldap_login",
+                    Some(format!("directory {}", directory.id)),
+                )?;
+
+                let response = begin_login(&mut conn, &user, &jwt_secret)?;
+
+                return Ok(Json(response));
+            }
+            DirectoryLogin::InvalidCredentials => {
+                let delay =
+                    record_failed_login(&mut conn, mailer.as_ref(), &config, user.as_ref(), ip)?;
+                drop(conn);
+                tokio::time::sleep(delay).await;
+                return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into()));
+            }
+            // Users the directories do not know log in with their local password.
+            DirectoryLogin::NotFound => {}
+        }
+    }
+
+    // Service accounts have no password and are treated like unknown emails.
+    let user = match user.filter(|user| user.password_hash.is_some()) {
         Some(user) => user,
-        None => return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into())),
+        None => {
+            // Spend the same time as for a wrong password so unknown emails cannot be told apart.
+            verify_dummy_password(&payload.password);
+            let delay = record_failed_login(&mut conn, mailer.as_ref(), &config, None, ip)?;
+            drop(conn);
+            tokio::time::sleep(delay).await;
+            return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into()));
+        }
     };
 
-    if !user.is_active {
-        return Err((StatusCode::FORBIDDEN, "Account is inactive".into()));
+    // Answered like a wrong password so the lock does not reveal the account. The failure only
+    // counts against the IP, so it does not extend the lock.
+    if is_locked(&user) {
+        verify_dummy_password(&payload.password);
+        let delay = record_failed_login(&mut conn, mailer.as_ref(), &config, None, ip)?;
+        drop(conn);
+        tokio::time::sleep(delay).await;
+        return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into()));
     }
 
-    let is_valid = verify_password(&payload.password, &user.password_hash)
+    let stored_hash = user.password_hash.as_deref().unwrap_or_default();
+    let is_valid = verify_password(&payload.password, stored_hash)
         .map_err(|e| internal_error("Password verification failed", e))?;
 
     if !is_valid {
+        let delay = record_failed_login(&mut conn, mailer.as_ref(), &config, Some(&user), ip)?;
+        drop(conn);
+        tokio::time::sleep(delay).await;
         return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into()));
     }
 
-    let token =
-        create_jwt(&user, &jwt_secret).map_err(|e| internal_error("JWT generation failed", e))?;
+    if !user.is_active {
+        return Err((StatusCode::FORBIDDEN, "Account is inactive".into()));
+    }
+
+    clear_failed_logins(&mut conn, user.id)?;
+
+    if config.require_email_verification && user.email_verified_at.is_none() {
+        return Err((StatusCode::FORBIDDEN, "Email address is not verified".into()));
+    }
+
+    let response = begin_login(&mut conn, &user, &jwt_secret)?;
+
+    Ok(Json(response))
+}
+
+/// Emails a single-use sign-in link.
+///
+/// **Authentication:** No authentication required.
+///
+/// If an active account exists for the email, a link that logs in without a password is
+/// emailed to it. At most `MAGIC_LINK_MAX_PER_HOUR` links are sent to an account per hour.
+/// The response is the same in every case, so it cannot be used to discover registered
+/// emails.
+/// ___
+/// # Returns
+/// - `202 ACCEPTED` in every case except server errors.
+/// - `500 INTERNAL_SERVER_ERROR` on database pool error.
+/// ---
+/// ## `MagicLinkRequest` JSON Payload Example
+/// ```json
+/// {
+///   "email": "user@example.com"
+/// }
+/// ```
+pub async fn request_magic_link(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<MagicLinkRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let user = users
+        .filter(email.eq(&payload.email.to_lowercase()))
+        .filter(is_active.eq(true))
+        .filter(is_service_account.eq(false))
+        .first::<User>(&mut conn)
+        .optional();
+
+    match user {
+        Ok(Some(user)) => {
+            if let Err((_, e)) = send_magic_link(&mut conn, mailer.as_ref(), &config, &user) {
+                tracing::error!("Magic link for user {} failed: {}", user.id, e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => tracing::error!("Magic link lookup failed: {}", e),
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Logs in with the token from a sign-in link.
+///
+/// **Authentication:** No authentication required.
+///
+/// The token is single-use and expires after `MAGIC_LINK_TTL_MINUTES`. The link replaces
+/// the password only; the response is the same as for `POST /auth`, so users with an
+/// authenticator still get an `mfa` challenge token.
+/// ___
+/// # Returns
+/// - `200 OK` with a `LoginResponse` as JSON on success.
+/// - `400 BAD_REQUEST` if the token is invalid, used or expired.
+/// - `403 FORBIDDEN` if the user account is inactive, or the email address is not verified
+///   while `REQUIRE_EMAIL_VERIFICATION` is enabled.
+/// - `500 INTERNAL_SERVER_ERROR` on database or token generation error.
+/// ---
+/// ## `MagicLinkConsume` JSON Payload Example
+/// ```json
+/// {
+///   "token": "<token from the email>"
+/// }
+/// ```
+pub async fn login_with_magic_link(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<MagicLinkConsume>,
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let user = consume_magic_link(&mut conn, &payload.token)?;
+
+    ensure_login_allowed(&config, &user)?;
+
+    let response = begin_login(&mut conn, &user, &jwt_secret)?;
+
+    Ok(Json(response))
+}
+
+/// Starts the forgotten-password flow.
+///
+/// **Authentication:** No authentication required.
+///
+/// If an active account exists for the email, a single-use reset link is emailed to it.
+/// The response is the same whether or not the account exists, so it cannot be used to
+/// discover registered emails.
+/// ___
+/// # Returns
+/// - `202 ACCEPTED` in every case except server errors.
+/// - `500 INTERNAL_SERVER_ERROR` on database pool error.
+/// ---
+/// ## `PasswordResetRequest` JSON Payload Example
+/// ```json
+/// {
+///   "email": "user@example.com"
+/// }
+/// ```
+pub async fn request_password_reset(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<PasswordResetRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let user = users
+        .filter(email.eq(&payload.email.to_lowercase()))
+        .filter(is_active.eq(true))
+        .filter(is_service_account.eq(false))
+        .first::<User>(&mut conn)
+        .optional();
+
+    match user {
+        Ok(Some(user)) => {
+            if let Err((_, e)) = send_reset_link(&mut conn, mailer.as_ref(), &config, &user) {
+                tracing::error!("Password reset for user {} failed: {}", user.id, e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => tracing::error!("Password reset lookup failed: {}", e),
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Sets a new password using the token from a reset link.
+///
+/// **Authentication:** No authentication required.
+///
+/// The token is single-use and expires after `PASSWORD_RESET_TTL_MINUTES`. On success every
+/// session of the user is revoked.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if the token is invalid, used or expired, or the password does not
+///   meet the password policy.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `PasswordResetConfirm` JSON Payload Example
+/// ```json
+/// {
+///   "token": "<token from the email>",
+///   "new_password": "correcthorse42"
+/// }
+/// ```
+pub async fn confirm_password_reset(
+    Extension(pool): Extension<Arc<Pool>>,
+    Json(payload): Json<PasswordResetConfirm>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    reset_password_with_token(&mut conn, &payload.token, &payload.new_password)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Verifies an email address using the token from the verification email.
+///
+/// **Authentication:** No authentication required.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if the token is invalid, used or expired.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `VerifyEmailInput` JSON Payload Example
+/// ```json
+/// {
+///   "token": "<token from the email>"
+/// }
+/// ```
+pub async fn verify_email(
+    Extension(pool): Extension<Arc<Pool>>,
+    Json(payload): Json<VerifyEmailInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    verify_email_token(&mut conn, &payload.token)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Confirms a change of email address using the token sent to the new address.
+///
+/// **Authentication:** No authentication required.
+///
+/// The new address replaces the old one and counts as verified. A notice is sent to the
+/// old address.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if the token is invalid, used or expired.
+/// - `409 CONFLICT` if another active account uses the new address.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `VerifyEmailInput` JSON Payload Example
+/// ```json
+/// {
+///   "token": "<token from the email>"
+/// }
+/// ```
+pub async fn confirm_email_change(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<VerifyEmailInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    change_email_with_token(&mut conn, mailer.as_ref(), &config, &payload.token)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sends a new verification email.
+///
+/// **Authentication:** No authentication required.
+///
+/// Nothing is sent if the account does not exist, is verified already, or was sent a
+/// verification email less than `EMAIL_VERIFICATION_RESEND_SECONDS` ago. The response is
+/// the same in every case.
+/// ___
+/// # Returns
+/// - `202 ACCEPTED` in every case except server errors.
+/// - `500 INTERNAL_SERVER_ERROR` on database pool error.
+/// ---
+/// ## `ResendVerificationInput` JSON Payload Example
+/// ```json
+/// {
+///   "email": "user@example.com"
+/// }
+/// ```
+pub async fn resend_verification(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<ResendVerificationInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let user = users
+        .filter(email.eq(&payload.email.to_lowercase()))
+        .filter(is_active.eq(true))
+        .filter(is_service_account.eq(false))
+        .first::<User>(&mut conn)
+        .optional();
+
+    match user {
+        Ok(Some(user)) => {
+            if let Err((_, e)) =
+                resend_verification_email(&mut conn, mailer.as_ref(), &config, &user)
+            {
+                tracing::error!("Verification email for user {} failed: {}", user.id, e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => tracing::error!("Verification lookup failed: {}", e),
+    }
 
-    Ok(Json(token))
+    Ok(StatusCode::ACCEPTED)
 }
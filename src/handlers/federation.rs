@@ -0,0 +1,343 @@
+use crate::config::AppConfig;
+use crate::handlers::auth::ensure_login_allowed;
+use crate::handlers::mfa::load_user;
+use crate::models::{
+    FederatedCallbackInput, FederatedIdentity, FederatedIdentityView, FederatedLoginStartView,
+    FederatedProviderView, LoginResponse,
+};
+use crate::services::audit::record_event;
+use crate::services::federation::accounts::{
+    create_login_state, link_identity, list_identities, resolve_federated_user,
+    take_login_state, unlink_identity,
+};
+use crate::services::federation::FederatedProviders;
+use crate::services::jwt::{ensure_login_token, extract_user_from_jwt};
+use crate::services::mfa::begin_login;
+use crate::{db::Pool, utils::error::internal_error};
+use axum::extract::Path;
+use axum::{http::HeaderMap, http::StatusCode, Extension, Json};
+use std::sync::Arc;
+
+fn identity_view(
+    providers: &FederatedProviders,
+    identity: FederatedIdentity,
+) -> FederatedIdentityView {
+    // Identities of providers removed from the configuration are still listed, under their id.
+    let provider_name = providers
+        .find(&identity.provider)
+        .map(|provider| provider.name.clone())
+        .unwrap_or_else(|_| identity.provider.clone());
+
+    FederatedIdentityView {
+        provider: identity.provider,
+        provider_name,
+        email: identity.email,
+        created_at: identity.created_at,
+        last_login_at: identity.last_login_at,
+    }
+}
+
+/// Returns the configured upstream identity providers, for the login page to offer.
+///
+/// **Authentication:** No authentication required.
+/// ___
+/// # Returns
+/// - `200 OK` with a list of providers as JSON.
+pub async fn view_federated_providers(
+    Extension(providers): Extension<Arc<FederatedProviders>>,
+) -> Json<Vec<FederatedProviderView>> {
+    Json(
+        providers
+            .list()
+            .iter()
+            .map(|provider| FederatedProviderView {
+                id: provider.id.clone(),
+                name: provider.name.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// Starts a login with the upstream identity `provider`.
+///
+/// **Authentication:** No authentication required.
+///
+/// Returns the provider's authorization URL to send the browser to. The provider redirects
+/// back to `FEDERATED_REDIRECT_URL` with `code` and `state`, which the login frontend passes
+/// to `POST /auth/federated/{provider}/finish` within 10 minutes.
+/// ___
+/// # Returns
+/// - `200 OK` with the `authorization_url` as JSON on success.
+/// - `404 NOT_FOUND` if the provider is not configured.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// - `502 BAD_GATEWAY` if the provider's discovery document cannot be loaded.
+pub async fn start_federated_login(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(providers): Extension<Arc<FederatedProviders>>,
+    Path(provider_id): Path<String>,
+) -> Result<Json<FederatedLoginStartView>, (StatusCode, String)> {
+    let provider = providers.find(&provider_id)?;
+
+    let started = {
+        let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+        create_login_state(&mut conn, provider, None)?
+    };
+
+    let authorization_url = providers
+        .authorization_url(
+            provider,
+            &config.federated_redirect_url,
+            &started.state,
+            &started.nonce,
+            &started.code_verifier,
+        )
+        .await?;
+
+    Ok(Json(FederatedLoginStartView { authorization_url }))
+}
+
+/// Completes a login with the upstream identity `provider`.
+///
+/// **Authentication:** No authentication required.
+///
+/// Exchanges the code at the provider and verifies its ID token, then returns the same
+/// response as `POST /auth`. Identities already linked log in their user. Otherwise, if the
+/// provider verified the email address, the identity is linked to the user with that email,
+/// and unknown addresses get a new user with the provider's default roles. Each depends on the
+/// provider's `LINK_BY_EMAIL` and `AUTO_PROVISION` settings.
+/// ___
+/// # Returns
+/// - `200 OK` with a `LoginResponse` as JSON on success.
+/// - `400 BAD_REQUEST` if the state is unknown or expired, or the provider rejected the code.
+/// - `403 FORBIDDEN` if no user may be linked or created for the identity, or the user may
+///   not log in.
+/// - `404 NOT_FOUND` if the provider is not configured.
+/// - `409 CONFLICT` if a user with this email exists but cannot be linked automatically.
+/// - `500 INTERNAL_SERVER_ERROR` on database or token generation error.
+/// - `502 BAD_GATEWAY` if the provider cannot be reached or returns an invalid ID token.
+/// ---
+/// ## `FederatedCallbackInput` JSON Payload Example
+/// ```json
+/// {
+///   "code": "4/0AbCdEf...",
+///   "state": "pZ1v2m0k9Qe7tTf6Zr3l8w"
+/// }
+/// ```
+pub async fn finish_federated_login(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(providers): Extension<Arc<FederatedProviders>>,
+    Path(provider_id): Path<String>,
+    Json(payload): Json<FederatedCallbackInput>,
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
+    let provider = providers.find(&provider_id)?;
+
+    let login_state = {
+        let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+        take_login_state(&mut conn, provider, &payload.state)?
+    };
+    if login_state.link_user_id.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired state".into()));
+    }
+
+    // No connection is held while waiting for the provider.
+    let identity = providers
+        .authenticate(
+            provider,
+            &config.federated_redirect_url,
+            &payload.code,
+            &login_state.code_verifier,
+            &login_state.nonce,
+        )
+        .await?;
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let user = resolve_federated_user(&mut conn, &provider.id, &provider.accounts, &identity)?;
+
+    ensure_login_allowed(&config, &user)?;
+
+    record_event(
+        &mut conn,
+        Some(user.id),
+        "federated_login",
+        Some(format!("provider {}", provider.id)),
+    )?;
+
+    let response = begin_login(&mut conn, &user, &jwt_secret)?;
+
+    Ok(Json(response))
+}
+
+/// Returns the upstream identities linked to the logged-in `user`.
+///
+/// **Authentication:** Logged-in user.
+/// ___
+/// # Returns
+/// - `200 OK` with a list of linked identities as JSON on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_own_federated_identities(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(providers): Extension<Arc<FederatedProviders>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<FederatedIdentityView>>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let identities = list_identities(&mut conn, user.id)?;
+
+    Ok(Json(
+        identities
+            .into_iter()
+            .map(|identity| identity_view(&providers, identity))
+            .collect(),
+    ))
+}
+
+/// Starts linking an identity of the upstream `provider` to the logged-in `user`.
+///
+/// **Authentication:** Logged-in user; API keys are not accepted.
+///
+/// Works like `POST /auth/federated/{provider}/start`, but the login frontend passes the
+/// provider's answer to `POST /users/profile/federated-identities/{provider}/finish`.
+/// ___
+/// # Returns
+/// - `200 OK` with the `authorization_url` as JSON on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key.
+/// - `404 NOT_FOUND` if the provider is not configured.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// - `502 BAD_GATEWAY` if the provider's discovery document cannot be loaded.
+pub async fn start_identity_link(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(providers): Extension<Arc<FederatedProviders>>,
+    headers: HeaderMap,
+    Path(provider_id): Path<String>,
+) -> Result<Json<FederatedLoginStartView>, (StatusCode, String)> {
+    let provider = providers.find(&provider_id)?;
+
+    let started = {
+        let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+        let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+        ensure_login_token(&claims)?;
+        let user = load_user(&mut conn, &claims)?;
+
+        create_login_state(&mut conn, provider, Some(user.id))?
+    };
+
+    let authorization_url = providers
+        .authorization_url(
+            provider,
+            &config.federated_redirect_url,
+            &started.state,
+            &started.nonce,
+            &started.code_verifier,
+        )
+        .await?;
+
+    Ok(Json(FederatedLoginStartView { authorization_url }))
+}
+
+/// Completes linking an identity of the upstream `provider` to the logged-in `user`.
+///
+/// **Authentication:** Logged-in user; API keys are not accepted.
+///
+/// The user can log in with the provider afterwards, whatever email address it reports.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the linked identity as JSON on success.
+/// - `400 BAD_REQUEST` if the state is unknown, expired or was started by another user, or
+///   the provider rejected the code.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key.
+/// - `404 NOT_FOUND` if the provider is not configured.
+/// - `409 CONFLICT` if the identity is linked to another user, or the user already linked
+///   an identity of this provider.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// - `502 BAD_GATEWAY` if the provider cannot be reached or returns an invalid ID token.
+/// ---
+/// ## `FederatedCallbackInput` JSON Payload Example
+/// ```json
+/// {
+///   "code": "4/0AbCdEf...",
+///   "state": "pZ1v2m0k9Qe7tTf6Zr3l8w"
+/// }
+/// ```
+pub async fn finish_identity_link(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(providers): Extension<Arc<FederatedProviders>>,
+    headers: HeaderMap,
+    Path(provider_id): Path<String>,
+    Json(payload): Json<FederatedCallbackInput>,
+) -> Result<(StatusCode, Json<FederatedIdentityView>), (StatusCode, String)> {
+    let provider = providers.find(&provider_id)?;
+
+    let (user, login_state) = {
+        let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+        let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+        ensure_login_token(&claims)?;
+        let user = load_user(&mut conn, &claims)?;
+
+        (user, take_login_state(&mut conn, provider, &payload.state)?)
+    };
+    if login_state.link_user_id != Some(user.id) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired state".into()));
+    }
+
+    let identity = providers
+        .authenticate(
+            provider,
+            &config.federated_redirect_url,
+            &payload.code,
+            &login_state.code_verifier,
+            &login_state.nonce,
+        )
+        .await?;
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let linked = link_identity(&mut conn, &provider.id, &identity, user.id)?;
+
+    Ok((StatusCode::CREATED, Json(identity_view(&providers, linked))))
+}
+
+/// Removes the logged-in `user`'s link to the upstream `provider`.
+///
+/// **Authentication:** Logged-in user; API keys are not accepted.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key.
+/// - `404 NOT_FOUND` if the user has no identity of this provider linked.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn unlink_federated_identity(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+    Path(provider_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    if !unlink_identity(&mut conn, user.id, &provider_id)? {
+        return Err((StatusCode::NOT_FOUND, "No identity of this provider is linked".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
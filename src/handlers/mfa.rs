@@ -0,0 +1,289 @@
+use crate::config::AppConfig;
+use crate::models::{
+    LoginResponse, MfaCodeInput, MfaVerifyInput, RecoveryCodesView, TotpEnrollmentView, User,
+};
+use crate::services::audit::record_event;
+use crate::services::mailer::templates::{render, MailTemplate};
+use crate::services::mailer::Mailer;
+use crate::services::jwt::{
+    ensure_login_token, extract_claims_for, extract_user_from_jwt, Claims, TokenPurpose,
+};
+use crate::services::mfa::{
+    confirm_totp_enrollment, consume_recovery_code, disable_totp, finish_login,
+    generate_recovery_codes, has_confirmed_totp, role_requires_mfa, start_totp_enrollment,
+    take_mfa_attempt, verify_totp_code,
+};
+use crate::services::sessions::revoke_session;
+use crate::{db::Pool, schema::users::dsl::*, utils::error::internal_error};
+use axum::{http::HeaderMap, http::StatusCode, Extension, Json};
+use diesel::prelude::*;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub(crate) fn load_user(
+    conn: &mut PgConnection,
+    claims: &Claims,
+) -> Result<User, (StatusCode, String)> {
+    let temp_uuid = Uuid::parse_str(&claims.user_temp_id)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid UUID in token".into()))?;
+
+    users
+        .filter(temp_id.eq(temp_uuid))
+        .first::<User>(conn)
+        .map_err(|e| internal_error("Failed to load user", e))
+}
+
+/// Starts TOTP enrollment for the logged-in `user`.
+///
+/// **Authentication:** Logged-in user, or a token restricted to `mfa_enrollment`.
+///
+/// Generates a new secret; it only takes effect once confirmed with a code.
+/// ___
+/// # Returns
+/// - `200 OK` with the secret and `otpauth://` URI as JSON on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `409 CONFLICT` if TOTP is already enabled.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn enroll_totp(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+) -> Result<Json<TotpEnrollmentView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims =
+        extract_claims_for(TokenPurpose::MfaEnrollment, &jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let (secret, otpauth_uri) = start_totp_enrollment(&mut conn, &user, &config.mfa_issuer)?;
+
+    Ok(Json(TotpEnrollmentView {
+        secret,
+        otpauth_uri,
+    }))
+}
+
+/// Confirms TOTP enrollment with a code from the authenticator app.
+///
+/// **Authentication:** Logged-in user, or a token restricted to `mfa_enrollment`.
+///
+/// After confirmation every login requires a code. A user who enrolled with an
+/// `mfa_enrollment` token logs in again to get a full token. A fresh set of recovery codes
+/// is returned; they are not shown again.
+/// ___
+/// # Returns
+/// - `200 OK` with the recovery codes as JSON on success.
+/// - `400 BAD_REQUEST` if there is no pending enrollment or the code is invalid.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `MfaCodeInput` JSON Payload Example
+/// ```json
+/// {
+///   "code": "123456"
+/// }
+/// ```
+pub async fn confirm_totp(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Json(payload): Json<MfaCodeInput>,
+) -> Result<Json<RecoveryCodesView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims =
+        extract_claims_for(TokenPurpose::MfaEnrollment, &jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    confirm_totp_enrollment(&mut conn, &user, &config.mfa_issuer, &payload.code)?;
+
+    let recovery_codes = generate_recovery_codes(&mut conn, user.id)?;
+
+    Ok(Json(RecoveryCodesView { recovery_codes }))
+}
+
+/// Replaces the recovery codes of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user; API keys are not accepted.
+///
+/// Every earlier code stops working. The new codes are not shown again.
+/// ___
+/// # Returns
+/// - `200 OK` with the recovery codes as JSON on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key.
+/// - `409 CONFLICT` if TOTP is not enabled.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn regenerate_recovery_codes(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+) -> Result<Json<RecoveryCodesView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    if !has_confirmed_totp(&mut conn, user.id)? {
+        return Err((StatusCode::CONFLICT, "TOTP is not enabled".into()));
+    }
+
+    let recovery_codes = generate_recovery_codes(&mut conn, user.id)?;
+
+    record_event(&mut conn, Some(user.id), "mfa_recovery_codes_regenerated", None)?;
+
+    Ok(Json(RecoveryCodesView { recovery_codes }))
+}
+
+/// Turns off TOTP for the logged-in `user`.
+///
+/// **Authentication:** Logged-in user.
+///
+/// Requires a current code. Not allowed while a role of the user requires MFA.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if the code is invalid.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if a role of the user requires MFA or the request was made with an API
+///   key or OAuth token.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn remove_totp(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Json(payload): Json<MfaCodeInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    if role_requires_mfa(&mut conn, &user)? {
+        return Err((StatusCode::FORBIDDEN, "MFA is required for your role".into()));
+    }
+
+    if !verify_totp_code(&mut conn, &user, &config.mfa_issuer, &payload.code)? {
+        return Err((StatusCode::BAD_REQUEST, "Invalid code".into()));
+    }
+
+    disable_totp(&mut conn, user.id)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Completes a login that requires a second factor.
+///
+/// **Authentication:** Token restricted to `mfa`, as returned by `POST /auth`.
+///
+/// Exchanges the challenge token and a TOTP code, or one of the recovery codes, for the
+/// same response as `POST /auth`. The challenge token is revoked on success. Using a
+/// recovery code is written to the audit log and the user is notified by email.
+///
+/// Five codes can be tried with one challenge token. After that the token is revoked and the
+/// user has to log in with their password again.
+/// ___
+/// # Returns
+/// - `200 OK` with a `LoginResponse` as JSON on success.
+/// - `401 UNAUTHORIZED` if the token or the code is invalid, or too many codes were tried.
+/// - `403 FORBIDDEN` if the token is not an MFA challenge token or the account is inactive.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `MfaVerifyInput` JSON Payload Example
+/// ```json
+/// {
+///   "code": "123456"
+/// }
+/// ```
+/// or
+/// ```json
+/// {
+///   "recovery_code": "abcde-fghjk"
+/// }
+/// ```
+pub async fn verify_mfa(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    headers: HeaderMap,
+    Json(payload): Json<MfaVerifyInput>,
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_claims_for(TokenPurpose::Mfa, &jwt_secret, &headers, &mut conn).await?;
+    if claims.purpose != Some(TokenPurpose::Mfa) {
+        return Err((StatusCode::FORBIDDEN, "Not an MFA challenge token".into()));
+    }
+
+    let user = load_user(&mut conn, &claims)?;
+
+    if !user.is_active {
+        return Err((StatusCode::FORBIDDEN, "Account is inactive".into()));
+    }
+
+    if !take_mfa_attempt(&mut conn, &claims)? {
+        revoke_session(&mut conn, &claims)?;
+        record_event(
+            &mut conn,
+            Some(user.id),
+            "mfa_challenge_revoked",
+            Some("too many invalid codes".into()),
+        )?;
+        return Err((StatusCode::UNAUTHORIZED, "Too many invalid codes; log in again".into()));
+    }
+
+    match (&payload.code, &payload.recovery_code) {
+        (Some(code), None) => {
+            if !verify_totp_code(&mut conn, &user, &config.mfa_issuer, code)? {
+                return Err((StatusCode::UNAUTHORIZED, "Invalid code".into()));
+            }
+        }
+        (None, Some(recovery_code)) => {
+            let remaining = consume_recovery_code(&mut conn, user.id, recovery_code)?
+                .ok_or((StatusCode::UNAUTHORIZED, "Invalid code".into()))?;
+
+            record_event(
+                &mut conn,
+                Some(user.id),
+                "mfa_recovery_code_used",
+                Some(format!("{} recovery codes left", remaining)),
+            )?;
+
+            let message = render(
+                &config,
+                MailTemplate::RecoveryCodeUsed,
+                &user.locale,
+                &user.email,
+                &[
+                    ("first_name", user.greeting_name()),
+                    ("remaining", &remaining.to_string()),
+                ],
+            );
+            if let Err(e) = mailer.send(&message) {
+                tracing::error!("Recovery code notification for user {} failed: {}", user.id, e);
+            }
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Provide either code or recovery_code".into(),
+            ));
+        }
+    }
+
+    revoke_session(&mut conn, &claims)?;
+
+    let response = finish_login(&mut conn, &user, &jwt_secret)?;
+
+    Ok(Json(response))
+}
@@ -1,4 +1,17 @@
+// Handlers take each shared service as its own `Extension` extractor, so some need more
+// arguments than clippy allows by default.
+#![allow(clippy::too_many_arguments)]
+
 pub mod users;
 pub mod auth;
+pub mod federation;
+pub mod mfa;
+pub mod oauth;
+pub mod oidc;
 pub mod permissions;
-pub mod roles;
\ No newline at end of file
+pub mod roles;
+pub mod saml;
+pub mod scim;
+pub mod passkeys;
+pub mod api_keys;
+pub mod service_accounts;
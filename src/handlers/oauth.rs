@@ -0,0 +1,944 @@
+use crate::config::AppConfig;
+use crate::handlers::mfa::load_user;
+use crate::models::{
+    AuthorizationDecision, AuthorizationPromptView, AuthorizationRedirectView,
+    AuthorizationRequest, CreatedOAuthClientView, DeviceAuthorizationRequest, DeviceCodeQuery,
+    DeviceDecision, DevicePromptView, IntrospectionResponse, NewOAuthClientInput, OAuthClient,
+    OAuthClientView, TokenLookupRequest, TokenRequest, TokenResponse, User,
+};
+use crate::services::audit::record_event;
+use crate::services::jwt::{ensure_login_token, extract_user_from_jwt, Claims};
+use crate::services::oauth::clients::{
+    authenticate_client, delete_client, find_client, list_clients, register_client,
+    resolve_redirect_uri, validate_jwt_public_key, validate_redirect_uri, ClientAuthentication,
+};
+use crate::services::oauth::device::{
+    create_device_code, decide_device_code, find_pending_device_code, poll_device_code,
+    DEVICE_CODE_GRANT_TYPE,
+};
+use crate::services::oauth::tokens::{
+    consented_scope, create_authorization_code, find_refresh_token, is_valid_code_challenge,
+    issue_client_credentials_token, issue_tokens, record_consent, redeem_authorization_code,
+    refresh_tokens, IssuedTokens,
+};
+use crate::services::oauth::{
+    format_scope, parse_scope, redirect_with, scope_names, OAuthError, Scope, IDENTITY_OPENID,
+};
+use crate::services::oidc::{create_id_token, load_user_info, OidcSigningKey};
+use crate::services::permissions::{resolve_permissions, PermissionCache, ResolvedPermissions};
+use crate::services::service_accounts::find_service_account;
+use crate::services::sessions::{revoke_session_by_id, session_started_at};
+use crate::{db::Pool, utils::error::internal_error};
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Form, Json};
+use diesel::prelude::*;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const REQUIRED_PERMISSION: &str = "can_manage_oauth_clients";
+const SERVICE_ACCOUNT_PERMISSION: &str = "can_manage_service_accounts";
+const MAX_CLIENT_NAME_LENGTH: usize = 100;
+const MAX_NONCE_LENGTH: usize = 255;
+
+/// An authorization request whose client and redirect URI were verified.
+struct ValidatedAuthorization {
+    client: OAuthClient,
+    redirect_uri: String,
+    scope: Scope,
+}
+
+/// Authenticates the caller and checks `can_manage_oauth_clients`.
+async fn authorize_client_management(
+    jwt_secret: &str,
+    permission_cache: &PermissionCache,
+    headers: &HeaderMap,
+    conn: &mut PgConnection,
+) -> Result<(Claims, ResolvedPermissions), (StatusCode, String)> {
+    let claims = extract_user_from_jwt(jwt_secret, headers, conn).await?;
+
+    let resolved = resolve_permissions(permission_cache, &claims, conn).await?;
+    if !resolved.has(REQUIRED_PERMISSION) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing permission: {}", REQUIRED_PERMISSION),
+        ));
+    }
+
+    Ok((claims, resolved))
+}
+
+/// Loads the user a grant was made for; deactivated accounts get no tokens.
+fn load_grant_user(conn: &mut PgConnection, grant_user_id: i32) -> Result<User, OAuthError> {
+    use crate::schema::users::dsl::users;
+
+    let user = users
+        .find(grant_user_id)
+        .first::<User>(conn)
+        .map_err(|e| internal_error("Failed to load user", e))?;
+    if !user.is_active {
+        return Err(OAuthError::invalid_grant("Account is inactive"));
+    }
+
+    Ok(user)
+}
+
+fn to_client_view(client: OAuthClient, resolved: &ResolvedPermissions) -> OAuthClientView {
+    OAuthClientView {
+        scopes: resolved.names_of(client.scopes),
+        public: client.is_public(),
+        token_endpoint_auth_method: client.auth_method(),
+        service_account_id: client.service_account_id,
+        client_id: client.client_id,
+        name: client.name,
+        redirect_uris: client.redirect_uris,
+        created_at: client.created_at,
+    }
+}
+
+/// Checks an authorization request.
+///
+/// Errors about the client or redirect URI are returned directly, since the redirect URI
+/// cannot be trusted. Any other error is sent to the client through its redirect URI and is
+/// returned as the inner `Err`.
+fn validate_authorization(
+    conn: &mut PgConnection,
+    permission_cache: &PermissionCache,
+    request: &AuthorizationRequest,
+) -> Result<Result<ValidatedAuthorization, AuthorizationRedirectView>, (StatusCode, String)> {
+    let client = find_client(conn, &request.client_id)?
+        .ok_or((StatusCode::BAD_REQUEST, "Unknown client_id".to_string()))?;
+
+    let redirect_uri = resolve_redirect_uri(&client, request.redirect_uri.as_deref())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid redirect_uri".to_string()))?;
+
+    let reject = |error: &str, description: &str| {
+        let mut params = vec![("error", error), ("error_description", description)];
+        if let Some(state) = &request.state {
+            params.push(("state", state));
+        }
+        Ok(Err(AuthorizationRedirectView {
+            redirect_to: redirect_with(&redirect_uri, &params),
+        }))
+    };
+
+    if request.response_type != "code" {
+        return reject("unsupported_response_type", "Only response_type=code is supported");
+    }
+
+    match (&request.code_challenge, request.code_challenge_method.as_deref()) {
+        (Some(challenge), Some("S256")) if is_valid_code_challenge(challenge) => {}
+        (Some(_), Some("S256")) => return reject("invalid_request", "Invalid code_challenge"),
+        (Some(_), _) => {
+            return reject("invalid_request", "code_challenge_method must be S256");
+        }
+        (None, _) if client.is_public() => {
+            return reject("invalid_request", "Public clients must use PKCE");
+        }
+        (None, _) => {}
+    }
+
+    if request.nonce.as_ref().is_some_and(|n| n.len() > MAX_NONCE_LENGTH) {
+        return reject("invalid_request", "nonce is too long");
+    }
+
+    let scope = match parse_scope(permission_cache, conn, request.scope.as_deref())? {
+        Some(scope) if scope.permissions & !client.scopes == 0 => scope,
+        _ => return reject("invalid_scope", "Scope is unknown or not allowed for this client"),
+    };
+
+    Ok(Ok(ValidatedAuthorization {
+        client,
+        redirect_uri,
+        scope,
+    }))
+}
+
+/// Registers an OAuth client.
+///
+/// **Authentication:** `can_manage_oauth_clients`; API keys and OAuth tokens are not accepted.
+///
+/// Scopes are permission names the client may request; the caller must hold them. Public
+/// clients get no secret and must use PKCE. Clients with a `jwt_public_key` authenticate with
+/// `private_key_jwt` instead of a secret.
+///
+/// Linking a `service_account_id` lets the client use the `client_credentials` grant as that
+/// account, and requires `can_manage_service_accounts` too. Such clients may have no redirect
+/// URIs.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the client as JSON on success, including `client_secret` once for
+///   clients authenticating with a secret.
+/// - `400 BAD_REQUEST` if the name, a redirect URI or the public key is invalid, or a scope
+///   is not held.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if the service account does not exist.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `NewOAuthClientInput` JSON Payload Example
+/// ```json
+/// {
+///   "name": "Grafana",
+///   "redirect_uris": ["https://grafana.example.com/login/generic_oauth"],
+///   "scopes": ["can_view_user_table"],
+///   "public": false
+/// }
+/// ```
+pub async fn register_oauth_client(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Json(payload): Json<NewOAuthClientInput>,
+) -> Result<(StatusCode, Json<CreatedOAuthClientView>), (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, resolved) =
+        authorize_client_management(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_CLIENT_NAME_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Name must be between 1 and 100 characters".into(),
+        ));
+    }
+
+    if payload.redirect_uris.is_empty() && payload.service_account_id.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "At least one redirect URI is required".into(),
+        ));
+    }
+    for redirect_uri in &payload.redirect_uris {
+        validate_redirect_uri(redirect_uri).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+
+    let authentication = match (payload.public, payload.jwt_public_key) {
+        (true, Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Public clients cannot have a jwt_public_key".into(),
+            ));
+        }
+        (true, None) => ClientAuthentication::None,
+        (false, Some(pem)) => {
+            validate_jwt_public_key(&pem).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            ClientAuthentication::PrivateKeyJwt(pem)
+        }
+        (false, None) => ClientAuthentication::Secret,
+    };
+
+    if let Some(account_id) = payload.service_account_id {
+        if !resolved.has(SERVICE_ACCOUNT_PERMISSION) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("Missing permission: {}", SERVICE_ACCOUNT_PERMISSION),
+            ));
+        }
+        if payload.public {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Public clients cannot act as a service account".into(),
+            ));
+        }
+        find_service_account(&mut conn, account_id)?;
+    }
+
+    let scope = resolved.bits_of(&payload.scopes)?;
+    let creator = load_user(&mut conn, &claims)?;
+
+    let (client, client_secret) = register_client(
+        &mut conn,
+        name,
+        payload.redirect_uris,
+        scope,
+        authentication,
+        payload.service_account_id,
+        creator.id,
+    )?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedOAuthClientView {
+            client_secret,
+            client: to_client_view(client, &resolved),
+        }),
+    ))
+}
+
+/// Returns every registered OAuth client.
+///
+/// **Authentication:** `can_manage_oauth_clients`
+/// ___
+/// # Returns
+/// - `200 OK` with a list of clients as JSON on success. Secrets are not included.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_oauth_clients(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<OAuthClientView>>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (_, resolved) =
+        authorize_client_management(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let clients = list_clients(&mut conn)?;
+
+    Ok(Json(
+        clients
+            .into_iter()
+            .map(|client| to_client_view(client, &resolved))
+            .collect(),
+    ))
+}
+
+/// Deletes an OAuth client.
+///
+/// **Authentication:** `can_manage_oauth_clients`
+///
+/// Every token issued to the client stops working immediately.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if there is no such client.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn delete_oauth_client(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(client_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize_client_management(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    if !delete_client(&mut conn, &client_id)? {
+        return Err((StatusCode::NOT_FOUND, "Client not found".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Checks an authorization request and describes the consent to ask the logged-in `user`
+/// for.
+///
+/// **Authentication:** Logged-in user. API keys and OAuth tokens are not accepted.
+///
+/// The login frontend calls this with the query the client sent the browser to, and shows
+/// the returned client and scopes. If `consent_required` is `false`, the user already
+/// approved them and the frontend may submit the decision right away.
+/// ___
+/// # Returns
+/// - `200 OK` with the consent prompt as JSON, or with `redirect_to` if the request is
+///   invalid and the client must be told so.
+/// - `400 BAD_REQUEST` if the client or redirect URI is unknown.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_authorization_request(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Query(request): Query<AuthorizationRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let authorization = match validate_authorization(&mut conn, &permission_cache, &request)? {
+        Ok(authorization) => authorization,
+        Err(redirect) => return Ok(Json(redirect).into_response()),
+    };
+
+    let consented = consented_scope(&mut conn, user.id, &authorization.client)?;
+
+    Ok(Json(AuthorizationPromptView {
+        scopes: scope_names(&permission_cache, &mut conn, authorization.scope)?,
+        consent_required: consented.is_none_or(|granted| !granted.contains(authorization.scope)),
+        client_id: authorization.client.client_id,
+        client_name: authorization.client.name,
+    })
+    .into_response())
+}
+
+/// Records the logged-in `user`'s decision on an authorization request.
+///
+/// **Authentication:** Logged-in user. API keys and OAuth tokens are not accepted.
+///
+/// On approval a single-use authorization code is created and the scopes are remembered for
+/// the client. The browser must then be sent to `redirect_to`, which carries the code or
+/// `error=access_denied`.
+/// ___
+/// # Returns
+/// - `200 OK` with `redirect_to` as JSON.
+/// - `400 BAD_REQUEST` if the client or redirect URI is unknown.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `AuthorizationDecision` JSON Payload Example
+/// ```json
+/// {
+///   "response_type": "code",
+///   "client_id": "4f6c1d0e9a2b4c7d8e1f2a3b4c5d6e7f",
+///   "redirect_uri": "https://app.example.com/callback",
+///   "scope": "can_view_user_table",
+///   "state": "af0ifjsldkj",
+///   "code_challenge": "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+///   "code_challenge_method": "S256",
+///   "approve": true
+/// }
+/// ```
+pub async fn decide_authorization_request(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Json(decision): Json<AuthorizationDecision>,
+) -> Result<Json<AuthorizationRedirectView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let request = decision.request;
+    let authorization = match validate_authorization(&mut conn, &permission_cache, &request)? {
+        Ok(authorization) => authorization,
+        Err(redirect) => return Ok(Json(redirect)),
+    };
+
+    let mut params = Vec::new();
+
+    let code = if decision.approve {
+        let authenticated_at = session_started_at(&mut conn, &claims)?;
+        let consented = consented_scope(&mut conn, user.id, &authorization.client)?;
+        record_consent(
+            &mut conn,
+            user.id,
+            &authorization.client,
+            consented.unwrap_or_default().union(authorization.scope),
+        )?;
+
+        Some(create_authorization_code(
+            &mut conn,
+            &authorization.client,
+            user.id,
+            &request,
+            authorization.scope,
+            authenticated_at,
+        )?)
+    } else {
+        None
+    };
+
+    match &code {
+        Some(code) => params.push(("code", code.as_str())),
+        None => params.push(("error", "access_denied")),
+    }
+    if let Some(state) = &request.state {
+        params.push(("state", state));
+    }
+
+    Ok(Json(AuthorizationRedirectView {
+        redirect_to: redirect_with(&authorization.redirect_uri, &params),
+    }))
+}
+
+/// Starts a device authorization request for a client without a browser.
+///
+/// **Authentication:** Client credentials, as at the token endpoint. Public clients only send
+/// `client_id`.
+///
+/// The device shows `user_code` and `verification_uri` to the user and polls the token
+/// endpoint with `device_code` every `interval` seconds. The form body is
+/// `application/x-www-form-urlencoded`.
+/// ___
+/// # Returns
+/// - `200 OK` with `device_code`, `user_code`, `verification_uri`,
+///   `verification_uri_complete`, `expires_in` and `interval`.
+/// - `400 BAD_REQUEST` with `invalid_scope` if a scope is unknown or not allowed for the client.
+/// - `401 UNAUTHORIZED` with `invalid_client` if client authentication failed.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn authorize_device(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Form(form): Form<DeviceAuthorizationRequest>,
+) -> Result<Response, OAuthError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let client = authenticate_client(&mut conn, &config, &headers, &form.client)?;
+
+    let scope = match parse_scope(&permission_cache, &mut conn, form.scope.as_deref())? {
+        Some(scope) if scope.permissions & !client.scopes == 0 => scope,
+        _ => {
+            return Err(OAuthError::invalid_scope(
+                "Scope is unknown or not allowed for this client",
+            ));
+        }
+    };
+
+    let device = create_device_code(&mut conn, &config, &client, scope)?;
+
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(device)).into_response())
+}
+
+/// Describes the device request with `user_code` to the logged-in `user`.
+///
+/// **Authentication:** Logged-in user. API keys and OAuth tokens are not accepted.
+///
+/// The login frontend's verification page calls this with the code the user typed and shows
+/// the returned client and scopes before asking for approval.
+/// ___
+/// # Returns
+/// - `200 OK` with the client and scopes as JSON.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `404 NOT_FOUND` if the code is unknown, expired or already decided.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_device_request(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Query(query): Query<DeviceCodeQuery>,
+) -> Result<Json<DevicePromptView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+
+    let (device, client) = find_pending_device_code(&mut conn, &query.user_code)?
+        .ok_or((StatusCode::NOT_FOUND, "Unknown or expired user code".to_string()))?;
+
+    let scope = Scope {
+        permissions: device.scope,
+        identity: device.identity_scope,
+    };
+
+    Ok(Json(DevicePromptView {
+        scopes: scope_names(&permission_cache, &mut conn, scope)?,
+        client_id: client.client_id,
+        client_name: client.name,
+    }))
+}
+
+/// Records the logged-in `user`'s decision on a device request.
+///
+/// **Authentication:** Logged-in user. API keys and OAuth tokens are not accepted.
+///
+/// On approval the device receives tokens for the user on its next poll, and the scopes are
+/// remembered for the client.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `404 NOT_FOUND` if the code is unknown, expired or already decided.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `DeviceDecision` JSON Payload Example
+/// ```json
+/// {
+///   "user_code": "BCDF-GHJK",
+///   "approve": true
+/// }
+/// ```
+pub async fn decide_device_request(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+    Json(decision): Json<DeviceDecision>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let not_found = || (StatusCode::NOT_FOUND, "Unknown or expired user code".to_string());
+
+    let (device, client) =
+        find_pending_device_code(&mut conn, &decision.user_code)?.ok_or_else(not_found)?;
+
+    let approval = if decision.approve {
+        Some((user.id, session_started_at(&mut conn, &claims)?))
+    } else {
+        None
+    };
+
+    if !decide_device_code(&mut conn, &decision.user_code, approval)? {
+        return Err(not_found());
+    }
+
+    if decision.approve {
+        let scope = Scope {
+            permissions: device.scope,
+            identity: device.identity_scope,
+        };
+        let consented = consented_scope(&mut conn, user.id, &client)?;
+        record_consent(&mut conn, user.id, &client, consented.unwrap_or_default().union(scope))?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// OAuth token endpoint.
+///
+/// **Authentication:** Client credentials, with HTTP Basic authentication,
+/// `client_id`/`client_secret` in the form, or a `private_key_jwt` `client_assertion`.
+/// Public clients only send `client_id`.
+///
+/// Supports the `authorization_code` grant, with `code_verifier` if PKCE was used, the
+/// `refresh_token` grant, the `client_credentials` grant for clients linked to a service
+/// account, and the device code grant. The form body is `application/x-www-form-urlencoded`.
+/// An ID token is included when the `openid` scope was granted.
+/// ___
+/// # Returns
+/// - `200 OK` with `access_token`, `token_type`, `expires_in`, `refresh_token`, `id_token` and
+///   `scope`.
+/// - `400 BAD_REQUEST` with an OAuth `error` such as `invalid_grant`, `invalid_scope` or
+///   `unauthorized_client`, or `authorization_pending` and `slow_down` while a device code
+///   waits for the user.
+/// - `401 UNAUTHORIZED` with `invalid_client` if client authentication failed.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn issue_token(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(signing_key): Extension<Arc<OidcSigningKey>>,
+    headers: HeaderMap,
+    Form(form): Form<TokenRequest>,
+) -> Result<Response, OAuthError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let client = authenticate_client(&mut conn, &config, &headers, &form.client)?;
+
+    let (issued, nonce): (IssuedTokens, Option<String>) = match form.grant_type.as_str() {
+        "authorization_code" => {
+            let code = form
+                .code
+                .as_deref()
+                .ok_or_else(|| OAuthError::invalid_request("Missing code"))?;
+
+            let code = redeem_authorization_code(
+                &mut conn,
+                &client,
+                code,
+                form.redirect_uri.as_deref(),
+                form.code_verifier.as_deref(),
+            )?;
+
+            let user = load_grant_user(&mut conn, code.user_id)?;
+
+            let scope = Scope {
+                permissions: code.scope,
+                identity: code.identity_scope,
+            };
+            let issued = issue_tokens(
+                &mut conn,
+                &config,
+                &jwt_secret,
+                user,
+                &client,
+                scope,
+                code.auth_time,
+            )?;
+
+            (issued, code.nonce)
+        }
+        "refresh_token" => {
+            let refresh_token = form
+                .refresh_token
+                .as_deref()
+                .ok_or_else(|| OAuthError::invalid_request("Missing refresh_token"))?;
+
+            let requested_scope = match form.scope.as_deref() {
+                Some(scope) => Some(
+                    parse_scope(&permission_cache, &mut conn, Some(scope))?
+                        .ok_or_else(|| OAuthError::invalid_scope("Unknown scope"))?,
+                ),
+                None => None,
+            };
+
+            let issued = refresh_tokens(
+                &mut conn,
+                &config,
+                &jwt_secret,
+                &client,
+                refresh_token,
+                requested_scope,
+            )?;
+
+            (issued, None)
+        }
+        "client_credentials" => {
+            let account_id = match client.service_account_id {
+                Some(account_id) if !client.is_public() => account_id,
+                _ => {
+                    return Err(OAuthError::new(
+                        StatusCode::BAD_REQUEST,
+                        "unauthorized_client",
+                        "Client is not linked to a service account",
+                    ));
+                }
+            };
+
+            let scope = match form.scope.as_deref() {
+                Some(scope) => parse_scope(&permission_cache, &mut conn, Some(scope))?
+                    .ok_or_else(|| OAuthError::invalid_scope("Unknown scope"))?,
+                None => Scope {
+                    permissions: client.scopes,
+                    identity: 0,
+                },
+            };
+            if scope.identity != 0 || scope.permissions & !client.scopes != 0 {
+                return Err(OAuthError::invalid_scope(
+                    "Scope is not allowed for this client",
+                ));
+            }
+
+            let account = load_grant_user(&mut conn, account_id)?;
+
+            let issued = issue_client_credentials_token(
+                &mut conn,
+                &config,
+                &jwt_secret,
+                account,
+                &client,
+                scope,
+            )?;
+
+            (issued, None)
+        }
+        DEVICE_CODE_GRANT_TYPE => {
+            let device_code = form
+                .device_code
+                .as_deref()
+                .ok_or_else(|| OAuthError::invalid_request("Missing device_code"))?;
+
+            let approved = poll_device_code(&mut conn, &client, device_code)?;
+            let user = load_grant_user(&mut conn, approved.user_id)?;
+
+            let issued = issue_tokens(
+                &mut conn,
+                &config,
+                &jwt_secret,
+                user,
+                &client,
+                approved.scope,
+                approved.auth_time,
+            )?;
+
+            (issued, None)
+        }
+        _ => {
+            return Err(OAuthError::new(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                format!("Unsupported grant_type: {}", form.grant_type),
+            ));
+        }
+    };
+
+    let id_token = if issued.scope.has_identity(IDENTITY_OPENID) {
+        let info = load_user_info(&mut conn, &issued.user, issued.scope.identity)?;
+        Some(create_id_token(
+            &signing_key,
+            &config,
+            &client.client_id,
+            info,
+            nonce,
+            issued.auth_time,
+            issued.expires_at,
+        )?)
+    } else {
+        None
+    };
+
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(TokenResponse {
+            access_token: issued.access_token,
+            token_type: "Bearer",
+            expires_in: issued.expires_in,
+            refresh_token: issued.refresh_token,
+            id_token,
+            scope: format_scope(&permission_cache, &mut conn, issued.scope)?,
+        }),
+    )
+        .into_response())
+}
+
+/// Builds headers carrying `token` as bearer token, to check it like a request would.
+fn bearer_headers(token: &str) -> Option<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {}", token)).ok()?,
+    );
+    Some(headers)
+}
+
+/// Claims of `token` if it is an access token or API key that would be accepted right now.
+async fn active_access_token(
+    conn: &mut PgConnection,
+    jwt_secret: &str,
+    token: &str,
+) -> Result<Option<Claims>, (StatusCode, String)> {
+    let Some(headers) = bearer_headers(token) else {
+        return Ok(None);
+    };
+
+    match extract_user_from_jwt(jwt_secret, &headers, conn).await {
+        Ok(claims) => Ok(Some(claims)),
+        Err((status, message)) if status.is_server_error() => Err((status, message)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Describes a token to a resource server.
+///
+/// **Authentication:** Client credentials, as at the token endpoint. Public clients are not
+/// accepted.
+///
+/// Access tokens, API keys and refresh tokens are checked exactly as if they were used, so a
+/// revoked session or deactivated account makes them inactive. The form body is
+/// `application/x-www-form-urlencoded` with `token`.
+/// ___
+/// # Returns
+/// - `200 OK` with `active` and, for active tokens, `sub`, `username`, `client_id`, `scope`,
+///   `exp`, `token_type` and, for access tokens, the effective `permissions`.
+/// - `400 BAD_REQUEST` with `unauthorized_client` for public clients.
+/// - `401 UNAUTHORIZED` with `invalid_client` if client authentication failed.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn introspect_token(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Form(form): Form<TokenLookupRequest>,
+) -> Result<Response, OAuthError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let client = authenticate_client(&mut conn, &config, &headers, &form.client)?;
+    if client.is_public() {
+        return Err(OAuthError::new(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "Public clients cannot introspect tokens",
+        ));
+    }
+
+    let response = if let Some(grant) = find_refresh_token(&mut conn, &form.token)? {
+        if grant.active {
+            IntrospectionResponse {
+                active: true,
+                scope: Some(format_scope(&permission_cache, &mut conn, grant.scope)?),
+                client_id: Some(grant.client_id),
+                username: Some(grant.user.username),
+                token_type: Some("refresh_token"),
+                exp: Some(grant.expires_at.timestamp()),
+                sub: Some(grant.user.temp_id.to_string()),
+                permissions: None,
+            }
+        } else {
+            IntrospectionResponse::default()
+        }
+    } else if let Some(claims) = active_access_token(&mut conn, &jwt_secret, &form.token).await? {
+        let user = load_user(&mut conn, &claims)?;
+        let resolved = resolve_permissions(&permission_cache, &claims, &mut conn).await?;
+
+        let scope = match claims.scope {
+            Some(permissions) => {
+                let granted = Scope {
+                    permissions,
+                    identity: claims.identity_scope.unwrap_or(0),
+                };
+                Some(format_scope(&permission_cache, &mut conn, granted)?)
+            }
+            None => None,
+        };
+
+        IntrospectionResponse {
+            active: true,
+            scope,
+            client_id: claims.client_id,
+            username: Some(user.username),
+            token_type: Some("Bearer"),
+            // API keys without expiry report 0.
+            exp: Some(claims.exp as i64).filter(|exp| *exp > 0),
+            sub: Some(claims.user_temp_id),
+            permissions: Some(resolved.names()),
+        }
+    } else {
+        IntrospectionResponse::default()
+    };
+
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(response)).into_response())
+}
+
+/// Revokes a token issued to the calling client.
+///
+/// **Authentication:** Client credentials, as at the token endpoint. Public clients only send
+/// `client_id`.
+///
+/// Revoking an access token or refresh token ends the whole grant: the session behind it, with
+/// every access and refresh token issued for it. As required by RFC 7009, unknown tokens and
+/// tokens of other clients are answered with success and left alone. The form body is
+/// `application/x-www-form-urlencoded` with `token`.
+/// ___
+/// # Returns
+/// - `200 OK` on success.
+/// - `401 UNAUTHORIZED` with `invalid_client` if client authentication failed.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn revoke_token(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Form(form): Form<TokenLookupRequest>,
+) -> Result<StatusCode, OAuthError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let client = authenticate_client(&mut conn, &config, &headers, &form.client)?;
+
+    let revoked = if let Some(grant) = find_refresh_token(&mut conn, &form.token)? {
+        (grant.client_id == client.client_id).then_some((grant.session_id, grant.user.id))
+    } else if let Some(claims) = active_access_token(&mut conn, &jwt_secret, &form.token).await?
+        && claims.client_id.as_ref() == Some(&client.client_id)
+    {
+        let session_id = Uuid::parse_str(&claims.sid)
+            .map_err(|_| OAuthError::invalid_request("Invalid token"))?;
+        Some((session_id, load_user(&mut conn, &claims)?.id))
+    } else {
+        None
+    };
+
+    if let Some((session_id, owner_id)) = revoked {
+        revoke_session_by_id(&mut conn, session_id)?;
+        record_event(
+            &mut conn,
+            Some(owner_id),
+            "oauth_token_revoked",
+            Some(format!("client {}", client.client_id)),
+        )?;
+    }
+
+    Ok(StatusCode::OK)
+}
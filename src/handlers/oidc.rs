@@ -0,0 +1,114 @@
+use crate::config::AppConfig;
+use crate::handlers::mfa::load_user;
+use crate::models::UserInfoView;
+use crate::services::jwt::extract_user_from_jwt;
+use crate::services::oauth::device::DEVICE_CODE_GRANT_TYPE;
+use crate::services::oauth::{scope_names, Scope, IDENTITY_EMAIL, IDENTITY_OPENID, IDENTITY_PROFILE};
+use crate::services::oidc::{load_user_info, OidcSigningKey};
+use crate::services::permissions::PermissionCache;
+use crate::{db::Pool, utils::error::internal_error};
+use axum::{http::HeaderMap, http::StatusCode, Extension, Json};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Returns the OpenID Connect discovery document.
+///
+/// **Authentication:** None
+/// ___
+/// # Returns
+/// - `200 OK` with the provider metadata as JSON.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_openid_configuration(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let every_scope = Scope {
+        permissions: !0,
+        identity: IDENTITY_OPENID | IDENTITY_PROFILE | IDENTITY_EMAIL,
+    };
+    let issuer = &config.public_url;
+
+    Ok(Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": config.oauth_authorize_url,
+        "token_endpoint": format!("{}/oauth/token", issuer),
+        "device_authorization_endpoint": format!("{}/oauth/device_authorization", issuer),
+        "introspection_endpoint": format!("{}/oauth/introspect", issuer),
+        "revocation_endpoint": format!("{}/oauth/revoke", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+        "jwks_uri": format!("{}/.well-known/jwks.json", issuer),
+        "scopes_supported": scope_names(&permission_cache, &mut conn, every_scope)?,
+        "response_types_supported": ["code"],
+        "grant_types_supported": [
+            "authorization_code",
+            "refresh_token",
+            "client_credentials",
+            DEVICE_CODE_GRANT_TYPE
+        ],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+        "token_endpoint_auth_methods_supported": [
+            "client_secret_basic",
+            "client_secret_post",
+            "private_key_jwt",
+            "none"
+        ],
+        "token_endpoint_auth_signing_alg_values_supported": ["RS256", "ES256"],
+        "code_challenge_methods_supported": ["S256"],
+        "claims_supported": [
+            "sub", "iss", "aud", "exp", "iat", "auth_time", "nonce", "name", "given_name",
+            "family_name", "preferred_username", "locale", "roles", "updated_at", "email",
+            "email_verified"
+        ],
+    })))
+}
+
+/// Returns the public keys ID tokens are signed with.
+///
+/// **Authentication:** None
+/// ___
+/// # Returns
+/// - `200 OK` with a JSON Web Key Set.
+pub async fn view_jwks(Extension(signing_key): Extension<Arc<OidcSigningKey>>) -> Json<Value> {
+    Json(signing_key.jwks())
+}
+
+/// Returns OpenID Connect claims about the `user` of the token.
+///
+/// **Authentication:** Logged-in user, API key, or OAuth token with the `openid` scope.
+///
+/// OAuth tokens only see the claims of the granted `profile` and `email` scopes; other
+/// credentials see all of them. `sub` is the same as in ID tokens.
+/// ___
+/// # Returns
+/// - `200 OK` with the claims as JSON.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if an OAuth token lacks the `openid` scope.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_user_info(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+) -> Result<Json<UserInfoView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+
+    let identity_scope = match &claims.client_id {
+        Some(_) => claims.identity_scope.unwrap_or(0),
+        None => IDENTITY_OPENID | IDENTITY_PROFILE | IDENTITY_EMAIL,
+    };
+    if identity_scope & IDENTITY_OPENID == 0 {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Token was not granted the openid scope".into(),
+        ));
+    }
+
+    let user = load_user(&mut conn, &claims)?;
+
+    Ok(Json(load_user_info(&mut conn, &user, identity_scope)?))
+}
@@ -0,0 +1,328 @@
+use crate::config::AppConfig;
+use crate::handlers::auth::ensure_login_allowed;
+use crate::handlers::mfa::load_user;
+use crate::models::{LoginResponse, PasskeyLoginStartInput, PasskeyView, User, WebauthnCredential};
+use crate::services::jwt::{ensure_login_token, extract_user_from_jwt};
+use crate::services::mfa::{begin_login, finish_login};
+use crate::services::webauthn::{
+    create_challenge, creation_options, find_credential, list_credentials, record_credential_use,
+    request_options, store_credential, take_challenge, verify_assertion, verify_registration,
+    AuthenticationFinish, CeremonyStart, CreationOptions, RegistrationFinish, RequestOptions,
+    CEREMONY_AUTHENTICATION, CEREMONY_REGISTRATION,
+};
+use crate::{db::Pool, utils::error::internal_error};
+use axum::extract::Path;
+use axum::{http::HeaderMap, http::StatusCode, Extension, Json};
+use diesel::prelude::*;
+use std::sync::Arc;
+
+const MAX_PASSKEY_NAME_LENGTH: usize = 100;
+
+impl From<WebauthnCredential> for PasskeyView {
+    fn from(credential: WebauthnCredential) -> Self {
+        PasskeyView {
+            id: credential.id,
+            name: credential.name,
+            transports: credential.transports,
+            created_at: credential.created_at,
+            last_used_at: credential.last_used_at,
+        }
+    }
+}
+
+/// Starts registering a passkey for the logged-in `user`.
+///
+/// **Authentication:** Logged-in user; API keys are not accepted.
+///
+/// Returns the options for `navigator.credentials.create()` with binary fields base64url
+/// encoded, and the id of the challenge, which expires after 5 minutes.
+/// ___
+/// # Returns
+/// - `200 OK` with the `challengeId` and `publicKey` options as JSON on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn start_passkey_registration(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+) -> Result<Json<CeremonyStart<CreationOptions>>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let existing = list_credentials(&mut conn, user.id)?;
+    let (challenge_id, challenge) =
+        create_challenge(&mut conn, Some(user.id), CEREMONY_REGISTRATION)?;
+
+    let display_name = match (&user.first_name, &user.last_name) {
+        (Some(first), Some(last)) => format!("{} {}", first, last),
+        _ => user.username.clone(),
+    };
+
+    Ok(Json(CeremonyStart {
+        challenge_id,
+        public_key: creation_options(
+            &config,
+            challenge,
+            &user.temp_id,
+            &user.email,
+            &display_name,
+            &existing,
+        ),
+    }))
+}
+
+/// Completes registering a passkey for the logged-in `user`.
+///
+/// **Authentication:** Logged-in user; API keys are not accepted.
+///
+/// Verifies the credential returned by the browser against the challenge and stores its
+/// public key. Only ES256 and RS256 keys are accepted; attestation is not verified.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the new passkey as JSON on success.
+/// - `400 BAD_REQUEST` if the challenge is unknown or expired, or the credential is invalid.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key.
+/// - `409 CONFLICT` if the passkey is already registered.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `RegistrationFinish` JSON Payload Example
+/// ```json
+/// {
+///   "challengeId": "0b6f3c1e-4d0e-4a8e-9f59-2f6bfa0c4a11",
+///   "name": "Work laptop",
+///   "credential": {
+///     "id": "<base64url credential id>",
+///     "response": {
+///       "clientDataJSON": "<base64url>",
+///       "attestationObject": "<base64url>",
+///       "transports": ["internal", "hybrid"]
+///     }
+///   }
+/// }
+/// ```
+pub async fn finish_passkey_registration(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Json(payload): Json<RegistrationFinish>,
+) -> Result<(StatusCode, Json<PasskeyView>), (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let name = payload
+        .name
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "Passkey".to_string());
+    if name.chars().count() > MAX_PASSKEY_NAME_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "Passkey name is too long".into()));
+    }
+
+    let (challenge, challenge_user) =
+        take_challenge(&mut conn, payload.challenge_id, CEREMONY_REGISTRATION)?;
+    if challenge_user != Some(user.id) {
+        return Err((StatusCode::BAD_REQUEST, "Unknown or expired challenge".into()));
+    }
+
+    let registered = verify_registration(&config, &challenge, &payload.credential)?;
+    let credential = store_credential(
+        &mut conn,
+        user.id,
+        registered,
+        payload.credential.response.transports,
+        name,
+    )?;
+
+    Ok((StatusCode::CREATED, Json(credential.into())))
+}
+
+/// Returns the passkeys of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user.
+/// ___
+/// # Returns
+/// - `200 OK` with a list of passkeys as JSON on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_own_passkeys(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<PasskeyView>>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let credentials = list_credentials(&mut conn, user.id)?;
+
+    Ok(Json(credentials.into_iter().map(PasskeyView::from).collect()))
+}
+
+/// Removes a passkey of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user; API keys and OAuth tokens are not accepted.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `404 NOT_FOUND` if the user has no passkey with this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn remove_passkey(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+    Path(passkey_id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+    let user = load_user(&mut conn, &claims)?;
+
+    let deleted = diesel::delete(
+        webauthn_credentials
+            .filter(id.eq(passkey_id))
+            .filter(user_id.eq(user.id)),
+    )
+    .execute(&mut conn)
+    .map_err(|e| internal_error("DB delete error", e))?;
+
+    if deleted == 0 {
+        return Err((StatusCode::NOT_FOUND, "Passkey not found".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Starts a passkey login.
+///
+/// **Authentication:** No authentication required.
+///
+/// Returns the options for `navigator.credentials.get()`. With an `email`, only the
+/// passkeys of that account are allowed; without one the browser offers any discoverable
+/// passkey for this site. The response looks the same whether or not the account exists.
+/// ___
+/// # Returns
+/// - `200 OK` with the `challengeId` and `publicKey` options as JSON on success.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `PasskeyLoginStartInput` JSON Payload Example
+/// ```json
+/// {
+///   "email": "user@example.com"
+/// }
+/// ```
+pub async fn start_passkey_login(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<PasskeyLoginStartInput>,
+) -> Result<Json<CeremonyStart<RequestOptions>>, (StatusCode, String)> {
+    use crate::schema::users::dsl::{email, id, is_active, users};
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let allowed = match payload.email {
+        Some(address) => {
+            let owner = users
+                .filter(email.eq(address.to_lowercase()))
+                .filter(is_active.eq(true))
+                .select(id)
+                .first::<i32>(&mut conn)
+                .optional()
+                .map_err(|e| internal_error("DB query error", e))?;
+
+            match owner {
+                Some(owner) => list_credentials(&mut conn, owner)?,
+                None => Vec::new(),
+            }
+        }
+        None => Vec::new(),
+    };
+
+    let (challenge_id, challenge) = create_challenge(&mut conn, None, CEREMONY_AUTHENTICATION)?;
+
+    Ok(Json(CeremonyStart {
+        challenge_id,
+        public_key: request_options(&config, challenge, &allowed),
+    }))
+}
+
+/// Completes a passkey login.
+///
+/// **Authentication:** No authentication required.
+///
+/// Verifies the signed challenge and returns the same response as `POST /auth`. A passkey
+/// that verified the user (PIN or biometrics) counts as both factors; otherwise users with
+/// TOTP still get an `mfa` challenge token.
+/// ___
+/// # Returns
+/// - `200 OK` with a `LoginResponse` as JSON on success.
+/// - `400 BAD_REQUEST` if the challenge is unknown or expired, or the response is malformed.
+/// - `401 UNAUTHORIZED` if the passkey is unknown or the signature is invalid.
+/// - `403 FORBIDDEN` if the user account is inactive, or the email address is not verified
+///   while `REQUIRE_EMAIL_VERIFICATION` is enabled.
+/// - `500 INTERNAL_SERVER_ERROR` on database or token generation error.
+/// ---
+/// ## `AuthenticationFinish` JSON Payload Example
+/// ```json
+/// {
+///   "challengeId": "5b1d0a8e-7c1f-4c55-a4a7-2cf0b0b5e9d2",
+///   "credential": {
+///     "id": "<base64url credential id>",
+///     "response": {
+///       "clientDataJSON": "<base64url>",
+///       "authenticatorData": "<base64url>",
+///       "signature": "<base64url>"
+///     }
+///   }
+/// }
+/// ```
+pub async fn finish_passkey_login(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<AuthenticationFinish>,
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
+    use crate::schema::users::dsl::users;
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (challenge, _) = take_challenge(&mut conn, payload.challenge_id, CEREMONY_AUTHENTICATION)?;
+
+    let Some(stored) = find_credential(&mut conn, &payload.credential.id)? else {
+        return Err((StatusCode::UNAUTHORIZED, "Unknown passkey".into()));
+    };
+
+    let assertion = verify_assertion(&config, &challenge, &stored, &payload.credential)?;
+
+    let user = users
+        .find(stored.user_id)
+        .first::<User>(&mut conn)
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    ensure_login_allowed(&config, &user)?;
+
+    record_credential_use(&mut conn, stored.id, assertion.sign_count)?;
+
+    let response = if assertion.user_verified {
+        finish_login(&mut conn, &user, &jwt_secret)?
+    } else {
+        begin_login(&mut conn, &user, &jwt_secret)?
+    };
+
+    Ok(Json(response))
+}
@@ -1,5 +1,5 @@
 use crate::services::jwt::extract_user_from_jwt;
-use crate::services::permissions::user_has_permission;
+use crate::services::permissions::{user_has_permission, PermissionCache, PermissionCacheStats};
 use crate::{
     db::Pool, models::Permission, schema::permissions::dsl::*, utils::error::internal_error,
 };
@@ -21,15 +21,17 @@ use std::sync::Arc;
 pub async fn view_permissions_table(
     Extension(pool): Extension<Arc<Pool>>,
     Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
     headers: HeaderMap,
 ) -> Result<Json<Vec<Permission>>, (StatusCode, String)> {
     let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
 
-    let claims = extract_user_from_jwt(&jwt_secret, &headers).await?;
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
 
     const REQUIRED_PERMISSION: &str = "can_view_permission_table";
 
-    let allowed = user_has_permission(&claims, &mut conn, REQUIRED_PERMISSION).await?;
+    let allowed =
+        user_has_permission(&permission_cache, &claims, &mut conn, REQUIRED_PERMISSION).await?;
     if !allowed {
         return Err((
             StatusCode::FORBIDDEN,
@@ -43,3 +45,73 @@ pub async fn view_permissions_table(
 
     Ok(Json(all_permissions))
 }
+
+/// Returns hit/miss counters of the process-wide permission cache.
+///
+/// **Authentication:** `can_view_permission_table`
+///
+/// Extracts user info from JWT in headers and verifies access.
+/// ___
+/// # Returns
+/// - `200 OK` with the cache statistics as JSON on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_permission_cache_stats(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+) -> Result<Json<PermissionCacheStats>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+
+    const REQUIRED_PERMISSION: &str = "can_view_permission_table";
+
+    let allowed =
+        user_has_permission(&permission_cache, &claims, &mut conn, REQUIRED_PERMISSION).await?;
+    if !allowed {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing permission: {}", REQUIRED_PERMISSION),
+        ));
+    }
+
+    Ok(Json(permission_cache.stats()))
+}
+
+/// Drops every entry of the process-wide permission cache.
+///
+/// **Authentication:** `can_assign_permission`
+///
+/// Meant for after roles or grants were changed directly in the database.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn flush_permission_cache(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+
+    const REQUIRED_PERMISSION: &str = "can_assign_permission";
+
+    let allowed =
+        user_has_permission(&permission_cache, &claims, &mut conn, REQUIRED_PERMISSION).await?;
+    if !allowed {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing permission: {}", REQUIRED_PERMISSION),
+        ));
+    }
+
+    permission_cache.invalidate_all();
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,9 +1,9 @@
-use crate::models::{PermissionView, RoleTableView, RoleView};
+use crate::models::{PermissionView, RoleMfaInput, RoleTableView, RoleView};
 use crate::schema::roles::dsl::roles;
 use crate::services::jwt::extract_user_from_jwt;
-use crate::services::permissions::user_has_permission;
+use crate::services::permissions::{user_has_permission, PermissionCache};
 use crate::{db::Pool, utils::error::internal_error};
-use axum::{http::HeaderMap, http::StatusCode, Extension, Json};
+use axum::{extract::Path, http::HeaderMap, http::StatusCode, Extension, Json};
 use diesel::prelude::*;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -34,6 +34,7 @@ pub async fn view_roles(
 pub async fn view_role_table(
     Extension(pool): Extension<Arc<Pool>>,
     Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
     headers: HeaderMap,
 ) -> Result<Json<Vec<RoleTableView>>, (StatusCode, String)> {
     use crate::schema::permissions::dsl::{
@@ -43,10 +44,11 @@ pub async fn view_role_table(
 
     let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
 
-    let claims = extract_user_from_jwt(&jwt_secret, &headers).await?;
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
     const REQUIRED_PERMISSION: &str = "can_view_role_table";
 
-    let allowed = user_has_permission(&claims, &mut conn, REQUIRED_PERMISSION).await?;
+    let allowed =
+        user_has_permission(&permission_cache, &claims, &mut conn, REQUIRED_PERMISSION).await?;
     if !allowed {
         return Err((
             StatusCode::FORBIDDEN,
@@ -101,3 +103,58 @@ pub async fn view_role_table(
 
     Ok(Json(result))
 }
+
+/// Sets whether members of a `role` must use multi-factor authentication.
+///
+/// **Authentication:** `can_assign_permission`
+///
+/// Members without an authenticator only receive an `mfa_enrollment` token on login
+/// until they set one up.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if no role has the given name.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ___
+/// ## `RoleMfaInput` JSON Payload Example
+/// ```json
+/// {
+///   "require_mfa": true
+/// }
+/// ```
+pub async fn set_role_mfa_requirement(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(role_name): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<RoleMfaInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    use crate::schema::roles::dsl::{name, require_mfa};
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    const REQUIRED_PERMISSION: &str = "can_assign_permission";
+
+    let allowed =
+        user_has_permission(&permission_cache, &claims, &mut conn, REQUIRED_PERMISSION).await?;
+    if !allowed {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing permission: {}", REQUIRED_PERMISSION),
+        ));
+    }
+
+    let updated = diesel::update(roles.filter(name.eq(&role_name)))
+        .set(require_mfa.eq(payload.require_mfa))
+        .execute(&mut conn)
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    if updated == 0 {
+        return Err((StatusCode::NOT_FOUND, "Role not found".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
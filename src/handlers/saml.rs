@@ -0,0 +1,195 @@
+use crate::config::AppConfig;
+use crate::handlers::auth::ensure_login_allowed;
+use crate::models::{
+    FederatedProviderView, LoginResponse, SamlLoginCodeInput, SamlLoginStartView,
+    SamlResponseInput, User,
+};
+use crate::services::audit::record_event;
+use crate::services::federation::accounts::resolve_federated_user;
+use crate::services::mfa::begin_login;
+use crate::services::permissions::PermissionCache;
+use crate::services::saml::login::{
+    consume_login_code, create_authn_request, create_login_code, record_assertion,
+    take_authn_request,
+};
+use crate::services::saml::response::{decode_response, in_response_to, parse_response};
+use crate::services::saml::{sp_metadata, SamlProviders};
+use crate::{db::Pool, utils::error::internal_error};
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::{Extension, Form, Json};
+use diesel::prelude::*;
+use std::sync::Arc;
+use url::Url;
+
+/// Returns our SAML service provider metadata, to register at identity providers.
+///
+/// **Authentication:** No authentication required.
+/// ___
+/// # Returns
+/// - `200 OK` with the metadata as `application/samlmetadata+xml`.
+pub async fn view_sp_metadata(Extension(config): Extension<Arc<AppConfig>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        sp_metadata(&config),
+    )
+        .into_response()
+}
+
+/// Returns the configured SAML identity providers, for the login page to offer.
+///
+/// **Authentication:** No authentication required.
+/// ___
+/// # Returns
+/// - `200 OK` with a list of providers as JSON.
+pub async fn view_saml_providers(
+    Extension(providers): Extension<Arc<SamlProviders>>,
+) -> Json<Vec<FederatedProviderView>> {
+    Json(
+        providers
+            .list()
+            .iter()
+            .map(|provider| FederatedProviderView {
+                id: provider.id.clone(),
+                name: provider.name.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// Starts a login with the SAML identity `provider`.
+///
+/// **Authentication:** No authentication required.
+///
+/// Returns the provider's single sign-on URL carrying an `AuthnRequest`, to send the browser
+/// to. The provider posts its response to `POST /saml/acs` within 10 minutes.
+/// ___
+/// # Returns
+/// - `200 OK` with the `redirect_url` as JSON on success.
+/// - `404 NOT_FOUND` if the provider is not configured.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn start_saml_login(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(providers): Extension<Arc<SamlProviders>>,
+    Path(provider_id): Path<String>,
+) -> Result<Json<SamlLoginStartView>, (StatusCode, String)> {
+    let provider = providers.find(&provider_id)?;
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let request_id = create_authn_request(&mut conn, provider)?;
+    let redirect_url = provider.authn_request_url(&config, &request_id)?;
+
+    Ok(Json(SamlLoginStartView { redirect_url }))
+}
+
+/// Assertion consumer service: receives the response the identity provider makes the browser
+/// post after a login started with `POST /auth/saml/{provider}/start`.
+///
+/// **Authentication:** No authentication required; the response must be signed by the
+/// provider the request was sent to.
+///
+/// Verifies the response and finds, links or creates the user like a federated login. Roles
+/// the provider sends replace the user's roles. The browser is then redirected to
+/// `SAML_LOGIN_REDIRECT_URL` with a `code` that the login frontend exchanges at
+/// `POST /auth/saml/consume` within 60 seconds.
+/// ___
+/// # Returns
+/// - `303 SEE_OTHER` to the login frontend on success.
+/// - `400 BAD_REQUEST` if the response is invalid, answers an unknown or expired request, or
+///   its assertion was already used.
+/// - `401 UNAUTHORIZED` if the provider did not authenticate the user.
+/// - `403 FORBIDDEN` if no user may be linked or created for the identity, or the user may
+///   not log in.
+/// - `409 CONFLICT` if a user with this email exists but cannot be linked automatically.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn consume_saml_response(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(providers): Extension<Arc<SamlProviders>>,
+    Form(form): Form<SamlResponseInput>,
+) -> Result<Redirect, (StatusCode, String)> {
+    let xml = decode_response(&form.saml_response)?;
+    let document = parse_response(&xml)?;
+    let request_id = in_response_to(&document).ok_or((
+        StatusCode::BAD_REQUEST,
+        "Invalid SAML response: logins must be started here".to_string(),
+    ))?;
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    // A request is used up by its first response, whether or not that one is valid.
+    let provider_id = take_authn_request(&mut conn, request_id)?;
+    let provider = providers.find(&provider_id)?;
+
+    let assertion = provider.verify_response(&config, &document, request_id)?;
+    record_assertion(&mut conn, provider, &assertion.id, assertion.expires_at)?;
+
+    let user =
+        resolve_federated_user(&mut conn, &provider.id, &provider.accounts, &assertion.identity)?;
+    if assertion.identity.roles.is_some() {
+        permission_cache.invalidate_user(user.temp_id);
+    }
+
+    ensure_login_allowed(&config, &user)?;
+
+    record_event(
+        &mut conn,
+        Some(user.id),
+        "saml_login",
+        Some(format!("provider {}", provider.id)),
+    )?;
+
+    let code = create_login_code(&mut conn, user.id)?;
+
+    let mut redirect_url = Url::parse(&config.saml_login_redirect_url)
+        .map_err(|e| internal_error("Invalid SAML_LOGIN_REDIRECT_URL", e))?;
+    redirect_url.query_pairs_mut().append_pair("code", &code);
+
+    Ok(Redirect::to(redirect_url.as_str()))
+}
+
+/// Completes a SAML login with the code the assertion consumer service redirected with.
+///
+/// **Authentication:** No authentication required.
+///
+/// The code is single-use and expires after 60 seconds. The response is the same as for
+/// `POST /auth`, so users with an authenticator still get an `mfa` challenge token.
+/// ___
+/// # Returns
+/// - `200 OK` with a `LoginResponse` as JSON on success.
+/// - `401 UNAUTHORIZED` if the code is invalid, used or expired.
+/// - `403 FORBIDDEN` if the user may not log in.
+/// - `500 INTERNAL_SERVER_ERROR` on database or token generation error.
+/// ---
+/// ## `SamlLoginCodeInput` JSON Payload Example
+/// ```json
+/// {
+///   "code": "<code from the redirect>"
+/// }
+/// ```
+pub async fn login_with_saml_code(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<SamlLoginCodeInput>,
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
+    use crate::schema::users::dsl::users;
+
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let owner_id = consume_login_code(&mut conn, &payload.code)?;
+    let user = users
+        .find(owner_id)
+        .first::<User>(&mut conn)
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    ensure_login_allowed(&config, &user)?;
+
+    let response = begin_login(&mut conn, &user, &jwt_secret)?;
+
+    Ok(Json(response))
+}
@@ -0,0 +1,671 @@
+use crate::config::AppConfig;
+use crate::models::{
+    Role, ScimGroupInput, ScimGroupView, ScimListQuery, ScimListResponse, ScimPatchRequest,
+    ScimResourceQuery, ScimUserInput, ScimUserView, User,
+};
+use crate::services::audit::record_event;
+use crate::services::jwt::{extract_user_from_jwt, Claims};
+use crate::services::permissions::{resolve_permissions, PermissionCache, ResolvedPermissions};
+use crate::services::scim::groups::{
+    apply_patch, delete_role, ensure_display_name, find_role, insert_role, list_roles,
+    load_members, load_roles, member_ids, members, role_bit, role_name, sync_members,
+};
+use crate::services::scim::users::{
+    delete_user, find_user, granted_permissions, insert_user, list_users, update_user,
+    ScimUserFields,
+};
+use crate::services::scim::{
+    groups, parse_filter, users, Filter, Page, Scim, ScimError, MAX_PAGE_SIZE,
+    SERVICE_PROVIDER_CONFIG_SCHEMA,
+};
+use crate::{db::Pool, utils::error::internal_error};
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use diesel::PgConnection;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+const REQUIRED_PERMISSION: &str = "can_provision_users";
+
+/// Authenticates the caller and checks `can_provision_users`.
+async fn authorize(
+    jwt_secret: &str,
+    permission_cache: &PermissionCache,
+    headers: &HeaderMap,
+    conn: &mut PgConnection,
+) -> Result<(Claims, ResolvedPermissions), ScimError> {
+    let claims = extract_user_from_jwt(jwt_secret, headers, conn).await?;
+
+    let resolved = resolve_permissions(permission_cache, &claims, conn).await?;
+    if !resolved.has(REQUIRED_PERMISSION) {
+        return Err(ScimError::forbidden(format!(
+            "Missing permission: {}",
+            REQUIRED_PERMISSION
+        )));
+    }
+
+    Ok((claims, resolved))
+}
+
+/// Users and groups can only be changed by callers holding all the permissions they grant.
+fn ensure_covers(resolved: &ResolvedPermissions, bits: i64) -> Result<(), ScimError> {
+    if !resolved.covers(bits) {
+        return Err(ScimError::forbidden("The resource grants permissions you do not hold"));
+    }
+    Ok(())
+}
+
+fn base_url(config: &AppConfig) -> String {
+    format!("{}/scim/v2", config.public_url)
+}
+
+fn list_filter(query: &ScimListQuery) -> Result<Option<Filter>, ScimError> {
+    query.filter.as_deref().map(parse_filter).transpose()
+}
+
+fn excludes_members(excluded_attributes: Option<&str>) -> bool {
+    excluded_attributes.is_some_and(|attributes| {
+        attributes
+            .split(',')
+            .any(|attribute| attribute.trim().eq_ignore_ascii_case("members"))
+    })
+}
+
+fn created(location: String, resource: impl serde::Serialize) -> Response {
+    (StatusCode::CREATED, [(header::LOCATION, location)], Scim(resource)).into_response()
+}
+
+/// Records an update of `user` made through SCIM, naming (de)activations.
+fn record_user_update(
+    conn: &mut PgConnection,
+    before: &User,
+    after: &User,
+    claims: &Claims,
+) -> Result<(), ScimError> {
+    let event = match (before.is_active, after.is_active) {
+        (true, false) => "scim_user_deactivated",
+        (false, true) => "scim_user_reactivated",
+        _ => "scim_user_updated",
+    };
+    record_event(conn, Some(after.id), event, Some(format!("by {}", claims.sub)))?;
+    Ok(())
+}
+
+/// Records and applies the role changes of members added to or removed from `role`.
+fn record_membership_changes(
+    conn: &mut PgConnection,
+    permission_cache: &PermissionCache,
+    role: &Role,
+    changed: &[User],
+    claims: &Claims,
+) -> Result<(), ScimError> {
+    for user in changed {
+        permission_cache.invalidate_user(user.temp_id);
+        let change = if user.roles & role_bit(role) != 0 {
+            "added"
+        } else {
+            "removed"
+        };
+        record_event(
+            conn,
+            Some(user.id),
+            "scim_roles_changed",
+            Some(format!("role {} {} by {}", role.name, change, claims.sub)),
+        )?;
+    }
+    Ok(())
+}
+
+/// Describes the SCIM features we support.
+///
+/// **Authentication:** No authentication required.
+/// ___
+/// # Returns
+/// - `200 OK` with the service provider configuration as `application/scim+json`.
+pub async fn view_scim_service_provider_config(
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Scim<Value> {
+    Scim(json!({
+        "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+        "patch": { "supported": true },
+        "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+        "filter": { "supported": true, "maxResults": MAX_PAGE_SIZE },
+        "changePassword": { "supported": false },
+        "sort": { "supported": false },
+        "etag": { "supported": false },
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "Bearer token",
+            "description": "An API key or access token with the can_provision_users permission",
+            "primary": true
+        }],
+        "meta": {
+            "resourceType": "ServiceProviderConfig",
+            "location": format!("{}/ServiceProviderConfig", base_url(&config))
+        }
+    }))
+}
+
+/// Lists users, except service accounts.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// Supports `filter` with `userName`, `emails`, `active` or `id` and the `eq` operator, and
+/// pagination with `startIndex` and `count` (at most 200).
+/// ___
+/// # Returns
+/// - `200 OK` with a `ListResponse` as `application/scim+json`.
+/// - `400 BAD_REQUEST` if the filter is not supported.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn list_scim_users(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<ScimListQuery>,
+    headers: HeaderMap,
+) -> Result<Scim<ScimListResponse<ScimUserView>>, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let filter = list_filter(&query)?;
+    let page = Page::new(query.start_index, query.count);
+    let (total, found) = list_users(&mut conn, filter.as_ref(), page)?;
+    let roles = load_roles(&mut conn)?;
+
+    let base_url = base_url(&config);
+    let resources = found
+        .into_iter()
+        .map(|user| users::to_view(user, &roles, &base_url))
+        .collect();
+
+    Ok(Scim(page.respond(total, resources)))
+}
+
+/// Returns the user with the SCIM id `id`, their `temp_id`.
+///
+/// **Authentication:** `can_provision_users`
+/// ___
+/// # Returns
+/// - `200 OK` with the user as `application/scim+json`.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if no user has this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_scim_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Scim<ScimUserView>, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let user = find_user(&mut conn, &user_id)?;
+    let roles = load_roles(&mut conn)?;
+
+    Ok(Scim(users::to_view(user, &roles, &base_url(&config))))
+}
+
+/// Creates a user.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// The user has no password and no roles; they log in through the identity provider, and
+/// roles are assigned through group memberships. The email address counts as verified.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the user as `application/scim+json` on success.
+/// - `400 BAD_REQUEST` if an attribute is invalid or no email address is given.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `409 CONFLICT` if an active user has the userName or email.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `ScimUserInput` JSON Payload Example
+/// ```json
+/// {
+///   "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+///   "userName": "alice@example.com",
+///   "name": { "givenName": "Alice", "familyName": "Liddell" },
+///   "emails": [{ "value": "alice@example.com", "type": "work", "primary": true }],
+///   "active": true
+/// }
+/// ```
+pub async fn create_scim_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Json(payload): Json<ScimUserInput>,
+) -> Result<Response, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, _) = authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let fields = ScimUserFields::from_input(payload)?;
+    let user = insert_user(&mut conn, fields)?;
+
+    record_event(
+        &mut conn,
+        Some(user.id),
+        "scim_user_created",
+        Some(format!("by {}", claims.sub)),
+    )?;
+
+    let roles = load_roles(&mut conn)?;
+    let view = users::to_view(user, &roles, &base_url(&config));
+
+    Ok(created(view.meta.location.clone(), view))
+}
+
+/// Replaces the attributes of a user.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// Deactivating a user ends their sessions. Group memberships are not changed.
+/// ___
+/// # Returns
+/// - `200 OK` with the user as `application/scim+json` on success.
+/// - `400 BAD_REQUEST` if an attribute is invalid.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions, or the user holds permissions the caller
+///   does not hold.
+/// - `404 NOT_FOUND` if no user has this id.
+/// - `409 CONFLICT` if another active user has the userName or email.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn replace_scim_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<ScimUserInput>,
+) -> Result<Scim<ScimUserView>, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, resolved) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let user = find_user(&mut conn, &user_id)?;
+    let roles = load_roles(&mut conn)?;
+    ensure_covers(&resolved, granted_permissions(&user, &roles))?;
+
+    let fields = ScimUserFields::from_input(payload)?;
+    let updated = update_user(&mut conn, &user, fields)?;
+    permission_cache.invalidate_user(user.temp_id);
+    record_user_update(&mut conn, &user, &updated, &claims)?;
+
+    Ok(Scim(users::to_view(updated, &roles, &base_url(&config))))
+}
+
+/// Changes attributes of a user with PATCH operations.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// Supports `add`, `replace` and `remove` of `userName`, `name`, `name.givenName`,
+/// `name.familyName`, `emails` and `active`, with or without a path. Other attributes are
+/// ignored. Deactivating a user ends their sessions.
+/// ___
+/// # Returns
+/// - `200 OK` with the user as `application/scim+json` on success.
+/// - `400 BAD_REQUEST` if an operation or value is invalid.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions, or the user holds permissions the caller
+///   does not hold.
+/// - `404 NOT_FOUND` if no user has this id.
+/// - `409 CONFLICT` if another active user has the userName or email.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `ScimPatchRequest` JSON Payload Example
+/// ```json
+/// {
+///   "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+///   "Operations": [
+///     { "op": "replace", "path": "active", "value": false }
+///   ]
+/// }
+/// ```
+pub async fn patch_scim_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<ScimPatchRequest>,
+) -> Result<Scim<ScimUserView>, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, resolved) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let user = find_user(&mut conn, &user_id)?;
+    let roles = load_roles(&mut conn)?;
+    ensure_covers(&resolved, granted_permissions(&user, &roles))?;
+
+    let fields = ScimUserFields::from_user(&user).apply(&payload.operations)?;
+    let updated = update_user(&mut conn, &user, fields)?;
+    permission_cache.invalidate_user(user.temp_id);
+    record_user_update(&mut conn, &user, &updated, &claims)?;
+
+    Ok(Scim(users::to_view(updated, &roles, &base_url(&config))))
+}
+
+/// Deletes a user and everything that belongs to them.
+///
+/// **Authentication:** `can_provision_users`
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions, or the user holds permissions the caller
+///   does not hold.
+/// - `404 NOT_FOUND` if no user has this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn delete_scim_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, resolved) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let user = find_user(&mut conn, &user_id)?;
+    let roles = load_roles(&mut conn)?;
+    ensure_covers(&resolved, granted_permissions(&user, &roles))?;
+
+    delete_user(&mut conn, &user)?;
+    permission_cache.invalidate_user(user.temp_id);
+
+    record_event(
+        &mut conn,
+        None,
+        "scim_user_deleted",
+        Some(format!("user {} ({}) by {}", user.temp_id, user.email, claims.sub)),
+    )?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists groups, which are our roles.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// Supports `filter` with `displayName` or `id` and the `eq` operator, pagination with
+/// `startIndex` and `count`, and `excludedAttributes=members`.
+/// ___
+/// # Returns
+/// - `200 OK` with a `ListResponse` as `application/scim+json`.
+/// - `400 BAD_REQUEST` if the filter is not supported.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn list_scim_groups(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<ScimListQuery>,
+    headers: HeaderMap,
+) -> Result<Scim<ScimListResponse<ScimGroupView>>, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let filter = list_filter(&query)?;
+    let page = Page::new(query.start_index, query.count);
+    let (total, found) = list_roles(&mut conn, filter.as_ref(), page)?;
+
+    let base_url = base_url(&config);
+    let with_members = !excludes_members(query.excluded_attributes.as_deref());
+    let resources = found
+        .into_iter()
+        .map(|role| {
+            let role_members = with_members.then(|| members(&mut conn, &role)).transpose()?;
+            Ok(groups::to_view(role, role_members, &base_url))
+        })
+        .collect::<Result<_, ScimError>>()?;
+
+    Ok(Scim(page.respond(total, resources)))
+}
+
+/// Returns the group with the SCIM id `id`, the role id.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// Members are left out with `excludedAttributes=members`.
+/// ___
+/// # Returns
+/// - `200 OK` with the group as `application/scim+json`.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if no role has this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_scim_group(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(group_id): Path<String>,
+    Query(query): Query<ScimResourceQuery>,
+    headers: HeaderMap,
+) -> Result<Scim<ScimGroupView>, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let role = find_role(&mut conn, &group_id)?;
+    let role_members = if excludes_members(query.excluded_attributes.as_deref()) {
+        None
+    } else {
+        Some(members(&mut conn, &role)?)
+    };
+
+    Ok(Scim(groups::to_view(role, role_members, &base_url(&config))))
+}
+
+/// Creates a group as a role without permissions.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// The `displayName` becomes the role name, lowercased; it may only contain letters and `_`.
+/// Permissions are given to the role by an administrator. At most 16 roles can exist.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the group as `application/scim+json` on success.
+/// - `400 BAD_REQUEST` if the name is invalid, a member is unknown, or 16 roles exist.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `409 CONFLICT` if a role has the name.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `ScimGroupInput` JSON Payload Example
+/// ```json
+/// {
+///   "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Group"],
+///   "displayName": "support",
+///   "members": [{ "value": "0b9b6c4e-5d0a-4d8e-9a51-8f3f0d5c2a10" }]
+/// }
+/// ```
+pub async fn create_scim_group(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Json(payload): Json<ScimGroupInput>,
+) -> Result<Response, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, _) = authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let name = role_name(&payload.display_name)?;
+    let requested = load_members(&mut conn, &member_ids(&payload.members)?)?;
+
+    let role = insert_role(&mut conn, &name)?;
+    permission_cache.invalidate_all();
+    record_event(
+        &mut conn,
+        None,
+        "scim_group_created",
+        Some(format!("role {} by {}", role.name, claims.sub)),
+    )?;
+
+    let changed = sync_members(&mut conn, &role, &requested)?;
+    record_membership_changes(&mut conn, &permission_cache, &role, &changed, &claims)?;
+
+    let role_members = members(&mut conn, &role)?;
+    let view = groups::to_view(role, Some(role_members), &base_url(&config));
+
+    Ok(created(view.meta.location.clone(), view))
+}
+
+/// Replaces the members of a group.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// The `displayName` must be the role name; groups cannot be renamed.
+/// ___
+/// # Returns
+/// - `200 OK` with the group as `application/scim+json` on success.
+/// - `400 BAD_REQUEST` if the name differs or a member is unknown.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions, or the role grants permissions the caller
+///   does not hold.
+/// - `404 NOT_FOUND` if no role has this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn replace_scim_group(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(group_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<ScimGroupInput>,
+) -> Result<Scim<ScimGroupView>, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, resolved) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let role = find_role(&mut conn, &group_id)?;
+    ensure_covers(&resolved, role.permission)?;
+    ensure_display_name(&role, &payload.display_name)?;
+
+    let requested = load_members(&mut conn, &member_ids(&payload.members)?)?;
+    let changed = sync_members(&mut conn, &role, &requested)?;
+    record_membership_changes(&mut conn, &permission_cache, &role, &changed, &claims)?;
+
+    let role_members = members(&mut conn, &role)?;
+
+    Ok(Scim(groups::to_view(role, Some(role_members), &base_url(&config))))
+}
+
+/// Adds and removes members of a group with PATCH operations.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// Supports `add`, `replace` and `remove` of `members`, including
+/// `members[value eq "<id>"]` paths. Replacing `displayName` with the role name is accepted;
+/// other attributes are ignored.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if an operation is invalid, the name differs or a member is unknown.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions, or the role grants permissions the caller
+///   does not hold.
+/// - `404 NOT_FOUND` if no role has this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `ScimPatchRequest` JSON Payload Example
+/// ```json
+/// {
+///   "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+///   "Operations": [
+///     { "op": "add", "path": "members", "value": [{ "value": "<user id>" }] },
+///     { "op": "remove", "path": "members[value eq \"<user id>\"]" }
+///   ]
+/// }
+/// ```
+pub async fn patch_scim_group(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(group_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<ScimPatchRequest>,
+) -> Result<StatusCode, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, resolved) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let role = find_role(&mut conn, &group_id)?;
+    ensure_covers(&resolved, role.permission)?;
+
+    let mut member_ids: HashSet<_> = members(&mut conn, &role)?
+        .into_iter()
+        .map(|member| member.temp_id)
+        .collect();
+    apply_patch(&role, &mut member_ids, &payload.operations)?;
+
+    let requested = load_members(&mut conn, &member_ids)?;
+    let changed = sync_members(&mut conn, &role, &requested)?;
+    record_membership_changes(&mut conn, &permission_cache, &role, &changed, &claims)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes a group and takes the role from its members.
+///
+/// **Authentication:** `can_provision_users`
+///
+/// Only roles that grant no permissions can be deleted, such as those created through SCIM.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `401 UNAUTHORIZED` if the token is missing or invalid.
+/// - `403 FORBIDDEN` if user lacks permissions, or the role grants permissions.
+/// - `404 NOT_FOUND` if no role has this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn delete_scim_group(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(group_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ScimError> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, _) = authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let role = find_role(&mut conn, &group_id)?;
+    delete_role(&mut conn, &role)?;
+    permission_cache.invalidate_all();
+
+    record_event(
+        &mut conn,
+        None,
+        "scim_group_deleted",
+        Some(format!("role {} by {}", role.name, claims.sub)),
+    )?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,317 @@
+use crate::handlers::api_keys::to_view;
+use crate::models::{
+    ApiKeyView, CreatedApiKeyView, NewApiKeyInput, NewServiceAccount, NewServiceAccountInput,
+    ServiceAccountView, User,
+};
+use crate::services::api_keys::{create_api_key, list_api_keys, revoke_api_key};
+use crate::services::audit::record_event;
+use crate::services::jwt::{ensure_login_token, extract_user_from_jwt, Claims};
+use crate::services::permissions::{
+    resolve_permissions, resolve_user_permissions, PermissionCache, ResolvedPermissions,
+};
+use crate::services::service_accounts::{
+    find_service_account, insert_service_account, list_service_accounts, load_role_map,
+    role_names, roles_by_name,
+};
+use crate::{db::Pool, utils::error::internal_error};
+use axum::extract::Path;
+use axum::{http::HeaderMap, http::StatusCode, Extension, Json};
+use chrono::{Duration, Utc};
+use diesel::PgConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const REQUIRED_PERMISSION: &str = "can_manage_service_accounts";
+
+/// Authenticates the caller and checks `can_manage_service_accounts`.
+async fn authorize(
+    jwt_secret: &str,
+    permission_cache: &PermissionCache,
+    headers: &HeaderMap,
+    conn: &mut PgConnection,
+) -> Result<(Claims, ResolvedPermissions), (StatusCode, String)> {
+    let claims = extract_user_from_jwt(jwt_secret, headers, conn).await?;
+
+    let resolved = resolve_permissions(permission_cache, &claims, conn).await?;
+    if !resolved.has(REQUIRED_PERMISSION) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing permission: {}", REQUIRED_PERMISSION),
+        ));
+    }
+
+    Ok((claims, resolved))
+}
+
+fn to_account_view(account: User, role_map: &HashMap<i32, String>) -> ServiceAccountView {
+    ServiceAccountView {
+        id: account.id,
+        roles: role_names(role_map, account.roles),
+        username: account.username,
+        email: account.email,
+        description: account.description,
+        is_active: account.is_active,
+        created_at: account.created_at,
+    }
+}
+
+/// Creates a service account.
+///
+/// **Authentication:** `can_manage_service_accounts`
+///
+/// Service accounts hold roles like users but have no password; they authenticate only with
+/// credentials issued for them. The caller must hold every permission the requested roles
+/// grant.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the service account as JSON on success.
+/// - `400 BAD_REQUEST` if the username or description is invalid, or a role is unknown.
+/// - `403 FORBIDDEN` if user lacks permissions, or the roles grant permissions the caller
+///   does not hold.
+/// - `409 CONFLICT` if the username or email is taken.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `NewServiceAccountInput` JSON Payload Example
+/// ```json
+/// {
+///   "username": "billing-sync",
+///   "description": "Nightly export to the billing system",
+///   "roles": ["admin"]
+/// }
+/// ```
+pub async fn create_service_account(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+    Json(payload): Json<NewServiceAccountInput>,
+) -> Result<(StatusCode, Json<ServiceAccountView>), (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, resolved) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let username = payload.username.trim().to_lowercase();
+    if username.is_empty()
+        || username.len() > 200
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        return Err((StatusCode::BAD_REQUEST, "Invalid username".into()));
+    }
+
+    if payload.description.as_ref().is_some_and(|d| d.chars().count() > 255) {
+        return Err((StatusCode::BAD_REQUEST, "Description is too long".into()));
+    }
+
+    let (role_bits, role_permissions) = roles_by_name(&mut conn, &payload.roles)?;
+    if !resolved.covers(role_permissions) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Roles grant permissions you do not hold".into(),
+        ));
+    }
+
+    let account_email = payload
+        .email
+        .map(|e| e.trim().to_lowercase())
+        .unwrap_or_else(|| format!("{}@service-accounts.invalid", username));
+
+    let account = insert_service_account(
+        &mut conn,
+        &NewServiceAccount {
+            email: account_email,
+            username,
+            description: payload.description,
+            roles: role_bits,
+            is_service_account: true,
+        },
+    )?;
+
+    record_event(
+        &mut conn,
+        Some(account.id),
+        "service_account_created",
+        Some(format!("created by {}", claims.sub)),
+    )?;
+
+    let role_map = load_role_map(&mut conn)?;
+
+    Ok((StatusCode::CREATED, Json(to_account_view(account, &role_map))))
+}
+
+/// Returns every service account.
+///
+/// **Authentication:** `can_manage_service_accounts`
+/// ___
+/// # Returns
+/// - `200 OK` with a list of service accounts as JSON on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_service_accounts(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ServiceAccountView>>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let accounts = list_service_accounts(&mut conn)?;
+    let role_map = load_role_map(&mut conn)?;
+
+    Ok(Json(
+        accounts
+            .into_iter()
+            .map(|account| to_account_view(account, &role_map))
+            .collect(),
+    ))
+}
+
+/// Issues an API key for a service account.
+///
+/// **Authentication:** `can_manage_service_accounts`; API keys are not accepted.
+///
+/// The key is limited to the listed permissions, which both the service account and the
+/// caller must hold. The full key is returned once.
+/// ___
+/// # Returns
+/// - `201 CREATED` with the key as JSON on success.
+/// - `400 BAD_REQUEST` if the name is invalid or the account lacks a permission.
+/// - `403 FORBIDDEN` if user lacks permissions, or requests a permission it does not hold.
+/// - `404 NOT_FOUND` if there is no service account with this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ---
+/// ## `NewApiKeyInput` JSON Payload Example
+/// ```json
+/// {
+///   "name": "production",
+///   "permissions": ["can_view_user_table"],
+///   "expires_in_days": 365
+/// }
+/// ```
+pub async fn create_service_account_api_key(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(account_id): Path<i32>,
+    headers: HeaderMap,
+    Json(payload): Json<NewApiKeyInput>,
+) -> Result<(StatusCode, Json<CreatedApiKeyView>), (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, caller) = authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+    ensure_login_token(&claims)?;
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Name must be between 1 and 100 characters".into(),
+        ));
+    }
+
+    let account = find_service_account(&mut conn, account_id)?;
+    let account_permissions =
+        resolve_user_permissions(&permission_cache, account.temp_id, &mut conn).await?;
+
+    let scope = account_permissions.bits_of(&payload.permissions)?;
+    if !caller.covers(scope) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Cannot grant permissions you do not hold".into(),
+        ));
+    }
+
+    let expires_at = payload
+        .expires_in_days
+        .map(|days| Utc::now() + Duration::days(days.into()));
+
+    let (api_key, key) = create_api_key(&mut conn, account.id, name, scope, expires_at)?;
+
+    record_event(
+        &mut conn,
+        Some(account.id),
+        "api_key_issued",
+        Some(format!("key {} issued by {}", api_key.prefix, claims.sub)),
+    )?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKeyView {
+            key,
+            api_key: to_view(api_key, &account_permissions),
+        }),
+    ))
+}
+
+/// Returns the active API keys of a service account.
+///
+/// **Authentication:** `can_manage_service_accounts`
+/// ___
+/// # Returns
+/// - `200 OK` with a list of API keys as JSON on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if there is no service account with this id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_service_account_api_keys(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(account_id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ApiKeyView>>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let account = find_service_account(&mut conn, account_id)?;
+    let account_permissions =
+        resolve_user_permissions(&permission_cache, account.temp_id, &mut conn).await?;
+
+    let keys = list_api_keys(&mut conn, account.id)?;
+
+    Ok(Json(
+        keys.into_iter()
+            .map(|api_key| to_view(api_key, &account_permissions))
+            .collect(),
+    ))
+}
+
+/// Revokes an API key of a service account.
+///
+/// **Authentication:** `can_manage_service_accounts`
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if there is no such service account or active key.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn revoke_service_account_api_key(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path((account_id, key_id)): Path<(i32, i32)>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, _) = authorize(&jwt_secret, &permission_cache, &headers, &mut conn).await?;
+
+    let account = find_service_account(&mut conn, account_id)?;
+
+    if !revoke_api_key(&mut conn, account.id, key_id)? {
+        return Err((StatusCode::NOT_FOUND, "API key not found".into()));
+    }
+
+    record_event(
+        &mut conn,
+        Some(account.id),
+        "api_key_revoked",
+        Some(format!("key {} revoked by {}", key_id, claims.sub)),
+    )?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,30 +1,77 @@
-use crate::models::{UserTableView, UserView};
-use crate::services::jwt::extract_user_from_jwt;
+use crate::config::AppConfig;
+use crate::models::{
+    AdminPasswordResetInput, AdminPasswordResetMode, AdminUserUpdateInput, EmailChangeInput,
+    ProfileUpdateInput, SuspendUserInput, SuspensionView, TemporaryPasswordView, UserDetailView,
+    UserTableView, UserView,
+};
+use crate::services::audit::record_event;
+use crate::services::email_change::{
+    normalized_email, notify_email_changed, send_email_change_link,
+};
+use crate::services::email_verification::send_verification_email;
+use crate::services::login_guard::unlock_account;
+use crate::services::mailer::templates::valid_locale;
+use crate::services::mailer::Mailer;
+use crate::services::password_reset::send_reset_link;
+use crate::services::jwt::{
+    ensure_login_token, extract_claims_for, extract_user_from_jwt, Claims, TokenPurpose,
+};
+use crate::services::suspensions::{self, open_suspension};
 use crate::{
     db::Pool,
-    models::{NewUser, NewUserInput, User},
+    models::{ChangePasswordInput, NewUser, NewUserInput, User},
     schema::users::dsl::*,
-    services::permissions::user_has_permission,
-    utils::{error::internal_error, hash::hash_password},
+    services::permissions::{
+        resolve_permissions, resolve_user_permissions, user_has_permission, PermissionCache,
+        ResolvedPermissions,
+    },
+    services::sessions::revoke_user_sessions,
+    utils::{
+        error::internal_error,
+        hash::{hash_password, verify_password},
+        password_policy::{generate_temporary_password, validate_password},
+    },
 };
 use axum::{
-    http::{HeaderMap, StatusCode}, Extension,
-    Json,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
 use std::sync::Arc;
 use uuid::Uuid;
 
+const MAX_USERNAME_LENGTH: usize = 200;
+const MAX_NAME_LENGTH: usize = 100;
+
+/// Username, first name and last name.
+type ProfileFields = (String, Option<String>, Option<String>);
+
+type UserRow = (
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    i16,
+    Option<DateTime<Utc>>,
+);
+
 /// Create a new user.
 ///
 /// **Authentication:** No authentication required.
 ///
 /// Accepts a JSON payload based on the `NewUserInput` struct containing minimal
 /// user information to create a new user in the database.
+///
+/// A verification link is emailed to the new address.
 /// ___
 /// # Returns
 /// - `201 Created` with the email on success.
+/// - `400 BAD_REQUEST` if the password does not meet the password policy or the locale
+///   is invalid.
 /// - `500 INTERNAL_SERVER_ERROR` on database error.
 /// ___
 /// ## `NewUserInput` JSON Payload Example
@@ -33,13 +80,23 @@ use uuid::Uuid;
 ///   "email": "user@example.com",
 ///   "password": "password123",
 ///   "first_name": "John",
-///   "last_name": "Doe"
+///   "last_name": "Doe",
+///   "locale": "en"
 /// }
 /// ```
 pub async fn create_user(
     Extension(pool): Extension<Arc<Pool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
     Json(payload): Json<NewUserInput>,
 ) -> Result<(StatusCode, Json<String>), (StatusCode, String)> {
+    validate_password(&payload.password)?;
+
+    let user_locale = payload.locale.unwrap_or_else(|| "en".into());
+    if !valid_locale(&user_locale) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid locale".into()));
+    }
+
     let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
 
     let hashed_password = hash_password(&payload.password)
@@ -48,16 +105,21 @@ pub async fn create_user(
     let new_user = NewUser {
         email: payload.email.to_lowercase(),
         username: payload.username,
-        password_hash: hashed_password,
-        first_name: payload.first_name,
-        last_name: payload.last_name,
+        password_hash: Some(hashed_password),
+        first_name: Some(payload.first_name),
+        last_name: Some(payload.last_name),
+        locale: user_locale,
     };
 
-    diesel::insert_into(users)
+    let user = diesel::insert_into(users)
         .values(&new_user)
         .get_result::<User>(&mut conn)
         .map_err(|e| internal_error("DB insert error", e))?;
 
+    if let Err((_, e)) = send_verification_email(&mut conn, mailer.as_ref(), &config, &user) {
+        tracing::error!("Verification email for user {} failed: {}", user.id, e);
+    }
+
     Ok((StatusCode::CREATED, Json(new_user.email)))
 }
 
@@ -74,15 +136,17 @@ pub async fn create_user(
 pub async fn view_user_table(
     Extension(pool): Extension<Arc<Pool>>,
     Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
     headers: HeaderMap,
 ) -> Result<Json<Vec<UserTableView>>, (StatusCode, String)> {
     let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
 
-    let claims = extract_user_from_jwt(&jwt_secret, &headers).await?;
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
 
     const REQUIRED_PERMISSION: &str = "can_view_user_table";
 
-    let allowed = user_has_permission(&claims, &mut conn, REQUIRED_PERMISSION).await?;
+    let allowed =
+        user_has_permission(&permission_cache, &claims, &mut conn, REQUIRED_PERMISSION).await?;
     if !allowed {
         return Err((
             StatusCode::FORBIDDEN,
@@ -103,7 +167,8 @@ pub async fn view_user_table(
 /// **Authentication:** No authentication required.
 ///
 /// Extracts user info from JWT in headers and verifies access.
-/// Returns the name of the roles instead of bitmask.
+/// Returns the name of the roles instead of bitmask. Service accounts are listed at
+/// `GET /service-accounts` instead.
 /// ___
 /// # Returns
 /// - `200 OK` with JSON list of **users** on success.
@@ -117,8 +182,9 @@ pub async fn view_users(
     let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
 
     let raw_users = users
+        .filter(is_service_account.eq(false))
         .select((email, username, first_name, last_name, roles, created_at))
-        .load::<(String, String, String, String, i16, Option<DateTime<Utc>>)>(&mut conn)
+        .load::<UserRow>(&mut conn)
         .map_err(|e| internal_error("DB load error", e))?;
 
     let all_roles = roles_table
@@ -187,7 +253,7 @@ pub async fn view_own_user(
 
     let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
 
-    let claims = extract_user_from_jwt(&jwt_secret, &headers).await?;
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
 
     let temp_uuid = Uuid::parse_str(&claims.user_temp_id)
         .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid UUID in token".into()))?;
@@ -196,7 +262,7 @@ pub async fn view_own_user(
         users
             .filter(temp_id.eq(temp_uuid))
             .select((email, username, first_name, last_name, roles, created_at))
-            .first::<(String, String, String, String, i16, Option<DateTime<Utc>>)>(&mut conn)
+            .first::<UserRow>(&mut conn)
             .map_err(|e| internal_error("Failed to load user", e))?;
 
     let all_roles = roles_table
@@ -230,3 +296,738 @@ pub async fn view_own_user(
     Ok(Json(user_view))
 }
 
+/// Loads the `user` the request is authenticated as. The account's identity is only changed
+/// with login tokens, not with API keys or OAuth tokens.
+async fn load_own_user(
+    jwt_secret: &str,
+    headers: &HeaderMap,
+    conn: &mut PgConnection,
+) -> Result<User, (StatusCode, String)> {
+    let claims = extract_user_from_jwt(jwt_secret, headers, conn).await?;
+    ensure_login_token(&claims)?;
+
+    let temp_uuid = Uuid::parse_str(&claims.user_temp_id)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid UUID in token".into()))?;
+
+    users
+        .filter(temp_id.eq(temp_uuid))
+        .first::<User>(conn)
+        .map_err(|e| internal_error("Failed to load user", e))
+}
+
+/// `None` for blank names, else the trimmed name if it fits the column.
+fn profile_name(name: &str) -> Result<Option<String>, (StatusCode, String)> {
+    let name = name.trim();
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "Names are limited to 100 characters".into()));
+    }
+    Ok((!name.is_empty()).then(|| name.to_string()))
+}
+
+/// Username, first name and last name of `user` after the changes in `input`.
+fn changed_profile(
+    user: &User,
+    input: ProfileUpdateInput,
+) -> Result<ProfileFields, (StatusCode, String)> {
+    let new_username = match input.username {
+        Some(requested) => requested.trim().to_string(),
+        None => user.username.clone(),
+    };
+    if new_username.is_empty() || new_username.chars().count() > MAX_USERNAME_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "Invalid username".into()));
+    }
+    let new_first_name = match input.first_name {
+        Some(name) => profile_name(&name)?,
+        None => user.first_name.clone(),
+    };
+    let new_last_name = match input.last_name {
+        Some(name) => profile_name(&name)?,
+        None => user.last_name.clone(),
+    };
+
+    Ok((new_username, new_first_name, new_last_name))
+}
+
+/// `409 CONFLICT` with `message` for violations of the unique indexes of active users.
+fn taken(e: Error, message: &str) -> (StatusCode, String) {
+    match e {
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            (StatusCode::CONFLICT, message.to_string())
+        }
+        e => internal_error("DB update error", e),
+    }
+}
+
+/// Updates the username and names of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user.
+///
+/// Accepts a JSON payload based on the `ProfileUpdateInput` struct. Fields that are left out
+/// are kept; blank names are cleared. The email address is changed at
+/// `POST /users/profile/email` instead.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if the username is blank, a value is too long or the user is a
+///   service account.
+/// - `401 UNAUTHORIZED` if the token is invalid.
+/// - `403 FORBIDDEN` if the request was made with an API key or OAuth token.
+/// - `409 CONFLICT` if another active user has the username.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ___
+/// ## `ProfileUpdateInput` JSON Payload Example
+/// ```json
+/// {
+///   "username": "jdoe",
+///   "first_name": "John",
+///   "last_name": ""
+/// }
+/// ```
+pub async fn update_own_profile(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+    Json(payload): Json<ProfileUpdateInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let user = load_own_user(&jwt_secret, &headers, &mut conn).await?;
+
+    if user.is_service_account {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Service accounts are managed at /service-accounts".into(),
+        ));
+    }
+
+    let (new_username, new_first_name, new_last_name) = changed_profile(&user, payload)?;
+
+    diesel::update(users.find(user.id))
+        .set((
+            username.eq(new_username),
+            first_name.eq(new_first_name),
+            last_name.eq(new_last_name),
+            updated_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
+        .map_err(|e| taken(e, "Username is already taken"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Starts changing the email address of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user.
+///
+/// Accepts a JSON payload based on the `EmailChangeInput` struct. The current password must be
+/// supplied. A confirmation link is emailed to the new address; the address is only changed
+/// once it is confirmed at `POST /auth/email-change/confirm`. A new request replaces any
+/// earlier one.
+/// ___
+/// # Returns
+/// - `202 ACCEPTED` once the confirmation link is sent.
+/// - `400 BAD_REQUEST` if the new address is invalid or the current one.
+/// - `401 UNAUTHORIZED` if the token or the current password is invalid.
+/// - `403 FORBIDDEN` if the account has no password or the request was made with an API key
+///   or OAuth token.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ___
+/// ## `EmailChangeInput` JSON Payload Example
+/// ```json
+/// {
+///   "new_email": "new@example.com",
+///   "current_password": "password123"
+/// }
+/// ```
+pub async fn request_email_change(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Json(payload): Json<EmailChangeInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let user = load_own_user(&jwt_secret, &headers, &mut conn).await?;
+
+    // Accounts without a password get their address from the provider they log in with.
+    let Some(current_hash) = user.password_hash.as_deref() else {
+        return Err((StatusCode::FORBIDDEN, "Account has no password".into()));
+    };
+
+    let is_valid = verify_password(&payload.current_password, current_hash)
+        .map_err(|e| internal_error("Password verification failed", e))?;
+
+    if !is_valid {
+        return Err((StatusCode::UNAUTHORIZED, "Current password is incorrect".into()));
+    }
+
+    send_email_change_link(&mut conn, mailer.as_ref(), &config, &user, &payload.new_email)?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Changes the password of the logged-in `user`.
+///
+/// **Authentication:** Logged-in user, or a token restricted to `password_change`.
+///
+/// Accepts a JSON payload based on the `ChangePasswordInput` struct. The current password
+/// must be supplied and the new one must meet the password policy. Every other session of
+/// the user is revoked; the token used for this request stays valid unless it was a
+/// restricted `password_change` token.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if the new password does not meet the password policy.
+/// - `401 UNAUTHORIZED` if the token or the current password is invalid.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ___
+/// ## `ChangePasswordInput` JSON Payload Example
+/// ```json
+/// {
+///   "current_password": "password123",
+///   "new_password": "correcthorse42"
+/// }
+/// ```
+pub async fn change_own_password(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    headers: HeaderMap,
+    Json(payload): Json<ChangePasswordInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims =
+        extract_claims_for(TokenPurpose::PasswordChange, &jwt_secret, &headers, &mut conn).await?;
+
+    let temp_uuid = Uuid::parse_str(&claims.user_temp_id)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid UUID in token".into()))?;
+    let session_id = Uuid::parse_str(&claims.sid)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+
+    let user = users
+        .filter(temp_id.eq(temp_uuid))
+        .first::<User>(&mut conn)
+        .map_err(|e| internal_error("Failed to load user", e))?;
+
+    let Some(current_hash) = user.password_hash.as_deref() else {
+        return Err((StatusCode::FORBIDDEN, "Account has no password".into()));
+    };
+
+    let is_valid = verify_password(&payload.current_password, current_hash)
+        .map_err(|e| internal_error("Password verification failed", e))?;
+
+    if !is_valid {
+        return Err((StatusCode::UNAUTHORIZED, "Current password is incorrect".into()));
+    }
+
+    if payload.new_password == payload.current_password {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "New password must differ from the current password".into(),
+        ));
+    }
+
+    validate_password(&payload.new_password)?;
+
+    let hashed_password = hash_password(&payload.new_password)
+        .map_err(|e| internal_error("Password hashing failed", e))?;
+
+    conn.transaction(|conn| {
+        diesel::update(users.find(user.id))
+            .set((
+                password_hash.eq(hashed_password),
+                must_change_password.eq(false),
+            ))
+            .execute(conn)?;
+
+        let keep = claims.purpose.is_none().then_some(session_id);
+        revoke_user_sessions(conn, user.id, keep)
+    })
+    .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Resets the password of another `user`.
+///
+/// **Authentication:** `can_reset_user_password`
+///
+/// The caller must hold every permission of the user. Callers change their own password at
+/// `/users/profile/password`.
+///
+/// Accepts a JSON payload based on the `AdminPasswordResetInput` struct.
+/// - `temporary_password` replaces the password with a generated one, revokes every session
+///   of the user and forces a password change on the next login.
+/// - `email_link` emails the user a single-use reset link.
+/// ___
+/// # Returns
+/// - `200 OK` with the temporary password as JSON for `temporary_password`.
+/// - `202 ACCEPTED` for `email_link`.
+/// - `400 BAD_REQUEST` if the user is the caller.
+/// - `403 FORBIDDEN` if user lacks permissions or the user holds permissions the caller does
+///   not hold.
+/// - `404 NOT_FOUND` if no user or service account has the given id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ___
+/// ## `AdminPasswordResetInput` JSON Payload Example
+/// ```json
+/// {
+///   "mode": "temporary_password"
+/// }
+/// ```
+pub async fn reset_user_password(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(user_id): Path<i32>,
+    headers: HeaderMap,
+    Json(payload): Json<AdminPasswordResetInput>,
+) -> Result<Response, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, caller) = authorize(
+        &jwt_secret,
+        &permission_cache,
+        &headers,
+        &mut conn,
+        "can_reset_user_password",
+    )
+    .await?;
+
+    let (user, granted) = find_user(&permission_cache, &mut conn, user_id).await?;
+    ensure_manageable(&claims, &caller, &user, &granted)?;
+
+    match payload.mode {
+        AdminPasswordResetMode::TemporaryPassword => {
+            let temporary_password = generate_temporary_password();
+            let hashed_password = hash_password(&temporary_password)
+                .map_err(|e| internal_error("Password hashing failed", e))?;
+
+            conn.transaction(|conn| {
+                diesel::update(users.find(user.id))
+                    .set((
+                        password_hash.eq(hashed_password),
+                        must_change_password.eq(true),
+                    ))
+                    .execute(conn)?;
+
+                revoke_user_sessions(conn, user.id, None)
+            })
+            .map_err(|e| internal_error("DB update error", e))?;
+
+            Ok(Json(TemporaryPasswordView { temporary_password }).into_response())
+        }
+        AdminPasswordResetMode::EmailLink => {
+            send_reset_link(&mut conn, mailer.as_ref(), &config, &user)?;
+
+            Ok(StatusCode::ACCEPTED.into_response())
+        }
+    }
+}
+
+/// Lifts the login lock of another `user` before it expires.
+///
+/// **Authentication:** `can_suspend_user`
+///
+/// Also forgets the failed login attempts of the account. The unlock is written to the
+/// audit log.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success, also if the account was not locked.
+/// - `400 BAD_REQUEST` if the user is the caller.
+/// - `403 FORBIDDEN` if user lacks permissions or the user holds permissions the caller does
+///   not hold.
+/// - `404 NOT_FOUND` if no user has the given id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn unlock_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(user_id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, caller) = authorize(
+        &jwt_secret,
+        &permission_cache,
+        &headers,
+        &mut conn,
+        "can_suspend_user",
+    )
+    .await?;
+
+    let (user, granted) = find_user(&permission_cache, &mut conn, user_id).await?;
+    ensure_manageable(&claims, &caller, &user, &granted)?;
+
+    if !unlock_account(&mut conn, user.id, &claims.sub)? {
+        return Err((StatusCode::NOT_FOUND, "User not found".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Resolves the permissions of the caller, who must hold `required_permission`.
+async fn authorize(
+    jwt_secret: &str,
+    permission_cache: &PermissionCache,
+    headers: &HeaderMap,
+    conn: &mut PgConnection,
+    required_permission: &str,
+) -> Result<(Claims, ResolvedPermissions), (StatusCode, String)> {
+    let claims = extract_user_from_jwt(jwt_secret, headers, conn).await?;
+
+    let resolved = resolve_permissions(permission_cache, &claims, conn).await?;
+    ensure_permission(&resolved, required_permission)?;
+
+    Ok((claims, resolved))
+}
+
+fn ensure_permission(
+    resolved: &ResolvedPermissions,
+    required_permission: &str,
+) -> Result<(), (StatusCode, String)> {
+    if !resolved.has(required_permission) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing permission: {}", required_permission),
+        ));
+    }
+    Ok(())
+}
+
+/// Loads the user with id `user_id` and their permissions. Service accounts are managed at
+/// `/service-accounts` and reported as not found.
+async fn find_user(
+    permission_cache: &PermissionCache,
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<(User, ResolvedPermissions), (StatusCode, String)> {
+    let user = users
+        .find(user_id)
+        .filter(is_service_account.eq(false))
+        .first::<User>(conn)
+        .optional()
+        .map_err(|e| internal_error("Failed to load user", e))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".into()))?;
+
+    let granted = resolve_user_permissions(permission_cache, user.temp_id, conn).await?;
+
+    Ok((user, granted))
+}
+
+/// Users can only be managed by callers holding all of their permissions. Callers manage their
+/// own account at `/users/profile`.
+fn ensure_manageable(
+    claims: &Claims,
+    caller: &ResolvedPermissions,
+    user: &User,
+    granted: &ResolvedPermissions,
+) -> Result<(), (StatusCode, String)> {
+    if user.temp_id.to_string() == claims.user_temp_id {
+        return Err((StatusCode::BAD_REQUEST, "Use /users/profile for your own account".into()));
+    }
+    if !caller.covers(granted.bits()) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "The user holds permissions you do not hold".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn detail_view(
+    conn: &mut PgConnection,
+    user: User,
+    granted: &ResolvedPermissions,
+) -> Result<UserDetailView, (StatusCode, String)> {
+    use crate::schema::roles::dsl::{id as role_id, name as role_name, roles as roles_table};
+
+    let role_names = roles_table
+        .select((role_id, role_name))
+        .order(role_id.asc())
+        .load::<(i32, String)>(conn)
+        .map_err(|e| internal_error("Failed to load roles", e))?
+        .into_iter()
+        .filter(|(rid, _)| (1..=16).contains(rid) && user.roles & (1 << (rid - 1)) != 0)
+        .map(|(_, name)| name)
+        .collect();
+
+    let suspension = open_suspension(conn, user.id)?.map(|suspension| SuspensionView {
+        reason: suspension.reason,
+        suspended_by: suspension.suspended_by,
+        ends_at: suspension.ends_at,
+        created_at: suspension.created_at,
+    });
+
+    Ok(UserDetailView {
+        id: user.id,
+        temp_id: user.temp_id,
+        email: user.email,
+        username: user.username,
+        first_name: user.first_name,
+        last_name: user.last_name,
+        is_active: user.is_active,
+        roles: role_names,
+        permissions: granted.names(),
+        email_verified_at: user.email_verified_at,
+        locked_until: user.locked_until,
+        created_at: user.created_at,
+        updated_at: user.updated_at,
+        suspension,
+    })
+}
+
+/// Returns a single `user` with their roles, permissions and suspension.
+///
+/// **Authentication:** `can_view_user_table`
+/// ___
+/// # Returns
+/// - `200 OK` with a `UserDetailView` as JSON on success.
+/// - `403 FORBIDDEN` if user lacks permissions.
+/// - `404 NOT_FOUND` if no user has the given id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn view_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(user_id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<Json<UserDetailView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    authorize(&jwt_secret, &permission_cache, &headers, &mut conn, "can_view_user_table").await?;
+
+    let (user, granted) = find_user(&permission_cache, &mut conn, user_id).await?;
+
+    Ok(Json(detail_view(&mut conn, user, &granted)?))
+}
+
+/// Changes the username, names or email address of another `user`.
+///
+/// **Authentication:** `can_edit_user`
+///
+/// Accepts a JSON payload based on the `AdminUserUpdateInput` struct. Fields that are left
+/// out are kept; blank names are cleared. A new email address counts as unverified and is
+/// sent a verification link; the previous address is told about the change and every
+/// session of the user is revoked. Only users whose permissions the caller holds can be changed;
+/// the caller's own account is changed at `/users/profile`.
+/// ___
+/// # Returns
+/// - `200 OK` with the updated `UserDetailView` as JSON on success.
+/// - `400 BAD_REQUEST` if a value is blank, invalid or too long, or for the caller's own
+///   account.
+/// - `403 FORBIDDEN` if user lacks permissions or the user holds permissions the caller
+///   does not.
+/// - `404 NOT_FOUND` if no user has the given id.
+/// - `409 CONFLICT` if another active user has the username or email address.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ___
+/// ## `AdminUserUpdateInput` JSON Payload Example
+/// ```json
+/// {
+///   "username": "jdoe",
+///   "email": "john.doe@example.com"
+/// }
+/// ```
+pub async fn update_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(user_id): Path<i32>,
+    headers: HeaderMap,
+    Json(payload): Json<AdminUserUpdateInput>,
+) -> Result<Json<UserDetailView>, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, caller) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn, "can_edit_user").await?;
+
+    let (user, granted) = find_user(&permission_cache, &mut conn, user_id).await?;
+    ensure_manageable(&claims, &caller, &user, &granted)?;
+
+    let new_email = match payload.email.as_deref() {
+        Some(requested) => normalized_email(requested)?,
+        None => user.email.clone(),
+    };
+    let (new_username, new_first_name, new_last_name) = changed_profile(&user, payload.profile)?;
+    let verified_at = if new_email == user.email {
+        user.email_verified_at
+    } else {
+        None
+    };
+
+    let updated = conn
+        .transaction(|conn| {
+            let updated = diesel::update(users.find(user.id))
+                .set((
+                    email.eq(new_email),
+                    username.eq(new_username),
+                    first_name.eq(new_first_name),
+                    last_name.eq(new_last_name),
+                    email_verified_at.eq(verified_at),
+                    updated_at.eq(Utc::now()),
+                ))
+                .get_result::<User>(conn)?;
+
+            // Sessions were started with the old address, which may no longer be the user's.
+            if updated.email != user.email {
+                revoke_user_sessions(conn, user.id, None)?;
+            }
+            Ok(updated)
+        })
+        .map_err(|e| taken(e, "Username or email address is already taken"))?;
+
+    record_event(&mut conn, Some(user.id), "user_updated", Some(format!("by {}", claims.sub)))?;
+
+    if updated.email != user.email {
+        notify_email_changed(mailer.as_ref(), &config, &user, &updated.email);
+        if let Err((_, e)) = send_verification_email(&mut conn, mailer.as_ref(), &config, &updated)
+        {
+            tracing::error!("Verification email for user {} failed: {}", updated.id, e);
+        }
+    }
+
+    Ok(Json(detail_view(&mut conn, updated, &granted)?))
+}
+
+/// Deletes another `user` together with their sessions, credentials and tokens.
+///
+/// **Authentication:** `can_delete_admin` for users holding any permission, else
+/// `can_delete_any_user`
+///
+/// Only users whose permissions the caller holds can be deleted, and not the caller's own
+/// account. The deletion is written to the audit log.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` for the caller's own account.
+/// - `403 FORBIDDEN` if user lacks permissions or the user holds permissions the caller
+///   does not.
+/// - `404 NOT_FOUND` if no user has the given id.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn delete_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(user_id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let claims = extract_user_from_jwt(&jwt_secret, &headers, &mut conn).await?;
+    let caller = resolve_permissions(&permission_cache, &claims, &mut conn).await?;
+
+    let (user, granted) = find_user(&permission_cache, &mut conn, user_id).await?;
+
+    // Any permission makes a user an administrator of some part of the system.
+    let required_permission = if granted.bits() != 0 {
+        "can_delete_admin"
+    } else {
+        "can_delete_any_user"
+    };
+    ensure_permission(&caller, required_permission)?;
+    ensure_manageable(&claims, &caller, &user, &granted)?;
+
+    diesel::delete(users.find(user.id))
+        .execute(&mut conn)
+        .map_err(|e| internal_error("DB delete error", e))?;
+    permission_cache.invalidate_user(user.temp_id);
+
+    record_event(
+        &mut conn,
+        None,
+        "user_deleted",
+        Some(format!("user {} ({}) by {}", user.id, user.email, claims.sub)),
+    )?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Suspends another `user`: the account is deactivated and every session is revoked.
+///
+/// **Authentication:** `can_suspend_user`
+///
+/// Accepts a JSON payload based on the `SuspendUserInput` struct. With `ends_at`, the user is
+/// reactivated once it has passed; otherwise only `POST /users/{id}/reactivate` does. Only
+/// users whose permissions the caller holds can be suspended, and not the caller's own
+/// account.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `400 BAD_REQUEST` if the reason is blank or too long, `ends_at` has passed, or for the
+///   caller's own account.
+/// - `403 FORBIDDEN` if user lacks permissions or the user holds permissions the caller
+///   does not.
+/// - `404 NOT_FOUND` if no user has the given id.
+/// - `409 CONFLICT` if the user is inactive or suspended already.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+/// ___
+/// ## `SuspendUserInput` JSON Payload Example
+/// ```json
+/// {
+///   "reason": "Suspicious activity",
+///   "ends_at": "2025-08-01T00:00:00Z"
+/// }
+/// ```
+pub async fn suspend_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(user_id): Path<i32>,
+    headers: HeaderMap,
+    Json(payload): Json<SuspendUserInput>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, caller) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn, "can_suspend_user").await?;
+
+    let (user, granted) = find_user(&permission_cache, &mut conn, user_id).await?;
+    ensure_manageable(&claims, &caller, &user, &granted)?;
+
+    suspensions::suspend_user(&mut conn, &user, &payload.reason, payload.ends_at, &claims.sub)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Activates a suspended or otherwise deactivated `user` again.
+///
+/// **Authentication:** `can_suspend_user`
+///
+/// Lifts the user's suspension. Email addresses and usernames only need to be unique among
+/// active users, so reactivation fails while another active user has the same one.
+/// ___
+/// # Returns
+/// - `204 NO_CONTENT` on success.
+/// - `403 FORBIDDEN` if user lacks permissions or the user holds permissions the caller
+///   does not.
+/// - `404 NOT_FOUND` if no user has the given id.
+/// - `409 CONFLICT` if the user is active already, or another active user has the same
+///   email address or username.
+/// - `500 INTERNAL_SERVER_ERROR` on database error.
+pub async fn reactivate_user(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(jwt_secret): Extension<Arc<String>>,
+    Extension(permission_cache): Extension<Arc<PermissionCache>>,
+    Path(user_id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut conn = pool.get().map_err(|e| internal_error("DB Pool error", e))?;
+
+    let (claims, caller) =
+        authorize(&jwt_secret, &permission_cache, &headers, &mut conn, "can_suspend_user").await?;
+
+    let (user, granted) = find_user(&permission_cache, &mut conn, user_id).await?;
+    ensure_manageable(&claims, &caller, &user, &granted)?;
+
+    suspensions::reactivate_user(&mut conn, &user, &claims.sub)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
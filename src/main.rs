@@ -1,3 +1,4 @@
+mod config;
 mod db;
 mod handlers;
 mod models;
@@ -5,19 +6,67 @@ mod schema;
 mod services;
 mod utils;
 
-use crate::handlers::users::{view_own_user, view_user_table, view_users};
+use crate::handlers::users::{
+    change_own_password, delete_user, reactivate_user, request_email_change,
+    reset_user_password, suspend_user, unlock_user, update_own_profile, update_user,
+    view_own_user, view_user, view_user_table, view_users,
+};
 use axum::{
-    routing::{get, post}, Extension,
+    middleware,
+    routing::{delete, get, post, put}, Extension,
     Router,
 };
 use handlers::{
-    auth::login,
-    permissions::view_permissions_table,
+    auth::{
+        confirm_email_change, confirm_password_reset, login, login_with_magic_link,
+        request_magic_link, request_password_reset, resend_verification, verify_email,
+    },
+    permissions::{flush_permission_cache, view_permission_cache_stats, view_permissions_table},
     users::{create_user},
     roles::view_role_table,
 };
-use std::{env, sync::Arc};
-use crate::handlers::roles::view_roles;
+use std::{env, net::SocketAddr, sync::Arc};
+use crate::config::AppConfig;
+use crate::services::federation::FederatedProviders;
+use crate::services::ldap::LdapDirectories;
+use crate::services::saml::SamlProviders;
+use crate::services::mailer::mailer_from_env;
+use crate::services::oidc::OidcSigningKey;
+use crate::services::permissions::PermissionCache;
+use crate::services::rate_limit::{enforce_rate_limit, prune_buckets_periodically, RateLimiter};
+use crate::services::suspensions::lift_expired_suspensions_periodically;
+use crate::handlers::roles::{set_role_mfa_requirement, view_roles};
+use crate::handlers::mfa::{
+    confirm_totp, enroll_totp, regenerate_recovery_codes, remove_totp, verify_mfa,
+};
+use crate::handlers::api_keys::{create_own_api_key, revoke_own_api_key, view_own_api_keys};
+use crate::handlers::service_accounts::{
+    create_service_account, create_service_account_api_key, revoke_service_account_api_key,
+    view_service_account_api_keys, view_service_accounts,
+};
+use crate::handlers::oauth::{
+    authorize_device, decide_authorization_request, decide_device_request, delete_oauth_client,
+    introspect_token, issue_token, register_oauth_client, revoke_token,
+    view_authorization_request, view_device_request, view_oauth_clients,
+};
+use crate::handlers::federation::{
+    finish_federated_login, finish_identity_link, start_federated_login, start_identity_link,
+    unlink_federated_identity, view_federated_providers, view_own_federated_identities,
+};
+use crate::handlers::saml::{
+    consume_saml_response, login_with_saml_code, start_saml_login, view_saml_providers,
+    view_sp_metadata,
+};
+use crate::handlers::scim::{
+    create_scim_group, create_scim_user, delete_scim_group, delete_scim_user, list_scim_groups,
+    list_scim_users, patch_scim_group, patch_scim_user, replace_scim_group, replace_scim_user,
+    view_scim_group, view_scim_service_provider_config, view_scim_user,
+};
+use crate::handlers::oidc::{view_jwks, view_openid_configuration, view_user_info};
+use crate::handlers::passkeys::{
+    finish_passkey_login, finish_passkey_registration, remove_passkey, start_passkey_login,
+    start_passkey_registration, view_own_passkeys,
+};
 
 #[tokio::main]
 async fn main() {
@@ -26,25 +75,152 @@ async fn main() {
 
     let pool = Arc::new(db::establish_connection_pool());
     let jwt_secret = Arc::new(env::var("JWT_SECRET").expect("JWT_SECRET must be set"));
+    let permission_cache = Arc::new(PermissionCache::from_env());
+    let config = Arc::new(AppConfig::from_env());
+    let mailer = mailer_from_env().expect("Failed to configure mailer");
+    let signing_key =
+        Arc::new(OidcSigningKey::from_env().expect("Failed to load OIDC signing key"));
+    let federated_providers = Arc::new(
+        FederatedProviders::from_env().expect("Failed to configure federated login"),
+    );
+    let saml_providers = Arc::new(
+        SamlProviders::from_env(&federated_providers).expect("Failed to configure SAML login"),
+    );
+    let ldap_directories = Arc::new(
+        LdapDirectories::from_env(&federated_providers, &saml_providers)
+            .expect("Failed to configure LDAP directories"),
+    );
+    let rate_limiter = Arc::new(
+        RateLimiter::from_env(pool.clone(), jwt_secret.clone(), &config)
+            .expect("Failed to configure rate limiting"),
+    );
+
+    tokio::spawn(lift_expired_suspensions_periodically(pool.clone()));
+    tokio::spawn(prune_buckets_periodically(rate_limiter.clone()));
 
     let app = Router::new()
         .route("/", get(root))
         .route("/users", post(create_user).get(view_users))
-        .route("/users/profile", get(view_own_user))
+        .route("/users/profile", get(view_own_user).patch(update_own_profile))
+        .route("/users/profile/password", put(change_own_password))
+        .route("/users/profile/email", post(request_email_change))
+        .route("/users/profile/mfa/totp", post(enroll_totp).delete(remove_totp))
+        .route("/users/profile/mfa/totp/confirm", post(confirm_totp))
+        .route("/users/profile/mfa/recovery-codes", post(regenerate_recovery_codes))
+        .route("/users/profile/api-keys", post(create_own_api_key).get(view_own_api_keys))
+        .route("/users/profile/api-keys/{id}", delete(revoke_own_api_key))
+        .route("/users/profile/passkeys", get(view_own_passkeys))
+        .route("/users/profile/passkeys/{id}", delete(remove_passkey))
+        .route("/users/profile/passkeys/register/start", post(start_passkey_registration))
+        .route("/users/profile/passkeys/register/finish", post(finish_passkey_registration))
+        .route("/users/profile/federated-identities", get(view_own_federated_identities))
+        .route(
+            "/users/profile/federated-identities/{provider}",
+            delete(unlink_federated_identity),
+        )
+        .route(
+            "/users/profile/federated-identities/{provider}/start",
+            post(start_identity_link),
+        )
+        .route(
+            "/users/profile/federated-identities/{provider}/finish",
+            post(finish_identity_link),
+        )
         // Need Permissions:
+        .route("/users/{id}", get(view_user).patch(update_user).delete(delete_user))
+        .route("/users/{id}/password-reset", post(reset_user_password))
+        .route("/users/{id}/unlock", post(unlock_user))
+        .route("/users/{id}/suspend", post(suspend_user))
+        .route("/users/{id}/reactivate", post(reactivate_user))
+        .route(
+            "/service-accounts",
+            post(create_service_account).get(view_service_accounts),
+        )
+        .route(
+            "/service-accounts/{id}/api-keys",
+            post(create_service_account_api_key).get(view_service_account_api_keys),
+        )
+        .route(
+            "/service-accounts/{id}/api-keys/{key_id}",
+            delete(revoke_service_account_api_key),
+        )
+        .route("/oauth/clients", post(register_oauth_client).get(view_oauth_clients))
+        .route("/oauth/clients/{client_id}", delete(delete_oauth_client))
         .route("/dev/users", get(view_user_table))
         .route("/dev/roles", get(view_role_table))
         .route("/roles", get(view_roles))
+        .route("/roles/{name}/mfa", put(set_role_mfa_requirement))
         .route("/permissions", get(view_permissions_table))
+        .route(
+            "/dev/permissions/cache",
+            get(view_permission_cache_stats).delete(flush_permission_cache),
+        )
         .route("/auth", post(login))
+        .route("/auth/magic-link", post(request_magic_link))
+        .route("/auth/magic-link/consume", post(login_with_magic_link))
+        .route("/auth/mfa/verify", post(verify_mfa))
+        .route("/auth/passkeys/login/start", post(start_passkey_login))
+        .route("/auth/passkeys/login/finish", post(finish_passkey_login))
+        .route("/auth/federated", get(view_federated_providers))
+        .route("/auth/federated/{provider}/start", post(start_federated_login))
+        .route("/auth/federated/{provider}/finish", post(finish_federated_login))
+        .route("/auth/saml", get(view_saml_providers))
+        .route("/auth/saml/{provider}/start", post(start_saml_login))
+        .route("/auth/saml/consume", post(login_with_saml_code))
+        .route("/saml/metadata", get(view_sp_metadata))
+        .route("/saml/acs", post(consume_saml_response))
+        .route("/auth/password-reset", post(request_password_reset))
+        .route("/auth/password-reset/confirm", post(confirm_password_reset))
+        .route("/auth/verify-email", post(verify_email))
+        .route("/auth/verify-email/resend", post(resend_verification))
+        .route("/auth/email-change/confirm", post(confirm_email_change))
+        .route(
+            "/oauth/authorize",
+            get(view_authorization_request).post(decide_authorization_request),
+        )
+        .route("/oauth/token", post(issue_token))
+        .route("/oauth/introspect", post(introspect_token))
+        .route("/oauth/revoke", post(revoke_token))
+        .route("/oauth/device_authorization", post(authorize_device))
+        .route("/oauth/device", get(view_device_request).post(decide_device_request))
+        .route("/userinfo", get(view_user_info).post(view_user_info))
+        .route("/.well-known/openid-configuration", get(view_openid_configuration))
+        .route("/.well-known/jwks.json", get(view_jwks))
+        .route("/scim/v2/ServiceProviderConfig", get(view_scim_service_provider_config))
+        .route("/scim/v2/Users", get(list_scim_users).post(create_scim_user))
+        .route(
+            "/scim/v2/Users/{id}",
+            get(view_scim_user)
+                .put(replace_scim_user)
+                .patch(patch_scim_user)
+                .delete(delete_scim_user),
+        )
+        .route("/scim/v2/Groups", get(list_scim_groups).post(create_scim_group))
+        .route(
+            "/scim/v2/Groups/{id}",
+            get(view_scim_group)
+                .put(replace_scim_group)
+                .patch(patch_scim_group)
+                .delete(delete_scim_group),
+        )
+        .layer(middleware::from_fn_with_state(rate_limiter, enforce_rate_limit))
         .layer(Extension(pool))
-        .layer(Extension(jwt_secret));
+        .layer(Extension(jwt_secret))
+        .layer(Extension(permission_cache))
+        .layer(Extension(config))
+        .layer(Extension(mailer))
+        .layer(Extension(signing_key))
+        .layer(Extension(federated_providers))
+        .layer(Extension(saml_providers))
+        .layer(Extension(ldap_directories));
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
         .expect("Failed to bind port 3000");
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
 
 async fn root() -> &'static str {
@@ -1,9 +1,17 @@
-use super::schema::{users, roles, permissions};
+use super::schema::{
+    api_keys, audit_log, email_change_tokens, email_verification_tokens, failed_logins,
+    federated_identities, federated_login_states, magic_link_tokens, mfa_recovery_codes,
+    oauth_authorization_codes, oauth_client_assertions, oauth_clients,
+    oauth_consents, oauth_device_codes,
+    oauth_refresh_tokens, password_reset_tokens, permissions, roles, saml_assertions,
+    saml_login_codes, saml_requests, user_sessions, user_suspensions, users, webauthn_challenges,
+    webauthn_credentials,
+};
 use chrono::{DateTime, Utc};
 use diesel::{Insertable, Queryable, Identifiable, Selectable};
-use diesel::sql_types::Text;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use crate::services::jwt::TokenPurpose;
 
 #[derive(Queryable, Identifiable, Serialize, Deserialize)]
 #[diesel(table_name = users)]
@@ -12,14 +20,27 @@ pub struct User {
     pub temp_id: Uuid,
     pub email: String,
     pub username: String,
-    pub password_hash: String,
-    pub first_name: String,
-    pub last_name: String,
+    pub password_hash: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
     pub is_active: bool,
     pub roles: i16,
     pub permissions: i64,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub must_change_password: bool,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub locale: String,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub is_service_account: bool,
+    pub description: Option<String>,
+}
+
+impl User {
+    /// Name used to greet the user in emails.
+    pub fn greeting_name(&self) -> &str {
+        self.first_name.as_deref().unwrap_or(&self.username)
+    }
 }
 
 #[derive(Insertable, Deserialize, Serialize)]
@@ -27,9 +48,55 @@ pub struct User {
 pub struct NewUser {
     pub email: String,
     pub username: String,
-    pub password_hash: String,
-    pub first_name: String,
-    pub last_name: String,
+    pub password_hash: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub locale: String,
+}
+
+/// A user created on their first login through an upstream identity provider.
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct NewFederatedUser {
+    pub email: String,
+    pub username: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub locale: String,
+    pub roles: i16,
+    pub email_verified_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct NewServiceAccount {
+    pub email: String,
+    pub username: String,
+    pub description: Option<String>,
+    pub roles: i16,
+    pub is_service_account: bool,
+}
+
+#[derive(Deserialize)]
+pub struct NewServiceAccountInput {
+    pub username: String,
+    pub description: Option<String>,
+    /// Contact address of the team owning the account; defaults to an undeliverable one.
+    pub email: Option<String>,
+    /// Names of the roles the account holds.
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ServiceAccountView {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub description: Option<String>,
+    pub is_active: bool,
+    pub roles: Vec<String>,
+    pub created_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Queryable, Identifiable)]
@@ -39,6 +106,7 @@ pub struct Role {
     pub name: String,
     pub description: Option<String>,
     pub permission: i64,
+    pub require_mfa: bool,
 }
 
 #[derive(Insertable, Deserialize, Serialize)]
@@ -70,6 +138,937 @@ pub struct Login {
     pub password: String,
 }
 
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub token: String,
+    /// Set when the token is only accepted by the endpoint that completes this step.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restricted_to: Option<TokenPurpose>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminPasswordResetMode {
+    /// Replace the password with a generated one that must be changed on next login.
+    TemporaryPassword,
+    /// Email the user a reset link; the current password keeps working until it is used.
+    EmailLink,
+}
+
+#[derive(Deserialize)]
+pub struct AdminPasswordResetInput {
+    pub mode: AdminPasswordResetMode,
+}
+
+#[derive(Serialize)]
+pub struct TemporaryPasswordView {
+    pub temporary_password: String,
+}
+
+/// Changes to another user; missing fields are kept and blank names are cleared.
+#[derive(Deserialize)]
+pub struct AdminUserUpdateInput {
+    #[serde(flatten)]
+    pub profile: ProfileUpdateInput,
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SuspendUserInput {
+    pub reason: String,
+    /// When the suspension is lifted by itself; never if missing.
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Queryable, Identifiable)]
+#[diesel(table_name = user_suspensions)]
+pub struct UserSuspension {
+    pub id: i32,
+    pub user_id: i32,
+    pub reason: String,
+    pub suspended_by: String,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub lifted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = user_suspensions)]
+pub struct NewUserSuspension {
+    pub user_id: i32,
+    pub reason: String,
+    pub suspended_by: String,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct SuspensionView {
+    pub reason: String,
+    pub suspended_by: String,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A user as seen by administrators at `/users/{id}`.
+#[derive(Serialize)]
+pub struct UserDetailView {
+    pub id: i32,
+    pub temp_id: Uuid,
+    pub email: String,
+    pub username: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub is_active: bool,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// The suspension in force, if the user is suspended.
+    pub suspension: Option<SuspensionView>,
+}
+
+#[derive(Serialize)]
+pub struct TotpEnrollmentView {
+    /// Base32 secret for manual entry in an authenticator app.
+    pub secret: String,
+    /// `otpauth://` URI, also the payload to encode as a QR code.
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaCodeInput {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaVerifyInput {
+    /// Code from the authenticator app.
+    pub code: Option<String>,
+    /// One of the recovery codes, used instead of `code`.
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesView {
+    /// Shown once; only hashes are stored.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mfa_recovery_codes)]
+pub struct NewRecoveryCode {
+    pub user_id: i32,
+    pub code_hash: String,
+}
+
+#[derive(Queryable, Identifiable, Selectable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub permissions: i64,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub permissions: i64,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKeyInput {
+    pub name: String,
+    /// Permissions of the owner the key may use.
+    pub permissions: Vec<String>,
+    /// Days until the key expires; omit for a key that does not expire.
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyView {
+    pub id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub permissions: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKeyView {
+    /// The full key; it is not shown again.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyView,
+}
+
+#[derive(Queryable, Identifiable, Selectable)]
+#[diesel(table_name = oauth_clients)]
+pub struct OAuthClient {
+    pub id: i32,
+    pub client_id: String,
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: i64,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub service_account_id: Option<i32>,
+    pub jwt_public_key: Option<String>,
+}
+
+impl OAuthClient {
+    /// Public clients have no credentials and must use PKCE.
+    pub fn is_public(&self) -> bool {
+        self.client_secret_hash.is_none() && self.jwt_public_key.is_none()
+    }
+
+    /// How the client authenticates at the token endpoint.
+    pub fn auth_method(&self) -> &'static str {
+        if self.jwt_public_key.is_some() {
+            "private_key_jwt"
+        } else if self.client_secret_hash.is_some() {
+            "client_secret_basic"
+        } else {
+            "none"
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = oauth_clients)]
+pub struct NewOAuthClient {
+    pub client_id: String,
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: i64,
+    pub created_by: Option<i32>,
+    pub service_account_id: Option<i32>,
+    pub jwt_public_key: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = oauth_client_assertions)]
+pub struct NewOAuthClientAssertion {
+    pub oauth_client_id: i32,
+    pub jti: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NewOAuthClientInput {
+    pub name: String,
+    /// May be empty for clients that only use the `client_credentials` grant.
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    /// Permissions the client may request as scopes.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Public clients, such as single-page and native apps, get no secret and must use PKCE.
+    #[serde(default)]
+    pub public: bool,
+    /// PEM public key (RSA or P-256) for `private_key_jwt` authentication instead of a secret.
+    pub jwt_public_key: Option<String>,
+    /// Service account the client acts as in the `client_credentials` grant.
+    pub service_account_id: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct OAuthClientView {
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub public: bool,
+    pub token_endpoint_auth_method: &'static str,
+    pub service_account_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedOAuthClientView {
+    /// Only set for confidential clients; it is not shown again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(flatten)]
+    pub client: OAuthClientView,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = oauth_authorization_codes)]
+pub struct OAuthAuthorizationCode {
+    pub oauth_client_id: i32,
+    pub user_id: i32,
+    pub redirect_uri: Option<String>,
+    pub scope: i64,
+    pub code_challenge: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub identity_scope: i16,
+    pub nonce: Option<String>,
+    pub auth_time: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = oauth_authorization_codes)]
+pub struct NewOAuthAuthorizationCode {
+    pub code_hash: String,
+    pub oauth_client_id: i32,
+    pub user_id: i32,
+    pub redirect_uri: Option<String>,
+    pub scope: i64,
+    pub code_challenge: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub identity_scope: i16,
+    pub nonce: Option<String>,
+    pub auth_time: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = oauth_consents)]
+pub struct NewOAuthConsent {
+    pub user_id: i32,
+    pub oauth_client_id: i32,
+    pub scope: i64,
+    pub identity_scope: i16,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = oauth_refresh_tokens)]
+pub struct NewOAuthRefreshToken {
+    pub token_hash: String,
+    pub session_id: Uuid,
+    pub scope: i64,
+    pub expires_at: DateTime<Utc>,
+    pub identity_scope: i16,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = oauth_device_codes)]
+pub struct OAuthDeviceCode {
+    pub oauth_client_id: i32,
+    pub scope: i64,
+    pub identity_scope: i16,
+    pub user_id: Option<i32>,
+    pub auth_time: Option<DateTime<Utc>>,
+    pub denied: bool,
+    pub poll_interval: i32,
+    pub last_polled_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = oauth_device_codes)]
+pub struct NewOAuthDeviceCode {
+    pub device_code_hash: String,
+    pub user_code: String,
+    pub oauth_client_id: i32,
+    pub scope: i64,
+    pub identity_scope: i16,
+    pub poll_interval: i32,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Query of `GET /oauth/authorize`, repeated in the body of `POST /oauth/authorize`.
+#[derive(Deserialize)]
+pub struct AuthorizationRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    /// Space-separated permission names and OpenID Connect scopes.
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    /// Copied into the ID token to bind it to the client's session.
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuthorizationDecision {
+    #[serde(flatten)]
+    pub request: AuthorizationRequest,
+    /// Whether the user approved the client.
+    pub approve: bool,
+}
+
+#[derive(Serialize)]
+pub struct AuthorizationPromptView {
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+    /// `false` if the user already approved these scopes for this client.
+    pub consent_required: bool,
+}
+
+#[derive(Serialize)]
+pub struct AuthorizationRedirectView {
+    /// Where to send the browser; carries either the code or an OAuth error.
+    pub redirect_to: String,
+}
+
+/// Client authentication sent in the form body of the OAuth endpoints.
+#[derive(Deserialize)]
+pub struct ClientCredentials {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    /// JWT signed with the client's private key, for `private_key_jwt` authentication.
+    pub client_assertion: Option<String>,
+}
+
+/// Form body of `POST /oauth/token`.
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub device_code: Option<String>,
+    pub scope: Option<String>,
+    #[serde(flatten)]
+    pub client: ClientCredentials,
+}
+
+/// Form body of `POST /oauth/introspect` and `POST /oauth/revoke`.
+///
+/// `token_type_hint` is accepted but not needed; both kinds of token are looked up.
+#[derive(Deserialize)]
+pub struct TokenLookupRequest {
+    pub token: String,
+    #[serde(flatten)]
+    pub client: ClientCredentials,
+}
+
+/// Token metadata as defined by RFC 7662; only `active` is set for inactive tokens.
+#[derive(Serialize, Default)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    /// The user's stable id, the same as `sub` in ID tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// Effective permissions of an access token: the user's permissions limited to its scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Vec<String>>,
+}
+
+/// Form body of `POST /oauth/device_authorization`.
+#[derive(Deserialize)]
+pub struct DeviceAuthorizationRequest {
+    /// Space-separated permission names and OpenID Connect scopes.
+    pub scope: Option<String>,
+    #[serde(flatten)]
+    pub client: ClientCredentials,
+}
+
+#[derive(Serialize)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    /// Code the user enters on the verification page, e.g. `BCDF-GHJK`.
+    pub user_code: String,
+    pub verification_uri: String,
+    /// The verification page with the user code filled in, e.g. for a QR code.
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    /// Seconds the device must wait between token requests.
+    pub interval: i32,
+}
+
+/// Query of `GET /oauth/device`.
+#[derive(Deserialize)]
+pub struct DeviceCodeQuery {
+    pub user_code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceDecision {
+    pub user_code: String,
+    /// Whether the user approved the device.
+    pub approve: bool,
+}
+
+#[derive(Serialize)]
+pub struct DevicePromptView {
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Set when the `openid` scope was granted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    pub scope: String,
+}
+
+/// Standard OpenID Connect claims of a user, limited to the granted scopes.
+#[derive(Serialize)]
+pub struct UserInfoView {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+    /// Seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = failed_logins)]
+pub struct NewFailedLogin {
+    pub user_id: Option<i32>,
+    pub ip_address: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry {
+    pub user_id: Option<i32>,
+    pub event: String,
+    pub detail: Option<String>,
+}
+
+#[derive(Queryable, Identifiable, Selectable)]
+#[diesel(table_name = webauthn_credentials)]
+pub struct WebauthnCredential {
+    pub id: i32,
+    pub user_id: i32,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub transports: Vec<String>,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webauthn_credentials)]
+pub struct NewWebauthnCredential {
+    pub user_id: i32,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub transports: Vec<String>,
+    pub name: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webauthn_challenges)]
+pub struct NewWebauthnChallenge {
+    pub id: Uuid,
+    pub user_id: Option<i32>,
+    pub ceremony: String,
+    pub challenge: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PasskeyView {
+    pub id: i32,
+    pub name: String,
+    pub transports: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginStartInput {
+    /// Limits the allowed credentials to this account; omit for discoverable credentials.
+    pub email: Option<String>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = federated_identities)]
+pub struct FederatedIdentity {
+    pub provider: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = federated_identities)]
+pub struct NewFederatedIdentity {
+    pub user_id: i32,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = federated_login_states)]
+pub struct FederatedLoginState {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub link_user_id: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = federated_login_states)]
+pub struct NewFederatedLoginState {
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub link_user_id: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct FederatedProviderView {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct FederatedLoginStartView {
+    /// Where to send the browser; the provider redirects back with `code` and `state`.
+    pub authorization_url: String,
+}
+
+/// Query the provider sent the browser back with, passed on by the login frontend.
+#[derive(Deserialize)]
+pub struct FederatedCallbackInput {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Serialize)]
+pub struct FederatedIdentityView {
+    pub provider: String,
+    pub provider_name: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = saml_requests)]
+pub struct NewSamlRequest {
+    pub id: String,
+    pub provider: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = saml_assertions)]
+pub struct NewSamlAssertion {
+    pub provider: String,
+    pub assertion_id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = saml_login_codes)]
+pub struct NewSamlLoginCode {
+    pub code_hash: String,
+    pub user_id: i32,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct SamlLoginStartView {
+    /// Where to send the browser; the provider posts its response to `/saml/acs`.
+    pub redirect_url: String,
+}
+
+/// Form the identity provider makes the browser post to the assertion consumer service.
+#[derive(Deserialize)]
+pub struct SamlResponseInput {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+}
+
+#[derive(Deserialize)]
+pub struct SamlLoginCodeInput {
+    pub code: String,
+}
+
+/// A user created through SCIM provisioning.
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct NewScimUser {
+    pub email: String,
+    pub username: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub is_active: bool,
+    pub email_verified_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimName {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+/// Reference to a user from a group, or to a group from a user.
+#[derive(Serialize, Deserialize)]
+pub struct ScimMember {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimMeta {
+    pub resource_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<DateTime<Utc>>,
+    pub location: String,
+}
+
+/// Body of `POST /scim/v2/Users` and `PUT /scim/v2/Users/{id}`; other attributes are ignored.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserInput {
+    pub user_name: String,
+    pub name: Option<ScimName>,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+    pub active: Option<bool>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserView {
+    pub schemas: [&'static str; 1],
+    /// The user's `temp_id`.
+    pub id: Uuid,
+    pub user_name: String,
+    pub name: ScimName,
+    pub emails: Vec<ScimEmail>,
+    pub active: bool,
+    /// The user's roles.
+    pub groups: Vec<ScimMember>,
+    pub meta: ScimMeta,
+}
+
+/// Body of `POST /scim/v2/Groups` and `PUT /scim/v2/Groups/{id}`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroupInput {
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<ScimMember>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroupView {
+    pub schemas: [&'static str; 1],
+    /// The role id.
+    pub id: String,
+    pub display_name: String,
+    /// Left out when requested with `excludedAttributes=members`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<ScimMember>>,
+    pub meta: ScimMeta,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListResponse<T> {
+    pub schemas: [&'static str; 1],
+    pub total_results: i64,
+    pub items_per_page: usize,
+    pub start_index: i64,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+    /// Comma-separated attributes to leave out; only `members` of groups is honored.
+    pub excluded_attributes: Option<String>,
+}
+
+/// Query of `GET /scim/v2/Groups/{id}`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimResourceQuery {
+    pub excluded_attributes: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ScimPatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<ScimPatchOperation>,
+}
+
+#[derive(Deserialize)]
+pub struct ScimPatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleMfaInput {
+    pub require_mfa: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Fields of the own profile to change; missing fields are kept and blank names are cleared.
+#[derive(Deserialize)]
+pub struct ProfileUpdateInput {
+    pub username: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EmailChangeInput {
+    pub new_email: String,
+    pub current_password: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = email_change_tokens)]
+pub struct NewEmailChangeToken {
+    pub user_id: i32,
+    pub new_email: String,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = user_sessions)]
+pub struct NewUserSession {
+    pub id: Uuid,
+    pub user_id: i32,
+    pub expires_at: DateTime<Utc>,
+    pub oauth_client_id: Option<i32>,
+    pub auth_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = password_reset_tokens)]
+pub struct NewPasswordResetToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = magic_link_tokens)]
+pub struct NewMagicLinkToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = email_verification_tokens)]
+pub struct NewEmailVerificationToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailInput {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationInput {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkConsume {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetConfirm {
+    pub token: String,
+    pub new_password: String,
+}
+
 #[derive(Deserialize)]
 pub struct NewUserInput {
     pub email: String,
@@ -77,6 +1076,8 @@ pub struct NewUserInput {
     pub password: String,
     pub first_name: String,
     pub last_name: String,
+    /// Language used for emails, e.g. `en` or `da-DK`. Defaults to `en`.
+    pub locale: Option<String>,
 }
 
 #[derive(Queryable, Serialize, Selectable)]
@@ -85,13 +1086,14 @@ pub struct UserTableView {
     pub id: i32,
     pub email: String,
     pub username: String,
-    pub first_name: String,
-    pub last_name: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
     pub is_active: bool,
     pub roles: i16,
     pub permissions: i64,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub is_service_account: bool,
 }
 
 #[derive(Queryable, Serialize, Selectable)]
@@ -99,8 +1101,8 @@ pub struct UserTableView {
 pub struct UserView {
     pub email: String,
     pub username: String,
-    pub first_name: String,
-    pub last_name: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
     pub roles: Vec<String>,
     pub created_at: String,
 }
@@ -1,5 +1,225 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 16]
+        prefix -> Varchar,
+        #[max_length = 64]
+        key_hash -> Varchar,
+        permissions -> Int8,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 100]
+        event -> Varchar,
+        detail -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    email_change_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 255]
+        new_email -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    email_verification_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    failed_logins (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 45]
+        ip_address -> Varchar,
+        attempted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    federated_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 50]
+        provider -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        #[max_length = 255]
+        email -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        last_login_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    federated_login_states (state_hash) {
+        #[max_length = 64]
+        state_hash -> Varchar,
+        #[max_length = 50]
+        provider -> Varchar,
+        #[max_length = 64]
+        nonce -> Varchar,
+        #[max_length = 128]
+        code_verifier -> Varchar,
+        link_user_id -> Nullable<Int4>,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    magic_link_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    mfa_recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 255]
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    oauth_authorization_codes (code_hash) {
+        #[max_length = 64]
+        code_hash -> Varchar,
+        oauth_client_id -> Int4,
+        user_id -> Int4,
+        redirect_uri -> Nullable<Text>,
+        scope -> Int8,
+        #[max_length = 128]
+        code_challenge -> Nullable<Varchar>,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+        identity_scope -> Int2,
+        #[max_length = 255]
+        nonce -> Nullable<Varchar>,
+        auth_time -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    oauth_client_assertions (oauth_client_id, jti) {
+        oauth_client_id -> Int4,
+        #[max_length = 255]
+        jti -> Varchar,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    oauth_clients (id) {
+        id -> Int4,
+        #[max_length = 64]
+        client_id -> Varchar,
+        #[max_length = 64]
+        client_secret_hash -> Nullable<Varchar>,
+        #[max_length = 100]
+        name -> Varchar,
+        redirect_uris -> Array<Text>,
+        scopes -> Int8,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        service_account_id -> Nullable<Int4>,
+        jwt_public_key -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    oauth_consents (user_id, oauth_client_id) {
+        user_id -> Int4,
+        oauth_client_id -> Int4,
+        scope -> Int8,
+        granted_at -> Timestamptz,
+        identity_scope -> Int2,
+    }
+}
+
+diesel::table! {
+    oauth_device_codes (device_code_hash) {
+        #[max_length = 64]
+        device_code_hash -> Varchar,
+        #[max_length = 8]
+        user_code -> Varchar,
+        oauth_client_id -> Int4,
+        scope -> Int8,
+        identity_scope -> Int2,
+        user_id -> Nullable<Int4>,
+        auth_time -> Nullable<Timestamptz>,
+        denied -> Bool,
+        poll_interval -> Int4,
+        last_polled_at -> Nullable<Timestamptz>,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    oauth_refresh_tokens (id) {
+        id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        session_id -> Uuid,
+        scope -> Int8,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        identity_scope -> Int2,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     permissions (id) {
         id -> Int4,
@@ -10,6 +230,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    rate_limit_buckets (key) {
+        #[max_length = 255]
+        key -> Varchar,
+        tokens -> Float8,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     roles (id) {
         id -> Int4,
@@ -18,6 +247,75 @@ diesel::table! {
         #[max_length = 255]
         description -> Nullable<Varchar>,
         permission -> Int8,
+        require_mfa -> Bool,
+    }
+}
+
+diesel::table! {
+    saml_assertions (provider, assertion_id) {
+        #[max_length = 50]
+        provider -> Varchar,
+        #[max_length = 255]
+        assertion_id -> Varchar,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    saml_login_codes (code_hash) {
+        #[max_length = 64]
+        code_hash -> Varchar,
+        user_id -> Int4,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    saml_requests (id) {
+        #[max_length = 80]
+        id -> Varchar,
+        #[max_length = 50]
+        provider -> Varchar,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    totp_credentials (user_id) {
+        user_id -> Int4,
+        #[max_length = 128]
+        secret -> Varchar,
+        confirmed_at -> Nullable<Timestamptz>,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_sessions (id) {
+        id -> Uuid,
+        user_id -> Int4,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+        oauth_client_id -> Nullable<Int4>,
+        auth_time -> Nullable<Timestamptz>,
+        mfa_attempts -> Int4,
+    }
+}
+
+diesel::table! {
+    user_suspensions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        reason -> Text,
+        #[max_length = 255]
+        suspended_by -> Varchar,
+        ends_at -> Nullable<Timestamptz>,
+        lifted_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -30,21 +328,109 @@ diesel::table! {
         #[max_length = 200]
         username -> Varchar,
         #[max_length = 255]
-        password_hash -> Varchar,
+        password_hash -> Nullable<Varchar>,
         #[max_length = 100]
-        first_name -> Varchar,
+        first_name -> Nullable<Varchar>,
         #[max_length = 100]
-        last_name -> Varchar,
+        last_name -> Nullable<Varchar>,
         is_active -> Bool,
         roles -> Int2,
         permissions -> Int8,
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
+        must_change_password -> Bool,
+        email_verified_at -> Nullable<Timestamptz>,
+        #[max_length = 35]
+        locale -> Varchar,
+        locked_until -> Nullable<Timestamptz>,
+        is_service_account -> Bool,
+        #[max_length = 255]
+        description -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    webauthn_challenges (id) {
+        id -> Uuid,
+        user_id -> Nullable<Int4>,
+        #[max_length = 20]
+        ceremony -> Varchar,
+        #[max_length = 64]
+        challenge -> Varchar,
+        expires_at -> Timestamptz,
     }
 }
 
+diesel::table! {
+    webauthn_credentials (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 1366]
+        credential_id -> Varchar,
+        public_key -> Bytea,
+        sign_count -> Int8,
+        transports -> Array<Text>,
+        #[max_length = 100]
+        name -> Varchar,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(audit_log -> users (user_id));
+diesel::joinable!(email_change_tokens -> users (user_id));
+diesel::joinable!(email_verification_tokens -> users (user_id));
+diesel::joinable!(failed_logins -> users (user_id));
+diesel::joinable!(federated_identities -> users (user_id));
+diesel::joinable!(federated_login_states -> users (link_user_id));
+diesel::joinable!(magic_link_tokens -> users (user_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(oauth_authorization_codes -> oauth_clients (oauth_client_id));
+diesel::joinable!(oauth_authorization_codes -> users (user_id));
+diesel::joinable!(oauth_client_assertions -> oauth_clients (oauth_client_id));
+diesel::joinable!(oauth_clients -> users (created_by));
+diesel::joinable!(oauth_consents -> oauth_clients (oauth_client_id));
+diesel::joinable!(oauth_consents -> users (user_id));
+diesel::joinable!(oauth_device_codes -> oauth_clients (oauth_client_id));
+diesel::joinable!(oauth_device_codes -> users (user_id));
+diesel::joinable!(oauth_refresh_tokens -> user_sessions (session_id));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(saml_login_codes -> users (user_id));
+diesel::joinable!(totp_credentials -> users (user_id));
+diesel::joinable!(user_sessions -> oauth_clients (oauth_client_id));
+diesel::joinable!(user_sessions -> users (user_id));
+diesel::joinable!(user_suspensions -> users (user_id));
+diesel::joinable!(webauthn_challenges -> users (user_id));
+diesel::joinable!(webauthn_credentials -> users (user_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
+    email_change_tokens,
+    email_verification_tokens,
+    failed_logins,
+    federated_identities,
+    federated_login_states,
+    magic_link_tokens,
+    mfa_recovery_codes,
+    oauth_authorization_codes,
+    oauth_client_assertions,
+    oauth_clients,
+    oauth_consents,
+    oauth_device_codes,
+    oauth_refresh_tokens,
+    password_reset_tokens,
     permissions,
+    rate_limit_buckets,
     roles,
+    saml_assertions,
+    saml_login_codes,
+    saml_requests,
+    totp_credentials,
+    user_sessions,
+    user_suspensions,
     users,
+    webauthn_challenges,
+    webauthn_credentials,
 );
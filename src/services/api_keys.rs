@@ -0,0 +1,175 @@
+//! Personal access tokens for scripts and CI.
+//!
+//! A key looks like `uak_<prefix>_<secret>`. The prefix is stored in clear text to find the
+//! key; only the SHA-256 hash of the full key is kept.
+
+use crate::models::{ApiKey, NewApiKey};
+use crate::services::jwt::Claims;
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::{HeaderMap, StatusCode};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use rand::RngCore;
+use uuid::Uuid;
+
+pub const KEY_PREFIX: &str = "uak_";
+
+/// Returns the API key sent as bearer token or in `X-API-Key`, if any.
+pub fn api_key_from_headers(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+        .filter(|token| token.starts_with(KEY_PREFIX));
+
+    bearer.or_else(|| headers.get("x-api-key").and_then(|value| value.to_str().ok()))
+}
+
+/// Creates a key for `owner_id` and returns the stored row and the full key.
+pub fn create_api_key(
+    conn: &mut PgConnection,
+    owner_id: i32,
+    key_name: String,
+    scope: i64,
+    expires: Option<DateTime<Utc>>,
+) -> Result<(ApiKey, String), (StatusCode, String)> {
+    use crate::schema::api_keys::dsl::api_keys;
+
+    let mut prefix_bytes = [0u8; 6];
+    rand::rng().fill_bytes(&mut prefix_bytes);
+    let key_prefix = hex::encode(prefix_bytes);
+
+    let key = format!("{}{}_{}", KEY_PREFIX, key_prefix, generate_token());
+
+    let api_key = diesel::insert_into(api_keys)
+        .values(&NewApiKey {
+            user_id: owner_id,
+            name: key_name,
+            prefix: key_prefix,
+            key_hash: hash_token(&key),
+            permissions: scope,
+            expires_at: expires,
+        })
+        .returning(ApiKey::as_returning())
+        .get_result(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok((api_key, key))
+}
+
+fn key_prefix_of(key: &str) -> Option<&str> {
+    key.strip_prefix(KEY_PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+        .map(|(key_prefix, _)| key_prefix)
+}
+
+/// Returns the id of the key if it is valid, without marking it as used.
+///
+/// Unlike `authenticate_api_key` the owner is not checked; meant for rate limiting.
+pub fn verified_api_key_id(conn: &mut PgConnection, key: &str) -> QueryResult<Option<i32>> {
+    use crate::schema::api_keys::dsl::*;
+
+    let Some(key_prefix) = key_prefix_of(key) else {
+        return Ok(None);
+    };
+
+    api_keys
+        .filter(prefix.eq(key_prefix))
+        .filter(key_hash.eq(hash_token(key)))
+        .filter(revoked_at.is_null())
+        .filter(expires_at.is_null().or(expires_at.gt(Utc::now())))
+        .select(id)
+        .first(conn)
+        .optional()
+}
+
+/// Checks an API key and returns claims for its owner, limited to the key's permissions.
+pub fn authenticate_api_key(
+    conn: &mut PgConnection,
+    key: &str,
+) -> Result<Claims, (StatusCode, String)> {
+    use crate::schema::api_keys::dsl::*;
+    use crate::schema::users::dsl::{email, is_active, temp_id, users};
+
+    let invalid = || (StatusCode::UNAUTHORIZED, "Invalid API key".to_string());
+
+    let key_prefix = key_prefix_of(key).ok_or_else(invalid)?;
+
+    let found = api_keys
+        .inner_join(users)
+        .filter(prefix.eq(key_prefix))
+        .filter(revoked_at.is_null())
+        .select((id, key_hash, permissions, expires_at, email, temp_id, is_active))
+        .first::<(i32, String, i64, Option<DateTime<Utc>>, String, Uuid, bool)>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    let Some((key_id, stored_hash, scope, expiry, owner_email, owner_temp_id, owner_active)) =
+        found
+    else {
+        return Err(invalid());
+    };
+
+    if stored_hash != hash_token(key) || expiry.is_some_and(|at| at <= Utc::now()) {
+        return Err(invalid());
+    }
+
+    if !owner_active {
+        return Err((StatusCode::FORBIDDEN, "Account is inactive".into()));
+    }
+
+    diesel::update(api_keys.find(key_id))
+        .set(last_used_at.eq(Utc::now()))
+        .execute(conn)
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(Claims {
+        sub: owner_email,
+        exp: expiry.map(|at| at.timestamp() as usize).unwrap_or(0),
+        user_temp_id: owner_temp_id.to_string(),
+        sid: String::new(),
+        purpose: None,
+        scope: Some(scope),
+        client_id: None,
+        identity_scope: None,
+        api_key_id: Some(key_id),
+    })
+}
+
+/// Active keys of `owner_id`, newest first.
+pub fn list_api_keys(
+    conn: &mut PgConnection,
+    owner_id: i32,
+) -> Result<Vec<ApiKey>, (StatusCode, String)> {
+    use crate::schema::api_keys::dsl::*;
+
+    api_keys
+        .filter(user_id.eq(owner_id))
+        .filter(revoked_at.is_null())
+        .order(created_at.desc())
+        .select(ApiKey::as_select())
+        .load(conn)
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+/// Revokes a key of `owner_id`. Returns `false` if there is no such active key.
+pub fn revoke_api_key(
+    conn: &mut PgConnection,
+    owner_id: i32,
+    key_id: i32,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::api_keys::dsl::*;
+
+    let revoked = diesel::update(
+        api_keys
+            .filter(id.eq(key_id))
+            .filter(user_id.eq(owner_id))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(Utc::now()))
+    .execute(conn)
+    .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(revoked > 0)
+}
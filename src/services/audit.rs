@@ -0,0 +1,25 @@
+use crate::models::NewAuditEntry;
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use diesel::prelude::*;
+
+/// Appends an entry to the `audit_log` table.
+pub fn record_event(
+    conn: &mut PgConnection,
+    target_user_id: Option<i32>,
+    event: &str,
+    detail: Option<String>,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::audit_log::dsl::audit_log;
+
+    diesel::insert_into(audit_log)
+        .values(&NewAuditEntry {
+            user_id: target_user_id,
+            event: event.to_string(),
+            detail,
+        })
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::config::AppConfig;
+use crate::models::{NewEmailChangeToken, User};
+use crate::services::audit::record_event;
+use crate::services::mailer::templates::{render, MailTemplate};
+use crate::services::mailer::Mailer;
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+
+const MAX_EMAIL_LENGTH: usize = 255;
+
+/// Lowercases and checks an email address set for an existing user.
+pub fn normalized_email(address: &str) -> Result<String, (StatusCode, String)> {
+    let address = address.trim().to_lowercase();
+    if !address.contains('@') || address.chars().count() > MAX_EMAIL_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "Invalid email address".into()));
+    }
+    Ok(address)
+}
+
+/// Creates a token for changing the address of `user` to `requested_email` and emails the
+/// confirmation link to the new address. Earlier requests of the user stop being valid.
+pub fn send_email_change_link(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: &User,
+    requested_email: &str,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::email_change_tokens::dsl::*;
+
+    let address = normalized_email(requested_email)?;
+    if address == user.email {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "New email must differ from the current email".into(),
+        ));
+    }
+
+    let token = generate_token();
+
+    let change_token = NewEmailChangeToken {
+        user_id: user.id,
+        new_email: address.clone(),
+        token_hash: hash_token(&token),
+        expires_at: Utc::now() + Duration::hours(config.email_verification_ttl_hours),
+    };
+
+    conn.transaction(|conn| {
+        diesel::update(email_change_tokens.filter(user_id.eq(user.id)).filter(used_at.is_null()))
+            .set(used_at.eq(Utc::now()))
+            .execute(conn)?;
+
+        diesel::insert_into(email_change_tokens)
+            .values(&change_token)
+            .execute(conn)
+    })
+    .map_err(|e| internal_error("DB insert error", e))?;
+
+    let link = format!("{}/confirm-email-change?token={}", config.public_url, token);
+    let expires_in = config.email_verification_ttl_hours.to_string();
+
+    let message = render(
+        config,
+        MailTemplate::EmailChange,
+        &user.locale,
+        &address,
+        &[
+            ("first_name", user.greeting_name()),
+            ("link", &link),
+            ("expires_hours", &expires_in),
+        ],
+    );
+
+    mailer
+        .send(&message)
+        .map_err(|e| internal_error("Failed to send email", e))
+}
+
+/// Moves the user behind `token` to the address it was issued for, which counts as verified,
+/// and consumes the token. The previous address is told about the change.
+///
+/// Whether the address is taken is only checked here, so requesting a change does not reveal
+/// which addresses have accounts.
+pub fn change_email_with_token(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    token: &str,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::email_change_tokens::dsl::*;
+    use crate::schema::users::dsl::{
+        email, email_verified_at, id as users_id, is_active, updated_at, users,
+    };
+
+    let changed = conn
+        .transaction(|conn| {
+            let active_users = users.filter(is_active.eq(true)).select(users_id);
+
+            let target = diesel::update(
+                email_change_tokens
+                    .filter(token_hash.eq(hash_token(token)))
+                    .filter(used_at.is_null())
+                    .filter(expires_at.gt(Utc::now()))
+                    .filter(user_id.eq_any(active_users)),
+            )
+            .set(used_at.eq(Utc::now()))
+            .returning((user_id, new_email))
+            .get_result::<(i32, String)>(conn)
+            .optional()?;
+
+            let Some((target_user_id, address)) = target else {
+                return Ok(None);
+            };
+
+            let previous = users.find(target_user_id).first::<User>(conn)?;
+
+            diesel::update(users.find(target_user_id))
+                .set((
+                    email.eq(&address),
+                    email_verified_at.eq(Utc::now()),
+                    updated_at.eq(Utc::now()),
+                ))
+                .execute(conn)?;
+
+            diesel::update(
+                email_change_tokens
+                    .filter(user_id.eq(target_user_id))
+                    .filter(used_at.is_null()),
+            )
+            .set(used_at.eq(Utc::now()))
+            .execute(conn)?;
+
+            Ok::<_, Error>(Some((previous, address)))
+        })
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                (StatusCode::CONFLICT, "Email address is already taken".to_string())
+            }
+            e => internal_error("DB update error", e),
+        })?;
+
+    let Some((user, address)) = changed else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired token".into()));
+    };
+
+    record_event(
+        conn,
+        Some(user.id),
+        "email_changed",
+        Some(format!("from {} to {}", user.email, address)),
+    )?;
+
+    notify_email_changed(mailer, config, &user, &address);
+
+    Ok(())
+}
+
+/// Tells `user` at their previous address that it was changed to `new_address`. Failures are
+/// only logged, as the change has been made.
+pub fn notify_email_changed(
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: &User,
+    new_address: &str,
+) {
+    let message = render(
+        config,
+        MailTemplate::EmailChanged,
+        &user.locale,
+        &user.email,
+        &[("first_name", user.greeting_name()), ("new_email", new_address)],
+    );
+    if let Err(e) = mailer.send(&message) {
+        tracing::error!("Email change notification for user {} failed: {}", user.id, e);
+    }
+}
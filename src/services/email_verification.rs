@@ -0,0 +1,129 @@
+use crate::config::AppConfig;
+use crate::models::{NewEmailVerificationToken, User};
+use crate::services::mailer::templates::{render, MailTemplate};
+use crate::services::mailer::Mailer;
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+
+/// Creates a verification token for `user` and emails the verification link.
+pub fn send_verification_email(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: &User,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::email_verification_tokens::dsl::email_verification_tokens;
+
+    let token = generate_token();
+
+    let verification_token = NewEmailVerificationToken {
+        user_id: user.id,
+        token_hash: hash_token(&token),
+        expires_at: Utc::now() + Duration::hours(config.email_verification_ttl_hours),
+    };
+
+    diesel::insert_into(email_verification_tokens)
+        .values(&verification_token)
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    let link = format!("{}/verify-email?token={}", config.public_url, token);
+    let expires_in = config.email_verification_ttl_hours.to_string();
+
+    let message = render(
+        config,
+        MailTemplate::VerifyEmail,
+        &user.locale,
+        &user.email,
+        &[
+            ("first_name", user.greeting_name()),
+            ("link", &link),
+            ("expires_hours", &expires_in),
+        ],
+    );
+
+    mailer
+        .send(&message)
+        .map_err(|e| internal_error("Failed to send email", e))
+}
+
+/// Sends a new verification email unless the account is verified already or one was
+/// sent less than `email_verification_resend_seconds` ago.
+pub fn resend_verification_email(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: &User,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::email_verification_tokens::dsl::*;
+
+    if user.email_verified_at.is_some() {
+        return Ok(());
+    }
+
+    let throttle_start = Utc::now() - Duration::seconds(config.email_verification_resend_seconds);
+
+    let recently_sent = email_verification_tokens
+        .filter(user_id.eq(user.id))
+        .filter(created_at.gt(throttle_start))
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    if recently_sent > 0 {
+        return Ok(());
+    }
+
+    send_verification_email(conn, mailer, config, user)
+}
+
+/// Marks the email address behind `token` as verified and consumes the token.
+pub fn verify_email_token(
+    conn: &mut PgConnection,
+    token: &str,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::email_verification_tokens::dsl::*;
+    use crate::schema::users::dsl::{email_verified_at, users};
+
+    let verified = conn
+        .transaction(|conn| {
+            let target_user_id = diesel::update(
+                email_verification_tokens
+                    .filter(token_hash.eq(hash_token(token)))
+                    .filter(used_at.is_null())
+                    .filter(expires_at.gt(Utc::now())),
+            )
+            .set(used_at.eq(Utc::now()))
+            .returning(user_id)
+            .get_result::<i32>(conn)
+            .optional()?;
+
+            let Some(target_user_id) = target_user_id else {
+                return Ok(false);
+            };
+
+            diesel::update(users.find(target_user_id).filter(email_verified_at.is_null()))
+                .set(email_verified_at.eq(Utc::now()))
+                .execute(conn)?;
+
+            diesel::update(
+                email_verification_tokens
+                    .filter(user_id.eq(target_user_id))
+                    .filter(used_at.is_null()),
+            )
+            .set(used_at.eq(Utc::now()))
+            .execute(conn)?;
+
+            Ok::<_, diesel::result::Error>(true)
+        })
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    if !verified {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired token".into()));
+    }
+
+    Ok(())
+}
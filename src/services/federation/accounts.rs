@@ -0,0 +1,522 @@
+//! Login states of federated logins, and the identities linking upstream accounts to users.
+//!
+//! Identities of SAML providers and users of LDAP directories are linked the same way, under the
+//! provider or directory id.
+
+use super::{FederatedProvider, ProviderEnv, UpstreamIdentity};
+use crate::models::{
+    FederatedIdentity, FederatedLoginState, NewFederatedIdentity, NewFederatedLoginState,
+    NewFederatedUser, User,
+};
+use crate::services::audit::record_event;
+use crate::services::mailer::templates::valid_locale;
+use crate::services::service_accounts::roles_by_name;
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+use rand::Rng;
+
+const STATE_LIFETIME_MINUTES: i64 = 10;
+const MAX_USERNAME_BASE_LENGTH: usize = 50;
+const USERNAME_ATTEMPTS: usize = 10;
+
+/// How the identities of a provider become local users.
+pub struct AccountPolicy {
+    /// Roles given to users created on their first login.
+    pub default_roles: Vec<String>,
+    /// Whether unknown identities get a new local user.
+    pub auto_provision: bool,
+    /// Whether an identity with a verified email is linked to the user with that email.
+    pub link_by_email: bool,
+}
+
+impl AccountPolicy {
+    /// Reads `DEFAULT_ROLES`, `AUTO_PROVISION` and `LINK_BY_EMAIL` of a provider.
+    pub fn from_env(settings: &ProviderEnv) -> Self {
+        Self {
+            default_roles: settings.list("DEFAULT_ROLES"),
+            auto_provision: settings.flag("AUTO_PROVISION", true),
+            link_by_email: settings.flag("LINK_BY_EMAIL", true),
+        }
+    }
+}
+
+/// Values of a started login that the browser carries to the provider and back.
+pub struct StartedLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Starts a login or, with `link_user_id`, a link to an existing user.
+pub fn create_login_state(
+    conn: &mut PgConnection,
+    provider: &FederatedProvider,
+    link_user_id: Option<i32>,
+) -> Result<StartedLogin, (StatusCode, String)> {
+    use crate::schema::federated_login_states::dsl::{expires_at, federated_login_states};
+
+    let started = StartedLogin {
+        state: generate_token(),
+        nonce: generate_token(),
+        code_verifier: generate_token(),
+    };
+
+    conn.transaction(|conn| {
+        diesel::delete(federated_login_states.filter(expires_at.le(Utc::now()))).execute(conn)?;
+
+        diesel::insert_into(federated_login_states)
+            .values(&NewFederatedLoginState {
+                state_hash: hash_token(&started.state),
+                provider: provider.id.clone(),
+                nonce: started.nonce.clone(),
+                code_verifier: started.code_verifier.clone(),
+                link_user_id,
+                expires_at: Utc::now() + Duration::minutes(STATE_LIFETIME_MINUTES),
+            })
+            .execute(conn)
+    })
+    .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok(started)
+}
+
+/// Consumes the login state the provider returned; each state can only be used once.
+pub fn take_login_state(
+    conn: &mut PgConnection,
+    provider: &FederatedProvider,
+    state: &str,
+) -> Result<FederatedLoginState, (StatusCode, String)> {
+    use crate::schema::federated_login_states::dsl::federated_login_states;
+
+    diesel::delete(federated_login_states.find(hash_token(state)))
+        .returning(FederatedLoginState::as_returning())
+        .get_result(conn)
+        .optional()
+        .map_err(|e| internal_error("DB delete error", e))?
+        .filter(|found| found.provider == provider.id && found.expires_at > Utc::now())
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid or expired state".into()))
+}
+
+/// Finds the local user for an upstream identity, linking or creating one if the provider
+/// allows it.
+///
+/// Identities are looked up by provider and subject first. Otherwise a verified email links
+/// them to the active user with that email when `link_by_email` is set, and a new user is
+/// created when `auto_provision` is set. Roles sent by the provider replace the user's roles.
+/// Inactive users and service accounts are refused before anything is written.
+pub fn resolve_federated_user(
+    conn: &mut PgConnection,
+    provider_id: &str,
+    policy: &AccountPolicy,
+    identity: &UpstreamIdentity,
+) -> Result<User, (StatusCode, String)> {
+    use crate::schema::federated_identities::dsl as identities;
+    use crate::schema::users;
+
+    let linked = identities::federated_identities
+        .inner_join(users::table)
+        .filter(identities::provider.eq(provider_id))
+        .filter(identities::subject.eq(&identity.subject))
+        .select((identities::id, users::all_columns))
+        .first::<(i32, User)>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    if let Some((identity_id, user)) = linked {
+        ensure_can_log_in(&user)?;
+        diesel::update(identities::federated_identities.find(identity_id))
+            .set(identities::last_login_at.eq(Utc::now()))
+            .execute(conn)
+            .map_err(|e| internal_error("DB update error", e))?;
+        return sync_roles(conn, user, identity);
+    }
+
+    let email = identity.email.as_deref().ok_or((
+        StatusCode::FORBIDDEN,
+        "The identity provider did not share an email address".to_string(),
+    ))?;
+
+    let existing = users::table
+        .filter(users::email.eq(email))
+        .filter(users::is_active.eq(true))
+        .first::<User>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    let user = match existing {
+        Some(user) => {
+            ensure_can_log_in(&user)?;
+            ensure_linkable(policy, identity)?;
+            // The provider vouched for the address, which is as good as our own check.
+            if user.email_verified_at.is_none() {
+                diesel::update(users::table.find(user.id))
+                    .set(users::email_verified_at.eq(Utc::now()))
+                    .execute(conn)
+                    .map_err(|e| internal_error("DB update error", e))?;
+            }
+            sync_roles(conn, user, identity)?
+        }
+        None if policy.auto_provision => {
+            // A suspended user must not get a fresh account with the same address.
+            let inactive = diesel::select(exists(
+                users::table
+                    .filter(users::email.eq(email))
+                    .filter(users::is_active.eq(false)),
+            ))
+            .get_result::<bool>(conn)
+            .map_err(|e| internal_error("DB query error", e))?;
+            if inactive {
+                return Err((StatusCode::FORBIDDEN, "Account is inactive".into()));
+            }
+            provision_user(conn, provider_id, policy, identity, email)?
+        }
+        None => {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "No account is linked to this identity".into(),
+            ));
+        }
+    };
+
+    link_identity(conn, provider_id, identity, user.id)?;
+
+    Ok(user)
+}
+
+/// Refuses users that cannot log in with an upstream identity.
+fn ensure_can_log_in(user: &User) -> Result<(), (StatusCode, String)> {
+    if user.is_service_account {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Service accounts cannot log in interactively".into(),
+        ));
+    }
+    if !user.is_active {
+        return Err((StatusCode::FORBIDDEN, "Account is inactive".into()));
+    }
+
+    Ok(())
+}
+
+/// Checks that an identity seen for the first time may be linked to the user with the same
+/// email.
+fn ensure_linkable(
+    policy: &AccountPolicy,
+    identity: &UpstreamIdentity,
+) -> Result<(), (StatusCode, String)> {
+    if !policy.link_by_email || !identity.email_verified {
+        return Err((
+            StatusCode::CONFLICT,
+            "An account with this email already exists; log in and link the provider in \
+             your profile"
+                .into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Links an upstream identity to `owner_id`.
+pub fn link_identity(
+    conn: &mut PgConnection,
+    provider_id: &str,
+    identity: &UpstreamIdentity,
+    owner_id: i32,
+) -> Result<FederatedIdentity, (StatusCode, String)> {
+    use crate::schema::federated_identities::dsl::federated_identities;
+
+    let linked = diesel::insert_into(federated_identities)
+        .values(&NewFederatedIdentity {
+            user_id: owner_id,
+            provider: provider_id.to_string(),
+            subject: identity.subject.clone(),
+            email: identity.email.clone(),
+        })
+        .returning(FederatedIdentity::as_returning())
+        .get_result(conn)
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => (
+                StatusCode::CONFLICT,
+                "This identity or provider is already linked to an account".into(),
+            ),
+            e => internal_error("DB insert error", e),
+        })?;
+
+    record_event(
+        conn,
+        Some(owner_id),
+        "federated_identity_linked",
+        Some(format!("provider {}", provider_id)),
+    )?;
+
+    Ok(linked)
+}
+
+/// Creates a user for an identity seen for the first time, with the provider's default roles.
+fn provision_user(
+    conn: &mut PgConnection,
+    provider_id: &str,
+    policy: &AccountPolicy,
+    identity: &UpstreamIdentity,
+    email: &str,
+) -> Result<User, (StatusCode, String)> {
+    use crate::schema::users::dsl::{username, users};
+
+    let role_names = identity.roles.as_ref().unwrap_or(&policy.default_roles);
+    let (role_bits, _) = roles_by_name(conn, role_names)
+        .map_err(|(_, e)| internal_error("Invalid provider roles", e))?;
+
+    let base = username_base(identity, email);
+
+    let mut candidate = base.clone();
+    for _ in 0..USERNAME_ATTEMPTS {
+        let taken = users
+            .filter(username.eq(&candidate))
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(|e| internal_error("DB query error", e))?;
+        if taken == 0 {
+            break;
+        }
+        candidate = format!("{}{}", base, rand::rng().random_range(1000..10000));
+    }
+
+    let user = diesel::insert_into(users)
+        .values(&NewFederatedUser {
+            email: email.to_string(),
+            username: candidate,
+            first_name: identity.given_name.clone(),
+            last_name: identity.family_name.clone(),
+            locale: provisioned_locale(identity).to_string(),
+            roles: role_bits,
+            email_verified_at: identity.email_verified.then(Utc::now),
+        })
+        .get_result::<User>(conn)
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => (
+                StatusCode::CONFLICT,
+                "Username or email is already taken".into(),
+            ),
+            e => internal_error("DB insert error", e),
+        })?;
+
+    record_event(
+        conn,
+        Some(user.id),
+        "federated_user_provisioned",
+        Some(format!("provider {}", provider_id)),
+    )?;
+
+    Ok(user)
+}
+
+/// Username for a provisioned user, from the preferred username or the email. A number is
+/// appended if it is taken.
+fn username_base(identity: &UpstreamIdentity, email: &str) -> String {
+    let base: String = identity
+        .preferred_username
+        .as_deref()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default())
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || "._-".contains(*c))
+        .take(MAX_USERNAME_BASE_LENGTH)
+        .collect();
+
+    if base.is_empty() { "user".to_string() } else { base }
+}
+
+fn provisioned_locale(identity: &UpstreamIdentity) -> &str {
+    identity
+        .locale
+        .as_deref()
+        .filter(|tag| valid_locale(tag))
+        .unwrap_or("en")
+}
+
+/// Gives `user` the roles the provider sent, if it sent any.
+fn sync_roles(
+    conn: &mut PgConnection,
+    user: User,
+    identity: &UpstreamIdentity,
+) -> Result<User, (StatusCode, String)> {
+    use crate::schema::users::dsl::{roles, users};
+
+    let Some(role_names) = &identity.roles else {
+        return Ok(user);
+    };
+
+    let (role_bits, _) = roles_by_name(conn, role_names)
+        .map_err(|(_, e)| internal_error("Invalid provider roles", e))?;
+    if role_bits == user.roles {
+        return Ok(user);
+    }
+
+    let updated = diesel::update(users.find(user.id))
+        .set(roles.eq(role_bits))
+        .get_result::<User>(conn)
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    record_event(
+        conn,
+        Some(user.id),
+        "federated_roles_synced",
+        Some(format!("roles {}", role_names.join(", "))),
+    )?;
+
+    Ok(updated)
+}
+
+pub fn list_identities(
+    conn: &mut PgConnection,
+    owner_id: i32,
+) -> Result<Vec<FederatedIdentity>, (StatusCode, String)> {
+    use crate::schema::federated_identities::dsl::*;
+
+    federated_identities
+        .filter(user_id.eq(owner_id))
+        .order(created_at.asc())
+        .select(FederatedIdentity::as_select())
+        .load(conn)
+        .map_err(|e| internal_error("DB load error", e))
+}
+
+/// Removes the user's link to `provider_id`. Returns `false` if there was none.
+pub fn unlink_identity(
+    conn: &mut PgConnection,
+    owner_id: i32,
+    provider_id: &str,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::federated_identities::dsl::*;
+
+    let deleted = diesel::delete(
+        federated_identities
+            .filter(user_id.eq(owner_id))
+            .filter(provider.eq(provider_id)),
+    )
+    .execute(conn)
+    .map_err(|e| internal_error("DB delete error", e))?;
+
+    if deleted > 0 {
+        record_event(
+            conn,
+            Some(owner_id),
+            "federated_identity_unlinked",
+            Some(format!("provider {}", provider_id)),
+        )?;
+    }
+
+    Ok(deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn policy(link_by_email: bool) -> AccountPolicy {
+        AccountPolicy {
+            default_roles: vec!["developer".into()],
+            auto_provision: true,
+            link_by_email,
+        }
+    }
+
+    fn identity(email_verified: bool) -> UpstreamIdentity {
+        UpstreamIdentity {
+            subject: "248289761001".into(),
+            email: Some("jane.doe@example.com".into()),
+            email_verified,
+            given_name: Some("Jane".into()),
+            family_name: Some("Doe".into()),
+            preferred_username: None,
+            locale: None,
+            roles: None,
+        }
+    }
+
+    fn user(is_active: bool, is_service_account: bool) -> User {
+        User {
+            id: 1,
+            temp_id: Uuid::new_v4(),
+            email: "jane.doe@example.com".into(),
+            username: "jdoe".into(),
+            password_hash: None,
+            first_name: None,
+            last_name: None,
+            is_active,
+            roles: 0,
+            permissions: 0,
+            created_at: None,
+            updated_at: None,
+            must_change_password: false,
+            email_verified_at: None,
+            locale: "en".into(),
+            locked_until: None,
+            is_service_account,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn links_verified_email_to_existing_user() {
+        assert!(ensure_linkable(&policy(true), &identity(true)).is_ok());
+    }
+
+    #[test]
+    fn refuses_to_link_unverified_email() {
+        let (status, _) = ensure_linkable(&policy(true), &identity(false)).unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn refuses_to_link_when_provider_does_not_link_by_email() {
+        let (status, _) = ensure_linkable(&policy(false), &identity(true)).unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn lets_only_active_users_log_in() {
+        assert!(ensure_can_log_in(&user(true, false)).is_ok());
+
+        let (status, message) = ensure_can_log_in(&user(false, false)).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(message, "Account is inactive");
+
+        let (status, message) = ensure_can_log_in(&user(true, true)).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(message, "Service accounts cannot log in interactively");
+    }
+
+    #[test]
+    fn provisions_username_from_preferred_username_or_email() {
+        let mut identity = identity(true);
+        assert_eq!(username_base(&identity, "jane.doe@example.com"), "jane.doe");
+
+        identity.preferred_username = Some("Jane Doe <admin>".into());
+        assert_eq!(username_base(&identity, "jane.doe@example.com"), "JaneDoeadmin");
+
+        identity.preferred_username = Some("x".repeat(80));
+        let base = username_base(&identity, "jane.doe@example.com");
+        assert_eq!(base.len(), MAX_USERNAME_BASE_LENGTH);
+
+        identity.preferred_username = Some("!!!".into());
+        assert_eq!(username_base(&identity, "jane.doe@example.com"), "user");
+    }
+
+    #[test]
+    fn provisions_locale_of_identity_if_valid() {
+        let mut identity = identity(true);
+        assert_eq!(provisioned_locale(&identity), "en");
+
+        identity.locale = Some("da-DK".into());
+        assert_eq!(provisioned_locale(&identity), "da-DK");
+
+        identity.locale = Some("<script>".into());
+        assert_eq!(provisioned_locale(&identity), "en");
+    }
+}
@@ -0,0 +1,421 @@
+//! Federated login through upstream OpenID Connect providers such as Google or Okta.
+//!
+//! We act as a relying party: the browser is sent to the provider with the authorization code
+//! flow and PKCE, and the ID token the provider returns is verified against its published
+//! keys. How upstream identities map to local users is decided in `accounts`.
+
+pub mod accounts;
+
+use crate::utils::error::upstream_error;
+use accounts::AccountPolicy;
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::RwLock;
+use std::time::Duration;
+use url::Url;
+
+const DEFAULT_SCOPES: &str = "openid email profile";
+const UPSTREAM_TIMEOUT_SECS: u64 = 10;
+const MAX_PROVIDER_ID_LENGTH: usize = 50;
+
+/// An upstream provider configured with `FEDERATED_<ID>_*` variables.
+pub struct FederatedProvider {
+    pub id: String,
+    pub name: String,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    scopes: String,
+    pub accounts: AccountPolicy,
+    /// Whether emails count as verified without an `email_verified` claim, for providers
+    /// such as Microsoft Entra ID that only issue addresses they own.
+    pub trust_email: bool,
+}
+
+#[derive(Deserialize, Clone)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct UpstreamTokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct UpstreamClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    /// Boolean, but some providers send it as a string.
+    email_verified: Option<Value>,
+    given_name: Option<String>,
+    family_name: Option<String>,
+    preferred_username: Option<String>,
+    locale: Option<String>,
+}
+
+/// The user as described by a verified upstream ID token.
+pub struct UpstreamIdentity {
+    pub subject: String,
+    /// Lowercased email address.
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub preferred_username: Option<String>,
+    pub locale: Option<String>,
+    /// Roles the provider assigns; they replace the user's roles on every login. `None` leaves
+    /// the roles of existing users alone.
+    pub roles: Option<Vec<String>>,
+}
+
+/// The configured providers, with their discovery documents and keys cached once fetched.
+pub struct FederatedProviders {
+    providers: Vec<FederatedProvider>,
+    http: reqwest::Client,
+    metadata: RwLock<HashMap<String, ProviderMetadata>>,
+    keys: RwLock<HashMap<String, JwkSet>>,
+}
+
+/// S256 PKCE challenge of `verifier`.
+pub fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// The variables `<KIND>_<ID>_*` configuring one provider, e.g. `FEDERATED_GOOGLE_ISSUER`.
+pub struct ProviderEnv {
+    pub id: String,
+    prefix: String,
+}
+
+impl ProviderEnv {
+    pub fn new(kind: &str, id: &str) -> Result<Self, String> {
+        if id.len() > MAX_PROVIDER_ID_LENGTH
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "Invalid provider id '{}': use lowercase letters, digits, '-' and '_'",
+                id
+            ));
+        }
+
+        Ok(Self {
+            id: id.to_string(),
+            prefix: format!("{}_{}_", kind, id.to_uppercase().replace('-', "_")),
+        })
+    }
+
+    pub fn var(&self, name: &str) -> Option<String> {
+        env::var(format!("{}{}", self.prefix, name)).ok()
+    }
+
+    pub fn required(&self, name: &str) -> Result<String, String> {
+        self.var(name)
+            .ok_or_else(|| format!("{}{} must be set", self.prefix, name))
+    }
+
+    pub fn flag(&self, name: &str, default: bool) -> bool {
+        self.var(name)
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(default)
+    }
+
+    /// A comma-separated list; empty if the variable is not set.
+    pub fn list(&self, name: &str) -> Vec<String> {
+        self.var(name)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// `group=role` pairs separated by commas; a group can map to several roles.
+    pub fn mapping(&self, name: &str) -> Result<HashMap<String, Vec<String>>, String> {
+        let mut mapping: HashMap<String, Vec<String>> = HashMap::new();
+        for pair in self.list(name) {
+            let (group, role) = pair.rsplit_once('=').ok_or_else(|| {
+                format!("Invalid {}{} entry '{}': use group=role", self.prefix, name, pair)
+            })?;
+            mapping
+                .entry(group.trim().to_string())
+                .or_default()
+                .push(role.trim().to_string());
+        }
+        Ok(mapping)
+    }
+}
+
+impl FederatedProvider {
+    fn from_env(id: &str) -> Result<Self, String> {
+        let settings = ProviderEnv::new("FEDERATED", id)?;
+
+        Ok(Self {
+            name: settings.var("NAME").unwrap_or_else(|| id.to_string()),
+            issuer: settings.required("ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: settings.required("CLIENT_ID")?,
+            client_secret: settings.required("CLIENT_SECRET")?,
+            scopes: settings.var("SCOPES").unwrap_or_else(|| DEFAULT_SCOPES.into()),
+            accounts: AccountPolicy::from_env(&settings),
+            trust_email: settings.flag("TRUST_EMAIL", false),
+            id: settings.id,
+        })
+    }
+}
+
+impl FederatedProviders {
+    /// Reads the comma-separated provider ids in `FEDERATED_PROVIDERS` and their settings.
+    pub fn from_env() -> Result<Self, String> {
+        let providers = env::var("FEDERATED_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(FederatedProvider::from_env)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(UPSTREAM_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+        Ok(Self {
+            providers,
+            http,
+            metadata: RwLock::new(HashMap::new()),
+            keys: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn list(&self) -> &[FederatedProvider] {
+        &self.providers
+    }
+
+    pub fn find(&self, id: &str) -> Result<&FederatedProvider, (StatusCode, String)> {
+        self.providers
+            .iter()
+            .find(|provider| provider.id == id)
+            .ok_or((StatusCode::NOT_FOUND, "Unknown identity provider".into()))
+    }
+
+    /// The provider's authorization URL the browser is sent to.
+    pub async fn authorization_url(
+        &self,
+        provider: &FederatedProvider,
+        redirect_uri: &str,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, (StatusCode, String)> {
+        let metadata = self.metadata(provider).await?;
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|e| upstream_error("Invalid authorization endpoint", e))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", &provider.scopes)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &code_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.to_string())
+    }
+
+    /// Exchanges an authorization code at the provider and verifies the returned ID token.
+    pub async fn authenticate(
+        &self,
+        provider: &FederatedProvider,
+        redirect_uri: &str,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<UpstreamIdentity, (StatusCode, String)> {
+        let metadata = self.metadata(provider).await?;
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .basic_auth(&provider.client_id, Some(&provider.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .map_err(|e| upstream_error("Token request failed", e))?;
+
+        if response.status().is_client_error() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "The provider rejected the authorization code".into(),
+            ));
+        }
+
+        let tokens = response
+            .error_for_status()
+            .map_err(|e| upstream_error("Token request failed", e))?
+            .json::<UpstreamTokenResponse>()
+            .await
+            .map_err(|e| upstream_error("Invalid token response", e))?;
+
+        let claims = self.verify_id_token(provider, &metadata, &tokens.id_token).await?;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err((StatusCode::BAD_REQUEST, "ID token nonce does not match".into()));
+        }
+
+        let email_verified = match claims.email_verified {
+            Some(Value::Bool(verified)) => verified,
+            Some(Value::String(verified)) => verified == "true",
+            _ => provider.trust_email,
+        };
+
+        Ok(UpstreamIdentity {
+            subject: claims.sub,
+            email: claims.email.map(|address| address.to_lowercase()),
+            email_verified,
+            given_name: claims.given_name,
+            family_name: claims.family_name,
+            preferred_username: claims.preferred_username,
+            locale: claims.locale,
+            roles: None,
+        })
+    }
+
+    async fn verify_id_token(
+        &self,
+        provider: &FederatedProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<UpstreamClaims, (StatusCode, String)> {
+        let header = decode_header(id_token).map_err(|e| upstream_error("Invalid ID token", e))?;
+
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+        ) {
+            return Err(upstream_error(
+                "Unsupported ID token algorithm",
+                format!("{:?}", header.alg),
+            ));
+        }
+
+        // Providers rotate keys, so an unknown key id fetches the key set once more.
+        let mut keys = self.keys(provider, metadata, false).await?;
+        let find_key = |keys: &JwkSet| match &header.kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        };
+        let jwk = match find_key(&keys) {
+            Some(jwk) => jwk,
+            None => {
+                keys = self.keys(provider, metadata, true).await?;
+                find_key(&keys).ok_or_else(|| {
+                    upstream_error("ID token verification failed", "signing key not found")
+                })?
+            }
+        };
+        let key = DecodingKey::from_jwk(&jwk)
+            .map_err(|e| upstream_error("Invalid provider key", e))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        decode::<UpstreamClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| upstream_error("ID token verification failed", e))
+    }
+
+    async fn metadata(
+        &self,
+        provider: &FederatedProvider,
+    ) -> Result<ProviderMetadata, (StatusCode, String)> {
+        if let Some(metadata) = self.metadata.read().unwrap().get(&provider.id) {
+            return Ok(metadata.clone());
+        }
+
+        let metadata = self
+            .http
+            .get(format!("{}/.well-known/openid-configuration", provider.issuer))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| upstream_error("Provider discovery failed", e))?
+            .json::<ProviderMetadata>()
+            .await
+            .map_err(|e| upstream_error("Invalid discovery document", e))?;
+
+        if metadata.issuer.trim_end_matches('/') != provider.issuer {
+            return Err(upstream_error(
+                "Provider discovery failed",
+                format!("issuer is '{}'", metadata.issuer),
+            ));
+        }
+
+        self.metadata
+            .write()
+            .unwrap()
+            .insert(provider.id.clone(), metadata.clone());
+
+        Ok(metadata)
+    }
+
+    async fn keys(
+        &self,
+        provider: &FederatedProvider,
+        metadata: &ProviderMetadata,
+        refresh: bool,
+    ) -> Result<JwkSet, (StatusCode, String)> {
+        if !refresh && let Some(keys) = self.keys.read().unwrap().get(&provider.id) {
+            return Ok(keys.clone());
+        }
+
+        let keys = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| upstream_error("Fetching provider keys failed", e))?
+            .json::<JwkSet>()
+            .await
+            .map_err(|e| upstream_error("Invalid provider keys", e))?;
+
+        self.keys
+            .write()
+            .unwrap()
+            .insert(provider.id.clone(), keys.clone());
+
+        Ok(keys)
+    }
+}
@@ -1,26 +1,74 @@
 use crate::models::User;
+use crate::services::api_keys::{api_key_from_headers, authenticate_api_key};
+use crate::services::sessions::ensure_session_active;
 use axum::http::{HeaderMap, StatusCode};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Utc};
+use diesel::PgConnection;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Limits a token to a single step instead of general API access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    /// Only accepted by the password change endpoint.
+    PasswordChange,
+    /// Second login step; only accepted by `POST /auth/mfa/verify`.
+    Mfa,
+    /// Issued when a role requires MFA the user has not set up; only accepted by the
+    /// TOTP enrollment endpoints.
+    MfaEnrollment,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
     pub user_temp_id: String,
+    /// Id of the `user_sessions` row this token belongs to.
+    pub sid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purpose: Option<TokenPurpose>,
+    /// Permission bitmask the credential is limited to; `None` for login tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<i64>,
+    /// OAuth client the token was issued to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// OpenID Connect scopes granted to the client, as stored in `identity_scope`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_scope: Option<i16>,
+    /// Set when the request authenticated with an API key instead of a login token.
+    #[serde(skip)]
+    pub api_key_id: Option<i32>,
 }
 
-pub fn create_jwt(user: &User, secret: &str) -> Result<String, jsonwebtoken::errors::Error> {
-    let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
-        .expect("valid timestamp")
-        .timestamp();
+/// OAuth client and scope an access token is issued for.
+pub struct TokenGrant<'a> {
+    pub client_id: &'a str,
+    pub scope: i64,
+    pub identity_scope: i16,
+}
 
+pub fn create_jwt(
+    user: &User,
+    session_id: Uuid,
+    expires_at: DateTime<Utc>,
+    purpose: Option<TokenPurpose>,
+    grant: Option<TokenGrant>,
+    secret: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
     let claims = Claims {
         sub: user.email.clone(),
-        exp: expiration as usize,
+        exp: expires_at.timestamp() as usize,
         user_temp_id: user.temp_id.to_string(),
+        sid: session_id.to_string(),
+        purpose,
+        scope: grant.as_ref().map(|g| g.scope),
+        identity_scope: grant.as_ref().map(|g| g.identity_scope),
+        client_id: grant.map(|g| g.client_id.to_string()),
+        api_key_id: None,
     };
 
     let header = Header::new(Algorithm::HS256);
@@ -28,23 +76,105 @@ pub fn create_jwt(user: &User, secret: &str) -> Result<String, jsonwebtoken::err
     encode(&header, &claims, &EncodingKey::from_secret(secret.as_ref()))
 }
 
+/// Decodes the bearer token and returns its claims if the session is still active.
+///
+/// API keys, sent as bearer token or in `X-API-Key`, are accepted as well. Restricted tokens
+/// are rejected; use `extract_claims_for` on endpoints that complete a restricted step.
 pub async fn extract_user_from_jwt(
     jwt_secret: &str,
     headers: &HeaderMap,
+    conn: &mut PgConnection,
 ) -> Result<Claims, (StatusCode, String)> {
-    if let Some(auth_header_value) = headers.get("authorization") {
-        if let Ok(auth_str) = auth_header_value.to_str() {
-            if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                let token_data = decode::<Claims>(
-                    token,
-                    &DecodingKey::from_secret(jwt_secret.as_bytes()),
-                    &Validation::new(Algorithm::HS256),
-                )
-                .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
-
-                return Ok(token_data.claims);
-            }
-        }
+    if let Some(key) = api_key_from_headers(headers) {
+        return authenticate_api_key(conn, key);
+    }
+
+    let claims = decode_bearer_token(jwt_secret, headers, conn)?;
+
+    if claims.purpose.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Token is restricted and cannot be used here".into(),
+        ));
+    }
+
+    Ok(claims)
+}
+
+/// Like `extract_user_from_jwt`, but also accepts tokens restricted to `purpose`.
+///
+/// API keys are not accepted.
+pub async fn extract_claims_for(
+    purpose: TokenPurpose,
+    jwt_secret: &str,
+    headers: &HeaderMap,
+    conn: &mut PgConnection,
+) -> Result<Claims, (StatusCode, String)> {
+    let claims = decode_bearer_token(jwt_secret, headers, conn)?;
+
+    if claims.purpose.is_some_and(|p| p != purpose) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Token is restricted and cannot be used here".into(),
+        ));
+    }
+
+    Ok(claims)
+}
+
+/// Rejects requests made with an API key or OAuth token, on endpoints that create new
+/// credentials or change the account's identity.
+///
+/// Without this a key could be turned into a login or a key with more permissions, or take
+/// over the account by changing its email address.
+pub fn ensure_login_token(claims: &Claims) -> Result<(), (StatusCode, String)> {
+    if claims.api_key_id.is_some() || claims.client_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "API keys and OAuth tokens cannot be used to create credentials".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+}
+
+fn decode_claims(jwt_secret: &str, token: &str) -> Option<Claims> {
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .ok()
+    .map(|token_data| token_data.claims)
+}
+
+/// Reads the claims of the bearer token without checking its session.
+///
+/// Only meant for decisions that need no database access, such as rate limiting; handlers
+/// must use `extract_user_from_jwt`.
+pub fn peek_claims(jwt_secret: &str, headers: &HeaderMap) -> Option<Claims> {
+    decode_claims(jwt_secret, bearer_token(headers)?)
+}
+
+fn decode_bearer_token(
+    jwt_secret: &str,
+    headers: &HeaderMap,
+    conn: &mut PgConnection,
+) -> Result<Claims, (StatusCode, String)> {
+    if let Some(token) = bearer_token(headers) {
+        let claims = decode_claims(jwt_secret, token)
+            .ok_or((StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+
+        ensure_session_active(conn, &claims)?;
+
+        return Ok(claims);
     }
 
     Err((
@@ -0,0 +1,582 @@
+//! Password logins against LDAP directories such as Active Directory.
+//!
+//! Each directory serves the email domains it is configured for. A login with such an address
+//! looks the user up in the directory and binds as them with the password; local password
+//! verification only applies when no directory has an entry for the address. Directory users
+//! are linked and created like those of federated logins, see `federation::accounts`.
+
+use crate::services::federation::accounts::AccountPolicy;
+use crate::services::federation::{FederatedProviders, ProviderEnv, UpstreamIdentity};
+use crate::services::saml::SamlProviders;
+use crate::utils::error::upstream_error;
+use axum::http::StatusCode;
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_USER_FILTER: &str = "(mail={email})";
+/// `invalidCredentials`, also returned by Active Directory for disabled or expired accounts.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Which attributes of a directory entry fill which fields of a user.
+struct DirectoryAttributes {
+    /// Stable id of the entry; the DN is used if the entry has none.
+    id: String,
+    email: String,
+    first_name: String,
+    last_name: String,
+    username: String,
+    /// Attribute listing the user's groups, mapped to roles with `role_mapping`.
+    roles: Option<String>,
+    /// Group names, or the first value of group DNs such as `Admins` in `cn=Admins,...`, to
+    /// role names.
+    role_mapping: HashMap<String, Vec<String>>,
+}
+
+/// A directory configured with `LDAP_<ID>_*` variables.
+pub struct LdapDirectory {
+    pub id: String,
+    url: String,
+    starttls: bool,
+    /// Account to search with; searches are anonymous without one.
+    search_bind: Option<(String, String)>,
+    base_dn: String,
+    user_filter: String,
+    /// Lowercased email domains the directory serves.
+    domains: Vec<String>,
+    attributes: DirectoryAttributes,
+    pub accounts: AccountPolicy,
+}
+
+/// Outcome of a login attempt against the directories.
+pub enum DirectoryLogin<'a> {
+    Authenticated(&'a LdapDirectory, UpstreamIdentity),
+    /// A directory has the user, but the password is wrong.
+    InvalidCredentials,
+    /// No directory has an entry for the email.
+    NotFound,
+}
+
+/// The configured directories, tried in order.
+pub struct LdapDirectories {
+    directories: Vec<LdapDirectory>,
+}
+
+fn unreachable(e: impl std::fmt::Display) -> (StatusCode, String) {
+    upstream_error("Directory request failed", e)
+}
+
+/// The group name of a `memberOf`-style value: the first RDN value of a DN, else the value.
+fn group_name(value: &str) -> &str {
+    value
+        .split(',')
+        .next()
+        .and_then(|rdn| rdn.split_once('='))
+        .map_or(value, |(_, name)| name.trim())
+}
+
+impl LdapDirectory {
+    fn from_env(id: &str) -> Result<Self, String> {
+        let settings = ProviderEnv::new("LDAP", id)?;
+
+        let search_bind = match settings.var("BIND_DN") {
+            Some(dn) => Some((dn, settings.required("BIND_PASSWORD")?)),
+            None => None,
+        };
+        let domains: Vec<String> = settings
+            .list("DOMAINS")
+            .into_iter()
+            .map(|domain| domain.to_lowercase())
+            .collect();
+        if domains.is_empty() {
+            return Err(format!("Directory '{}' serves no DOMAINS", id));
+        }
+
+        Ok(Self {
+            url: settings.required("URL")?,
+            starttls: settings.flag("STARTTLS", false),
+            search_bind,
+            base_dn: settings.required("BASE_DN")?,
+            user_filter: settings
+                .var("USER_FILTER")
+                .unwrap_or_else(|| DEFAULT_USER_FILTER.into()),
+            domains,
+            attributes: DirectoryAttributes {
+                id: settings.var("ID_ATTRIBUTE").unwrap_or_else(|| "entryUUID".into()),
+                email: settings.var("EMAIL_ATTRIBUTE").unwrap_or_else(|| "mail".into()),
+                first_name: settings
+                    .var("FIRST_NAME_ATTRIBUTE")
+                    .unwrap_or_else(|| "givenName".into()),
+                last_name: settings.var("LAST_NAME_ATTRIBUTE").unwrap_or_else(|| "sn".into()),
+                username: settings.var("USERNAME_ATTRIBUTE").unwrap_or_else(|| "uid".into()),
+                roles: settings.var("ROLES_ATTRIBUTE"),
+                role_mapping: settings.mapping("ROLE_MAPPING")?,
+            },
+            accounts: AccountPolicy::from_env(&settings),
+            id: settings.id,
+        })
+    }
+
+    fn serves(&self, email: &str) -> bool {
+        email
+            .rsplit_once('@')
+            .is_some_and(|(_, domain)| self.domains.iter().any(|served| served == domain))
+    }
+
+    /// Looks `email` up and binds as the entry found with `password`.
+    async fn authenticate(
+        &self,
+        email: &str,
+        password: &str,
+    ) -> Result<DirectoryLogin<'_>, (StatusCode, String)> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(TIMEOUT)
+            .set_starttls(self.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(unreachable)?;
+        ldap3::drive!(conn);
+
+        let result = self.bind_as_user(&mut ldap, email, password).await;
+        let _ = ldap.unbind().await;
+        result
+    }
+
+    async fn bind_as_user(
+        &self,
+        ldap: &mut Ldap,
+        email: &str,
+        password: &str,
+    ) -> Result<DirectoryLogin<'_>, (StatusCode, String)> {
+        if let Some((dn, search_password)) = &self.search_bind {
+            ldap.with_timeout(TIMEOUT)
+                .simple_bind(dn, search_password)
+                .await
+                .and_then(|result| result.success())
+                .map_err(unreachable)?;
+        }
+
+        let local_part = email.split('@').next().unwrap_or_default();
+        let filter = self
+            .user_filter
+            .replace("{email}", &ldap_escape(email))
+            .replace("{username}", &ldap_escape(local_part));
+        let mut requested = vec![
+            self.attributes.id.as_str(),
+            self.attributes.email.as_str(),
+            self.attributes.first_name.as_str(),
+            self.attributes.last_name.as_str(),
+            self.attributes.username.as_str(),
+        ];
+        requested.extend(self.attributes.roles.as_deref());
+
+        let (entries, _) = ldap
+            .with_timeout(TIMEOUT)
+            .search(&self.base_dn, Scope::Subtree, &filter, requested)
+            .await
+            .and_then(|result| result.success())
+            .map_err(unreachable)?;
+        let mut entries = entries.into_iter();
+        let Some(entry) = entries.next() else {
+            return Ok(DirectoryLogin::NotFound);
+        };
+        if entries.next().is_some() {
+            return Err(unreachable(format!("more than one entry matches {}", email)));
+        }
+        let entry = SearchEntry::construct(entry);
+
+        // An empty password would make this an unauthenticated bind, which servers accept.
+        if password.is_empty() {
+            return Ok(DirectoryLogin::InvalidCredentials);
+        }
+        let bind = ldap
+            .with_timeout(TIMEOUT)
+            .simple_bind(&entry.dn, password)
+            .await
+            .map_err(unreachable)?;
+        match bind.rc {
+            0 => Ok(DirectoryLogin::Authenticated(self, self.map_identity(entry, email))),
+            INVALID_CREDENTIALS => Ok(DirectoryLogin::InvalidCredentials),
+            _ => Err(unreachable(bind)),
+        }
+    }
+
+    fn map_identity(&self, entry: SearchEntry, email: &str) -> UpstreamIdentity {
+        let mapping = &self.attributes;
+        let first = |name: &str| {
+            entry
+                .attrs
+                .get(name)
+                .and_then(|values| values.first())
+                .filter(|value| !value.is_empty())
+                .cloned()
+        };
+
+        // Active Directory's objectGUID is binary.
+        let subject = first(&mapping.id)
+            .or_else(|| {
+                entry
+                    .bin_attrs
+                    .get(&mapping.id)
+                    .and_then(|values| values.first())
+                    .map(hex::encode)
+            })
+            .unwrap_or_else(|| entry.dn.clone());
+
+        let roles = mapping.roles.as_deref().map(|attribute| {
+            let mut roles = self.accounts.default_roles.clone();
+            for group in entry.attrs.get(attribute).into_iter().flatten() {
+                let name = group_name(group);
+                for (mapped, mapped_roles) in &mapping.role_mapping {
+                    if mapped.eq_ignore_ascii_case(name) {
+                        roles.extend(mapped_roles.iter().cloned());
+                    }
+                }
+            }
+            roles.sort();
+            roles.dedup();
+            roles
+        });
+
+        UpstreamIdentity {
+            subject,
+            email: Some(first(&mapping.email).unwrap_or_else(|| email.to_string()).to_lowercase()),
+            // The directory manages the addresses of its domains.
+            email_verified: true,
+            given_name: first(&mapping.first_name),
+            family_name: first(&mapping.last_name),
+            preferred_username: first(&mapping.username),
+            locale: None,
+            roles,
+        }
+    }
+}
+
+impl LdapDirectories {
+    /// Reads the comma-separated directory ids in `LDAP_DIRECTORIES` and their settings.
+    ///
+    /// Directory users are linked by directory id like identities of other providers, so the
+    /// ids must not be used by a federated or SAML provider too.
+    pub fn from_env(
+        federated: &FederatedProviders,
+        saml: &SamlProviders,
+    ) -> Result<Self, String> {
+        let directories = env::var("LDAP_DIRECTORIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(LdapDirectory::from_env)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(directory) = directories.iter().find(|directory| {
+            federated.find(&directory.id).is_ok() || saml.find(&directory.id).is_ok()
+        }) {
+            return Err(format!(
+                "Directory id '{}' is also used by a federated or SAML provider",
+                directory.id
+            ));
+        }
+
+        Ok(Self { directories })
+    }
+
+    /// Whether a directory serves the domain of `email`.
+    pub fn serves(&self, email: &str) -> bool {
+        self.directories.iter().any(|directory| directory.serves(email))
+    }
+
+    /// Tries the directories serving the domain of `email` in order, until one has the user.
+    pub async fn authenticate(
+        &self,
+        email: &str,
+        password: &str,
+    ) -> Result<DirectoryLogin<'_>, (StatusCode, String)> {
+        for directory in self.directories.iter().filter(|directory| directory.serves(email)) {
+            match directory.authenticate(email, password).await? {
+                DirectoryLogin::NotFound => continue,
+                login => return Ok(login),
+            }
+        }
+        Ok(DirectoryLogin::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const DN: &str = "uid=jdoe,ou=people,dc=example,dc=com";
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn directory(url: String, roles: Option<&str>) -> LdapDirectory {
+        LdapDirectory {
+            id: "corp".into(),
+            url,
+            starttls: false,
+            search_bind: None,
+            base_dn: "dc=example,dc=com".into(),
+            user_filter: DEFAULT_USER_FILTER.into(),
+            domains: vec!["example.com".into()],
+            attributes: DirectoryAttributes {
+                id: "entryUUID".into(),
+                email: "mail".into(),
+                first_name: "givenName".into(),
+                last_name: "sn".into(),
+                username: "uid".into(),
+                roles: roles.map(String::from),
+                role_mapping: HashMap::from([
+                    ("Admins".to_string(), vec!["admin".to_string()]),
+                    ("Staff".to_string(), vec!["developer".to_string(), "viewer".to_string()]),
+                ]),
+            },
+            accounts: AccountPolicy {
+                default_roles: vec!["developer".into()],
+                auto_provision: true,
+                link_by_email: true,
+            },
+        }
+    }
+
+    fn entry(attrs: &[(&str, &[&str])]) -> SearchEntry {
+        SearchEntry {
+            dn: DN.into(),
+            attrs: attrs
+                .iter()
+                .map(|(name, values)| {
+                    (name.to_string(), values.iter().map(|value| value.to_string()).collect())
+                })
+                .collect(),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn maps_entry_attributes() {
+        let directory = directory(String::new(), Some("memberOf"));
+        let identity = directory.map_identity(
+            entry(&[
+                ("entryUUID", &["0a7d5f3e-64b3-4c5e-9d3b-2f1c0e8a9b10"]),
+                ("mail", &["Jane.Doe@Example.com"]),
+                ("givenName", &["Jane"]),
+                ("sn", &["Doe"]),
+                ("uid", &["jdoe"]),
+                (
+                    "memberOf",
+                    &[
+                        "CN=admins,OU=Groups,DC=example,DC=com",
+                        "cn=Staff,ou=Groups,dc=example,dc=com",
+                        "cn=Printers,ou=Groups,dc=example,dc=com",
+                    ],
+                ),
+            ]),
+            "jdoe@example.com",
+        );
+
+        assert_eq!(identity.subject, "0a7d5f3e-64b3-4c5e-9d3b-2f1c0e8a9b10");
+        assert_eq!(identity.email.as_deref(), Some("jane.doe@example.com"));
+        assert!(identity.email_verified);
+        assert_eq!(identity.given_name.as_deref(), Some("Jane"));
+        assert_eq!(identity.family_name.as_deref(), Some("Doe"));
+        assert_eq!(identity.preferred_username.as_deref(), Some("jdoe"));
+        assert_eq!(
+            identity.roles,
+            Some(vec!["admin".into(), "developer".into(), "viewer".into()])
+        );
+    }
+
+    #[test]
+    fn maps_entry_without_optional_attributes() {
+        let directory = directory(String::new(), None);
+        let identity = directory.map_identity(
+            entry(&[("givenName", &[""]), ("memberOf", &["cn=Admins,dc=example,dc=com"])]),
+            "JDoe@example.com",
+        );
+
+        assert_eq!(identity.subject, DN);
+        assert_eq!(identity.email.as_deref(), Some("jdoe@example.com"));
+        assert_eq!(identity.given_name, None);
+        assert_eq!(identity.roles, None);
+    }
+
+    #[test]
+    fn maps_binary_entry_id() {
+        let mut directory = directory(String::new(), Some("memberOf"));
+        directory.attributes.id = "objectGUID".into();
+        let mut entry = entry(&[]);
+        entry.bin_attrs.insert("objectGUID".into(), vec![vec![0x3f, 0x00, 0xa1, 0xff]]);
+
+        let identity = directory.map_identity(entry, "jdoe@example.com");
+
+        assert_eq!(identity.subject, "3f00a1ff");
+        assert_eq!(identity.roles, Some(vec!["developer".into()]));
+    }
+
+    #[test]
+    fn reads_group_names() {
+        assert_eq!(group_name("CN=Domain Admins,OU=Groups,DC=example,DC=com"), "Domain Admins");
+        assert_eq!(group_name("cn = Staff ,dc=example"), "Staff");
+        assert_eq!(group_name("engineering"), "engineering");
+    }
+
+    #[test]
+    fn serves_configured_domains() {
+        let directory = directory(String::new(), None);
+
+        assert!(directory.serves("jdoe@example.com"));
+        assert!(!directory.serves("jdoe@sub.example.com"));
+        assert!(!directory.serves("example.com"));
+    }
+
+    /// Encodes a BER value with a definite length.
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        match content.len() {
+            len @ 0..0x80 => encoded.push(len as u8),
+            len @ 0x80..0x100 => encoded.extend([0x81, len as u8]),
+            len => encoded.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        encoded.extend(content);
+        encoded
+    }
+
+    /// Splits a BER value off `bytes` into its tag, content and the bytes after it.
+    fn split_tlv(bytes: &[u8]) -> (u8, &[u8], &[u8]) {
+        let (len, header) = match bytes[1] {
+            len @ 0..0x80 => (len as usize, 2),
+            long => {
+                let octets = (long & 0x7f) as usize;
+                let len = bytes[2..2 + octets].iter().fold(0, |len, b| len << 8 | *b as usize);
+                (len, 2 + octets)
+            }
+        };
+        (bytes[0], &bytes[header..header + len], &bytes[header + len..])
+    }
+
+    /// An `LDAPResult` with the result code `code` and no DN or message.
+    fn ldap_result(tag: u8, code: u8) -> Vec<u8> {
+        let content = [tlv(0x0a, &[code]), tlv(0x04, b""), tlv(0x04, b"")].concat();
+        tlv(tag, &content)
+    }
+
+    /// A directory server with the entry `DN` and the password `PASSWORD`, if `has_entry`.
+    /// Returns its URL and the number of binds it received.
+    async fn fake_server(has_entry: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let binds = Arc::new(AtomicUsize::new(0));
+        let counter = binds.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Some(message) = read_message(&mut stream).await {
+                let (_, content, _) = split_tlv(&message);
+                let (_, _, op) = split_tlv(content);
+                let message_id = &content[..content.len() - op.len()];
+                let (op_tag, op_content, _) = split_tlv(op);
+
+                let responses = match op_tag {
+                    // BindRequest: version, name and the simple password.
+                    0x60 => {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let (_, _, rest) = split_tlv(op_content);
+                        let (_, _, rest) = split_tlv(rest);
+                        let (_, password, _) = split_tlv(rest);
+                        let code = if password == PASSWORD.as_bytes() { 0 } else { 49 };
+                        vec![ldap_result(0x61, code)]
+                    }
+                    // SearchRequest
+                    0x63 => {
+                        let mut responses = Vec::new();
+                        if has_entry {
+                            let attribute =
+                                [tlv(0x04, b"uid"), tlv(0x31, &tlv(0x04, b"jdoe"))].concat();
+                            let attributes = tlv(0x30, &tlv(0x30, &attribute));
+                            let entry = [tlv(0x04, DN.as_bytes()), attributes].concat();
+                            responses.push(tlv(0x64, &entry));
+                        }
+                        responses.push(ldap_result(0x65, 0));
+                        responses
+                    }
+                    // UnbindRequest
+                    _ => break,
+                };
+                for response in responses {
+                    let message = tlv(0x30, &[message_id, &response].concat());
+                    stream.write_all(&message).await.unwrap();
+                }
+            }
+        });
+        (url, binds)
+    }
+
+    async fn read_message(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut message = vec![0; 2];
+        stream.read_exact(&mut message).await.ok()?;
+        let len = match message[1] {
+            len @ 0..0x80 => len as usize,
+            long => {
+                let mut octets = vec![0; (long & 0x7f) as usize];
+                stream.read_exact(&mut octets).await.ok()?;
+                message.extend(&octets);
+                octets.iter().fold(0, |len, b| len << 8 | *b as usize)
+            }
+        };
+        let mut content = vec![0; len];
+        stream.read_exact(&mut content).await.ok()?;
+        message.extend(content);
+        Some(message)
+    }
+
+    #[tokio::test]
+    async fn binds_as_found_entry() {
+        let (url, binds) = fake_server(true).await;
+        let directory = directory(url, None);
+
+        let login = directory.authenticate("jdoe@example.com", PASSWORD).await.unwrap();
+
+        let DirectoryLogin::Authenticated(_, identity) = login else {
+            panic!("not authenticated");
+        };
+        assert_eq!(identity.subject, DN);
+        assert_eq!(identity.preferred_username.as_deref(), Some("jdoe"));
+        assert_eq!(binds.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_password() {
+        let (url, _) = fake_server(true).await;
+        let directory = directory(url, None);
+
+        let login = directory.authenticate("jdoe@example.com", "wrong").await.unwrap();
+
+        assert!(matches!(login, DirectoryLogin::InvalidCredentials));
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_password_without_binding() {
+        let (url, binds) = fake_server(true).await;
+        let directory = directory(url, None);
+
+        let login = directory.authenticate("jdoe@example.com", "").await.unwrap();
+
+        assert!(matches!(login, DirectoryLogin::InvalidCredentials));
+        assert_eq!(binds.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn reports_missing_entry() {
+        let (url, binds) = fake_server(false).await;
+        let directory = directory(url, None);
+
+        let login = directory.authenticate("jdoe@example.com", PASSWORD).await.unwrap();
+
+        assert!(matches!(login, DirectoryLogin::NotFound));
+        assert_eq!(binds.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,202 @@
+//! Brute-force protection for password logins.
+//!
+//! Failed attempts are recorded per account and per client IP. Each failure delays the
+//! response a little longer, an account is locked once `login_lockout_threshold` failures
+//! fall within `login_failure_window_minutes`, and a client IP with more than
+//! `login_ip_max_failures` failures in the window is refused outright.
+//!
+//! Resetting the count of an account detaches its failures instead of deleting them, so they
+//! keep counting against the client IPs they came from.
+
+use crate::config::AppConfig;
+use crate::models::{NewFailedLogin, User};
+use crate::services::audit::record_event;
+use crate::services::mailer::templates::{render, MailTemplate};
+use crate::services::mailer::Mailer;
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use chrono::Utc;
+use diesel::prelude::*;
+use std::net::IpAddr;
+use std::time::Duration;
+
+const BASE_DELAY_MS: u64 = 250;
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+fn window_start(config: &AppConfig) -> chrono::DateTime<Utc> {
+    Utc::now() - chrono::Duration::minutes(config.login_failure_window_minutes)
+}
+
+fn failures_from_ip(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    ip: IpAddr,
+) -> Result<i64, (StatusCode, String)> {
+    use crate::schema::failed_logins::dsl::*;
+
+    failed_logins
+        .filter(ip_address.eq(ip.to_string()))
+        .filter(attempted_at.gt(window_start(config)))
+        .count()
+        .get_result(conn)
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+/// Refuses clients that failed too many logins recently.
+pub fn ensure_ip_allowed(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    ip: IpAddr,
+) -> Result<(), (StatusCode, String)> {
+    if failures_from_ip(conn, config, ip)? >= config.login_ip_max_failures {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed logins, try again later".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether the account is temporarily locked.
+pub fn is_locked(user: &User) -> bool {
+    user.locked_until.is_some_and(|until| until > Utc::now())
+}
+
+/// Records a failed login and returns how long to delay the response.
+///
+/// Locks the account and notifies its owner once the threshold is reached.
+pub fn record_failed_login(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: Option<&User>,
+    ip: IpAddr,
+) -> Result<Duration, (StatusCode, String)> {
+    use crate::schema::failed_logins::dsl::*;
+
+    diesel::insert_into(failed_logins)
+        .values(&NewFailedLogin {
+            user_id: user.map(|u| u.id),
+            ip_address: ip.to_string(),
+        })
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    let mut failures = failures_from_ip(conn, config, ip)?;
+
+    if let Some(user) = user {
+        let account_failures = failed_logins
+            .filter(user_id.eq(user.id))
+            .filter(attempted_at.gt(window_start(config)))
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(|e| internal_error("DB query error", e))?;
+
+        if account_failures >= config.login_lockout_threshold {
+            lock_account(conn, mailer, config, user)?;
+        }
+
+        failures = failures.max(account_failures);
+    }
+
+    Ok(delay_for(failures))
+}
+
+fn delay_for(failures: i64) -> Duration {
+    let exponent = failures.clamp(1, 16) as u32 - 1;
+    Duration::from_millis(BASE_DELAY_MS << exponent).min(MAX_DELAY)
+}
+
+fn lock_account(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: &User,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::users::dsl::{locked_until, users};
+
+    let until = Utc::now() + chrono::Duration::minutes(config.login_lockout_minutes);
+
+    conn.transaction(|conn| {
+        diesel::update(users.find(user.id))
+            .set(locked_until.eq(until))
+            .execute(conn)?;
+
+        // Start counting afresh once the lock expires.
+        detach_failed_logins(conn, user.id)
+    })
+    .map_err(|e| internal_error("DB update error", e))?;
+
+    record_event(
+        conn,
+        Some(user.id),
+        "account_locked",
+        Some(format!("locked until {}", until.to_rfc3339())),
+    )?;
+
+    let message = render(
+        config,
+        MailTemplate::AccountLocked,
+        &user.locale,
+        &user.email,
+        &[
+            ("first_name", user.greeting_name()),
+            ("locked_minutes", &config.login_lockout_minutes.to_string()),
+        ],
+    );
+    if let Err(e) = mailer.send(&message) {
+        tracing::error!("Lockout notification for user {} failed: {}", user.id, e);
+    }
+
+    Ok(())
+}
+
+/// Forgets the failed attempts of an account after a successful login.
+///
+/// The attempts still count against the client IPs they came from.
+pub fn clear_failed_logins(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+) -> Result<(), (StatusCode, String)> {
+    detach_failed_logins(conn, target_user_id)
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(())
+}
+
+fn detach_failed_logins(conn: &mut PgConnection, target_user_id: i32) -> QueryResult<usize> {
+    use crate::schema::failed_logins::dsl::*;
+
+    diesel::update(failed_logins.filter(user_id.eq(target_user_id)))
+        .set(user_id.eq(None::<i32>))
+        .execute(conn)
+}
+
+/// Lifts a lock before it expires. Returns `false` if no such user exists.
+pub fn unlock_account(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+    unlocked_by: &str,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::users::dsl::{locked_until, users};
+
+    let updated = diesel::update(users.find(target_user_id))
+        .set(locked_until.eq(None::<chrono::DateTime<Utc>>))
+        .execute(conn)
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    if updated == 0 {
+        return Ok(false);
+    }
+
+    clear_failed_logins(conn, target_user_id)?;
+    record_event(
+        conn,
+        Some(target_user_id),
+        "account_unlocked",
+        Some(format!("unlocked by {}", unlocked_by)),
+    )?;
+
+    Ok(true)
+}
@@ -0,0 +1,107 @@
+use crate::config::AppConfig;
+use crate::models::{NewMagicLinkToken, User};
+use crate::services::mailer::templates::{render, MailTemplate};
+use crate::services::mailer::Mailer;
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+
+/// Creates a single-use login token for `user` and emails the sign-in link.
+///
+/// Nothing is sent once `magic_link_max_per_hour` links were sent to the account within
+/// the last hour.
+pub fn send_magic_link(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: &User,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::magic_link_tokens::dsl::*;
+
+    let sent_last_hour = magic_link_tokens
+        .filter(user_id.eq(user.id))
+        .filter(created_at.gt(Utc::now() - Duration::hours(1)))
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    if sent_last_hour >= config.magic_link_max_per_hour {
+        return Ok(());
+    }
+
+    let token = generate_token();
+
+    let login_token = NewMagicLinkToken {
+        user_id: user.id,
+        token_hash: hash_token(&token),
+        expires_at: Utc::now() + Duration::minutes(config.magic_link_ttl_minutes),
+    };
+
+    diesel::insert_into(magic_link_tokens)
+        .values(&login_token)
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    let link = format!("{}/magic-link?token={}", config.public_url, token);
+    let expires_in = config.magic_link_ttl_minutes.to_string();
+
+    let message = render(
+        config,
+        MailTemplate::MagicLink,
+        &user.locale,
+        &user.email,
+        &[
+            ("first_name", user.greeting_name()),
+            ("link", &link),
+            ("expires_minutes", &expires_in),
+        ],
+    );
+
+    mailer
+        .send(&message)
+        .map_err(|e| internal_error("Failed to send email", e))
+}
+
+/// Consumes a login token and returns the user it was issued for.
+///
+/// Every other outstanding link of the same user stops working as well.
+pub fn consume_magic_link(
+    conn: &mut PgConnection,
+    token: &str,
+) -> Result<User, (StatusCode, String)> {
+    use crate::schema::magic_link_tokens::dsl::*;
+    use crate::schema::users::dsl::users;
+
+    let user = conn
+        .transaction(|conn| {
+            let target_user_id = diesel::update(
+                magic_link_tokens
+                    .filter(token_hash.eq(hash_token(token)))
+                    .filter(used_at.is_null())
+                    .filter(expires_at.gt(Utc::now())),
+            )
+            .set(used_at.eq(Utc::now()))
+            .returning(user_id)
+            .get_result::<i32>(conn)
+            .optional()?;
+
+            let Some(target_user_id) = target_user_id else {
+                return Ok(None);
+            };
+
+            diesel::update(
+                magic_link_tokens
+                    .filter(user_id.eq(target_user_id))
+                    .filter(used_at.is_null()),
+            )
+            .set(used_at.eq(Utc::now()))
+            .execute(conn)?;
+
+            users.find(target_user_id).first::<User>(conn).map(Some)
+        })
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    user.ok_or((StatusCode::BAD_REQUEST, "Invalid or expired token".into()))
+}
@@ -0,0 +1,74 @@
+use super::{build_message, EmailMessage, MailError, Mailer};
+use lettre::message::Mailbox;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// Writes every message as an `.eml` file into a maildir, for local development.
+///
+/// Messages are written to `tmp/` first and then moved into `new/`, so mail clients
+/// pointed at the directory never see a partially written file.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: Mailbox,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>, from: Mailbox) -> Result<Self, MailError> {
+        let dir = dir.into();
+
+        for sub_dir in ["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub_dir))
+                .map_err(|e| MailError(format!("Failed to create maildir: {}", e)))?;
+        }
+
+        Ok(Self { dir, from })
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, message: &EmailMessage) -> Result<(), MailError> {
+        let email = build_message(&self.from, message)?;
+        let file_name = format!("{}.eml", Uuid::new_v4());
+
+        let tmp_path = self.dir.join("tmp").join(&file_name);
+        fs::write(&tmp_path, email.formatted())
+            .map_err(|e| MailError(format!("Failed to write message: {}", e)))?;
+
+        fs::rename(&tmp_path, self.dir.join("new").join(&file_name))
+            .map_err(|e| MailError(format!("Failed to deliver message: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_written_message_into_new() {
+        let dir = std::env::temp_dir().join(format!("user_auth-maildir-{}", Uuid::new_v4()));
+        let mailer =
+            FileMailer::new(&dir, "user_auth <no-reply@localhost>".parse().unwrap()).unwrap();
+
+        mailer
+            .send(&EmailMessage {
+                to: "jane@example.com".into(),
+                subject: "Hello".into(),
+                text_body: "Plain body".into(),
+                html_body: None,
+            })
+            .unwrap();
+
+        let delivered: Vec<_> = fs::read_dir(dir.join("new")).unwrap().collect();
+        assert_eq!(delivered.len(), 1);
+        let path = delivered[0].as_ref().unwrap().path();
+        assert_eq!(path.extension().unwrap(), "eml");
+        let contents = fs::read_to_string(path).unwrap();
+        assert!(contents.contains("To: jane@example.com"));
+        assert!(contents.contains("Subject: Hello"));
+        assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(dir.join("cur")).unwrap().count(), 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
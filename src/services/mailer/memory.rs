@@ -0,0 +1,23 @@
+use super::{EmailMessage, MailError, Mailer};
+use std::sync::Mutex;
+
+/// Keeps every message in memory so tests can assert on what would have been sent.
+#[derive(Default)]
+pub struct MemoryMailer {
+    outbox: Mutex<Vec<EmailMessage>>,
+}
+
+impl MemoryMailer {
+    /// Removes and returns every captured message.
+    #[cfg(test)]
+    pub fn take(&self) -> Vec<EmailMessage> {
+        std::mem::take(&mut *self.outbox.lock().unwrap())
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, message: &EmailMessage) -> Result<(), MailError> {
+        self.outbox.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
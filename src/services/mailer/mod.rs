@@ -0,0 +1,171 @@
+mod file_sink;
+mod memory;
+mod smtp;
+pub mod templates;
+
+pub use file_sink::FileMailer;
+pub use memory::MemoryMailer;
+pub use smtp::SmtpMailer;
+
+use lettre::message::{header::ContentType, Mailbox, MultiPart, SinglePart};
+use lettre::Message;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct MailError(pub String);
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Outbound email delivery.
+pub trait Mailer: Send + Sync {
+    fn send(&self, message: &EmailMessage) -> Result<(), MailError>;
+}
+
+/// Writes messages to the log instead of sending them.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, message: &EmailMessage) -> Result<(), MailError> {
+        tracing::info!(
+            to = %message.to,
+            subject = %message.subject,
+            "email not sent (log mailer):\n{}",
+            message.text_body
+        );
+        Ok(())
+    }
+}
+
+/// Builds the mailer selected by `MAIL_TRANSPORT`.
+///
+/// - `log` (default): messages are only logged.
+/// - `smtp`: delivered through `SMTP_HOST`, see `SmtpMailer::from_env`.
+/// - `file`: written as `.eml` files into the maildir at `MAIL_FILE_DIR`.
+/// - `memory`: kept in memory, for tests.
+pub fn mailer_from_env() -> Result<Arc<dyn Mailer>, MailError> {
+    let transport = env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "log".into());
+
+    let mailer: Arc<dyn Mailer> = match transport.as_str() {
+        "log" => Arc::new(LogMailer),
+        "smtp" => Arc::new(SmtpMailer::from_env()?),
+        "file" => Arc::new(FileMailer::new(
+            env::var("MAIL_FILE_DIR").unwrap_or_else(|_| "mail".into()),
+            sender_from_env()?,
+        )?),
+        "memory" => Arc::new(MemoryMailer::default()),
+        other => return Err(MailError(format!("Unknown MAIL_TRANSPORT '{}'", other))),
+    };
+
+    Ok(mailer)
+}
+
+fn sender_from_env() -> Result<Mailbox, MailError> {
+    env::var("MAIL_FROM")
+        .unwrap_or_else(|_| "user_auth <no-reply@localhost>".into())
+        .parse::<Mailbox>()
+        .map_err(|e| MailError(format!("Invalid MAIL_FROM: {}", e)))
+}
+
+/// Converts an `EmailMessage` into a MIME message, using `multipart/alternative` when an
+/// HTML body is present.
+fn build_message(from: &Mailbox, message: &EmailMessage) -> Result<Message, MailError> {
+    let to = message
+        .to
+        .parse::<Mailbox>()
+        .map_err(|e| MailError(format!("Invalid recipient '{}': {}", message.to, e)))?;
+
+    let builder = Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(message.subject.clone());
+
+    let built = match &message.html_body {
+        Some(html) => builder.multipart(MultiPart::alternative_plain_html(
+            message.text_body.clone(),
+            html.clone(),
+        )),
+        None => builder.singlepart(
+            SinglePart::builder()
+                .header(ContentType::TEXT_PLAIN)
+                .body(message.text_body.clone()),
+        ),
+    };
+
+    built.map_err(|e| MailError(format!("Failed to build message: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(html_body: Option<&str>) -> EmailMessage {
+        EmailMessage {
+            to: "jane@example.com".into(),
+            subject: "Hello".into(),
+            text_body: "Plain body".into(),
+            html_body: html_body.map(str::to_string),
+        }
+    }
+
+    fn formatted(message: &EmailMessage) -> String {
+        let from = "user_auth <no-reply@localhost>".parse().unwrap();
+        String::from_utf8(build_message(&from, message).unwrap().formatted()).unwrap()
+    }
+
+    #[test]
+    fn sends_text_and_html_as_alternatives() {
+        let formatted = formatted(&message(Some("<p>HTML body</p>")));
+
+        assert!(formatted.contains("To: jane@example.com"));
+        assert!(formatted.contains("Subject: Hello"));
+        assert!(formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("Plain body"));
+        assert!(formatted.contains("<p>HTML body</p>"));
+    }
+
+    #[test]
+    fn sends_plain_text_without_html() {
+        let formatted = formatted(&message(None));
+
+        assert!(!formatted.contains("multipart"));
+        assert!(formatted.contains("Content-Type: text/plain"));
+        assert!(formatted.contains("Plain body"));
+    }
+
+    #[test]
+    fn rejects_invalid_recipients() {
+        let from = "user_auth <no-reply@localhost>".parse().unwrap();
+        let message = EmailMessage {
+            to: "not an address".into(),
+            ..message(None)
+        };
+
+        assert!(build_message(&from, &message).is_err());
+    }
+
+    #[test]
+    fn memory_mailer_captures_messages() {
+        let mailer = MemoryMailer::default();
+        mailer.send(&message(None)).unwrap();
+        mailer.send(&message(Some("<p>HTML body</p>"))).unwrap();
+
+        let sent = mailer.take();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].html_body.as_deref(), Some("<p>HTML body</p>"));
+        assert!(mailer.take().is_empty());
+    }
+}
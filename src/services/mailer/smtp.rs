@@ -0,0 +1,152 @@
+use super::{build_message, sender_from_env, EmailMessage, MailError, Mailer};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::env;
+use tokio::runtime::Handle;
+
+type Transport = AsyncSmtpTransport<Tokio1Executor>;
+
+/// Delivers messages through an SMTP relay.
+///
+/// Delivery runs in the background on the Tokio runtime, so request handlers do not wait for
+/// the relay. Failed deliveries are logged.
+pub struct SmtpMailer {
+    transport: Transport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Reads the relay settings from the environment.
+    ///
+    /// - `SMTP_HOST` (required) and `SMTP_PORT`.
+    /// - `SMTP_TLS`: `starttls` (default), `tls` or `none`. `none` talks plain SMTP and is
+    ///   meant for local stand-ins such as MailHog.
+    /// - `SMTP_USERNAME` / `SMTP_PASSWORD` when the relay requires authentication.
+    /// - `MAIL_FROM` as the sender mailbox.
+    pub fn from_env() -> Result<Self, MailError> {
+        let host = env::var("SMTP_HOST").map_err(|_| MailError("SMTP_HOST must be set".into()))?;
+        let tls = env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".into());
+
+        let mut builder = match tls.as_str() {
+            "starttls" => Transport::starttls_relay(&host)
+                .map_err(|e| MailError(format!("Invalid SMTP relay: {}", e)))?,
+            "tls" => Transport::relay(&host)
+                .map_err(|e| MailError(format!("Invalid SMTP relay: {}", e)))?,
+            "none" => Transport::builder_dangerous(&host),
+            other => return Err(MailError(format!("Unknown SMTP_TLS '{}'", other))),
+        };
+
+        if let Some(port) = env::var("SMTP_PORT").ok().and_then(|p| p.parse::<u16>().ok()) {
+            builder = builder.port(port);
+        }
+
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: sender_from_env()?,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: &EmailMessage) -> Result<(), MailError> {
+        let email = build_message(&self.from, message)?;
+        let runtime = Handle::try_current()
+            .map_err(|e| MailError(format!("SMTP delivery needs a Tokio runtime: {}", e)))?;
+
+        let transport = self.transport.clone();
+        let to = message.to.clone();
+        runtime.spawn(async move {
+            if let Err(e) = transport.send(email).await {
+                tracing::error!("SMTP delivery to {} failed: {}", to, e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    use tokio::time::{timeout, Duration};
+
+    /// An SMTP server that accepts one message and reports its envelope and DATA.
+    async fn fake_relay() -> (u16, oneshot::Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (received, receiver) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut transcript = Vec::new();
+
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = match line.split(' ').next().unwrap_or_default() {
+                    "EHLO" | "HELO" => b"250 localhost\r\n",
+                    "DATA" => {
+                        writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+                        let mut data = Vec::new();
+                        while let Some(line) = lines.next_line().await.unwrap() {
+                            if line == "." {
+                                break;
+                            }
+                            data.push(line);
+                        }
+                        transcript.push(data.join("\r\n"));
+                        writer.write_all(b"250 Queued\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => {
+                        transcript.push(line);
+                        b"250 OK\r\n"
+                    }
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+            let _ = received.send(transcript);
+        });
+
+        (port, receiver)
+    }
+
+    #[tokio::test]
+    async fn delivers_to_relay() {
+        let (port, received) = fake_relay().await;
+        // SAFETY: no other test reads or writes the SMTP_* variables.
+        unsafe {
+            env::set_var("SMTP_HOST", "127.0.0.1");
+            env::set_var("SMTP_PORT", port.to_string());
+            env::set_var("SMTP_TLS", "none");
+        }
+        let mailer = SmtpMailer::from_env().unwrap();
+
+        mailer
+            .send(&EmailMessage {
+                to: "jane@example.com".into(),
+                subject: "Verify your email address".into(),
+                text_body: "Open the link to verify.".into(),
+                html_body: None,
+            })
+            .unwrap();
+
+        let transcript = timeout(Duration::from_secs(10), received).await.unwrap().unwrap();
+        assert_eq!(transcript[0], "MAIL FROM:<no-reply@localhost>");
+        assert_eq!(transcript[1], "RCPT TO:<jane@example.com>");
+        let data = &transcript[2];
+        assert!(data.contains("From: user_auth <no-reply@localhost>"));
+        assert!(data.contains("To: jane@example.com"));
+        assert!(data.contains("Subject: Verify your email address"));
+        assert!(data.contains("Open the link to verify."));
+    }
+}
@@ -0,0 +1,303 @@
+use super::EmailMessage;
+use crate::config::AppConfig;
+use std::fs;
+
+const DEFAULT_LOCALE: &str = "en";
+const MAX_LOCALE_LENGTH: usize = 35;
+
+/// Emails sent by the application.
+#[derive(Clone, Copy)]
+pub enum MailTemplate {
+    VerifyEmail,
+    PasswordReset,
+    MagicLink,
+    RecoveryCodeUsed,
+    AccountLocked,
+    EmailChange,
+    EmailChanged,
+}
+
+impl MailTemplate {
+    fn name(self) -> &'static str {
+        match self {
+            MailTemplate::VerifyEmail => "verify_email",
+            MailTemplate::PasswordReset => "password_reset",
+            MailTemplate::MagicLink => "magic_link",
+            MailTemplate::RecoveryCodeUsed => "recovery_code_used",
+            MailTemplate::AccountLocked => "account_locked",
+            MailTemplate::EmailChange => "email_change",
+            MailTemplate::EmailChanged => "email_changed",
+        }
+    }
+}
+
+struct TemplateParts {
+    subject: String,
+    text: String,
+    html: Option<String>,
+}
+
+/// Renders `template` for `locale` and addresses it to `to`.
+///
+/// Templates are looked up for the full locale (`da-DK`), then its language (`da`), then
+/// English. For each candidate a template in `MAIL_TEMPLATE_DIR/<locale>/<name>.{subject,txt,html}`
+/// takes precedence over the built-in one. `{{key}}` placeholders are replaced with `vars`;
+/// values are HTML-escaped in the HTML body.
+pub fn render(
+    config: &AppConfig,
+    template: MailTemplate,
+    locale: &str,
+    to: &str,
+    vars: &[(&str, &str)],
+) -> EmailMessage {
+    let parts = locale_candidates(locale)
+        .into_iter()
+        .find_map(|candidate| {
+            load_from_dir(config, template, &candidate).or_else(|| built_in(template, &candidate))
+        })
+        .or_else(|| built_in(template, DEFAULT_LOCALE))
+        .expect("built-in English templates exist for every MailTemplate");
+
+    EmailMessage {
+        to: to.to_string(),
+        subject: substitute(&parts.subject, vars, false),
+        text_body: substitute(&parts.text, vars, false),
+        html_body: parts.html.map(|html| substitute(&html, vars, true)),
+    }
+}
+
+/// Whether `tag` can be stored as the locale of a user: a language tag such as `da-DK`.
+pub fn valid_locale(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= MAX_LOCALE_LENGTH
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn locale_candidates(locale: &str) -> Vec<String> {
+    let locale = locale.trim().to_lowercase().replace('_', "-");
+    let mut candidates = Vec::new();
+
+    if !locale.is_empty() {
+        candidates.push(locale.clone());
+    }
+    if let Some((language, _)) = locale.split_once('-') {
+        candidates.push(language.to_string());
+    }
+    candidates.push(DEFAULT_LOCALE.to_string());
+
+    candidates
+}
+
+fn load_from_dir(config: &AppConfig, template: MailTemplate, locale: &str) -> Option<TemplateParts> {
+    let dir = config.mail_template_dir.as_ref()?.join(locale);
+    let name = template.name();
+
+    let subject = fs::read_to_string(dir.join(format!("{}.subject", name))).ok()?;
+    let text = fs::read_to_string(dir.join(format!("{}.txt", name))).ok()?;
+    let html = fs::read_to_string(dir.join(format!("{}.html", name))).ok();
+
+    Some(TemplateParts {
+        subject: subject.trim().to_string(),
+        text,
+        html,
+    })
+}
+
+fn substitute(template: &str, vars: &[(&str, &str)], escape: bool) -> String {
+    vars.iter().fold(template.to_string(), |rendered, (key, value)| {
+        let value = if escape {
+            escape_html(value)
+        } else {
+            value.to_string()
+        };
+        rendered.replace(&format!("{{{{{}}}}}", key), &value)
+    })
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn built_in(template: MailTemplate, locale: &str) -> Option<TemplateParts> {
+    let (subject, text, html) = match (template, locale) {
+        (MailTemplate::VerifyEmail, "en") => (
+            "Verify your email address",
+            "Hi {{first_name}},\n\nPlease confirm your email address by opening the link below. It expires in {{expires_hours}} hours.\n\n{{link}}\n",
+            "<p>Hi {{first_name}},</p><p>Please confirm your email address. The link expires in {{expires_hours}} hours.</p><p><a href=\"{{link}}\">Verify email address</a></p>",
+        ),
+        (MailTemplate::VerifyEmail, "da") => (
+            "Bekræft din e-mailadresse",
+            "Hej {{first_name}},\n\nBekræft din e-mailadresse ved at åbne linket nedenfor. Det udløber om {{expires_hours}} timer.\n\n{{link}}\n",
+            "<p>Hej {{first_name}},</p><p>Bekræft din e-mailadresse. Linket udløber om {{expires_hours}} timer.</p><p><a href=\"{{link}}\">Bekræft e-mailadresse</a></p>",
+        ),
+        (MailTemplate::PasswordReset, "en") => (
+            "Reset your password",
+            "Hi {{first_name}},\n\nUse the link below to choose a new password. It expires in {{expires_minutes}} minutes.\n\n{{link}}\n\nIf you did not ask for this, you can ignore this email.\n",
+            "<p>Hi {{first_name}},</p><p>Use the link below to choose a new password. It expires in {{expires_minutes}} minutes.</p><p><a href=\"{{link}}\">Reset password</a></p><p>If you did not ask for this, you can ignore this email.</p>",
+        ),
+        (MailTemplate::PasswordReset, "da") => (
+            "Nulstil din adgangskode",
+            "Hej {{first_name}},\n\nBrug linket nedenfor til at vælge en ny adgangskode. Det udløber om {{expires_minutes}} minutter.\n\n{{link}}\n\nHvis du ikke har bedt om dette, kan du se bort fra denne e-mail.\n",
+            "<p>Hej {{first_name}},</p><p>Brug linket nedenfor til at vælge en ny adgangskode. Det udløber om {{expires_minutes}} minutter.</p><p><a href=\"{{link}}\">Nulstil adgangskode</a></p><p>Hvis du ikke har bedt om dette, kan du se bort fra denne e-mail.</p>",
+        ),
+        (MailTemplate::MagicLink, "en") => (
+            "Your sign-in link",
+            "Hi {{first_name}},\n\nUse the link below to sign in. It can be used once and expires in {{expires_minutes}} minutes.\n\n{{link}}\n\nIf you did not ask for this, you can ignore this email.\n",
+            "<p>Hi {{first_name}},</p><p>Use the link below to sign in. It can be used once and expires in {{expires_minutes}} minutes.</p><p><a href=\"{{link}}\">Sign in</a></p><p>If you did not ask for this, you can ignore this email.</p>",
+        ),
+        (MailTemplate::MagicLink, "da") => (
+            "Dit login-link",
+            "Hej {{first_name}},\n\nBrug linket nedenfor til at logge ind. Det kan bruges én gang og udløber om {{expires_minutes}} minutter.\n\n{{link}}\n\nHvis du ikke har bedt om dette, kan du se bort fra denne e-mail.\n",
+            "<p>Hej {{first_name}},</p><p>Brug linket nedenfor til at logge ind. Det kan bruges én gang og udløber om {{expires_minutes}} minutter.</p><p><a href=\"{{link}}\">Log ind</a></p><p>Hvis du ikke har bedt om dette, kan du se bort fra denne e-mail.</p>",
+        ),
+        (MailTemplate::RecoveryCodeUsed, "en") => (
+            "A recovery code was used to sign in",
+            "Hi {{first_name}},\n\nA recovery code was just used to sign in to your account. You have {{remaining}} recovery codes left.\n\nIf this was not you, reset your password and contact an administrator.\n",
+            "<p>Hi {{first_name}},</p><p>A recovery code was just used to sign in to your account. You have {{remaining}} recovery codes left.</p><p>If this was not you, reset your password and contact an administrator.</p>",
+        ),
+        (MailTemplate::RecoveryCodeUsed, "da") => (
+            "En gendannelseskode blev brugt til at logge ind",
+            "Hej {{first_name}},\n\nEn gendannelseskode er netop blevet brugt til at logge ind på din konto. Du har {{remaining}} gendannelseskoder tilbage.\n\nHvis det ikke var dig, så nulstil din adgangskode og kontakt en administrator.\n",
+            "<p>Hej {{first_name}},</p><p>En gendannelseskode er netop blevet brugt til at logge ind på din konto. Du har {{remaining}} gendannelseskoder tilbage.</p><p>Hvis det ikke var dig, så nulstil din adgangskode og kontakt en administrator.</p>",
+        ),
+        (MailTemplate::AccountLocked, "en") => (
+            "Your account was locked",
+            "Hi {{first_name}},\n\nYour account was locked for {{locked_minutes}} minutes after too many failed sign-in attempts.\n\nIf this was not you, reset your password once the lock has expired.\n",
+            "<p>Hi {{first_name}},</p><p>Your account was locked for {{locked_minutes}} minutes after too many failed sign-in attempts.</p><p>If this was not you, reset your password once the lock has expired.</p>",
+        ),
+        (MailTemplate::AccountLocked, "da") => (
+            "Din konto er blevet låst",
+            "Hej {{first_name}},\n\nDin konto er blevet låst i {{locked_minutes}} minutter efter for mange mislykkede loginforsøg.\n\nHvis det ikke var dig, så nulstil din adgangskode, når låsen er udløbet.\n",
+            "<p>Hej {{first_name}},</p><p>Din konto er blevet låst i {{locked_minutes}} minutter efter for mange mislykkede loginforsøg.</p><p>Hvis det ikke var dig, så nulstil din adgangskode, når låsen er udløbet.</p>",
+        ),
+        (MailTemplate::EmailChange, "en") => (
+            "Confirm your new email address",
+            "Hi {{first_name}},\n\nPlease confirm that you want to use this address for your account by opening the link below. It expires in {{expires_hours}} hours.\n\n{{link}}\n\nIf you did not ask for this, you can ignore this email.\n",
+            "<p>Hi {{first_name}},</p><p>Please confirm that you want to use this address for your account. The link expires in {{expires_hours}} hours.</p><p><a href=\"{{link}}\">Confirm email address</a></p><p>If you did not ask for this, you can ignore this email.</p>",
+        ),
+        (MailTemplate::EmailChange, "da") => (
+            "Bekræft din nye e-mailadresse",
+            "Hej {{first_name}},\n\nBekræft, at du vil bruge denne adresse til din konto, ved at åbne linket nedenfor. Det udløber om {{expires_hours}} timer.\n\n{{link}}\n\nHvis du ikke har bedt om dette, kan du se bort fra denne e-mail.\n",
+            "<p>Hej {{first_name}},</p><p>Bekræft, at du vil bruge denne adresse til din konto. Linket udløber om {{expires_hours}} timer.</p><p><a href=\"{{link}}\">Bekræft e-mailadresse</a></p><p>Hvis du ikke har bedt om dette, kan du se bort fra denne e-mail.</p>",
+        ),
+        (MailTemplate::EmailChanged, "en") => (
+            "Your email address was changed",
+            "Hi {{first_name}},\n\nThe email address of your account was changed to {{new_email}}. Emails will no longer be sent to this address.\n\nIf this was not you, contact an administrator.\n",
+            "<p>Hi {{first_name}},</p><p>The email address of your account was changed to {{new_email}}. Emails will no longer be sent to this address.</p><p>If this was not you, contact an administrator.</p>",
+        ),
+        (MailTemplate::EmailChanged, "da") => (
+            "Din e-mailadresse er blevet ændret",
+            "Hej {{first_name}},\n\nE-mailadressen på din konto er blevet ændret til {{new_email}}. Der sendes ikke længere e-mails til denne adresse.\n\nHvis det ikke var dig, så kontakt en administrator.\n",
+            "<p>Hej {{first_name}},</p><p>E-mailadressen på din konto er blevet ændret til {{new_email}}. Der sendes ikke længere e-mails til denne adresse.</p><p>Hvis det ikke var dig, så kontakt en administrator.</p>",
+        ),
+        _ => return None,
+    };
+
+    Some(TemplateParts {
+        subject: subject.to_string(),
+        text: text.to_string(),
+        html: Some(html.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn config(mail_template_dir: Option<PathBuf>) -> AppConfig {
+        AppConfig {
+            mail_template_dir,
+            ..AppConfig::from_env()
+        }
+    }
+
+    fn verify_email(config: &AppConfig, locale: &str) -> EmailMessage {
+        render(
+            config,
+            MailTemplate::VerifyEmail,
+            locale,
+            "jane@example.com",
+            &[
+                ("first_name", "Jane"),
+                ("link", "https://example.com/verify?token=a&b"),
+                ("expires_hours", "24"),
+            ],
+        )
+    }
+
+    #[test]
+    fn renders_text_and_html_bodies() {
+        let message = verify_email(&config(None), "en");
+
+        assert_eq!(message.to, "jane@example.com");
+        assert_eq!(message.subject, "Verify your email address");
+        assert!(message.text_body.starts_with("Hi Jane,"));
+        assert!(message.text_body.contains("https://example.com/verify?token=a&b"));
+        assert!(message.text_body.contains("24 hours"));
+
+        let html = message.html_body.unwrap();
+        assert!(html.contains("<p>Hi Jane,</p>"));
+        assert!(html.contains("href=\"https://example.com/verify?token=a&amp;b\""));
+        assert!(!html.contains("{{"));
+    }
+
+    #[test]
+    fn escapes_values_only_in_html() {
+        let message = render(
+            &config(None),
+            MailTemplate::EmailChanged,
+            "en",
+            "jane@example.com",
+            &[("first_name", "<b>Jane</b>"), ("new_email", "new@example.com")],
+        );
+
+        assert!(message.text_body.contains("<b>Jane</b>"));
+        assert!(message.html_body.unwrap().contains("&lt;b&gt;Jane&lt;/b&gt;"));
+    }
+
+    #[test]
+    fn falls_back_to_language_then_english() {
+        let config = config(None);
+
+        assert_eq!(verify_email(&config, "da").subject, "Bekræft din e-mailadresse");
+        assert_eq!(verify_email(&config, "da-DK").subject, "Bekræft din e-mailadresse");
+        assert_eq!(verify_email(&config, "DA_dk").subject, "Bekræft din e-mailadresse");
+        assert_eq!(verify_email(&config, "fr-FR").subject, "Verify your email address");
+        assert_eq!(verify_email(&config, "").subject, "Verify your email address");
+    }
+
+    #[test]
+    fn template_directory_overrides_built_in_templates() {
+        let dir = std::env::temp_dir().join(format!("mail-templates-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("da")).unwrap();
+        fs::write(dir.join("da/verify_email.subject"), "Velkommen {{first_name}}\n").unwrap();
+        fs::write(dir.join("da/verify_email.txt"), "Klik: {{link}}").unwrap();
+
+        let config = config(Some(dir.clone()));
+        let message = verify_email(&config, "da-DK");
+        let fallback = verify_email(&config, "en");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(message.subject, "Velkommen Jane");
+        assert_eq!(message.text_body, "Klik: https://example.com/verify?token=a&b");
+        assert!(message.html_body.is_none());
+        assert_eq!(fallback.subject, "Verify your email address");
+    }
+
+    #[test]
+    fn accepts_language_tags_as_locales() {
+        assert!(valid_locale("en"));
+        assert!(valid_locale("da-DK"));
+        assert!(valid_locale("zh_Hant_TW"));
+        assert!(!valid_locale(""));
+        assert!(!valid_locale("en US"));
+        assert!(!valid_locale("../en"));
+        assert!(!valid_locale(&"a".repeat(MAX_LOCALE_LENGTH + 1)));
+    }
+}
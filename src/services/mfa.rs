@@ -0,0 +1,381 @@
+use crate::models::{LoginResponse, NewRecoveryCode, User};
+use crate::services::jwt::{Claims, TokenPurpose};
+use crate::services::sessions::start_session;
+use crate::utils::error::internal_error;
+use crate::utils::hash::{hash_password, verify_password};
+use axum::http::StatusCode;
+use chrono::Utc;
+use diesel::prelude::*;
+use rand::Rng;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+/// Codes from this many steps before or after the current one are accepted.
+const TOTP_SKEW_STEPS: u64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Codes that can be tried with one MFA challenge token.
+const MAX_MFA_ATTEMPTS: i32 = 5;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+fn build_totp(
+    secret: &str,
+    issuer: &str,
+    account_name: &str,
+) -> Result<TOTP, (StatusCode, String)> {
+    let secret_bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| internal_error("Invalid TOTP secret", format!("{:?}", e)))?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW_STEPS as u8,
+        TOTP_STEP_SECONDS,
+        secret_bytes,
+        Some(issuer.to_string()),
+        account_name.to_string(),
+    )
+    .map_err(|e| internal_error("Invalid TOTP parameters", e))
+}
+
+/// Returns the time step `code` is valid for, if any.
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let current_step = Utc::now().timestamp() as u64 / TOTP_STEP_SECONDS;
+
+    (current_step.saturating_sub(TOTP_SKEW_STEPS)..=current_step + TOTP_SKEW_STEPS)
+        .find(|step| totp.generate(step * TOTP_STEP_SECONDS) == code.trim())
+        .map(|step| step as i64)
+}
+
+/// Whether the user has a confirmed TOTP authenticator.
+pub fn has_confirmed_totp(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::totp_credentials::dsl::*;
+
+    totp_credentials
+        .filter(user_id.eq(target_user_id))
+        .filter(confirmed_at.is_not_null())
+        .count()
+        .get_result::<i64>(conn)
+        .map(|count| count > 0)
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+/// Whether any role of the user requires MFA.
+pub fn role_requires_mfa(
+    conn: &mut PgConnection,
+    user: &User,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::roles::dsl::*;
+
+    let mfa_roles = roles
+        .filter(require_mfa.eq(true))
+        .select(id)
+        .load::<i32>(conn)
+        .map_err(|e| internal_error("Roles query failed", e))?;
+
+    let user_roles_bitmask = user.roles as i32;
+
+    Ok(mfa_roles
+        .into_iter()
+        .any(|rid| (user_roles_bitmask & (1 << (rid - 1))) != 0))
+}
+
+/// Generates a new, unconfirmed TOTP secret for the user, replacing any earlier
+/// unconfirmed one. Returns the secret and its `otpauth://` URI.
+pub fn start_totp_enrollment(
+    conn: &mut PgConnection,
+    user: &User,
+    issuer: &str,
+) -> Result<(String, String), (StatusCode, String)> {
+    use crate::schema::totp_credentials::dsl::*;
+
+    if has_confirmed_totp(conn, user.id)? {
+        return Err((StatusCode::CONFLICT, "TOTP is already enabled".into()));
+    }
+
+    let new_secret = Secret::generate_secret().to_encoded().to_string();
+    let totp = build_totp(&new_secret, issuer, &user.email)?;
+
+    diesel::insert_into(totp_credentials)
+        .values((user_id.eq(user.id), secret.eq(&new_secret)))
+        .on_conflict(user_id)
+        .do_update()
+        .set((
+            secret.eq(&new_secret),
+            confirmed_at.eq(None::<chrono::DateTime<Utc>>),
+            last_used_step.eq(None::<i64>),
+            created_at.eq(Utc::now()),
+        ))
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok((new_secret, totp.get_url()))
+}
+
+/// Confirms a pending enrollment with a code from the authenticator app.
+pub fn confirm_totp_enrollment(
+    conn: &mut PgConnection,
+    user: &User,
+    issuer: &str,
+    code: &str,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::totp_credentials::dsl::*;
+
+    let pending_secret = totp_credentials
+        .filter(user_id.eq(user.id))
+        .filter(confirmed_at.is_null())
+        .select(secret)
+        .first::<String>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?
+        .ok_or((StatusCode::BAD_REQUEST, "No pending TOTP enrollment".into()))?;
+
+    let totp = build_totp(&pending_secret, issuer, &user.email)?;
+    let step = matching_step(&totp, code)
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid code".into()))?;
+
+    diesel::update(totp_credentials.find(user.id))
+        .set((confirmed_at.eq(Utc::now()), last_used_step.eq(step)))
+        .execute(conn)
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(())
+}
+
+/// Checks a code against the confirmed authenticator of the user.
+///
+/// A code is accepted at most once: its time step must be newer than the last accepted one.
+pub fn verify_totp_code(
+    conn: &mut PgConnection,
+    user: &User,
+    issuer: &str,
+    code: &str,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::totp_credentials::dsl::*;
+
+    let credential = totp_credentials
+        .filter(user_id.eq(user.id))
+        .filter(confirmed_at.is_not_null())
+        .select(secret)
+        .first::<String>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    let Some(user_secret) = credential else {
+        return Ok(false);
+    };
+
+    let totp = build_totp(&user_secret, issuer, &user.email)?;
+
+    let Some(step) = matching_step(&totp, code) else {
+        return Ok(false);
+    };
+
+    let accepted = diesel::update(
+        totp_credentials
+            .find(user.id)
+            .filter(last_used_step.is_null().or(last_used_step.lt(step))),
+    )
+    .set(last_used_step.eq(step))
+    .execute(conn)
+    .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(accepted > 0)
+}
+
+/// Removes the authenticator and the recovery codes of the user.
+pub fn disable_totp(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::mfa_recovery_codes::dsl::{mfa_recovery_codes, user_id as code_user_id};
+    use crate::schema::totp_credentials::dsl::*;
+
+    conn.transaction(|conn| {
+        diesel::delete(mfa_recovery_codes.filter(code_user_id.eq(target_user_id)))
+            .execute(conn)?;
+        diesel::delete(totp_credentials.find(target_user_id)).execute(conn)
+    })
+    .map_err(|e| internal_error("DB delete error", e))?;
+
+    Ok(())
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Replaces every recovery code of the user with a new set and returns the codes.
+///
+/// Only Argon2 hashes are stored, so the codes cannot be shown again.
+pub fn generate_recovery_codes(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+) -> Result<Vec<String>, (StatusCode, String)> {
+    use crate::schema::mfa_recovery_codes::dsl::*;
+
+    let mut rng = rand::rng();
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let raw: String = (0..10)
+                .map(|_| {
+                    let index = rng.random_range(0..RECOVERY_CODE_ALPHABET.len());
+                    RECOVERY_CODE_ALPHABET[index] as char
+                })
+                .collect();
+            format!("{}-{}", &raw[..5], &raw[5..])
+        })
+        .collect();
+
+    let new_codes = codes
+        .iter()
+        .map(|code| {
+            hash_password(&normalize_recovery_code(code)).map(|hash| NewRecoveryCode {
+                user_id: target_user_id,
+                code_hash: hash,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| internal_error("Recovery code hashing failed", e))?;
+
+    conn.transaction(|conn| {
+        diesel::delete(mfa_recovery_codes.filter(user_id.eq(target_user_id))).execute(conn)?;
+
+        diesel::insert_into(mfa_recovery_codes)
+            .values(&new_codes)
+            .execute(conn)
+    })
+    .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok(codes)
+}
+
+/// Marks a matching unused recovery code as used.
+///
+/// Returns the number of codes left, or `None` if the code did not match.
+pub fn consume_recovery_code(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+    code: &str,
+) -> Result<Option<i64>, (StatusCode, String)> {
+    use crate::schema::mfa_recovery_codes::dsl::*;
+
+    let normalized = normalize_recovery_code(code);
+
+    let unused_codes = mfa_recovery_codes
+        .filter(user_id.eq(target_user_id))
+        .filter(used_at.is_null())
+        .select((id, code_hash))
+        .load::<(i32, String)>(conn)
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    for (code_id, hash) in unused_codes {
+        let matches = verify_password(&normalized, &hash)
+            .map_err(|e| internal_error("Recovery code verification failed", e))?;
+
+        if !matches {
+            continue;
+        }
+
+        let consumed = diesel::update(mfa_recovery_codes.find(code_id).filter(used_at.is_null()))
+            .set(used_at.eq(Utc::now()))
+            .execute(conn)
+            .map_err(|e| internal_error("DB update error", e))?;
+
+        if consumed == 0 {
+            return Ok(None);
+        }
+
+        let remaining = mfa_recovery_codes
+            .filter(user_id.eq(target_user_id))
+            .filter(used_at.is_null())
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(|e| internal_error("DB query error", e))?;
+
+        return Ok(Some(remaining));
+    }
+
+    Ok(None)
+}
+
+/// Uses up one of the code attempts of the MFA challenge behind `claims`.
+///
+/// Returns `false` once `MAX_MFA_ATTEMPTS` codes were tried with the challenge. Attempts are
+/// taken before the code is checked, so parallel requests cannot try more codes.
+pub fn take_mfa_attempt(
+    conn: &mut PgConnection,
+    claims: &Claims,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::user_sessions::dsl::*;
+
+    let session_id = Uuid::parse_str(&claims.sid)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+
+    let taken = diesel::update(
+        user_sessions
+            .find(session_id)
+            .filter(revoked_at.is_null())
+            .filter(mfa_attempts.lt(MAX_MFA_ATTEMPTS)),
+    )
+    .set(mfa_attempts.eq(mfa_attempts + 1))
+    .execute(conn)
+    .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(taken == 1)
+}
+
+/// Issues the token for the next login step once the password has been verified.
+///
+/// Users with an authenticator get an `mfa` challenge token; users whose role requires MFA
+/// but who have none get an `mfa_enrollment` token. Everyone else continues with
+/// `finish_login`.
+pub fn begin_login(
+    conn: &mut PgConnection,
+    user: &User,
+    jwt_secret: &str,
+) -> Result<LoginResponse, (StatusCode, String)> {
+    let restricted_to = if has_confirmed_totp(conn, user.id)? {
+        Some(TokenPurpose::Mfa)
+    } else if role_requires_mfa(conn, user)? {
+        Some(TokenPurpose::MfaEnrollment)
+    } else {
+        return finish_login(conn, user, jwt_secret);
+    };
+
+    let token = start_session(conn, user, jwt_secret, restricted_to)?;
+
+    Ok(LoginResponse {
+        token,
+        restricted_to,
+    })
+}
+
+/// Issues the token once every authentication factor has been verified.
+///
+/// The token is restricted to `password_change` if an administrator reset the password.
+pub fn finish_login(
+    conn: &mut PgConnection,
+    user: &User,
+    jwt_secret: &str,
+) -> Result<LoginResponse, (StatusCode, String)> {
+    let restricted_to = user
+        .must_change_password
+        .then_some(TokenPurpose::PasswordChange);
+
+    let token = start_session(conn, user, jwt_secret, restricted_to)?;
+
+    Ok(LoginResponse {
+        token,
+        restricted_to,
+    })
+}
@@ -1,2 +1,22 @@
+pub mod api_keys;
+pub mod audit;
+pub mod email_change;
+pub mod email_verification;
+pub mod federation;
 pub mod jwt;
-pub mod permissions;
\ No newline at end of file
+pub mod ldap;
+pub mod login_guard;
+pub mod magic_link;
+pub mod mailer;
+pub mod mfa;
+pub mod oauth;
+pub mod oidc;
+pub mod password_reset;
+pub mod permissions;
+pub mod rate_limit;
+pub mod service_accounts;
+pub mod saml;
+pub mod scim;
+pub mod sessions;
+pub mod suspensions;
+pub mod webauthn;
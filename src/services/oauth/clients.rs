@@ -0,0 +1,342 @@
+//! Registered OAuth clients and their authentication at the token endpoint.
+
+use super::OAuthError;
+use crate::config::AppConfig;
+use crate::models::{ClientCredentials, NewOAuthClient, NewOAuthClientAssertion, OAuthClient};
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::{HeaderMap, StatusCode};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use serde::Deserialize;
+use url::{Host, Url};
+
+/// `client_assertion_type` of `private_key_jwt` authentication (RFC 7523).
+pub const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// How a new client authenticates at the token endpoint.
+pub enum ClientAuthentication {
+    /// Public client without credentials.
+    None,
+    /// A generated secret, returned once.
+    Secret,
+    /// A JWT signed with the private key matching this PEM public key.
+    PrivateKeyJwt(String),
+}
+
+#[derive(Deserialize)]
+struct ClientAssertionClaims {
+    iss: String,
+    exp: i64,
+    jti: String,
+}
+
+/// Checks a redirect URI before it is registered.
+///
+/// It must be absolute and without fragment. Plain `http` is only allowed for loopback
+/// addresses, as used by native apps.
+pub fn validate_redirect_uri(redirect_uri: &str) -> Result<(), String> {
+    let url =
+        Url::parse(redirect_uri).map_err(|_| format!("Invalid redirect URI: {}", redirect_uri))?;
+
+    if url.fragment().is_some() {
+        return Err(format!("Redirect URI must not contain a fragment: {}", redirect_uri));
+    }
+
+    let is_loopback = match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    };
+
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if is_loopback => Ok(()),
+        _ => Err(format!("Redirect URI must use https: {}", redirect_uri)),
+    }
+}
+
+/// Checks a PEM public key for `private_key_jwt` before it is registered.
+pub fn validate_jwt_public_key(pem: &str) -> Result<(), String> {
+    DecodingKey::from_rsa_pem(pem.as_bytes())
+        .or_else(|_| DecodingKey::from_ec_pem(pem.as_bytes()))
+        .map(|_| ())
+        .map_err(|_| "jwt_public_key must be an RSA or P-256 public key in PEM".to_string())
+}
+
+/// Registers a client and returns it with its secret, if it authenticates with one.
+pub fn register_client(
+    conn: &mut PgConnection,
+    client_name: String,
+    uris: Vec<String>,
+    scope: i64,
+    authentication: ClientAuthentication,
+    service_account: Option<i32>,
+    creator_id: i32,
+) -> Result<(OAuthClient, Option<String>), (StatusCode, String)> {
+    use crate::schema::oauth_clients::dsl::oauth_clients;
+
+    let mut id_bytes = [0u8; 16];
+    rand::rng().fill_bytes(&mut id_bytes);
+
+    let (secret, public_key) = match authentication {
+        ClientAuthentication::None => (None, None),
+        ClientAuthentication::Secret => (Some(generate_token()), None),
+        ClientAuthentication::PrivateKeyJwt(pem) => (None, Some(pem)),
+    };
+
+    let client = diesel::insert_into(oauth_clients)
+        .values(&NewOAuthClient {
+            client_id: hex::encode(id_bytes),
+            client_secret_hash: secret.as_deref().map(hash_token),
+            name: client_name,
+            redirect_uris: uris,
+            scopes: scope,
+            created_by: Some(creator_id),
+            service_account_id: service_account,
+            jwt_public_key: public_key,
+        })
+        .returning(OAuthClient::as_returning())
+        .get_result(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok((client, secret))
+}
+
+pub fn list_clients(conn: &mut PgConnection) -> Result<Vec<OAuthClient>, (StatusCode, String)> {
+    use crate::schema::oauth_clients::dsl::*;
+
+    oauth_clients
+        .order(created_at.desc())
+        .select(OAuthClient::as_select())
+        .load(conn)
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+pub fn find_client(
+    conn: &mut PgConnection,
+    public_id: &str,
+) -> Result<Option<OAuthClient>, (StatusCode, String)> {
+    use crate::schema::oauth_clients::dsl::*;
+
+    oauth_clients
+        .filter(client_id.eq(public_id))
+        .select(OAuthClient::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+/// Deletes a client, together with its codes, consents and sessions.
+///
+/// Returns `false` if there is no such client.
+pub fn delete_client(
+    conn: &mut PgConnection,
+    public_id: &str,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::oauth_clients::dsl::*;
+
+    let deleted = diesel::delete(oauth_clients.filter(client_id.eq(public_id)))
+        .execute(conn)
+        .map_err(|e| internal_error("DB delete error", e))?;
+
+    Ok(deleted > 0)
+}
+
+/// The redirect URI to use for an authorization request.
+///
+/// It must exactly match a registered URI. It may be omitted if the client registered only
+/// one.
+pub fn resolve_redirect_uri(client: &OAuthClient, requested: Option<&str>) -> Option<String> {
+    match requested {
+        Some(uri) => client.redirect_uris.iter().find(|r| *r == uri).cloned(),
+        None if client.redirect_uris.len() == 1 => Some(client.redirect_uris[0].clone()),
+        None => None,
+    }
+}
+
+/// Authenticates the client of a request to the token or device authorization endpoint.
+///
+/// Confidential clients send their secret with HTTP Basic authentication or in the form
+/// body, or a `client_assertion` signed with their private key. Public clients only send
+/// `client_id`.
+pub fn authenticate_client(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    headers: &HeaderMap,
+    form: &ClientCredentials,
+) -> Result<OAuthClient, OAuthError> {
+    if let Some(assertion_type) = &form.client_assertion_type {
+        if assertion_type != CLIENT_ASSERTION_TYPE {
+            return Err(OAuthError::invalid_client("Unsupported client_assertion_type"));
+        }
+        let assertion = form
+            .client_assertion
+            .as_deref()
+            .ok_or_else(|| OAuthError::invalid_client("Missing client_assertion"))?;
+
+        let client = authenticate_assertion(conn, config, assertion)?;
+        if form.client_id.as_ref().is_some_and(|id| *id != client.client_id) {
+            return Err(OAuthError::invalid_client("client_id does not match the assertion"));
+        }
+        return Ok(client);
+    }
+
+    let basic = basic_credentials(headers);
+
+    let (presented_id, presented_secret) = match &basic {
+        Some((basic_id, basic_secret)) => (basic_id.as_str(), Some(basic_secret.as_str())),
+        None => (
+            form.client_id
+                .as_deref()
+                .ok_or_else(|| OAuthError::invalid_client("Missing client_id"))?,
+            form.client_secret.as_deref(),
+        ),
+    };
+
+    let client = find_client(conn, presented_id)?
+        .ok_or_else(|| OAuthError::invalid_client("Unknown client"))?;
+
+    match (&client.client_secret_hash, presented_secret) {
+        (Some(stored_hash), Some(secret)) if *stored_hash == hash_token(secret) => Ok(client),
+        (None, None) if client.is_public() => Ok(client),
+        (None, Some(_)) if client.is_public() => {
+            Err(OAuthError::invalid_client("Public clients have no secret"))
+        }
+        _ => Err(OAuthError::invalid_client("Invalid client credentials")),
+    }
+}
+
+/// Verifies a `private_key_jwt` client assertion and returns its client.
+///
+/// The assertion must be issued by and about the client, addressed to the token endpoint
+/// or the issuer, and unused; its `jti` is remembered until it expires.
+fn authenticate_assertion(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    assertion: &str,
+) -> Result<OAuthClient, OAuthError> {
+    use crate::schema::oauth_client_assertions::dsl::*;
+
+    let invalid = || OAuthError::invalid_client("Invalid client assertion");
+
+    let header = decode_header(assertion).map_err(|_| invalid())?;
+
+    // Read the issuer without checking the signature, only to find the key to check it with.
+    let mut unverified = Validation::new(header.alg);
+    unverified.insecure_disable_signature_validation();
+    unverified.validate_aud = false;
+    let issuer =
+        decode::<ClientAssertionClaims>(assertion, &DecodingKey::from_secret(&[]), &unverified)
+            .map_err(|_| invalid())?
+            .claims
+            .iss;
+
+    let client = find_client(conn, &issuer)?.ok_or_else(invalid)?;
+    let pem = client.jwt_public_key.as_deref().ok_or_else(invalid)?;
+
+    let key = match header.alg {
+        Algorithm::RS256 => DecodingKey::from_rsa_pem(pem.as_bytes()),
+        Algorithm::ES256 => DecodingKey::from_ec_pem(pem.as_bytes()),
+        _ => return Err(invalid()),
+    }
+    .map_err(|_| invalid())?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[
+        format!("{}/oauth/token", config.public_url),
+        config.public_url.clone(),
+    ]);
+    validation.set_issuer(&[&client.client_id]);
+    validation.sub = Some(client.client_id.clone());
+    validation.set_required_spec_claims(&["exp", "aud", "iss", "sub"]);
+
+    let claims = decode::<ClientAssertionClaims>(assertion, &key, &validation)
+        .map_err(|_| invalid())?
+        .claims;
+
+    let assertion_expiry = DateTime::<Utc>::from_timestamp(claims.exp, 0).ok_or_else(invalid)?;
+
+    let recorded = conn
+        .transaction(|conn| {
+            diesel::delete(
+                oauth_client_assertions
+                    .filter(oauth_client_id.eq(client.id))
+                    .filter(expires_at.le(Utc::now())),
+            )
+            .execute(conn)?;
+
+            diesel::insert_into(oauth_client_assertions)
+                .values(&NewOAuthClientAssertion {
+                    oauth_client_id: client.id,
+                    jti: claims.jti,
+                    expires_at: assertion_expiry,
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)
+        })
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    if recorded == 0 {
+        return Err(OAuthError::invalid_client("Client assertion was already used"));
+    }
+
+    Ok(client)
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|auth_str| auth_str.strip_prefix("Basic "))?;
+
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (basic_id, basic_secret) = decoded.split_once(':')?;
+
+    Some((basic_id.to_string(), basic_secret.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_https_redirect_uris() {
+        assert_eq!(validate_redirect_uri("https://app.example.com/callback"), Ok(()));
+        assert_eq!(validate_redirect_uri("https://app.example.com/cb?tenant=1"), Ok(()));
+    }
+
+    #[test]
+    fn accepts_http_only_on_loopback() {
+        assert_eq!(validate_redirect_uri("http://localhost:8080/callback"), Ok(()));
+        assert_eq!(validate_redirect_uri("http://127.0.0.1:51004/callback"), Ok(()));
+        assert_eq!(validate_redirect_uri("http://[::1]/callback"), Ok(()));
+
+        for uri in [
+            "http://app.example.com/callback",
+            "http://localhost.example.com/callback",
+            "http://10.0.0.1/callback",
+        ] {
+            assert!(validate_redirect_uri(uri).is_err(), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn rejects_other_redirect_uris() {
+        for uri in [
+            "https://app.example.com/callback#token",
+            "https://app.example.com/callback#",
+            "/callback",
+            "javascript:alert(1)",
+            "com.example.app:/callback",
+            "",
+        ] {
+            assert!(validate_redirect_uri(uri).is_err(), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Device authorization grant (RFC 8628) for clients without a browser, such as CLI tools.
+//!
+//! The device shows a short user code, the user approves it on another device where they are
+//! logged in, and the device polls the token endpoint until that happened.
+
+use super::{redirect_with, OAuthError, Scope};
+use crate::config::AppConfig;
+use crate::models::{
+    DeviceAuthorizationResponse, NewOAuthDeviceCode, OAuthClient, OAuthDeviceCode,
+};
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use rand::Rng;
+
+/// `grant_type` of token requests polling for a device code.
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+const DEVICE_CODE_LIFETIME_MINUTES: i64 = 10;
+const POLL_INTERVAL_SECONDS: i32 = 5;
+const SLOW_DOWN_SECONDS: i32 = 5;
+/// Consonants only, so codes spell no words and survive being read out; RFC 8628 section 6.1.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+/// A device code the user approved, ready to be exchanged for tokens.
+pub struct ApprovedDevice {
+    pub user_id: i32,
+    pub scope: Scope,
+    pub auth_time: DateTime<Utc>,
+}
+
+/// Turns a user code as typed, e.g. `bcdf-ghjk`, into its stored form `BCDFGHJK`.
+pub fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .map(|c| c.to_ascii_uppercase())
+        .filter(|c| c.is_ascii() && USER_CODE_ALPHABET.contains(&(*c as u8)))
+        .collect()
+}
+
+fn format_user_code(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(USER_CODE_LENGTH / 2);
+    format!("{}-{}", first, second)
+}
+
+/// Starts a device authorization request and returns the codes to give the device.
+pub fn create_device_code(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    client: &OAuthClient,
+    requested: Scope,
+) -> Result<DeviceAuthorizationResponse, (StatusCode, String)> {
+    use crate::schema::oauth_device_codes::dsl::*;
+
+    let device_code = generate_token();
+    let mut rng = rand::rng();
+    let code: String = (0..USER_CODE_LENGTH)
+        .map(|_| USER_CODE_ALPHABET[rng.random_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect();
+
+    let lifetime = Duration::minutes(DEVICE_CODE_LIFETIME_MINUTES);
+
+    conn.transaction(|conn| {
+        // User codes are short, so expired ones are removed to keep them unique.
+        diesel::delete(oauth_device_codes.filter(expires_at.le(Utc::now()))).execute(conn)?;
+
+        diesel::insert_into(oauth_device_codes)
+            .values(&NewOAuthDeviceCode {
+                device_code_hash: hash_token(&device_code),
+                user_code: code.clone(),
+                oauth_client_id: client.id,
+                scope: requested.permissions,
+                identity_scope: requested.identity,
+                poll_interval: POLL_INTERVAL_SECONDS,
+                expires_at: Utc::now() + lifetime,
+            })
+            .execute(conn)
+    })
+    .map_err(|e| internal_error("DB insert error", e))?;
+
+    let displayed_code = format_user_code(&code);
+
+    Ok(DeviceAuthorizationResponse {
+        device_code,
+        verification_uri: config.oauth_device_verification_url.clone(),
+        verification_uri_complete: redirect_with(
+            &config.oauth_device_verification_url,
+            &[("user_code", &displayed_code)],
+        ),
+        user_code: displayed_code,
+        expires_in: lifetime.num_seconds(),
+        interval: POLL_INTERVAL_SECONDS,
+    })
+}
+
+/// Loads the undecided, unexpired device request with `user_code` and its client.
+pub fn find_pending_device_code(
+    conn: &mut PgConnection,
+    user_code: &str,
+) -> Result<Option<(OAuthDeviceCode, OAuthClient)>, (StatusCode, String)> {
+    use crate::schema::oauth_clients;
+    use crate::schema::oauth_device_codes::dsl as codes;
+
+    codes::oauth_device_codes
+        .inner_join(oauth_clients::table)
+        .filter(codes::user_code.eq(normalize_user_code(user_code)))
+        .filter(codes::user_id.is_null())
+        .filter(codes::denied.eq(false))
+        .filter(codes::expires_at.gt(Utc::now()))
+        .select((OAuthDeviceCode::as_select(), OAuthClient::as_select()))
+        .first(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+/// Records the user's decision on a pending device request.
+///
+/// `approval` is the approving user and when they logged in, or `None` to deny. Returns
+/// `false` if the request was decided or expired in the meantime.
+pub fn decide_device_code(
+    conn: &mut PgConnection,
+    user_code: &str,
+    approval: Option<(i32, DateTime<Utc>)>,
+) -> Result<bool, (StatusCode, String)> {
+    use crate::schema::oauth_device_codes::dsl as codes;
+
+    let pending = codes::oauth_device_codes
+        .filter(codes::user_code.eq(normalize_user_code(user_code)))
+        .filter(codes::user_id.is_null())
+        .filter(codes::denied.eq(false))
+        .filter(codes::expires_at.gt(Utc::now()));
+
+    let decided = match approval {
+        Some((approver_id, authenticated_at)) => diesel::update(pending)
+            .set((
+                codes::user_id.eq(approver_id),
+                codes::auth_time.eq(authenticated_at),
+            ))
+            .execute(conn),
+        None => diesel::update(pending).set(codes::denied.eq(true)).execute(conn),
+    }
+    .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(decided > 0)
+}
+
+/// Answers a device polling the token endpoint.
+///
+/// Until the user decided, this fails with `authorization_pending`, or with `slow_down` if the
+/// device polls faster than its interval, which then grows. An approved code is deleted and
+/// returned, so it can only be exchanged once.
+pub fn poll_device_code(
+    conn: &mut PgConnection,
+    client: &OAuthClient,
+    device_code: &str,
+) -> Result<ApprovedDevice, OAuthError> {
+    use crate::schema::oauth_device_codes::dsl::*;
+
+    let code_hash = hash_token(device_code);
+    let poll_error = |error: &'static str, description: &str| {
+        OAuthError::new(StatusCode::BAD_REQUEST, error, description)
+    };
+
+    let found = oauth_device_codes
+        .find(&code_hash)
+        .select(OAuthDeviceCode::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?
+        .filter(|found| found.oauth_client_id == client.id)
+        .ok_or_else(|| OAuthError::invalid_grant("Invalid device code"))?;
+
+    let now = Utc::now();
+
+    if found.expires_at <= now || found.denied {
+        diesel::delete(oauth_device_codes.find(&code_hash))
+            .execute(conn)
+            .map_err(|e| internal_error("DB delete error", e))?;
+
+        return Err(if found.denied {
+            poll_error("access_denied", "The user denied the request")
+        } else {
+            poll_error("expired_token", "The device code expired")
+        });
+    }
+
+    if let Some(polled_at) = found.last_polled_at
+        && now < polled_at + Duration::seconds(found.poll_interval.into())
+    {
+        diesel::update(oauth_device_codes.find(&code_hash))
+            .set((
+                poll_interval.eq(poll_interval + SLOW_DOWN_SECONDS),
+                last_polled_at.eq(now),
+            ))
+            .execute(conn)
+            .map_err(|e| internal_error("DB update error", e))?;
+
+        return Err(poll_error("slow_down", "Polling too fast"));
+    }
+
+    if found.user_id.is_none() {
+        diesel::update(oauth_device_codes.find(&code_hash))
+            .set(last_polled_at.eq(now))
+            .execute(conn)
+            .map_err(|e| internal_error("DB update error", e))?;
+
+        return Err(poll_error("authorization_pending", "The user has not decided yet"));
+    }
+
+    let redeemed = diesel::delete(oauth_device_codes.find(&code_hash))
+        .returning(OAuthDeviceCode::as_returning())
+        .get_result(conn)
+        .optional()
+        .map_err(|e| internal_error("DB delete error", e))?;
+
+    match redeemed {
+        Some(OAuthDeviceCode {
+            user_id: Some(approver_id),
+            scope: granted_permissions,
+            identity_scope: granted_identity,
+            auth_time: authenticated_at,
+            ..
+        }) => Ok(ApprovedDevice {
+            user_id: approver_id,
+            scope: Scope {
+                permissions: granted_permissions,
+                identity: granted_identity,
+            },
+            auth_time: authenticated_at.unwrap_or(now),
+        }),
+        _ => Err(OAuthError::invalid_grant("Invalid device code")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_user_codes_as_typed() {
+        assert_eq!(normalize_user_code("BCDF-GHJK"), "BCDFGHJK");
+        assert_eq!(normalize_user_code("bcdf-ghjk"), "BCDFGHJK");
+        assert_eq!(normalize_user_code(" bcdf ghjk\n"), "BCDFGHJK");
+    }
+
+    #[test]
+    fn drops_characters_outside_the_alphabet() {
+        // Vowels, digits and lookalikes never appear in generated codes.
+        assert_eq!(normalize_user_code("BCDF-0AEI"), "BCDF");
+        assert_eq!(normalize_user_code("ВCDF-GHJK"), "CDFGHJK");
+        assert_eq!(normalize_user_code(""), "");
+    }
+
+    #[test]
+    fn formats_user_codes_in_two_halves() {
+        assert_eq!(format_user_code("BCDFGHJK"), "BCDF-GHJK");
+        assert_eq!(normalize_user_code(&format_user_code("BCDFGHJK")), "BCDFGHJK");
+    }
+}
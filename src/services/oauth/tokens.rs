@@ -0,0 +1,567 @@
+//! Authorization codes, consents and the access and refresh tokens issued for them.
+
+use super::{OAuthError, Scope};
+use crate::config::AppConfig;
+use crate::models::{
+    AuthorizationRequest, NewOAuthAuthorizationCode, NewOAuthConsent, NewOAuthRefreshToken,
+    NewUserSession, OAuthAuthorizationCode, OAuthClient, User,
+};
+use crate::services::audit::record_event;
+use crate::services::jwt::{create_jwt, TokenGrant};
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+const AUTHORIZATION_CODE_LIFETIME_MINUTES: i64 = 5;
+
+/// Tokens issued by a successful token request.
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub expires_in: i64,
+    pub scope: Scope,
+    /// The user the tokens were issued for, needed for an ID token.
+    pub user: User,
+    /// When the user authenticated.
+    pub auth_time: DateTime<Utc>,
+}
+
+impl IssuedTokens {
+    fn with_refresh_token(self, refresh_token: String) -> Self {
+        Self {
+            refresh_token: Some(refresh_token),
+            ..self
+        }
+    }
+}
+
+/// A refresh token looked up for introspection or revocation.
+pub struct RefreshTokenGrant {
+    pub session_id: Uuid,
+    /// `client_id` of the client the token was issued to.
+    pub client_id: String,
+    pub scope: Scope,
+    pub expires_at: DateTime<Utc>,
+    pub user: User,
+    /// Whether the token could still be exchanged.
+    pub active: bool,
+}
+
+/// Whether `challenge` is a valid S256 PKCE code challenge.
+pub fn is_valid_code_challenge(challenge: &str) -> bool {
+    challenge.len() == 43
+        && challenge
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Checks a PKCE code verifier against the S256 challenge sent to `/oauth/authorize`.
+pub fn verify_code_verifier(challenge: &str, verifier: &str) -> bool {
+    let well_formed = (43..=128).contains(&verifier.len())
+        && verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+
+    well_formed && URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) == challenge
+}
+
+/// Scope the user already approved for the client, or `None` if they never did.
+pub fn consented_scope(
+    conn: &mut PgConnection,
+    consenting_user_id: i32,
+    client: &OAuthClient,
+) -> Result<Option<Scope>, (StatusCode, String)> {
+    use crate::schema::oauth_consents::dsl::*;
+
+    Ok(oauth_consents
+        .find((consenting_user_id, client.id))
+        .select((scope, identity_scope))
+        .first::<(i64, i16)>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?
+        .map(|(permissions, identity)| Scope {
+            permissions,
+            identity,
+        }))
+}
+
+/// Remembers that the user approved `granted` for the client, replacing the earlier scope.
+pub fn record_consent(
+    conn: &mut PgConnection,
+    consenting_user_id: i32,
+    client: &OAuthClient,
+    granted: Scope,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::oauth_consents::dsl::*;
+
+    diesel::insert_into(oauth_consents)
+        .values(&NewOAuthConsent {
+            user_id: consenting_user_id,
+            oauth_client_id: client.id,
+            scope: granted.permissions,
+            identity_scope: granted.identity,
+        })
+        .on_conflict((user_id, oauth_client_id))
+        .do_update()
+        .set((
+            scope.eq(granted.permissions),
+            identity_scope.eq(granted.identity),
+            granted_at.eq(Utc::now()),
+        ))
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    record_event(
+        conn,
+        Some(consenting_user_id),
+        "oauth_consent_granted",
+        Some(format!("client {}", client.client_id)),
+    )
+}
+
+/// Creates a single-use authorization code for `request` and returns it.
+///
+/// `authenticated_at` is when the user logged in, reported as `auth_time` in ID tokens.
+pub fn create_authorization_code(
+    conn: &mut PgConnection,
+    client: &OAuthClient,
+    owner_id: i32,
+    request: &AuthorizationRequest,
+    granted: Scope,
+    authenticated_at: DateTime<Utc>,
+) -> Result<String, (StatusCode, String)> {
+    use crate::schema::oauth_authorization_codes::dsl::oauth_authorization_codes;
+
+    let code = generate_token();
+
+    diesel::insert_into(oauth_authorization_codes)
+        .values(&NewOAuthAuthorizationCode {
+            code_hash: hash_token(&code),
+            oauth_client_id: client.id,
+            user_id: owner_id,
+            redirect_uri: request.redirect_uri.clone(),
+            scope: granted.permissions,
+            code_challenge: request.code_challenge.clone(),
+            expires_at: Utc::now() + Duration::minutes(AUTHORIZATION_CODE_LIFETIME_MINUTES),
+            identity_scope: granted.identity,
+            nonce: request.nonce.clone(),
+            auth_time: authenticated_at,
+        })
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok(code)
+}
+
+/// Redeems an authorization code and returns it.
+///
+/// The code is deleted whether or not the request is valid, so it can only be tried once.
+pub fn redeem_authorization_code(
+    conn: &mut PgConnection,
+    client: &OAuthClient,
+    code: &str,
+    redirect_uri: Option<&str>,
+    code_verifier: Option<&str>,
+) -> Result<OAuthAuthorizationCode, OAuthError> {
+    use crate::schema::oauth_authorization_codes::dsl::oauth_authorization_codes;
+
+    let redeemed = diesel::delete(oauth_authorization_codes.find(hash_token(code)))
+        .returning(OAuthAuthorizationCode::as_returning())
+        .get_result(conn)
+        .optional()
+        .map_err(|e| internal_error("DB delete error", e))?
+        .ok_or_else(|| OAuthError::invalid_grant("Invalid authorization code"))?;
+
+    if redeemed.oauth_client_id != client.id || redeemed.expires_at <= Utc::now() {
+        return Err(OAuthError::invalid_grant("Invalid authorization code"));
+    }
+
+    if redeemed.redirect_uri.is_some() && redeemed.redirect_uri.as_deref() != redirect_uri {
+        return Err(OAuthError::invalid_grant("redirect_uri does not match"));
+    }
+
+    match (&redeemed.code_challenge, code_verifier) {
+        (Some(challenge), Some(verifier)) if verify_code_verifier(challenge, verifier) => {}
+        (Some(_), _) => return Err(OAuthError::invalid_grant("Invalid code_verifier")),
+        (None, Some(_)) => {
+            return Err(OAuthError::invalid_request("No code_challenge was sent"));
+        }
+        (None, None) => {}
+    }
+
+    Ok(redeemed)
+}
+
+/// Starts a session for the client and returns an access token and a refresh token.
+pub fn issue_tokens(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    jwt_secret: &str,
+    user: User,
+    client: &OAuthClient,
+    granted: Scope,
+    authenticated_at: DateTime<Utc>,
+) -> Result<IssuedTokens, (StatusCode, String)> {
+    use crate::schema::user_sessions::dsl::user_sessions;
+
+    let session = NewUserSession {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        expires_at: refresh_expiry(config),
+        oauth_client_id: Some(client.id),
+        auth_time: Some(authenticated_at),
+    };
+
+    conn.transaction(|conn| {
+        diesel::insert_into(user_sessions)
+            .values(&session)
+            .execute(conn)?;
+
+        store_refresh_token(conn, session.id, granted, session.expires_at)
+    })
+    .map_err(|e| internal_error("DB insert error", e))
+    .and_then(|refresh_token| {
+        access_token(config, jwt_secret, user, client, session.id, granted, authenticated_at)
+            .map(|tokens| tokens.with_refresh_token(refresh_token))
+    })
+}
+
+/// Starts a session for the client's service account and returns an access token for it.
+///
+/// No refresh token is issued; the client authenticates again once the token expires.
+pub fn issue_client_credentials_token(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    jwt_secret: &str,
+    account: User,
+    client: &OAuthClient,
+    granted: Scope,
+) -> Result<IssuedTokens, (StatusCode, String)> {
+    use crate::schema::user_sessions::dsl::user_sessions;
+
+    let now = Utc::now();
+    let session = NewUserSession {
+        id: Uuid::new_v4(),
+        user_id: account.id,
+        expires_at: now + Duration::minutes(config.oauth_access_token_ttl_minutes),
+        oauth_client_id: Some(client.id),
+        auth_time: None,
+    };
+
+    diesel::insert_into(user_sessions)
+        .values(&session)
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    access_token(config, jwt_secret, account, client, session.id, granted, now)
+}
+
+/// Exchanges a refresh token for a new access token and refresh token.
+///
+/// Refresh tokens are single-use. Presenting one that was already exchanged revokes the
+/// session, since either the client or an attacker holds a stolen copy.
+pub fn refresh_tokens(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    jwt_secret: &str,
+    client: &OAuthClient,
+    refresh_token: &str,
+    requested_scope: Option<Scope>,
+) -> Result<IssuedTokens, OAuthError> {
+    use crate::schema::oauth_refresh_tokens::dsl as tokens;
+    use crate::schema::user_sessions::dsl as sessions;
+    use crate::schema::users;
+
+    let invalid = || OAuthError::invalid_grant("Invalid refresh token");
+
+    let found = tokens::oauth_refresh_tokens
+        .inner_join(sessions::user_sessions.inner_join(users::table))
+        .filter(tokens::token_hash.eq(hash_token(refresh_token)))
+        .select((
+            tokens::id,
+            (tokens::scope, tokens::identity_scope),
+            tokens::expires_at,
+            tokens::revoked_at,
+            sessions::id,
+            sessions::oauth_client_id,
+            sessions::revoked_at,
+            sessions::auth_time,
+            users::all_columns,
+        ))
+        .first::<(
+            i32,
+            (i64, i16),
+            DateTime<Utc>,
+            Option<DateTime<Utc>>,
+            Uuid,
+            Option<i32>,
+            Option<DateTime<Utc>>,
+            Option<DateTime<Utc>>,
+            User,
+        )>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    let Some((
+        token_id,
+        (token_permissions, token_identity),
+        expiry,
+        used_at,
+        session_id,
+        session_client,
+        ended_at,
+        authenticated_at,
+        user,
+    )) = found
+    else {
+        return Err(invalid());
+    };
+    let token_scope = Scope {
+        permissions: token_permissions,
+        identity: token_identity,
+    };
+
+    if session_client != Some(client.id) || ended_at.is_some() || expiry <= Utc::now() {
+        return Err(invalid());
+    }
+
+    if used_at.is_some() {
+        diesel::update(sessions::user_sessions.find(session_id))
+            .set(sessions::revoked_at.eq(Utc::now()))
+            .execute(conn)
+            .map_err(|e| internal_error("DB update error", e))?;
+        record_event(
+            conn,
+            Some(user.id),
+            "oauth_refresh_token_reused",
+            Some(format!("client {}", client.client_id)),
+        )?;
+        return Err(invalid());
+    }
+
+    if !user.is_active {
+        return Err(OAuthError::invalid_grant("Account is inactive"));
+    }
+
+    let granted = match requested_scope {
+        Some(narrowed) if !token_scope.contains(narrowed) => {
+            return Err(OAuthError::invalid_scope("Scope exceeds the original grant"));
+        }
+        Some(narrowed) => narrowed,
+        None => token_scope,
+    };
+
+    let new_expiry = refresh_expiry(config);
+
+    let refresh_token = conn
+        .transaction(|conn| {
+            let exchanged = diesel::update(
+                tokens::oauth_refresh_tokens
+                    .find(token_id)
+                    .filter(tokens::revoked_at.is_null()),
+            )
+            .set(tokens::revoked_at.eq(Utc::now()))
+            .execute(conn)?;
+
+            if exchanged == 0 {
+                return Ok(None);
+            }
+
+            diesel::update(sessions::user_sessions.find(session_id))
+                .set(sessions::expires_at.eq(new_expiry))
+                .execute(conn)?;
+
+            store_refresh_token(conn, session_id, granted, new_expiry).map(Some)
+        })
+        .map_err(|e| internal_error("DB update error", e))?
+        .ok_or_else(invalid)?;
+
+    Ok(access_token(
+        config,
+        jwt_secret,
+        user,
+        client,
+        session_id,
+        granted,
+        authenticated_at.unwrap_or_default(),
+    )?
+    .with_refresh_token(refresh_token))
+}
+
+/// Looks up a refresh token without exchanging it.
+pub fn find_refresh_token(
+    conn: &mut PgConnection,
+    refresh_token: &str,
+) -> Result<Option<RefreshTokenGrant>, (StatusCode, String)> {
+    use crate::schema::oauth_refresh_tokens::dsl as tokens;
+    use crate::schema::user_sessions::dsl as sessions;
+    use crate::schema::{oauth_clients, users};
+
+    let found = tokens::oauth_refresh_tokens
+        .inner_join(
+            sessions::user_sessions
+                .inner_join(users::table)
+                .inner_join(oauth_clients::table),
+        )
+        .filter(tokens::token_hash.eq(hash_token(refresh_token)))
+        .select((
+            (tokens::scope, tokens::identity_scope),
+            tokens::expires_at,
+            tokens::revoked_at,
+            sessions::id,
+            sessions::revoked_at,
+            oauth_clients::client_id,
+            users::all_columns,
+        ))
+        .first::<(
+            (i64, i16),
+            DateTime<Utc>,
+            Option<DateTime<Utc>>,
+            Uuid,
+            Option<DateTime<Utc>>,
+            String,
+            User,
+        )>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    Ok(found.map(
+        |((permissions, identity), expiry, used_at, session_id, ended_at, client_id, user)| {
+            RefreshTokenGrant {
+                active: used_at.is_none()
+                    && ended_at.is_none()
+                    && expiry > Utc::now()
+                    && user.is_active,
+                session_id,
+                client_id,
+                scope: Scope {
+                    permissions,
+                    identity,
+                },
+                expires_at: expiry,
+                user,
+            }
+        },
+    ))
+}
+
+fn refresh_expiry(config: &AppConfig) -> DateTime<Utc> {
+    Utc::now() + Duration::days(config.oauth_refresh_token_ttl_days)
+}
+
+fn store_refresh_token(
+    conn: &mut PgConnection,
+    session: Uuid,
+    granted: Scope,
+    expiry: DateTime<Utc>,
+) -> QueryResult<String> {
+    use crate::schema::oauth_refresh_tokens::dsl::oauth_refresh_tokens;
+
+    let refresh_token = generate_token();
+
+    diesel::insert_into(oauth_refresh_tokens)
+        .values(&NewOAuthRefreshToken {
+            token_hash: hash_token(&refresh_token),
+            session_id: session,
+            scope: granted.permissions,
+            expires_at: expiry,
+            identity_scope: granted.identity,
+        })
+        .execute(conn)?;
+
+    Ok(refresh_token)
+}
+
+fn access_token(
+    config: &AppConfig,
+    jwt_secret: &str,
+    user: User,
+    client: &OAuthClient,
+    session_id: Uuid,
+    granted: Scope,
+    authenticated_at: DateTime<Utc>,
+) -> Result<IssuedTokens, (StatusCode, String)> {
+    let lifetime = Duration::minutes(config.oauth_access_token_ttl_minutes);
+    let expires_at = Utc::now() + lifetime;
+
+    let access_token = create_jwt(
+        &user,
+        session_id,
+        expires_at,
+        None,
+        Some(TokenGrant {
+            client_id: &client.client_id,
+            scope: granted.permissions,
+            identity_scope: granted.identity,
+        }),
+        jwt_secret,
+    )
+    .map_err(|e| internal_error("JWT generation failed", e))?;
+
+    Ok(IssuedTokens {
+        access_token,
+        refresh_token: None,
+        expires_at,
+        expires_in: lifetime.num_seconds(),
+        scope: granted,
+        user,
+        auth_time: authenticated_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Example of RFC 7636 appendix B.
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    #[test]
+    fn accepts_well_formed_code_challenges() {
+        assert!(is_valid_code_challenge(CHALLENGE));
+        assert!(!is_valid_code_challenge(&CHALLENGE[..42]));
+        assert!(!is_valid_code_challenge(&format!("{}A", CHALLENGE)));
+        // Standard base64 and padding are not base64url.
+        assert!(!is_valid_code_challenge("E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw+cM"));
+        assert!(!is_valid_code_challenge("E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-c="));
+    }
+
+    #[test]
+    fn verifies_rfc_7636_example() {
+        assert!(verify_code_verifier(CHALLENGE, VERIFIER));
+    }
+
+    #[test]
+    fn rejects_wrong_code_verifier() {
+        assert!(!verify_code_verifier(CHALLENGE, &VERIFIER.replace('d', "e")));
+        // The plain method, where the challenge is the verifier itself, is not supported.
+        assert!(!verify_code_verifier(VERIFIER, VERIFIER));
+    }
+
+    #[test]
+    fn rejects_malformed_code_verifier() {
+        let short = &VERIFIER[..42];
+        let short_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(short.as_bytes()));
+        assert!(!verify_code_verifier(&short_challenge, short));
+
+        let long = "a".repeat(129);
+        let long_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(long.as_bytes()));
+        assert!(!verify_code_verifier(&long_challenge, &long));
+
+        let invalid = format!("{}+", VERIFIER);
+        let invalid_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(invalid.as_bytes()));
+        assert!(!verify_code_verifier(&invalid_challenge, &invalid));
+
+        let longest = "~".repeat(128);
+        let longest_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(longest.as_bytes()));
+        assert!(verify_code_verifier(&longest_challenge, &longest));
+    }
+}
@@ -0,0 +1,165 @@
+//! OpenID Connect on top of the OAuth authorization server: signed ID tokens and the claims
+//! returned by `/userinfo`.
+
+use crate::config::AppConfig;
+use crate::models::{User, UserInfoView};
+use crate::services::oauth::{IDENTITY_EMAIL, IDENTITY_PROFILE};
+use crate::services::service_accounts::{load_role_map, role_names};
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use diesel::PgConnection;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::rand_core::OsRng;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::env;
+
+const GENERATED_KEY_BITS: usize = 2048;
+
+/// RSA key ID tokens are signed with, published at `/.well-known/jwks.json`.
+pub struct OidcSigningKey {
+    encoding_key: EncodingKey,
+    kid: String,
+    jwk: Value,
+}
+
+impl OidcSigningKey {
+    /// Loads the PEM key in `OIDC_SIGNING_KEY_FILE`, PKCS#8 or PKCS#1.
+    ///
+    /// Without it a key is generated at startup. ID tokens then stop verifying after a
+    /// restart and differ between instances, so this is only meant for development.
+    pub fn from_env() -> Result<Self, String> {
+        let private_key = match env::var("OIDC_SIGNING_KEY_FILE") {
+            Ok(path) => {
+                let pem = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+                RsaPrivateKey::from_pkcs8_pem(&pem)
+                    .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+                    .map_err(|e| format!("Invalid RSA key in {}: {}", path, e))?
+            }
+            Err(_) => {
+                tracing::warn!(
+                    "OIDC_SIGNING_KEY_FILE is not set; generated a signing key for this process"
+                );
+                RsaPrivateKey::new(&mut OsRng, GENERATED_KEY_BITS)
+                    .map_err(|e| format!("Failed to generate RSA key: {}", e))?
+            }
+        };
+
+        let der = private_key
+            .to_pkcs1_der()
+            .map_err(|e| format!("Failed to encode RSA key: {}", e))?;
+
+        let modulus = private_key.n().to_bytes_be();
+        let exponent = private_key.e().to_bytes_be();
+        let kid = hex::encode(&Sha256::digest([modulus.as_slice(), &exponent].concat())[..8]);
+
+        Ok(Self {
+            encoding_key: EncodingKey::from_rsa_der(der.as_bytes()),
+            jwk: json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": kid,
+                "n": URL_SAFE_NO_PAD.encode(modulus),
+                "e": URL_SAFE_NO_PAD.encode(exponent),
+            }),
+            kid,
+        })
+    }
+
+    /// The JSON Web Key Set with the public key.
+    pub fn jwks(&self) -> Value {
+        json!({ "keys": [self.jwk] })
+    }
+}
+
+#[derive(Serialize)]
+struct IdTokenClaims {
+    iss: String,
+    aud: String,
+    exp: i64,
+    iat: i64,
+    auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(flatten)]
+    info: UserInfoView,
+}
+
+/// Claims about `user` allowed by the OpenID Connect scopes in `identity_scope`.
+pub fn load_user_info(
+    conn: &mut PgConnection,
+    user: &User,
+    identity_scope: i16,
+) -> Result<UserInfoView, (StatusCode, String)> {
+    let mut info = UserInfoView {
+        sub: user.temp_id.to_string(),
+        name: None,
+        given_name: None,
+        family_name: None,
+        preferred_username: None,
+        locale: None,
+        roles: None,
+        updated_at: None,
+        email: None,
+        email_verified: None,
+    };
+
+    if identity_scope & IDENTITY_PROFILE != 0 {
+        let role_map = load_role_map(conn)?;
+
+        info.name = match (&user.first_name, &user.last_name) {
+            (Some(first), Some(last)) => Some(format!("{} {}", first, last)),
+            (first, last) => first.clone().or_else(|| last.clone()),
+        };
+        info.given_name = user.first_name.clone();
+        info.family_name = user.last_name.clone();
+        info.preferred_username = Some(user.username.clone());
+        info.locale = Some(user.locale.clone());
+        info.roles = Some(role_names(&role_map, user.roles));
+        info.updated_at = user.updated_at.map(|at| at.timestamp());
+    }
+
+    if identity_scope & IDENTITY_EMAIL != 0 {
+        info.email = Some(user.email.clone());
+        info.email_verified = Some(user.email_verified_at.is_some());
+    }
+
+    Ok(info)
+}
+
+/// Signs an ID token for `client_id` carrying `info`.
+pub fn create_id_token(
+    key: &OidcSigningKey,
+    config: &AppConfig,
+    client_id: &str,
+    info: UserInfoView,
+    nonce: Option<String>,
+    auth_time: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> Result<String, (StatusCode, String)> {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(key.kid.clone());
+
+    let claims = IdTokenClaims {
+        iss: config.public_url.clone(),
+        aud: client_id.to_string(),
+        exp: expires_at.timestamp(),
+        iat: Utc::now().timestamp(),
+        auth_time: auth_time.timestamp(),
+        nonce,
+        info,
+    };
+
+    encode(&header, &claims, &key.encoding_key)
+        .map_err(|e| internal_error("ID token generation failed", e))
+}
@@ -0,0 +1,115 @@
+use crate::config::AppConfig;
+use crate::models::{NewPasswordResetToken, User};
+use crate::services::mailer::templates::{render, MailTemplate};
+use crate::services::mailer::Mailer;
+use crate::services::sessions::revoke_user_sessions;
+use crate::utils::error::internal_error;
+use crate::utils::hash::hash_password;
+use crate::utils::password_policy::validate_password;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+
+/// Creates a single-use reset token for `user` and emails the reset link.
+pub fn send_reset_link(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    config: &AppConfig,
+    user: &User,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::password_reset_tokens::dsl::password_reset_tokens;
+
+    let token = generate_token();
+
+    let reset_token = NewPasswordResetToken {
+        user_id: user.id,
+        token_hash: hash_token(&token),
+        expires_at: Utc::now() + Duration::minutes(config.password_reset_ttl_minutes),
+    };
+
+    diesel::insert_into(password_reset_tokens)
+        .values(&reset_token)
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    let link = format!("{}/reset-password?token={}", config.public_url, token);
+    let expires_in = config.password_reset_ttl_minutes.to_string();
+
+    let message = render(
+        config,
+        MailTemplate::PasswordReset,
+        &user.locale,
+        &user.email,
+        &[
+            ("first_name", user.greeting_name()),
+            ("link", &link),
+            ("expires_minutes", &expires_in),
+        ],
+    );
+
+    mailer
+        .send(&message)
+        .map_err(|e| internal_error("Failed to send email", e))
+}
+
+/// Sets a new password using a reset token and revokes every session of the user.
+///
+/// The token is consumed together with every other outstanding token of the same user.
+pub fn reset_password_with_token(
+    conn: &mut PgConnection,
+    token: &str,
+    new_password: &str,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::password_reset_tokens::dsl::*;
+    use crate::schema::users::dsl::{id as users_id, is_active, password_hash, users};
+
+    validate_password(new_password)?;
+
+    let hashed_password = hash_password(new_password)
+        .map_err(|e| internal_error("Password hashing failed", e))?;
+
+    let reset = conn
+        .transaction(|conn| {
+            let active_users = users.filter(is_active.eq(true)).select(users_id);
+
+            let target_user_id = diesel::update(
+                password_reset_tokens
+                    .filter(token_hash.eq(hash_token(token)))
+                    .filter(used_at.is_null())
+                    .filter(expires_at.gt(Utc::now()))
+                    .filter(user_id.eq_any(active_users)),
+            )
+            .set(used_at.eq(Utc::now()))
+            .returning(user_id)
+            .get_result::<i32>(conn)
+            .optional()?;
+
+            let Some(target_user_id) = target_user_id else {
+                return Ok(false);
+            };
+
+            diesel::update(users.find(target_user_id))
+                .set(password_hash.eq(hashed_password))
+                .execute(conn)?;
+
+            diesel::update(
+                password_reset_tokens
+                    .filter(user_id.eq(target_user_id))
+                    .filter(used_at.is_null()),
+            )
+            .set(used_at.eq(Utc::now()))
+            .execute(conn)?;
+
+            revoke_user_sessions(conn, target_user_id, None)?;
+
+            Ok::<_, diesel::result::Error>(true)
+        })
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    if !reset {
+        return Err((StatusCode::BAD_REQUEST, "Invalid or expired token".into()));
+    }
+
+    Ok(())
+}
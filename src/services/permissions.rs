@@ -1,60 +1,532 @@
-use crate::models::{Permission, User};
 use crate::services::jwt::Claims;
 use crate::utils::error::internal_error;
 use axum::http::StatusCode;
 use diesel::{ExpressionMethods, PgConnection};
 use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-pub async fn user_has_permission(
-    claims: &Claims,
-    conn: &mut PgConnection,
-    permission_name: &str,
-) -> Result<bool, (StatusCode, String)> {
-    use crate::schema::permissions::dsl::{name as perm_name, permissions};
+/// Permission names and role bitmasks, shared by every user lookup.
+struct PermissionCatalog {
+    permission_ids: HashMap<String, i32>,
+    role_permissions: Vec<(i32, i64)>,
+}
+
+/// The raw grants stored on a `users` row.
+#[derive(Clone, Copy)]
+struct UserGrants {
+    roles: i16,
+    permissions: i64,
+}
+
+/// Effective permissions of the caller, resolved once and reused for every check
+/// made while handling the same request.
+pub struct ResolvedPermissions {
+    bits: i64,
+    catalog: Arc<PermissionCatalog>,
+}
+
+impl ResolvedPermissions {
+    pub fn has(&self, permission_name: &str) -> bool {
+        match self.catalog.permission_ids.get(permission_name) {
+            Some(perm_id) => (self.bits & permission_bit(*perm_id)) != 0,
+            None => false,
+        }
+    }
+
+    /// Bitmask of every permission held.
+    pub fn bits(&self) -> i64 {
+        self.bits
+    }
+
+    /// Whether every permission in `bits` is held.
+    pub fn covers(&self, bits: i64) -> bool {
+        bits & !self.bits == 0
+    }
+
+    /// Bitmask of `names`, which must all be held by the caller.
+    pub fn bits_of(&self, names: &[String]) -> Result<i64, (StatusCode, String)> {
+        names.iter().try_fold(0, |bits, permission_name| {
+            if !self.has(permission_name) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Permission not held: {}", permission_name),
+                ));
+            }
+            Ok(bits | permission_bit(self.catalog.permission_ids[permission_name]))
+        })
+    }
+
+    /// Names of the permissions in `bits`, ordered by id.
+    pub fn names_of(&self, bits: i64) -> Vec<String> {
+        self.catalog.names_of(bits)
+    }
+
+    /// Names of every permission held, ordered by id.
+    pub fn names(&self) -> Vec<String> {
+        self.catalog.names_of(self.bits)
+    }
+
+    /// Permissions of a user with `grants`: their own permissions and those of their roles.
+    fn combine(grants: UserGrants, catalog: Arc<PermissionCatalog>) -> Self {
+        let user_roles_bitmask = grants.roles as i32;
+
+        let mut combined_role_perm: i64 = 0;
+
+        for (rid, rperm) in catalog.role_permissions.iter() {
+            let role_bit = 1 << (rid - 1);
+
+            if (user_roles_bitmask & role_bit) != 0 {
+                combined_role_perm |= rperm;
+            }
+        }
+
+        Self {
+            bits: grants.permissions | combined_role_perm,
+            catalog,
+        }
+    }
+
+    /// Limits the permissions to `scope`, the bitmask a credential was issued for.
+    fn restricted_to(mut self, scope: Option<i64>) -> Self {
+        if let Some(scope) = scope {
+            self.bits &= scope;
+        }
+        self
+    }
+}
+
+impl PermissionCatalog {
+    fn names_of(&self, bits: i64) -> Vec<String> {
+        let mut granted: Vec<(i32, &String)> = self
+            .permission_ids
+            .iter()
+            .filter(|(_, perm_id)| bits & permission_bit(**perm_id) != 0)
+            .map(|(permission_name, perm_id)| (*perm_id, permission_name))
+            .collect();
+        granted.sort();
+
+        granted.into_iter().map(|(_, permission_name)| permission_name.clone()).collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct PermissionCacheStats {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub invalidations: u64,
+    pub cached_users: usize,
+}
+
+/// Process-wide cache of permission lookups.
+///
+/// Entries expire after `PERMISSION_CACHE_TTL_SECS` (default 30, `0` disables the cache).
+/// Any code that changes roles, permissions or the grants of a user must invalidate the
+/// affected entries so this instance stops serving them; other instances pick the change
+/// up once their TTL runs out.
+pub struct PermissionCache {
+    ttl: Option<Duration>,
+    catalog: RwLock<Option<(Instant, Arc<PermissionCatalog>)>>,
+    users: RwLock<HashMap<Uuid, (Instant, UserGrants)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl PermissionCache {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            catalog: RwLock::new(None),
+            users: RwLock::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let ttl_secs = env::var("PERMISSION_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(30);
+
+        Self::new((ttl_secs > 0).then(|| Duration::from_secs(ttl_secs)))
+    }
+
+    /// Drops every cached entry, e.g. after roles or grants were edited directly in SQL.
+    pub fn invalidate_all(&self) {
+        *self.catalog.write().unwrap() = None;
+        self.users.write().unwrap().clear();
+        self.invalidations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Drops the cached grants of one user after their roles or permissions changed.
+    pub fn invalidate_user(&self, user_temp_id: Uuid) {
+        self.users.write().unwrap().remove(&user_temp_id);
+        self.invalidations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> PermissionCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + misses;
+
+        PermissionCacheStats {
+            enabled: self.ttl.is_some(),
+            ttl_secs: self.ttl.map(|t| t.as_secs()).unwrap_or(0),
+            hits,
+            misses,
+            hit_rate: if total == 0 { 0.0 } else { hits as f64 / total as f64 },
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            cached_users: self.users.read().unwrap().len(),
+        }
+    }
+
+    /// Names of the permissions in `bits`, ordered by id, whoever holds them.
+    pub fn names_of(
+        &self,
+        conn: &mut PgConnection,
+        bits: i64,
+    ) -> Result<Vec<String>, (StatusCode, String)> {
+        Ok(self.catalog(conn)?.names_of(bits))
+    }
+
+    /// Bitmask of the permissions called `names`; `None` if one of them does not exist.
+    pub fn lookup_bits(
+        &self,
+        conn: &mut PgConnection,
+        names: &[&str],
+    ) -> Result<Option<i64>, (StatusCode, String)> {
+        let catalog = self.catalog(conn)?;
+
+        Ok(names.iter().try_fold(0, |bits, permission_name| {
+            catalog
+                .permission_ids
+                .get(*permission_name)
+                .map(|perm_id| bits | permission_bit(*perm_id))
+        }))
+    }
+
+    fn is_fresh(&self, stored_at: Instant) -> bool {
+        self.ttl.is_some_and(|ttl| stored_at.elapsed() < ttl)
+    }
+
+    fn record(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn catalog(
+        &self,
+        conn: &mut PgConnection,
+    ) -> Result<Arc<PermissionCatalog>, (StatusCode, String)> {
+        if let Some(catalog) = self.cached_catalog() {
+            self.record(true);
+            return Ok(catalog);
+        }
+        self.record(false);
+
+        let catalog = Arc::new(load_catalog(conn)?);
+        self.store_catalog(catalog.clone());
+
+        Ok(catalog)
+    }
+
+    fn cached_catalog(&self) -> Option<Arc<PermissionCatalog>> {
+        self.catalog
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(stored_at, _)| self.is_fresh(*stored_at))
+            .map(|(_, catalog)| catalog.clone())
+    }
+
+    fn store_catalog(&self, catalog: Arc<PermissionCatalog>) {
+        if self.ttl.is_some() {
+            *self.catalog.write().unwrap() = Some((Instant::now(), catalog));
+        }
+    }
+
+    fn user_grants(
+        &self,
+        conn: &mut PgConnection,
+        user_temp_id: Uuid,
+    ) -> Result<UserGrants, (StatusCode, String)> {
+        if let Some(grants) = self.cached_grants(user_temp_id) {
+            self.record(true);
+            return Ok(grants);
+        }
+        self.record(false);
+
+        let grants = load_user_grants(conn, user_temp_id)?;
+        self.store_grants(user_temp_id, grants);
+
+        Ok(grants)
+    }
+
+    fn cached_grants(&self, user_temp_id: Uuid) -> Option<UserGrants> {
+        self.users
+            .read()
+            .unwrap()
+            .get(&user_temp_id)
+            .filter(|(stored_at, _)| self.is_fresh(*stored_at))
+            .map(|(_, grants)| *grants)
+    }
+
+    fn store_grants(&self, user_temp_id: Uuid, grants: UserGrants) {
+        if self.ttl.is_some() {
+            self.users
+                .write()
+                .unwrap()
+                .insert(user_temp_id, (Instant::now(), grants));
+        }
+    }
+}
+
+fn permission_bit(perm_id: i32) -> i64 {
+    1i64 << (perm_id - 1)
+}
+
+fn load_catalog(conn: &mut PgConnection) -> Result<PermissionCatalog, (StatusCode, String)> {
+    use crate::schema::permissions::dsl::{id as perm_id, name as perm_name, permissions};
     use crate::schema::roles::dsl::{
         id as role_id, permission as role_permission, roles as roles_table,
     };
+
+    let permission_ids = permissions
+        .select((perm_name, perm_id))
+        .load::<(String, i32)>(conn)
+        .map_err(|e| internal_error("Permission query failed", e))?
+        .into_iter()
+        .collect();
+
+    let role_permissions = roles_table
+        .select((role_id, role_permission))
+        .load::<(i32, i64)>(conn)
+        .map_err(|e| internal_error("Roles query failed", e))?;
+
+    Ok(PermissionCatalog {
+        permission_ids,
+        role_permissions,
+    })
+}
+
+fn load_user_grants(
+    conn: &mut PgConnection,
+    user_temp_id: Uuid,
+) -> Result<UserGrants, (StatusCode, String)> {
     use crate::schema::users::dsl::*;
 
+    let grants = users
+        .filter(temp_id.eq(user_temp_id))
+        .select((roles, permissions))
+        .first::<(i16, i64)>(conn)
+        .optional()
+        .map_err(|e| internal_error("User query failed", e))?;
+
+    match grants {
+        Some((role_bits, permission_bits)) => Ok(UserGrants {
+            roles: role_bits,
+            permissions: permission_bits,
+        }),
+        None => Err((StatusCode::UNAUTHORIZED, "User not found".into())),
+    }
+}
+
+/// Resolves the combined role and user permissions of the caller.
+///
+/// Handlers that check more than one permission should call this once and use
+/// `ResolvedPermissions::has` for every check instead of calling `user_has_permission`
+/// repeatedly.
+pub async fn resolve_permissions(
+    cache: &PermissionCache,
+    claims: &Claims,
+    conn: &mut PgConnection,
+) -> Result<ResolvedPermissions, (StatusCode, String)> {
     let temp_uuid = Uuid::parse_str(&claims.user_temp_id)
         .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid UUID".into()))?;
 
-    let user = users
-        .filter(temp_id.eq(temp_uuid))
-        .first::<User>(conn)
-        .map_err(|_| (StatusCode::UNAUTHORIZED, "User not found".into()))?;
+    Ok(resolve_user_permissions(cache, temp_uuid, conn)
+        .await?
+        .restricted_to(claims.scope))
+}
 
+/// Resolves the combined role and user permissions of any user, e.g. a service account
+/// that credentials are issued for.
+pub async fn resolve_user_permissions(
+    cache: &PermissionCache,
+    temp_uuid: Uuid,
+    conn: &mut PgConnection,
+) -> Result<ResolvedPermissions, (StatusCode, String)> {
+    let grants = cache.user_grants(conn, temp_uuid)?;
+    let catalog = cache.catalog(conn)?;
 
-    let perm = permissions
-        .filter(perm_name.eq(permission_name))
-        .first::<Permission>(conn)
-        .optional()
-        .map_err(|e| internal_error("Permission query failed", e))?;
+    Ok(ResolvedPermissions::combine(grants, catalog))
+}
 
-    let perm = match perm {
-        Some(p) => p,
-        None => return Ok(false),
-    };
+pub async fn user_has_permission(
+    cache: &PermissionCache,
+    claims: &Claims,
+    conn: &mut PgConnection,
+    permission_name: &str,
+) -> Result<bool, (StatusCode, String)> {
+    Ok(resolve_permissions(cache, claims, conn)
+        .await?
+        .has(permission_name))
+}
 
-    let perm_bit = 1i64 << (perm.id - 1);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let all_roles = roles_table
-        .select((role_id, role_permission))
-        .load::<(i32, i64)>(conn)
-        .map_err(|e| internal_error("Roles query failed", e))?;
+    /// `can_view` (id 1) and `can_edit` (id 2) through role 1, `can_delete` (id 3) through
+    /// role 2.
+    fn catalog() -> Arc<PermissionCatalog> {
+        Arc::new(PermissionCatalog {
+            permission_ids: HashMap::from([
+                ("can_view".to_string(), 1),
+                ("can_edit".to_string(), 2),
+                ("can_delete".to_string(), 3),
+            ]),
+            role_permissions: vec![(1, 0b011), (2, 0b100)],
+        })
+    }
 
-    let user_roles_bitmask = user.roles as i32;
+    fn resolved(roles: i16, permissions: i64) -> ResolvedPermissions {
+        ResolvedPermissions::combine(UserGrants { roles, permissions }, catalog())
+    }
 
-    let mut combined_role_perm: i64 = 0;
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|name| name.to_string()).collect()
+    }
 
-    for (rid, rperm) in all_roles {
-        let role_bit = 1 << (rid - 1);
+    #[test]
+    fn combines_role_and_user_permissions() {
+        assert_eq!(resolved(0b01, 0).bits(), 0b011);
+        assert_eq!(resolved(0b10, 0).bits(), 0b100);
+        assert_eq!(resolved(0b11, 0).bits(), 0b111);
+        assert_eq!(resolved(0, 0b100).bits(), 0b100);
+        assert_eq!(resolved(0b01, 0b100).names(), names(&["can_view", "can_edit", "can_delete"]));
+    }
 
-        if (user_roles_bitmask & role_bit) != 0 {
-            combined_role_perm |= rperm;
-        }
+    #[test]
+    fn checks_permissions_by_name() {
+        let permissions = resolved(0b01, 0);
+
+        assert!(permissions.has("can_view"));
+        assert!(permissions.has("can_edit"));
+        assert!(!permissions.has("can_delete"));
+        assert!(!permissions.has("can_fly"));
     }
 
-    Ok((user.permissions & perm_bit) != 0 || (combined_role_perm & perm_bit) != 0)
+    #[test]
+    fn covers_only_held_permissions() {
+        let permissions = resolved(0b01, 0);
+
+        assert!(permissions.covers(0));
+        assert!(permissions.covers(0b001));
+        assert!(permissions.covers(0b011));
+        assert!(!permissions.covers(0b100));
+        assert!(!permissions.covers(0b111));
+    }
+
+    #[test]
+    fn bits_of_requires_every_permission_to_be_held() {
+        let permissions = resolved(0b01, 0);
+
+        assert_eq!(permissions.bits_of(&names(&["can_view", "can_edit"])), Ok(0b011));
+        assert_eq!(permissions.bits_of(&[]), Ok(0));
+
+        let (status, _) = permissions.bits_of(&names(&["can_view", "can_delete"])).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(permissions.bits_of(&names(&["can_fly"])).is_err());
+    }
+
+    #[test]
+    fn scope_limits_permissions() {
+        let scoped = resolved(0b11, 0).restricted_to(Some(0b101));
+        assert_eq!(scoped.bits(), 0b101);
+        assert!(!scoped.has("can_edit"));
+
+        // A scope cannot grant permissions the user does not hold.
+        assert_eq!(resolved(0b01, 0).restricted_to(Some(0b100)).bits(), 0);
+        assert_eq!(resolved(0b01, 0).restricted_to(None).bits(), 0b011);
+    }
+
+    #[test]
+    fn serves_cached_entries_until_they_expire() {
+        let user = Uuid::new_v4();
+        let grants = UserGrants {
+            roles: 0b01,
+            permissions: 0,
+        };
+
+        let cache = PermissionCache::new(Some(Duration::from_secs(60)));
+        cache.store_grants(user, grants);
+        cache.store_catalog(catalog());
+        assert_eq!(cache.cached_grants(user).map(|grants| grants.roles), Some(0b01));
+        assert!(cache.cached_catalog().is_some());
+
+        let expired = PermissionCache::new(Some(Duration::ZERO));
+        expired.store_grants(user, grants);
+        expired.store_catalog(catalog());
+        assert!(expired.cached_grants(user).is_none());
+        assert!(expired.cached_catalog().is_none());
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        let user = Uuid::new_v4();
+        let cache = PermissionCache::new(None);
+
+        cache.store_grants(
+            user,
+            UserGrants {
+                roles: 0b01,
+                permissions: 0,
+            },
+        );
+        cache.store_catalog(catalog());
+
+        assert!(cache.cached_grants(user).is_none());
+        assert!(cache.cached_catalog().is_none());
+        assert_eq!(cache.stats().cached_users, 0);
+    }
+
+    #[test]
+    fn invalidation_drops_entries() {
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let grants = UserGrants {
+            roles: 0b01,
+            permissions: 0,
+        };
+
+        let cache = PermissionCache::new(Some(Duration::from_secs(60)));
+        cache.store_grants(alice, grants);
+        cache.store_grants(bob, grants);
+        cache.store_catalog(catalog());
+
+        cache.invalidate_user(alice);
+        assert!(cache.cached_grants(alice).is_none());
+        assert!(cache.cached_grants(bob).is_some());
+        assert!(cache.cached_catalog().is_some());
+
+        cache.invalidate_all();
+        assert!(cache.cached_grants(bob).is_none());
+        assert!(cache.cached_catalog().is_none());
+        assert_eq!(cache.stats().invalidations, 2);
+    }
 }
@@ -0,0 +1,118 @@
+use super::{take_token, Decision, RateLimitRule, RateLimitStore};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before full ones are pruned on a request.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    refill_per_sec: f64,
+    capacity: f64,
+}
+
+impl Bucket {
+    fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens + elapsed * self.refill_per_sec >= self.capacity
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    /// Size at which a request prunes full buckets, at least `PRUNE_THRESHOLD`. It is raised
+    /// after each prune, so a map kept large by many clients is not scanned on every request.
+    prune_at: usize,
+}
+
+impl Buckets {
+    /// Drops the full buckets and returns how many there were.
+    fn prune(&mut self, now: Instant) -> usize {
+        let before = self.by_key.len();
+        // A full bucket behaves exactly like a missing one.
+        self.by_key.retain(|_, bucket| !bucket.is_full(now));
+        self.prune_at = PRUNE_THRESHOLD.max(self.by_key.len() * 2);
+
+        before - self.by_key.len()
+    }
+}
+
+/// Token buckets of this process only; every instance of the API counts on its own.
+#[derive(Default)]
+pub struct MemoryStore {
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimitStore for MemoryStore {
+    fn acquire(&self, key: &str, rule: &RateLimitRule) -> Result<Decision, String> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().map_err(|e| e.to_string())?;
+
+        if buckets.by_key.len() >= buckets.prune_at.max(PRUNE_THRESHOLD) {
+            buckets.prune(now);
+        }
+
+        let bucket = buckets.by_key.entry(key.to_string()).or_insert_with(|| Bucket {
+            tokens: rule.capacity,
+            updated_at: now,
+            refill_per_sec: rule.refill_per_sec,
+            capacity: rule.capacity,
+        });
+
+        let (tokens, decision) = take_token(bucket.tokens, now - bucket.updated_at, rule);
+        bucket.tokens = tokens;
+        bucket.updated_at = now;
+
+        Ok(decision)
+    }
+
+    fn prune(&self, _refill_time: Duration) -> Result<usize, String> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().map_err(|e| e.to_string())?;
+
+        Ok(buckets.prune(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_rule;
+    use super::*;
+
+    #[test]
+    fn prunes_only_full_buckets() {
+        let store = MemoryStore::default();
+        let slow = parse_rule("*=1/3600").unwrap();
+        let fast = parse_rule("*=1000/1").unwrap();
+
+        store.acquire("slow", &slow).unwrap();
+        store.acquire("fast", &fast).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(store.prune(Duration::from_secs(3600)), Ok(1));
+
+        let buckets = store.buckets.lock().unwrap();
+        assert!(buckets.by_key.contains_key("slow"));
+        assert!(!buckets.by_key.contains_key("fast"));
+    }
+
+    #[test]
+    fn raises_prune_threshold_while_buckets_are_in_use() {
+        let store = MemoryStore::default();
+        let slow = parse_rule("*=1/3600").unwrap();
+
+        for client in 0..PRUNE_THRESHOLD {
+            store.acquire(&client.to_string(), &slow).unwrap();
+        }
+        // None of the buckets is full, so pruning keeps them all and waits for twice as many.
+        store.acquire("next", &slow).unwrap();
+        store.acquire("after", &slow).unwrap();
+
+        let buckets = store.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), PRUNE_THRESHOLD + 2);
+        assert_eq!(buckets.prune_at, PRUNE_THRESHOLD * 2);
+    }
+}
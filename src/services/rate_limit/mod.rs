@@ -0,0 +1,431 @@
+//! Request throttling for every route.
+//!
+//! Rules are matched against the method and route pattern of a request; the first match
+//! decides which token bucket the request draws from. Buckets are keyed by client IP, user
+//! or API key and live in memory, or in Postgres when several instances share the limits.
+//! Buckets that refilled completely are pruned periodically.
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+
+use crate::config::AppConfig;
+use crate::db::Pool;
+use crate::services::api_keys::{api_key_from_headers, verified_api_key_id};
+use crate::services::jwt::peek_claims;
+use crate::utils::client_ip::client_ip;
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::{header::RETRY_AFTER, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_POLICIES: &str =
+    "POST /users=5/3600:ip; POST /auth=10/60:ip; GET /users=60/60:user; GET /roles=60/60:ip; *=300/60:user";
+const PRUNE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// What a bucket is keyed by. `User` and `ApiKey` fall back to the next weaker key when
+/// the request carries no such credential.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitKey {
+    Ip,
+    User,
+    ApiKey,
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimitRule {
+    /// `None` matches every method.
+    method: Option<String>,
+    /// Route pattern as registered on the router, `None` matches every route.
+    path: Option<String>,
+    key: RateLimitKey,
+    /// Bucket size, i.e. the burst allowed after a quiet period.
+    pub capacity: f64,
+    /// Tokens added per second.
+    pub refill_per_sec: f64,
+}
+
+impl RateLimitRule {
+    /// Time an empty bucket takes to fill up again.
+    fn refill_time(&self) -> Duration {
+        Duration::from_secs_f64(self.capacity / self.refill_per_sec)
+    }
+
+    fn matches(&self, method: &str, path: &str) -> bool {
+        self.method.as_deref().is_none_or(|m| m == method)
+            && self.path.as_deref().is_none_or(|p| p == path)
+    }
+
+    fn label(&self) -> String {
+        format!(
+            "{} {}",
+            self.method.as_deref().unwrap_or("*"),
+            self.path.as_deref().unwrap_or("*")
+        )
+    }
+}
+
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+/// Storage for token buckets.
+pub trait RateLimitStore: Send + Sync {
+    /// Takes one token from the bucket `key`, creating a full bucket if it does not exist.
+    fn acquire(&self, key: &str, rule: &RateLimitRule) -> Result<Decision, String>;
+
+    /// Drops buckets that are full again, given that no bucket takes longer than `refill_time`
+    /// to refill. Returns how many were dropped.
+    fn prune(&self, refill_time: Duration) -> Result<usize, String>;
+}
+
+/// Refills a bucket holding `tokens` after `elapsed` and takes one token if possible.
+///
+/// Returns the new token count and the decision.
+fn take_token(tokens: f64, elapsed: Duration, rule: &RateLimitRule) -> (f64, Decision) {
+    let tokens = (tokens + elapsed.as_secs_f64() * rule.refill_per_sec).min(rule.capacity);
+
+    if tokens >= 1.0 {
+        (tokens - 1.0, Decision::Allowed)
+    } else {
+        let wait = (1.0 - tokens) / rule.refill_per_sec;
+        (
+            tokens,
+            Decision::Limited {
+                retry_after: Duration::from_secs_f64(wait),
+            },
+        )
+    }
+}
+
+pub struct RateLimiter {
+    rules: Vec<RateLimitRule>,
+    store: Box<dyn RateLimitStore>,
+    pool: Arc<Pool>,
+    jwt_secret: Arc<String>,
+    trust_proxy_headers: bool,
+}
+
+impl RateLimiter {
+    /// Reads `RATE_LIMIT_POLICIES` and `RATE_LIMIT_STORE` (`memory` or `postgres`).
+    ///
+    /// An empty `RATE_LIMIT_POLICIES` turns rate limiting off.
+    pub fn from_env(
+        pool: Arc<Pool>,
+        jwt_secret: Arc<String>,
+        config: &AppConfig,
+    ) -> Result<Self, String> {
+        let policies = env::var("RATE_LIMIT_POLICIES").unwrap_or_else(|_| DEFAULT_POLICIES.into());
+        let rules = parse_policies(&policies)?;
+
+        let store: Box<dyn RateLimitStore> =
+            match env::var("RATE_LIMIT_STORE").as_deref().unwrap_or("memory") {
+                "memory" => Box::new(MemoryStore::default()),
+                "postgres" => Box::new(PostgresStore::new(pool.clone())),
+                other => return Err(format!("Unknown RATE_LIMIT_STORE '{}'", other)),
+            };
+
+        Ok(Self {
+            rules,
+            store,
+            pool,
+            jwt_secret,
+            trust_proxy_headers: config.trust_proxy_headers,
+        })
+    }
+
+    async fn bucket_key(
+        &self,
+        rule: &RateLimitRule,
+        headers: &HeaderMap,
+        peer: SocketAddr,
+    ) -> String {
+        let user = || {
+            peek_claims(&self.jwt_secret, headers)
+                .map(|claims| format!("user:{}", claims.user_temp_id))
+        };
+        let ip = || format!("ip:{}", client_ip(headers, peer, self.trust_proxy_headers));
+
+        let subject = match rule.key {
+            RateLimitKey::ApiKey => match self.api_key_subject(headers).await {
+                Some(subject) => subject,
+                None => user().unwrap_or_else(ip),
+            },
+            RateLimitKey::User => user().unwrap_or_else(ip),
+            RateLimitKey::Ip => ip(),
+        };
+
+        format!("{}|{}", rule.label(), subject)
+    }
+
+    /// `key:<id>` for a valid API key in `headers`. Only keys that exist get a bucket of their
+    /// own; made-up keys would each get a fresh one.
+    async fn api_key_subject(&self, headers: &HeaderMap) -> Option<String> {
+        let key = api_key_from_headers(headers)?.to_string();
+        let pool = self.pool.clone();
+
+        // The lookup blocks on the database, which must not stall the runtime's workers.
+        let verified = tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get().map_err(|e| e.to_string())?;
+            verified_api_key_id(&mut conn, &key).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .flatten();
+
+        match verified {
+            Ok(key_id) => key_id.map(|key_id| format!("key:{}", key_id)),
+            Err(e) => {
+                tracing::error!("Rate limit API key lookup failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Time the slowest bucket takes to refill.
+    fn refill_time(&self) -> Duration {
+        self.rules
+            .iter()
+            .map(RateLimitRule::refill_time)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Prunes full buckets every `PRUNE_INTERVAL`, for as long as the server runs.
+pub async fn prune_buckets_periodically(limiter: Arc<RateLimiter>) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match limiter.store.prune(limiter.refill_time()) {
+            Ok(0) => {}
+            Ok(count) => tracing::debug!("Pruned {} full rate limit buckets", count),
+            Err(e) => tracing::error!("Pruning rate limit buckets failed: {}", e),
+        }
+    }
+}
+
+/// Parses rules of the form `METHOD /route=REQUESTS/SECONDS[:ip|user|api_key]`, separated by
+/// `;`. A lone `*` instead of method and route matches every request.
+fn parse_policies(spec: &str) -> Result<Vec<RateLimitRule>, String> {
+    spec.split(';')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| parse_rule(rule).ok_or_else(|| format!("Invalid rate limit rule '{}'", rule)))
+        .collect()
+}
+
+fn parse_rule(rule: &str) -> Option<RateLimitRule> {
+    let (target, limit) = rule.split_once('=')?;
+    let (limit, key) = match limit.split_once(':') {
+        Some((limit, key)) => (limit, key.trim()),
+        None => (limit, "ip"),
+    };
+    let (requests, seconds) = limit.split_once('/')?;
+    let requests = requests.trim().parse::<u32>().ok().filter(|r| *r > 0)?;
+    let seconds = seconds.trim().parse::<u32>().ok().filter(|s| *s > 0)?;
+
+    let key = match key {
+        "ip" => RateLimitKey::Ip,
+        "user" => RateLimitKey::User,
+        "api_key" => RateLimitKey::ApiKey,
+        _ => return None,
+    };
+
+    let (method, path) = match target.trim() {
+        "*" => (None, None),
+        target => {
+            let (method, path) = target.split_once(' ')?;
+            let method = Some(method.trim().to_uppercase()).filter(|m| m != "*");
+            let path = Some(path.trim().to_string()).filter(|p| p != "*");
+            (method, path)
+        }
+    };
+
+    Some(RateLimitRule {
+        method,
+        path,
+        key,
+        capacity: requests as f64,
+        refill_per_sec: requests as f64 / seconds as f64,
+    })
+}
+
+/// Middleware answering `429 Too Many Requests` with `Retry-After` once a bucket is empty.
+///
+/// A failing store lets the request through, so an outage of the store cannot take the
+/// API down with it.
+pub async fn enforce_rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().as_str();
+    let path = matched_path
+        .as_ref()
+        .map(MatchedPath::as_str)
+        .unwrap_or_else(|| request.uri().path());
+
+    let Some(rule) = limiter.rules.iter().find(|rule| rule.matches(method, path)) else {
+        return next.run(request).await;
+    };
+
+    let key = limiter.bucket_key(rule, request.headers(), peer).await;
+
+    match limiter.store.acquire(&key, rule) {
+        Ok(Decision::Allowed) => next.run(request).await,
+        Ok(Decision::Limited { retry_after }) => {
+            let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, seconds.to_string())],
+                "Too many requests",
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Rate limit store failed: {}", e);
+            next.run(request).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use diesel::r2d2::ConnectionManager;
+
+    #[test]
+    fn parses_rule_with_defaults() {
+        let rule = parse_rule("post /auth=10/60").unwrap();
+
+        assert_eq!(rule.method.as_deref(), Some("POST"));
+        assert_eq!(rule.path.as_deref(), Some("/auth"));
+        assert_eq!(rule.key, RateLimitKey::Ip);
+        assert_eq!(rule.capacity, 10.0);
+        assert_eq!(rule.refill_per_sec, 10.0 / 60.0);
+        assert_eq!(rule.refill_time(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn parses_wildcards_and_keys() {
+        let any = parse_rule("*=300/60:user").unwrap();
+        assert!(any.method.is_none() && any.path.is_none());
+        assert_eq!(any.key, RateLimitKey::User);
+        assert!(any.matches("DELETE", "/users/{id}"));
+
+        let any_method = parse_rule("* /users=5/1:api_key").unwrap();
+        assert_eq!(any_method.key, RateLimitKey::ApiKey);
+        assert!(any_method.matches("GET", "/users"));
+        assert!(!any_method.matches("GET", "/roles"));
+
+        let any_path = parse_rule("GET *=5/1").unwrap();
+        assert!(any_path.matches("GET", "/roles"));
+        assert!(!any_path.matches("POST", "/roles"));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for rule in [
+            "POST /auth=10/60:session",
+            "POST /auth=0/60",
+            "POST /auth=10/0",
+            "POST /auth=-1/60",
+            "POST /auth=10",
+            "POST /auth",
+            "/auth=10/60",
+        ] {
+            assert!(parse_rule(rule).is_none(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(parse_policies(DEFAULT_POLICIES).unwrap().len(), 5);
+        assert!(parse_policies("").unwrap().is_empty());
+        assert_eq!(parse_policies(" *=1/1 ; ").unwrap().len(), 1);
+        assert_eq!(
+            parse_policies("*=1/1; GET /users=1/0").unwrap_err(),
+            "Invalid rate limit rule 'GET /users=1/0'"
+        );
+    }
+
+    #[test]
+    fn takes_tokens_and_refills() {
+        let rule = parse_rule("*=2/10").unwrap();
+
+        let (tokens, decision) = take_token(2.0, Duration::ZERO, &rule);
+        assert_eq!(tokens, 1.0);
+        assert!(matches!(decision, Decision::Allowed));
+
+        // Five seconds refill one token, and a bucket never holds more than its capacity.
+        let (tokens, decision) = take_token(0.0, Duration::from_secs(5), &rule);
+        assert_eq!(tokens, 0.0);
+        assert!(matches!(decision, Decision::Allowed));
+        let (tokens, _) = take_token(1.0, Duration::from_secs(3600), &rule);
+        assert_eq!(tokens, 1.0);
+    }
+
+    #[test]
+    fn reports_time_until_next_token() {
+        let rule = parse_rule("*=2/10").unwrap();
+
+        let (tokens, decision) = take_token(0.0, Duration::from_secs(1), &rule);
+
+        assert_eq!(tokens, 0.2);
+        let Decision::Limited { retry_after } = decision else {
+            panic!("not limited");
+        };
+        assert_eq!(retry_after, Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn answers_too_many_requests_with_retry_after() {
+        // The pool is never used for IP keyed rules, so it connects nowhere.
+        let pool = Pool::builder()
+            .build_unchecked(ConnectionManager::new("postgres://localhost/unused"));
+        let limiter = Arc::new(RateLimiter {
+            rules: parse_policies("GET /ping=2/60").unwrap(),
+            store: Box::new(MemoryStore::default()),
+            pool: Arc::new(pool),
+            jwt_secret: Arc::new("secret".into()),
+            trust_proxy_headers: false,
+        });
+        let app = Router::new()
+            .route("/ping", get(|| async { "pong" }))
+            .route("/other", get(|| async { "other" }))
+            .layer(middleware::from_fn_with_state(limiter, enforce_rate_limit));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        for _ in 0..2 {
+            let response = client.get(format!("{}/ping", url)).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let limited = client.get(format!("{}/ping", url)).send().await.unwrap();
+        let other = client.get(format!("{}/other", url)).send().await.unwrap();
+
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()[RETRY_AFTER], "30");
+        assert_eq!(other.status(), StatusCode::OK);
+    }
+}
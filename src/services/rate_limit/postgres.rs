@@ -0,0 +1,62 @@
+use super::{take_token, Decision, RateLimitRule, RateLimitStore};
+use crate::db::Pool;
+use chrono::Utc;
+use diesel::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Token buckets in the `rate_limit_buckets` table, shared by every instance of the API.
+pub struct PostgresStore {
+    pool: Arc<Pool>,
+}
+
+impl PostgresStore {
+    pub fn new(pool: Arc<Pool>) -> Self {
+        Self { pool }
+    }
+}
+
+impl RateLimitStore for PostgresStore {
+    fn acquire(&self, bucket_key: &str, rule: &RateLimitRule) -> Result<Decision, String> {
+        use crate::schema::rate_limit_buckets::dsl::*;
+
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        conn.transaction(|conn| {
+            let now = Utc::now();
+
+            diesel::insert_into(rate_limit_buckets)
+                .values((key.eq(bucket_key), tokens.eq(rule.capacity), updated_at.eq(now)))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
+            let (stored_tokens, stored_at) = rate_limit_buckets
+                .find(bucket_key)
+                .select((tokens, updated_at))
+                .for_update()
+                .first::<(f64, chrono::DateTime<Utc>)>(conn)?;
+
+            let elapsed = (now - stored_at).to_std().unwrap_or_default();
+            let (remaining, decision) = take_token(stored_tokens, elapsed, rule);
+
+            diesel::update(rate_limit_buckets.find(bucket_key))
+                .set((tokens.eq(remaining), updated_at.eq(now)))
+                .execute(conn)?;
+
+            Ok::<_, diesel::result::Error>(decision)
+        })
+        .map_err(|e| e.to_string())
+    }
+
+    fn prune(&self, refill_time: Duration) -> Result<usize, String> {
+        use crate::schema::rate_limit_buckets::dsl::*;
+
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        let idle = chrono::Duration::from_std(refill_time).map_err(|e| e.to_string())?;
+
+        // Rows do not know their rule; a bucket idle for the longest refill time is full.
+        diesel::delete(rate_limit_buckets.filter(updated_at.lt(Utc::now() - idle)))
+            .execute(&mut conn)
+            .map_err(|e| e.to_string())
+    }
+}
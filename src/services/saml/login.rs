@@ -0,0 +1,132 @@
+//! Stored state of SAML logins: pending requests, used assertions and the codes that hand a
+//! finished login over to the login frontend.
+
+use super::SamlProvider;
+use crate::models::{NewSamlAssertion, NewSamlLoginCode, NewSamlRequest};
+use crate::utils::error::internal_error;
+use crate::utils::token::{generate_token, hash_token};
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+
+const REQUEST_LIFETIME_MINUTES: i64 = 10;
+const LOGIN_CODE_LIFETIME_SECONDS: i64 = 60;
+
+/// Records a new `AuthnRequest` to `provider` and returns its id.
+pub fn create_authn_request(
+    conn: &mut PgConnection,
+    provider: &SamlProvider,
+) -> Result<String, (StatusCode, String)> {
+    use crate::schema::saml_requests::dsl::{expires_at, saml_requests};
+
+    // IDs are XML names, which must not start with a digit.
+    let request_id = format!("_{}", generate_token());
+
+    conn.transaction(|conn| {
+        diesel::delete(saml_requests.filter(expires_at.le(Utc::now()))).execute(conn)?;
+
+        diesel::insert_into(saml_requests)
+            .values(&NewSamlRequest {
+                id: request_id.clone(),
+                provider: provider.id.clone(),
+                expires_at: Utc::now() + Duration::minutes(REQUEST_LIFETIME_MINUTES),
+            })
+            .execute(conn)
+    })
+    .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok(request_id)
+}
+
+/// Consumes the pending request `request_id` and returns the provider it was sent to.
+///
+/// Responses the identity provider sends on its own, without a request, are rejected this way.
+pub fn take_authn_request(
+    conn: &mut PgConnection,
+    request_id: &str,
+) -> Result<String, (StatusCode, String)> {
+    use crate::schema::saml_requests::dsl::{expires_at, provider, saml_requests};
+
+    diesel::delete(saml_requests.find(request_id))
+        .returning((provider, expires_at))
+        .get_result::<(String, DateTime<Utc>)>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB delete error", e))?
+        .filter(|(_, expires)| *expires > Utc::now())
+        .map(|(provider_id, _)| provider_id)
+        .ok_or((StatusCode::BAD_REQUEST, "Unknown or expired SAML request".into()))
+}
+
+/// Remembers an assertion until it expires; fails if it was used before.
+pub fn record_assertion(
+    conn: &mut PgConnection,
+    provider: &SamlProvider,
+    assertion_id: &str,
+    valid_until: DateTime<Utc>,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::saml_assertions::dsl::{expires_at, saml_assertions};
+
+    conn.transaction(|conn| {
+        diesel::delete(saml_assertions.filter(expires_at.le(Utc::now()))).execute(conn)?;
+
+        diesel::insert_into(saml_assertions)
+            .values(&NewSamlAssertion {
+                provider: provider.id.clone(),
+                assertion_id: assertion_id.to_string(),
+                expires_at: valid_until,
+            })
+            .execute(conn)
+    })
+    .map_err(|e| match e {
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => (
+            StatusCode::BAD_REQUEST,
+            "Invalid SAML response: assertion was already used".into(),
+        ),
+        e => internal_error("DB insert error", e),
+    })?;
+
+    Ok(())
+}
+
+/// Creates the one-time code the login frontend exchanges for the login response.
+pub fn create_login_code(
+    conn: &mut PgConnection,
+    owner_id: i32,
+) -> Result<String, (StatusCode, String)> {
+    use crate::schema::saml_login_codes::dsl::{expires_at, saml_login_codes};
+
+    let code = generate_token();
+
+    conn.transaction(|conn| {
+        diesel::delete(saml_login_codes.filter(expires_at.le(Utc::now()))).execute(conn)?;
+
+        diesel::insert_into(saml_login_codes)
+            .values(&NewSamlLoginCode {
+                code_hash: hash_token(&code),
+                user_id: owner_id,
+                expires_at: Utc::now() + Duration::seconds(LOGIN_CODE_LIFETIME_SECONDS),
+            })
+            .execute(conn)
+    })
+    .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok(code)
+}
+
+/// Consumes a login code and returns the id of the user who logged in.
+pub fn consume_login_code(
+    conn: &mut PgConnection,
+    code: &str,
+) -> Result<i32, (StatusCode, String)> {
+    use crate::schema::saml_login_codes::dsl::{expires_at, saml_login_codes, user_id};
+
+    diesel::delete(saml_login_codes.find(hash_token(code)))
+        .returning((user_id, expires_at))
+        .get_result::<(i32, DateTime<Utc>)>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB delete error", e))?
+        .filter(|(_, expires)| *expires > Utc::now())
+        .map(|(owner_id, _)| owner_id)
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired login code".into()))
+}
@@ -0,0 +1,224 @@
+//! SAML 2.0 single sign-on, with us as the service provider.
+//!
+//! Logins start at our side: the browser is sent to the identity provider with an
+//! `AuthnRequest` (HTTP-Redirect binding) and comes back with a signed response posted to the
+//! assertion consumer service (HTTP-POST binding). Users are linked and created like those of
+//! federated OpenID Connect logins, see `federation::accounts`.
+
+pub mod login;
+pub mod response;
+pub mod xmldsig;
+
+use crate::config::AppConfig;
+use crate::services::federation::accounts::AccountPolicy;
+use crate::services::federation::{FederatedProviders, ProviderEnv};
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::RsaPublicKey;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use url::Url;
+use x509_cert::der::Encode;
+use x509_cert::Certificate;
+use xmldsig::escape_attribute;
+
+pub const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+pub const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const METADATA_NS: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const HTTP_POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const NAME_ID_FORMAT_UNSPECIFIED: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:unspecified";
+const NAME_ID_FORMAT_EMAIL: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+
+/// Which attributes of an assertion fill which fields of a user.
+struct AttributeMapping {
+    /// `None` takes the email address from the `NameID`.
+    email: Option<String>,
+    first_name: String,
+    last_name: String,
+    username: Option<String>,
+    /// Attribute listing the user's groups, mapped to roles with `role_mapping`.
+    roles: Option<String>,
+    /// Group to role names.
+    role_mapping: HashMap<String, Vec<String>>,
+}
+
+/// An identity provider configured with `SAML_<ID>_*` variables.
+pub struct SamlProvider {
+    pub id: String,
+    pub name: String,
+    entity_id: String,
+    sso_url: String,
+    /// Keys of the signing certificates; more than one while the provider rotates them.
+    keys: Vec<RsaPublicKey>,
+    attributes: AttributeMapping,
+    pub accounts: AccountPolicy,
+}
+
+/// The configured identity providers.
+pub struct SamlProviders {
+    providers: Vec<SamlProvider>,
+}
+
+/// Reads the RSA keys of the PEM certificates in `path`.
+fn load_certificate_keys(path: &str) -> Result<Vec<RsaPublicKey>, String> {
+    let pem = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let certificates = Certificate::load_pem_chain(&pem)
+        .map_err(|e| format!("Invalid certificate in {}: {}", path, e))?;
+    if certificates.is_empty() {
+        return Err(format!("No certificate in {}", path));
+    }
+
+    certificates
+        .iter()
+        .map(|certificate| {
+            let spki = certificate
+                .tbs_certificate
+                .subject_public_key_info
+                .to_der()
+                .map_err(|e| format!("Invalid certificate in {}: {}", path, e))?;
+            RsaPublicKey::from_public_key_der(&spki)
+                .map_err(|_| format!("Certificate in {} has no RSA key", path))
+        })
+        .collect()
+}
+
+impl SamlProvider {
+    fn from_env(id: &str) -> Result<Self, String> {
+        let settings = ProviderEnv::new("SAML", id)?;
+
+        Ok(Self {
+            name: settings.var("NAME").unwrap_or_else(|| id.to_string()),
+            entity_id: settings.required("ENTITY_ID")?,
+            sso_url: settings.required("SSO_URL")?,
+            keys: load_certificate_keys(&settings.required("CERTIFICATE_FILE")?)?,
+            attributes: AttributeMapping {
+                email: settings.var("EMAIL_ATTRIBUTE"),
+                first_name: settings
+                    .var("FIRST_NAME_ATTRIBUTE")
+                    .unwrap_or_else(|| "firstName".into()),
+                last_name: settings
+                    .var("LAST_NAME_ATTRIBUTE")
+                    .unwrap_or_else(|| "lastName".into()),
+                username: settings.var("USERNAME_ATTRIBUTE"),
+                roles: settings.var("ROLES_ATTRIBUTE"),
+                role_mapping: settings.mapping("ROLE_MAPPING")?,
+            },
+            accounts: AccountPolicy::from_env(&settings),
+            id: settings.id,
+        })
+    }
+
+    /// URL of the provider's single sign-on service carrying an `AuthnRequest` with
+    /// `request_id`, to send the browser to.
+    pub fn authn_request_url(
+        &self,
+        config: &AppConfig,
+        request_id: &str,
+    ) -> Result<String, (StatusCode, String)> {
+        let request = format!(
+            concat!(
+                "<samlp:AuthnRequest xmlns:samlp=\"{}\" xmlns:saml=\"{}\" ID=\"{}\" ",
+                "Version=\"2.0\" IssueInstant=\"{}\" Destination=\"{}\" ",
+                "AssertionConsumerServiceURL=\"{}\" ProtocolBinding=\"{}\">",
+                "<saml:Issuer>{}</saml:Issuer>",
+                "<samlp:NameIDPolicy Format=\"{}\" AllowCreate=\"true\"/>",
+                "</samlp:AuthnRequest>"
+            ),
+            PROTOCOL_NS,
+            ASSERTION_NS,
+            request_id,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            escape_attribute(&self.sso_url),
+            escape_attribute(&config.saml_acs_url),
+            HTTP_POST_BINDING,
+            escape_attribute(&config.saml_sp_entity_id),
+            NAME_ID_FORMAT_UNSPECIFIED,
+        );
+
+        // The redirect binding sends the request DEFLATE-compressed and base64-encoded.
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let compressed = encoder
+            .write_all(request.as_bytes())
+            .and_then(|_| encoder.finish())
+            .map_err(|e| internal_error("SAML request encoding failed", e))?;
+
+        let mut url =
+            Url::parse(&self.sso_url).map_err(|e| internal_error("Invalid SAML SSO URL", e))?;
+        url.query_pairs_mut()
+            .append_pair("SAMLRequest", &STANDARD.encode(compressed));
+
+        Ok(url.to_string())
+    }
+}
+
+impl SamlProviders {
+    /// Reads the comma-separated provider ids in `SAML_PROVIDERS` and their settings.
+    ///
+    /// Identities of both kinds of provider are linked by provider id, so the ids must not be
+    /// used by a federated OpenID Connect provider too.
+    pub fn from_env(federated: &FederatedProviders) -> Result<Self, String> {
+        let providers = env::var("SAML_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(SamlProvider::from_env)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(provider) = providers
+            .iter()
+            .find(|provider| federated.find(&provider.id).is_ok())
+        {
+            return Err(format!(
+                "Provider id '{}' is used in FEDERATED_PROVIDERS and SAML_PROVIDERS",
+                provider.id
+            ));
+        }
+
+        Ok(Self { providers })
+    }
+
+    pub fn list(&self) -> &[SamlProvider] {
+        &self.providers
+    }
+
+    pub fn find(&self, id: &str) -> Result<&SamlProvider, (StatusCode, String)> {
+        self.providers
+            .iter()
+            .find(|provider| provider.id == id)
+            .ok_or((StatusCode::NOT_FOUND, "Unknown identity provider".into()))
+    }
+}
+
+/// Our service provider metadata, to register at identity providers.
+pub fn sp_metadata(config: &AppConfig) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<md:EntityDescriptor xmlns:md=\"{}\" entityID=\"{}\">\n",
+            "  <md:SPSSODescriptor AuthnRequestsSigned=\"false\" WantAssertionsSigned=\"true\" ",
+            "protocolSupportEnumeration=\"{}\">\n",
+            "    <md:NameIDFormat>{}</md:NameIDFormat>\n",
+            "    <md:NameIDFormat>{}</md:NameIDFormat>\n",
+            "    <md:AssertionConsumerService Binding=\"{}\" Location=\"{}\" index=\"0\" ",
+            "isDefault=\"true\"/>\n",
+            "  </md:SPSSODescriptor>\n",
+            "</md:EntityDescriptor>\n"
+        ),
+        METADATA_NS,
+        escape_attribute(&config.saml_sp_entity_id),
+        PROTOCOL_NS,
+        NAME_ID_FORMAT_EMAIL,
+        NAME_ID_FORMAT_UNSPECIFIED,
+        HTTP_POST_BINDING,
+        escape_attribute(&config.saml_acs_url),
+    )
+}
@@ -0,0 +1,284 @@
+//! Verification of the responses identity providers post to the assertion consumer service.
+
+use super::xmldsig::{signature_of, verify_signature};
+use super::{SamlProvider, ASSERTION_NS, PROTOCOL_NS};
+use crate::config::AppConfig;
+use crate::services::federation::UpstreamIdentity;
+use axum::http::StatusCode;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::HashMap;
+
+const MAX_RESPONSE_BYTES: usize = 256 * 1024;
+const MAX_RESPONSE_NODES: u32 = 10_000;
+/// Tolerated difference between our clock and the identity provider's.
+const CLOCK_SKEW_SECONDS: i64 = 180;
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+
+/// The verified assertion of a response.
+pub struct VerifiedAssertion {
+    pub id: String,
+    /// When the assertion stops being valid; until then it is remembered to reject replays.
+    pub expires_at: DateTime<Utc>,
+    pub identity: UpstreamIdentity,
+}
+
+fn invalid(msg: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, format!("Invalid SAML response: {}", msg))
+}
+
+/// Decodes the base64 `SAMLResponse` form field.
+pub fn decode_response(encoded: &str) -> Result<String, (StatusCode, String)> {
+    let compact: String = encoded.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let decoded = STANDARD.decode(compact).map_err(|_| invalid("not base64"))?;
+    if decoded.len() > MAX_RESPONSE_BYTES {
+        return Err(invalid("too large"));
+    }
+    String::from_utf8(decoded).map_err(|_| invalid("not UTF-8"))
+}
+
+/// Parses a decoded response; DTDs, and with them entity expansion, are rejected.
+pub fn parse_response(xml: &str) -> Result<Document<'_>, (StatusCode, String)> {
+    Document::parse_with_options(
+        xml,
+        ParsingOptions {
+            allow_dtd: false,
+            nodes_limit: MAX_RESPONSE_NODES,
+            ..ParsingOptions::default()
+        },
+    )
+    .map_err(invalid)
+}
+
+/// `InResponseTo` of a response, naming the request it answers.
+///
+/// Read before the response is verified, to find the provider that has to verify it.
+pub fn in_response_to<'a>(document: &'a Document) -> Option<&'a str> {
+    document.root_element().attribute("InResponseTo")
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, ns: &str, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name((ns, name)))
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    ns: &'a str,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| child.has_tag_name((ns, name)))
+}
+
+fn text<'a>(node: Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or_default().trim()
+}
+
+fn instant(node: Node, attribute: &str) -> Result<Option<DateTime<Utc>>, (StatusCode, String)> {
+    node.attribute(attribute)
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|parsed| parsed.with_timezone(&Utc))
+                .map_err(|_| invalid(format!("invalid {}", attribute)))
+        })
+        .transpose()
+}
+
+impl SamlProvider {
+    /// Verifies a response to the request `request_id` and maps its assertion to an identity.
+    ///
+    /// Either the response or the assertion has to be signed by one of the provider's
+    /// certificates. The assertion must be issued by the provider for our entity id, be
+    /// currently valid and confirm a bearer subject for our assertion consumer service.
+    pub fn verify_response(
+        &self,
+        config: &AppConfig,
+        document: &Document,
+        request_id: &str,
+    ) -> Result<VerifiedAssertion, (StatusCode, String)> {
+        let response = document.root_element();
+        if !response.has_tag_name((PROTOCOL_NS, "Response"))
+            || response.attribute("Version") != Some("2.0")
+        {
+            return Err(invalid("not a SAML 2.0 response"));
+        }
+        if response.attribute("InResponseTo") != Some(request_id) {
+            return Err(invalid("response does not answer the request"));
+        }
+        if let Some(destination) = response.attribute("Destination")
+            && destination != config.saml_acs_url
+        {
+            return Err(invalid("wrong destination"));
+        }
+        if let Some(issuer) = child(response, ASSERTION_NS, "Issuer")
+            && text(issuer) != self.entity_id
+        {
+            return Err(invalid("unexpected issuer"));
+        }
+
+        let status = child(response, PROTOCOL_NS, "Status")
+            .and_then(|status| child(status, PROTOCOL_NS, "StatusCode"))
+            .and_then(|code| code.attribute("Value"))
+            .ok_or_else(|| invalid("missing status"))?;
+        if status != STATUS_SUCCESS {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                format!("The identity provider did not authenticate the user: {}", status),
+            ));
+        }
+
+        if child(response, ASSERTION_NS, "EncryptedAssertion").is_some() {
+            return Err(invalid("encrypted assertions are not supported"));
+        }
+        let mut assertions = children(response, ASSERTION_NS, "Assertion");
+        let assertion = assertions.next().ok_or_else(|| invalid("missing assertion"))?;
+        if assertions.next().is_some() {
+            return Err(invalid("more than one assertion"));
+        }
+
+        let response_signed = signature_of(response).is_some();
+        if response_signed {
+            verify_signature(response, &self.keys).map_err(invalid)?;
+        }
+        if signature_of(assertion).is_some() {
+            verify_signature(assertion, &self.keys).map_err(invalid)?;
+        } else if !response_signed {
+            return Err(invalid("neither the response nor the assertion is signed"));
+        }
+
+        self.verify_assertion(config, assertion, request_id)
+    }
+
+    fn verify_assertion(
+        &self,
+        config: &AppConfig,
+        assertion: Node,
+        request_id: &str,
+    ) -> Result<VerifiedAssertion, (StatusCode, String)> {
+        let now = Utc::now();
+        let skew = Duration::seconds(CLOCK_SKEW_SECONDS);
+
+        let id = assertion
+            .attribute("ID")
+            .ok_or_else(|| invalid("assertion has no ID"))?;
+        if child(assertion, ASSERTION_NS, "Issuer").map(text) != Some(self.entity_id.as_str()) {
+            return Err(invalid("unexpected assertion issuer"));
+        }
+
+        let conditions = child(assertion, ASSERTION_NS, "Conditions")
+            .ok_or_else(|| invalid("missing conditions"))?;
+        if instant(conditions, "NotBefore")?.is_some_and(|not_before| not_before > now + skew) {
+            return Err(invalid("assertion is not valid yet"));
+        }
+        let conditions_expire = instant(conditions, "NotOnOrAfter")?;
+        if conditions_expire.is_some_and(|not_on_or_after| not_on_or_after <= now - skew) {
+            return Err(invalid("assertion expired"));
+        }
+
+        let mut restrictions =
+            children(conditions, ASSERTION_NS, "AudienceRestriction").peekable();
+        if restrictions.peek().is_none() {
+            return Err(invalid("missing audience restriction"));
+        }
+        for restriction in restrictions {
+            if !children(restriction, ASSERTION_NS, "Audience")
+                .any(|audience| text(audience) == config.saml_sp_entity_id)
+            {
+                return Err(invalid("assertion is meant for another audience"));
+            }
+        }
+
+        let subject = child(assertion, ASSERTION_NS, "Subject")
+            .ok_or_else(|| invalid("missing subject"))?;
+        let name_id = child(subject, ASSERTION_NS, "NameID")
+            .map(text)
+            .filter(|name_id| !name_id.is_empty())
+            .ok_or_else(|| invalid("missing NameID"))?;
+
+        let mut confirmation_expires = None;
+        for confirmation in children(subject, ASSERTION_NS, "SubjectConfirmation") {
+            let Some(data) = child(confirmation, ASSERTION_NS, "SubjectConfirmationData") else {
+                continue;
+            };
+            let expires = instant(data, "NotOnOrAfter")?;
+            if confirmation.attribute("Method") == Some(BEARER)
+                && data.attribute("Recipient") == Some(config.saml_acs_url.as_str())
+                && data.attribute("InResponseTo").is_none_or(|id| id == request_id)
+                && expires.is_some_and(|not_on_or_after| not_on_or_after > now - skew)
+            {
+                confirmation_expires = expires;
+                break;
+            }
+        }
+        let confirmation_expires =
+            confirmation_expires.ok_or_else(|| invalid("subject is not confirmed for us"))?;
+
+        if child(assertion, ASSERTION_NS, "AuthnStatement").is_none() {
+            return Err(invalid("missing authentication statement"));
+        }
+
+        let mut attributes: HashMap<&str, Vec<&str>> = HashMap::new();
+        for statement in children(assertion, ASSERTION_NS, "AttributeStatement") {
+            for attribute in children(statement, ASSERTION_NS, "Attribute") {
+                let Some(name) = attribute.attribute("Name") else {
+                    continue;
+                };
+                attributes
+                    .entry(name)
+                    .or_default()
+                    .extend(children(attribute, ASSERTION_NS, "AttributeValue").map(text));
+            }
+        }
+
+        Ok(VerifiedAssertion {
+            id: id.to_string(),
+            expires_at: conditions_expire
+                .map_or(confirmation_expires, |expires| expires.min(confirmation_expires)),
+            identity: self.map_identity(name_id, &attributes),
+        })
+    }
+
+    fn map_identity(
+        &self,
+        name_id: &str,
+        attributes: &HashMap<&str, Vec<&str>>,
+    ) -> UpstreamIdentity {
+        let mapping = &self.attributes;
+        let first = |name: &str| {
+            attributes
+                .get(name)
+                .and_then(|values| values.first())
+                .filter(|value| !value.is_empty())
+                .map(|value| value.to_string())
+        };
+
+        let email = match &mapping.email {
+            Some(attribute) => first(attribute),
+            None => Some(name_id.to_string()).filter(|name_id| name_id.contains('@')),
+        };
+
+        let roles = mapping.roles.as_deref().map(|attribute| {
+            let mut roles = self.accounts.default_roles.clone();
+            for group in attributes.get(attribute).into_iter().flatten() {
+                roles.extend(mapping.role_mapping.get(*group).into_iter().flatten().cloned());
+            }
+            roles.sort();
+            roles.dedup();
+            roles
+        });
+
+        UpstreamIdentity {
+            subject: name_id.to_string(),
+            email: email.map(|address| address.to_lowercase()),
+            // The provider manages the directory the address comes from.
+            email_verified: true,
+            given_name: first(&mapping.first_name),
+            family_name: first(&mapping.last_name),
+            preferred_username: mapping.username.as_deref().and_then(first),
+            locale: None,
+            roles,
+        }
+    }
+}
@@ -0,0 +1,516 @@
+//! Verification of XML signatures, limited to what SAML identity providers send: one enveloped
+//! RSA-SHA256 signature over the element that contains it, canonicalized with exclusive XML
+//! canonicalization (<https://www.w3.org/TR/xml-exc-c14n/>).
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use roxmltree::{Node, NodeId, NodeType};
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+pub const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+/// Escapes `value` for use in a double-quoted attribute, as canonical XML does.
+pub fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' => escaped.push_str("&#x9;"),
+            '\n' => escaped.push_str("&#xA;"),
+            '\r' => escaped.push_str("&#xD;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_text(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// The `ds:Signature` element directly inside `element`, if it is signed.
+pub fn signature_of<'a, 'input>(element: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    element
+        .children()
+        .find(|child| child.has_tag_name((DSIG_NS, "Signature")))
+}
+
+/// Checks that `element` carries a valid signature over itself by one of `keys`.
+///
+/// The signature must reference the element by its `ID`, and that `ID` must be unique in the
+/// document, so the verified element is the one the caller goes on to read.
+pub fn verify_signature(element: Node, keys: &[RsaPublicKey]) -> Result<(), String> {
+    let id = element
+        .attribute("ID")
+        .ok_or("signed element has no ID")?;
+    let same_id = element
+        .document()
+        .descendants()
+        .filter(|node| node.attribute("ID") == Some(id))
+        .count();
+    if same_id != 1 {
+        return Err("duplicate ID".into());
+    }
+
+    let mut signatures = element
+        .children()
+        .filter(|child| child.has_tag_name((DSIG_NS, "Signature")));
+    let signature = signatures.next().ok_or("element is not signed")?;
+    if signatures.next().is_some() {
+        return Err("more than one signature".into());
+    }
+
+    let signed_info = dsig_child(signature, "SignedInfo")?;
+    let canonicalization = dsig_child(signed_info, "CanonicalizationMethod")?;
+    if canonicalization.attribute("Algorithm") != Some(EXC_C14N) {
+        return Err("unsupported canonicalization method".into());
+    }
+    if dsig_child(signed_info, "SignatureMethod")?.attribute("Algorithm") != Some(RSA_SHA256) {
+        return Err("unsupported signature method; only RSA-SHA256 is accepted".into());
+    }
+
+    let mut references = signed_info
+        .children()
+        .filter(|child| child.has_tag_name((DSIG_NS, "Reference")));
+    let reference = references.next().ok_or("signature has no reference")?;
+    if references.next().is_some() {
+        return Err("signature has more than one reference".into());
+    }
+    if reference.attribute("URI") != Some(&format!("#{}", id)) {
+        return Err("signature does not reference the signed element".into());
+    }
+
+    let mut enveloped = false;
+    let mut reference_prefixes = None;
+    if let Ok(transforms) = dsig_child(reference, "Transforms") {
+        for transform in transforms.children().filter(Node::is_element) {
+            match transform.attribute("Algorithm") {
+                Some(ENVELOPED_SIGNATURE) => enveloped = true,
+                Some(EXC_C14N) => reference_prefixes = Some(inclusive_prefixes(transform)),
+                _ => return Err("unsupported transform".into()),
+            }
+        }
+    }
+    let reference_prefixes =
+        reference_prefixes.ok_or("reference is not canonicalized with exclusive c14n")?;
+
+    if dsig_child(reference, "DigestMethod")?.attribute("Algorithm") != Some(SHA256) {
+        return Err("unsupported digest method; only SHA-256 is accepted".into());
+    }
+    let expected_digest = decode_base64(dsig_child(reference, "DigestValue")?)?;
+
+    let signed_element = canonicalize(
+        element,
+        enveloped.then(|| signature.id()),
+        &reference_prefixes,
+    );
+    if Sha256::digest(signed_element.as_bytes()).as_slice() != expected_digest.as_slice() {
+        return Err("digest does not match".into());
+    }
+
+    let signature_value = decode_base64(dsig_child(signature, "SignatureValue")?)?;
+    let signed_info_digest = Sha256::digest(
+        canonicalize(signed_info, None, &inclusive_prefixes(canonicalization)).as_bytes(),
+    );
+
+    if keys.iter().any(|key| {
+        key.verify(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &signed_info_digest,
+            &signature_value,
+        )
+        .is_ok()
+    }) {
+        Ok(())
+    } else {
+        Err("signature is not valid for the configured certificates".into())
+    }
+}
+
+fn dsig_child<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &str,
+) -> Result<Node<'a, 'input>, String> {
+    node.children()
+        .find(|child| child.has_tag_name((DSIG_NS, name)))
+        .ok_or_else(|| format!("missing {}", name))
+}
+
+fn decode_base64(node: Node) -> Result<Vec<u8>, String> {
+    let text: String = node
+        .text()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    STANDARD
+        .decode(text)
+        .map_err(|_| format!("invalid base64 in {}", node.tag_name().name()))
+}
+
+/// Prefixes listed in the `InclusiveNamespaces` of an exclusive c14n method or transform.
+fn inclusive_prefixes(method: Node) -> Vec<String> {
+    method
+        .children()
+        .find(|child| child.has_tag_name((EXC_C14N, "InclusiveNamespaces")))
+        .and_then(|list| list.attribute("PrefixList"))
+        .unwrap_or_default()
+        .split_ascii_whitespace()
+        .map(|prefix| {
+            if prefix == "#default" {
+                String::new()
+            } else {
+                prefix.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Exclusive canonical form, without comments, of `element` and its descendants, leaving out
+/// the subtree `excluded`.
+pub fn canonicalize(element: Node, excluded: Option<NodeId>, inclusive: &[String]) -> String {
+    let mut out = String::new();
+    write_element(element, excluded, inclusive, &HashMap::new(), &mut out);
+    out
+}
+
+/// Qualified name of an element or attribute as written in the document; parsers only keep
+/// the namespace URI, but canonical XML keeps the prefix.
+fn source_qname(input: &str, start: usize) -> &str {
+    let rest = &input[start..];
+    let end = rest
+        .find(|c: char| c.is_ascii_whitespace() || matches!(c, '/' | '>' | '='))
+        .unwrap_or(rest.len());
+    &rest[..end]
+}
+
+fn prefix_of(qname: &str) -> &str {
+    qname.split_once(':').map_or("", |(prefix, _)| prefix)
+}
+
+fn write_element<'a>(
+    element: Node<'a, '_>,
+    excluded: Option<NodeId>,
+    inclusive: &[String],
+    rendered: &HashMap<String, &'a str>,
+    out: &mut String,
+) {
+    let input = element.document().input_text();
+    let qname = source_qname(input, element.range().start + 1);
+
+    // Only the namespaces the element and its attributes use are rendered, plus the
+    // inclusive ones, and only where an output ancestor did not already declare them.
+    let mut used: Vec<&str> = vec![prefix_of(qname)];
+    for attribute in element.attributes() {
+        let prefix = prefix_of(&input[attribute.range_qname()]);
+        if !prefix.is_empty() && prefix != "xml" {
+            used.push(prefix);
+        }
+    }
+    used.extend(inclusive.iter().map(String::as_str).filter(|prefix| {
+        let prefix = (!prefix.is_empty()).then_some(*prefix);
+        element.lookup_namespace_uri(prefix).is_some()
+    }));
+    used.sort_unstable();
+    used.dedup();
+
+    let mut rendered = rendered.clone();
+    let mut declarations = String::new();
+    for prefix in used {
+        let uri = element
+            .lookup_namespace_uri((!prefix.is_empty()).then_some(prefix))
+            .unwrap_or("");
+        if rendered.get(prefix).copied().unwrap_or("") == uri {
+            continue;
+        }
+        if prefix.is_empty() {
+            declarations.push_str(&format!(" xmlns=\"{}\"", escape_attribute(uri)));
+        } else {
+            declarations.push_str(&format!(" xmlns:{}=\"{}\"", prefix, escape_attribute(uri)));
+        }
+        rendered.insert(prefix.to_string(), uri);
+    }
+
+    let mut attributes: Vec<_> = element.attributes().collect();
+    attributes.sort_by_key(|attribute| (attribute.namespace().unwrap_or(""), attribute.name()));
+
+    out.push('<');
+    out.push_str(qname);
+    out.push_str(&declarations);
+    for attribute in attributes {
+        out.push(' ');
+        out.push_str(&input[attribute.range_qname()]);
+        out.push_str("=\"");
+        out.push_str(&escape_attribute(attribute.value()));
+        out.push('"');
+    }
+    out.push('>');
+
+    for child in element.children() {
+        if Some(child.id()) == excluded {
+            continue;
+        }
+        match child.node_type() {
+            NodeType::Element => write_element(child, excluded, inclusive, &rendered, out),
+            NodeType::Text => escape_text(child.text().unwrap_or_default(), out),
+            NodeType::PI => {
+                if let Some(pi) = child.pi() {
+                    out.push_str("<?");
+                    out.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        out.push(' ');
+                        out.push_str(value);
+                    }
+                    out.push_str("?>");
+                }
+            }
+            NodeType::Comment | NodeType::Root => {}
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(qname);
+    out.push('>');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roxmltree::Document;
+    use rsa::pkcs8::DecodePrivateKey;
+    use rsa::RsaPrivateKey;
+
+    /// An unsigned response with one assertion, as an identity provider sends it.
+    const RESPONSE: &str = include_str!("testdata/response.xml");
+    const RESPONSE_ID: &str = "_8e8dc5f69a98cc4c1ff3427e5ce34606fd672f91e6";
+    const ASSERTION_ID: &str = "_d71a3a8e9fcc45c9e9d248ef7049393fc8f04e5f75";
+
+    fn key(pem: &str) -> RsaPrivateKey {
+        RsaPrivateKey::from_pkcs8_pem(pem).unwrap()
+    }
+
+    fn idp_key() -> RsaPrivateKey {
+        key(include_str!("testdata/idp_key.pem"))
+    }
+
+    fn other_key() -> RsaPrivateKey {
+        key(include_str!("testdata/other_key.pem"))
+    }
+
+    /// Signs the element with the ID `id` the way identity providers do: an enveloped
+    /// signature right after the element's `Issuer`.
+    fn sign(xml: &str, id: &str, key: &RsaPrivateKey) -> String {
+        let document = Document::parse(xml).unwrap();
+        let element = by_id(&document, id);
+        let digest = STANDARD.encode(Sha256::digest(canonicalize(element, None, &[])));
+
+        let signed_info = format!(
+            "<ds:SignedInfo xmlns:ds=\"{DSIG_NS}\">\
+             <ds:CanonicalizationMethod Algorithm=\"{EXC_C14N}\"/>\
+             <ds:SignatureMethod Algorithm=\"{RSA_SHA256}\"/>\
+             <ds:Reference URI=\"#{id}\"><ds:Transforms>\
+             <ds:Transform Algorithm=\"{ENVELOPED_SIGNATURE}\"/>\
+             <ds:Transform Algorithm=\"{EXC_C14N}\"/>\
+             </ds:Transforms><ds:DigestMethod Algorithm=\"{SHA256}\"/>\
+             <ds:DigestValue>{digest}</ds:DigestValue></ds:Reference></ds:SignedInfo>"
+        );
+        let signed_info_document = Document::parse(&signed_info).unwrap();
+        let canonical = canonicalize(signed_info_document.root_element(), None, &[]);
+        let value = key
+            .sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(canonical))
+            .unwrap();
+        let signature = format!(
+            "<ds:Signature xmlns:ds=\"{DSIG_NS}\">{signed_info}\
+             <ds:SignatureValue>{}</ds:SignatureValue></ds:Signature>",
+            STANDARD.encode(value)
+        );
+
+        let issuer_end = element
+            .children()
+            .find(|child| child.tag_name().name() == "Issuer")
+            .unwrap()
+            .range()
+            .end;
+        let mut signed = xml.to_string();
+        signed.insert_str(issuer_end, &signature);
+        signed
+    }
+
+    fn by_id<'a, 'input>(document: &'a Document<'input>, id: &str) -> Node<'a, 'input> {
+        document
+            .descendants()
+            .find(|node| node.attribute("ID") == Some(id))
+            .unwrap()
+    }
+
+    fn verify(xml: &str, id: &str, keys: &[RsaPrivateKey]) -> Result<(), String> {
+        let keys: Vec<_> = keys.iter().map(RsaPublicKey::from).collect();
+        let document = Document::parse(xml).unwrap();
+        verify_signature(by_id(&document, id), &keys)
+    }
+
+    #[test]
+    fn canonicalizes_with_only_used_namespaces() {
+        let document = Document::parse(
+            "<p:Response xmlns:p=\"urn:p\" xmlns:a=\"urn:a\" ID=\"r\">\
+             <a:Assertion xmlns:xs=\"urn:xs\" xmlns:b=\"urn:b\" b:z=\"1&amp;2\" ID=\"a\">\
+             <a:Subject/><!-- comment --></a:Assertion></p:Response>",
+        )
+        .unwrap();
+        let assertion = by_id(&document, "a");
+
+        assert_eq!(
+            canonicalize(assertion, None, &[]),
+            "<a:Assertion xmlns:a=\"urn:a\" xmlns:b=\"urn:b\" ID=\"a\" b:z=\"1&amp;2\">\
+             <a:Subject></a:Subject></a:Assertion>"
+        );
+        assert_eq!(
+            canonicalize(assertion, None, &["xs".into()]),
+            "<a:Assertion xmlns:a=\"urn:a\" xmlns:b=\"urn:b\" xmlns:xs=\"urn:xs\" ID=\"a\" \
+             b:z=\"1&amp;2\"><a:Subject></a:Subject></a:Assertion>"
+        );
+    }
+
+    #[test]
+    fn accepts_signed_response() {
+        let signed = sign(RESPONSE, RESPONSE_ID, &idp_key());
+
+        assert_eq!(verify(&signed, RESPONSE_ID, &[idp_key()]), Ok(()));
+    }
+
+    #[test]
+    fn accepts_signed_assertion() {
+        let signed = sign(RESPONSE, ASSERTION_ID, &idp_key());
+
+        assert_eq!(verify(&signed, ASSERTION_ID, &[idp_key()]), Ok(()));
+        let document = Document::parse(&signed).unwrap();
+        assert!(signature_of(document.root_element()).is_none());
+    }
+
+    #[test]
+    fn accepts_signed_assertion_in_signed_response() {
+        let signed = sign(RESPONSE, ASSERTION_ID, &idp_key());
+        let signed = sign(&signed, RESPONSE_ID, &idp_key());
+
+        assert_eq!(verify(&signed, RESPONSE_ID, &[idp_key()]), Ok(()));
+        assert_eq!(verify(&signed, ASSERTION_ID, &[idp_key()]), Ok(()));
+    }
+
+    #[test]
+    fn accepts_any_configured_key() {
+        let signed = sign(RESPONSE, RESPONSE_ID, &idp_key());
+
+        assert_eq!(verify(&signed, RESPONSE_ID, &[other_key(), idp_key()]), Ok(()));
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let signed = sign(RESPONSE, RESPONSE_ID, &other_key());
+
+        assert_eq!(
+            verify(&signed, RESPONSE_ID, &[idp_key()]),
+            Err("signature is not valid for the configured certificates".into())
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_content() {
+        let signed = sign(RESPONSE, ASSERTION_ID, &idp_key());
+        let tampered = signed.replace(">jane.doe@example.com<", ">admin@example.com<");
+
+        assert_eq!(
+            verify(&tampered, ASSERTION_ID, &[idp_key()]),
+            Err("digest does not match".into())
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_signed_info() {
+        let signed = sign(RESPONSE, ASSERTION_ID, &idp_key());
+        // A digest recomputed over forged content, without a new signature.
+        let forged = RESPONSE.replace(">jane.doe@example.com<", ">admin@example.com<");
+        let document = Document::parse(&forged).unwrap();
+        let digest = STANDARD.encode(Sha256::digest(canonicalize(
+            by_id(&document, ASSERTION_ID),
+            None,
+            &[],
+        )));
+        let start = signed.find("<ds:DigestValue>").unwrap() + "<ds:DigestValue>".len();
+        let end = signed.find("</ds:DigestValue>").unwrap();
+        let tampered = format!("{}{}{}", &signed[..start], digest, &signed[end..])
+            .replace(">jane.doe@example.com<", ">admin@example.com<");
+
+        assert_eq!(
+            verify(&tampered, ASSERTION_ID, &[idp_key()]),
+            Err("signature is not valid for the configured certificates".into())
+        );
+    }
+
+    #[test]
+    fn rejects_wrapped_signature() {
+        // The signed assertion is hidden in the response's extensions and a forged one without
+        // its own signature takes its place, carrying the original signature.
+        let signed = sign(RESPONSE, ASSERTION_ID, &idp_key());
+        let document = Document::parse(&signed).unwrap();
+        let original = &signed[by_id(&document, ASSERTION_ID).range()];
+        let forged = original
+            .replace(ASSERTION_ID, "_forged")
+            .replace("#_forged", &format!("#{}", ASSERTION_ID))
+            .replace(">jane.doe@example.com<", ">admin@example.com<");
+        let wrapped = signed.replace(
+            original,
+            &format!("<samlp:Extensions>{}</samlp:Extensions>{}", original, forged),
+        );
+
+        assert_eq!(verify(&wrapped, ASSERTION_ID, &[idp_key()]), Ok(()));
+        assert_eq!(
+            verify(&wrapped, "_forged", &[idp_key()]),
+            Err("signature does not reference the signed element".into())
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_id() {
+        let signed = sign(RESPONSE, ASSERTION_ID, &idp_key());
+        let document = Document::parse(&signed).unwrap();
+        let original = &signed[by_id(&document, ASSERTION_ID).range()];
+        let forged = original.replace(">jane.doe@example.com<", ">admin@example.com<");
+        let duplicated = signed.replace(
+            original,
+            &format!("<samlp:Extensions>{}</samlp:Extensions>{}", original, forged),
+        );
+        let document = Document::parse(&duplicated).unwrap();
+        let keys = [RsaPublicKey::from(&idp_key())];
+
+        for assertion in document
+            .descendants()
+            .filter(|node| node.attribute("ID") == Some(ASSERTION_ID))
+        {
+            assert_eq!(verify_signature(assertion, &keys), Err("duplicate ID".into()));
+        }
+    }
+
+    #[test]
+    fn rejects_unsigned_element() {
+        assert_eq!(
+            verify(RESPONSE, RESPONSE_ID, &[idp_key()]),
+            Err("element is not signed".into())
+        );
+    }
+}
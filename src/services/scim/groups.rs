@@ -0,0 +1,319 @@
+//! SCIM groups, backed by `roles`.
+//!
+//! Members of a group are the users holding the role. Role assignments are bits of
+//! `users.roles`, so groups can only be created while a role id up to 16 is free.
+
+use crate::models::{Role, ScimGroupView, ScimMember, ScimMeta, ScimPatchOperation, User};
+use crate::schema::{roles, users};
+use crate::services::scim::{parse_path, text_value, Filter, Page, PatchOp, ScimError, GROUP_SCHEMA};
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+use diesel::sql_types::{Bool, SmallInt};
+use serde_json::Value;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Number of bits in `users.roles`.
+const MAX_ROLE_ID: i32 = 16;
+const MAX_ROLE_NAME_LENGTH: usize = 255;
+
+/// Bit of `role` in `users.roles`.
+pub fn role_bit(role: &Role) -> i16 {
+    if (1..=MAX_ROLE_ID).contains(&role.id) {
+        1 << (role.id - 1)
+    } else {
+        0
+    }
+}
+
+pub fn load_roles(conn: &mut PgConnection) -> Result<Vec<Role>, ScimError> {
+    Ok(roles::table
+        .order(roles::id.asc())
+        .load::<Role>(conn)
+        .map_err(|e| internal_error("DB load roles error", e))?)
+}
+
+pub fn find_role(conn: &mut PgConnection, group_id: &str) -> Result<Role, ScimError> {
+    let not_found = || ScimError::not_found(format!("Group {} not found", group_id));
+    let role_id = group_id.parse::<i32>().map_err(|_| not_found())?;
+
+    roles::table
+        .find(role_id)
+        .first::<Role>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?
+        .ok_or_else(not_found)
+}
+
+/// The groups matching `filter` in `page`, with the number of all matching groups.
+pub fn list_roles(
+    conn: &mut PgConnection,
+    filter: Option<&Filter>,
+    page: Page,
+) -> Result<(i64, Vec<Role>), ScimError> {
+    let mut found = load_roles(conn)?;
+
+    if let Some(filter) = filter {
+        let value = filter.value.text()?;
+        match filter.attribute.as_str() {
+            "displayname" => found.retain(|role| role.name.eq_ignore_ascii_case(value)),
+            "id" => found.retain(|role| role.id.to_string() == value),
+            _ => {
+                return Err(ScimError::invalid_filter(format!(
+                    "Filtering by '{}' is not supported",
+                    filter.attribute
+                )));
+            }
+        }
+    }
+
+    let total = found.len() as i64;
+    let found = found
+        .into_iter()
+        .skip(page.offset() as usize)
+        .take(page.count as usize)
+        .collect();
+
+    Ok((total, found))
+}
+
+/// Users holding `role`, except service accounts.
+pub fn members(conn: &mut PgConnection, role: &Role) -> Result<Vec<User>, ScimError> {
+    Ok(users::table
+        .filter(users::is_service_account.eq(false))
+        .filter(sql::<Bool>("users.roles & ").bind::<SmallInt, _>(role_bit(role)).sql(" <> 0"))
+        .order(users::id.asc())
+        .load::<User>(conn)
+        .map_err(|e| internal_error("DB load error", e))?)
+}
+
+pub fn to_view(role: Role, members: Option<Vec<User>>, base_url: &str) -> ScimGroupView {
+    ScimGroupView {
+        schemas: [GROUP_SCHEMA],
+        id: role.id.to_string(),
+        members: members.map(|members| {
+            members
+                .into_iter()
+                .map(|member| ScimMember {
+                    value: member.temp_id.to_string(),
+                    display: Some(member.username),
+                })
+                .collect()
+        }),
+        meta: ScimMeta {
+            resource_type: "Group",
+            created: None,
+            last_modified: None,
+            location: format!("{}/Groups/{}", base_url, role.id),
+        },
+        display_name: role.name,
+    }
+}
+
+/// Role name for a group's `displayName`. Role names are lowercase letters and `_`.
+pub fn role_name(display_name: &str) -> Result<String, ScimError> {
+    let name = display_name.trim().to_lowercase();
+    if name.is_empty()
+        || name.len() > MAX_ROLE_NAME_LENGTH
+        || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+    {
+        return Err(ScimError::invalid_value("Group names may only contain letters and '_'"));
+    }
+    Ok(name)
+}
+
+/// Groups are roles referenced by name elsewhere, so they cannot be renamed.
+pub fn ensure_display_name(role: &Role, display_name: &str) -> Result<(), ScimError> {
+    if !role.name.eq_ignore_ascii_case(display_name.trim()) {
+        return Err(ScimError::mutability("Groups cannot be renamed"));
+    }
+    Ok(())
+}
+
+/// Creates a role without permissions under the lowest free role id.
+pub fn insert_role(conn: &mut PgConnection, name: &str) -> Result<Role, ScimError> {
+    let taken_ids = roles::table
+        .select(roles::id)
+        .load::<i32>(conn)
+        .map_err(|e| internal_error("DB load roles error", e))?;
+    let role_id = (1..=MAX_ROLE_ID)
+        .find(|role_id| !taken_ids.contains(role_id))
+        .ok_or_else(|| {
+            ScimError::invalid_value(format!("No more than {} groups are supported", MAX_ROLE_ID))
+        })?;
+
+    diesel::insert_into(roles::table)
+        .values((
+            roles::id.eq(role_id),
+            roles::name.eq(name),
+            roles::description.eq("Provisioned through SCIM"),
+            roles::permission.eq(0),
+        ))
+        .get_result::<Role>(conn)
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                (StatusCode::CONFLICT, "A group with this name already exists".to_string())
+                    .into()
+            }
+            e => internal_error("DB insert error", e).into(),
+        })
+}
+
+/// Parses the user ids of `members`.
+pub fn member_ids(members: &[ScimMember]) -> Result<HashSet<Uuid>, ScimError> {
+    members
+        .iter()
+        .map(|member| {
+            Uuid::parse_str(&member.value)
+                .map_err(|_| ScimError::invalid_value(format!("Unknown member {}", member.value)))
+        })
+        .collect()
+}
+
+/// The ids of a member or a list of members set by a PATCH operation.
+fn member_values(value: Option<&Value>) -> Result<HashSet<Uuid>, ScimError> {
+    let members = match value {
+        Some(Value::Array(values)) => values.clone(),
+        Some(member @ Value::Object(_)) => vec![member.clone()],
+        _ => return Err(ScimError::invalid_value("members must be a list")),
+    };
+    let members: Vec<ScimMember> = serde_json::from_value(Value::Array(members))
+        .map_err(|e| ScimError::invalid_value(format!("Invalid members: {}", e)))?;
+
+    member_ids(&members)
+}
+
+/// Applies the operations of a PATCH request to `members`, the ids of the group's members.
+pub fn apply_patch(
+    role: &Role,
+    members: &mut HashSet<Uuid>,
+    operations: &[ScimPatchOperation],
+) -> Result<(), ScimError> {
+    for operation in operations {
+        let op = PatchOp::parse(&operation.op)?;
+        match &operation.path {
+            Some(path) => set(role, members, op, path, operation.value.as_ref())?,
+            // Without a path, the value holds the attributes to add or replace.
+            None => match &operation.value {
+                Some(Value::Object(attributes)) if op != PatchOp::Remove => {
+                    for (path, value) in attributes {
+                        set(role, members, op, path, Some(value))?;
+                    }
+                }
+                _ => return Err(ScimError::invalid_path("A path is required")),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn set(
+    role: &Role,
+    members: &mut HashSet<Uuid>,
+    op: PatchOp,
+    path: &str,
+    value: Option<&Value>,
+) -> Result<(), ScimError> {
+    let path = parse_path(path)?;
+
+    match (path.attribute.as_str(), op) {
+        ("displayname", PatchOp::Remove) => {
+            return Err(ScimError::mutability("displayName cannot be removed"));
+        }
+        ("displayname", _) => ensure_display_name(role, &text_value(value, "displayName")?)?,
+        ("members", PatchOp::Add) => members.extend(member_values(value)?),
+        ("members", PatchOp::Replace) => *members = member_values(value)?,
+        // `members[value eq "..."]` selects the members to remove; without it, the value does.
+        ("members", PatchOp::Remove) => match (&path.filter, value) {
+            (Some(filter), _) if filter.attribute == "value" => {
+                if let Ok(member_id) = Uuid::parse_str(filter.value.text()?) {
+                    members.remove(&member_id);
+                }
+            }
+            (Some(_), _) => {
+                return Err(ScimError::invalid_filter("Members are selected by value"));
+            }
+            (None, Some(value)) => {
+                for member_id in member_values(Some(value))? {
+                    members.remove(&member_id);
+                }
+            }
+            (None, None) => members.clear(),
+        },
+        // Attributes we do not store, such as `externalId`, are ignored.
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Loads the users with the ids in `member_ids`, which must all exist.
+pub fn load_members(
+    conn: &mut PgConnection,
+    member_ids: &HashSet<Uuid>,
+) -> Result<Vec<User>, ScimError> {
+    let found = users::table
+        .filter(users::temp_id.eq_any(member_ids))
+        .filter(users::is_service_account.eq(false))
+        .load::<User>(conn)
+        .map_err(|e| internal_error("DB load error", e))?;
+
+    if let Some(unknown) = member_ids
+        .iter()
+        .find(|member_id| !found.iter().any(|user| user.temp_id == **member_id))
+    {
+        return Err(ScimError::invalid_value(format!("Unknown member {}", unknown)));
+    }
+
+    Ok(found)
+}
+
+/// Makes exactly `requested` hold `role` and returns the users whose roles changed.
+pub fn sync_members(
+    conn: &mut PgConnection,
+    role: &Role,
+    requested: &[User],
+) -> Result<Vec<User>, ScimError> {
+    let bit = role_bit(role);
+
+    let mut changes: Vec<(i32, i16)> = requested
+        .iter()
+        .filter(|user| user.roles & bit == 0)
+        .map(|user| (user.id, user.roles | bit))
+        .collect();
+    changes.extend(
+        members(conn, role)?
+            .iter()
+            .filter(|user| !requested.iter().any(|kept| kept.id == user.id))
+            .map(|user| (user.id, user.roles & !bit)),
+    );
+
+    conn.transaction(|conn| {
+        changes
+            .iter()
+            .map(|(user_id, role_bits)| {
+                diesel::update(users::table.find(user_id))
+                    .set(users::roles.eq(role_bits))
+                    .get_result::<User>(conn)
+            })
+            .collect::<Result<Vec<User>, Error>>()
+    })
+    .map_err(|e| internal_error("DB update error", e).into())
+}
+
+/// Deletes `role` after taking it from its members. Roles that grant permissions are kept, so
+/// provisioning cannot take away access that was configured here.
+pub fn delete_role(conn: &mut PgConnection, role: &Role) -> Result<(), ScimError> {
+    if role.permission != 0 {
+        return Err(ScimError::forbidden("Groups that grant permissions cannot be deleted"));
+    }
+
+    sync_members(conn, role, &[])?;
+    diesel::delete(roles::table.find(role.id))
+        .execute(conn)
+        .map_err(|e| internal_error("DB delete error", e))?;
+
+    Ok(())
+}
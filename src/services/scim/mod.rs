@@ -0,0 +1,284 @@
+//! SCIM 2.0 provisioning (RFC 7643 and RFC 7644).
+//!
+//! Identity providers push the lifecycle of their users here. SCIM users are our users, except
+//! service accounts, identified by their stable `temp_id`. SCIM groups are our roles, identified
+//! by role id, so group memberships are role assignments.
+
+pub mod groups;
+pub mod users;
+
+use crate::models::ScimListResponse;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_RESPONSE_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const SERVICE_PROVIDER_CONFIG_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const CONTENT_TYPE: &str = "application/scim+json";
+
+/// Most resources returned by one list request.
+pub const MAX_PAGE_SIZE: i64 = 200;
+
+/// Error in the format of RFC 7644 section 3.12.
+#[derive(Debug)]
+pub struct ScimError {
+    status: StatusCode,
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+impl ScimError {
+    pub fn new(
+        status: StatusCode,
+        scim_type: Option<&'static str>,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            status,
+            scim_type,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn invalid_value(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidValue"), detail)
+    }
+
+    pub fn invalid_filter(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidFilter"), detail)
+    }
+
+    pub fn invalid_path(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidPath"), detail)
+    }
+
+    pub fn mutability(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("mutability"), detail)
+    }
+
+    pub fn forbidden(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, None, detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, None, detail)
+    }
+}
+
+impl From<(StatusCode, String)> for ScimError {
+    fn from((status, detail): (StatusCode, String)) -> Self {
+        let scim_type = (status == StatusCode::CONFLICT).then_some("uniqueness");
+        Self::new(status, scim_type, detail)
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": self.status.as_u16().to_string(),
+            "detail": self.detail,
+        });
+        if let Some(scim_type) = self.scim_type {
+            body["scimType"] = scim_type.into();
+        }
+
+        (self.status, Scim(body)).into_response()
+    }
+}
+
+/// A JSON response body sent as `application/scim+json`.
+pub struct Scim<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Scim<T> {
+    fn into_response(self) -> Response {
+        ([(header::CONTENT_TYPE, CONTENT_TYPE)], Json(self.0)).into_response()
+    }
+}
+
+/// Value compared against in a filter.
+pub enum FilterValue {
+    Text(String),
+    Boolean(bool),
+}
+
+impl FilterValue {
+    pub fn text(&self) -> Result<&str, ScimError> {
+        match self {
+            FilterValue::Text(text) => Ok(text),
+            FilterValue::Boolean(_) => Err(ScimError::invalid_filter("Expected a string value")),
+        }
+    }
+
+    pub fn boolean(&self) -> Result<bool, ScimError> {
+        match self {
+            FilterValue::Boolean(flag) => Ok(*flag),
+            FilterValue::Text(_) => Err(ScimError::invalid_filter("Expected true or false")),
+        }
+    }
+}
+
+/// A filter of the form `attribute eq value`, the only form supported.
+pub struct Filter {
+    /// Lowercased attribute path, e.g. `username` or `emails.value`.
+    pub attribute: String,
+    pub value: FilterValue,
+}
+
+/// Attribute names are case-insensitive and may be prefixed with their schema URN.
+fn attribute_name(name: &str) -> String {
+    let name = match name.get(..4) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("urn:") => {
+            name.rsplit_once(':').map_or(name, |(_, attribute)| attribute)
+        }
+        _ => name,
+    };
+    name.to_ascii_lowercase()
+}
+
+/// Parses a `filter` parameter or value path filter such as `userName eq "alice"`.
+pub fn parse_filter(filter: &str) -> Result<Filter, ScimError> {
+    let unsupported = || {
+        ScimError::invalid_filter(format!(
+            "Unsupported filter '{}': only `attribute eq value` is supported",
+            filter
+        ))
+    };
+
+    let mut parts = filter.trim().splitn(3, char::is_whitespace);
+    let (Some(attribute), Some(operator), Some(value)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(unsupported());
+    };
+    if !operator.eq_ignore_ascii_case("eq") {
+        return Err(unsupported());
+    }
+
+    let value = value.trim();
+    let value = if let Some(quoted) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        FilterValue::Text(quoted.replace("\\\"", "\"").replace("\\\\", "\\"))
+    } else if value.eq_ignore_ascii_case("true") {
+        FilterValue::Boolean(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        FilterValue::Boolean(false)
+    } else {
+        return Err(unsupported());
+    };
+
+    Ok(Filter {
+        attribute: attribute_name(attribute),
+        value,
+    })
+}
+
+/// Target of a PATCH operation, e.g. `name.givenName` or `members[value eq "..."]`.
+pub struct AttributePath {
+    /// Lowercased top-level attribute.
+    pub attribute: String,
+    /// Filter selecting values of a multi-valued attribute.
+    pub filter: Option<Filter>,
+    /// Lowercased sub-attribute.
+    pub sub_attribute: Option<String>,
+}
+
+pub fn parse_path(path: &str) -> Result<AttributePath, ScimError> {
+    let path = path.trim();
+    let (head, filter, rest) = match path.split_once('[') {
+        Some((head, tail)) => {
+            let (filter, rest) = tail
+                .split_once(']')
+                .ok_or_else(|| ScimError::invalid_path(format!("Invalid path '{}'", path)))?;
+            (head, Some(parse_filter(filter)?), rest.strip_prefix('.'))
+        }
+        None => (path, None, None),
+    };
+
+    let head = attribute_name(head);
+    let (attribute, sub_attribute) = match head.split_once('.') {
+        Some((attribute, sub_attribute)) => (attribute.to_string(), Some(sub_attribute.into())),
+        None => (head, rest.map(str::to_ascii_lowercase)),
+    };
+    if attribute.is_empty() {
+        return Err(ScimError::invalid_path(format!("Invalid path '{}'", path)));
+    }
+
+    Ok(AttributePath {
+        attribute,
+        filter,
+        sub_attribute,
+    })
+}
+
+/// Kind of a PATCH operation.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PatchOp {
+    Add,
+    Replace,
+    Remove,
+}
+
+impl PatchOp {
+    /// Operation names are case-insensitive; Entra ID sends `Replace`.
+    pub fn parse(op: &str) -> Result<Self, ScimError> {
+        match op.to_ascii_lowercase().as_str() {
+            "add" => Ok(PatchOp::Add),
+            "replace" => Ok(PatchOp::Replace),
+            "remove" => Ok(PatchOp::Remove),
+            _ => Err(ScimError::invalid_value(format!("Unknown operation '{}'", op))),
+        }
+    }
+}
+
+/// A string value of a PATCH operation.
+pub fn text_value(value: Option<&Value>, attribute: &str) -> Result<String, ScimError> {
+    match value {
+        Some(Value::String(text)) => Ok(text.clone()),
+        _ => Err(ScimError::invalid_value(format!("{} must be a string", attribute))),
+    }
+}
+
+/// A boolean value of a PATCH operation; Entra ID sends booleans as `"True"` and `"False"`.
+pub fn boolean_value(value: Option<&Value>, attribute: &str) -> Result<bool, ScimError> {
+    match value {
+        Some(Value::Bool(flag)) => Ok(*flag),
+        Some(Value::String(text)) if text.eq_ignore_ascii_case("true") => Ok(true),
+        Some(Value::String(text)) if text.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::invalid_value(format!("{} must be a boolean", attribute))),
+    }
+}
+
+/// Requested window of a list, from the 1-based `startIndex` and `count` parameters.
+#[derive(Clone, Copy)]
+pub struct Page {
+    pub start_index: i64,
+    pub count: i64,
+}
+
+impl Page {
+    pub fn new(start_index: Option<i64>, count: Option<i64>) -> Self {
+        Self {
+            start_index: start_index.unwrap_or(1).max(1),
+            count: count.unwrap_or(MAX_PAGE_SIZE).clamp(0, MAX_PAGE_SIZE),
+        }
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.start_index - 1
+    }
+
+    pub fn respond<T>(&self, total_results: i64, resources: Vec<T>) -> ScimListResponse<T> {
+        ScimListResponse {
+            schemas: [LIST_RESPONSE_SCHEMA],
+            total_results,
+            items_per_page: resources.len(),
+            start_index: self.start_index,
+            resources,
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! SCIM users, backed by the non-service rows of `users`.
+
+use crate::models::{
+    NewScimUser, Role, ScimEmail, ScimMember, ScimMeta, ScimName, ScimPatchOperation,
+    ScimUserInput, ScimUserView, User,
+};
+use crate::schema::users;
+use crate::services::scim::groups::role_bit;
+use crate::services::scim::{
+    boolean_value, parse_path, text_value, Filter, Page, PatchOp, ScimError, USER_SCHEMA,
+};
+use crate::services::sessions::revoke_user_sessions;
+use crate::services::suspensions::lift_open_suspension;
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use chrono::Utc;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+use diesel::sql_types::Text;
+use serde_json::Value;
+use uuid::Uuid;
+
+const MAX_USERNAME_LENGTH: usize = 200;
+const MAX_EMAIL_LENGTH: usize = 255;
+const MAX_NAME_LENGTH: usize = 100;
+
+diesel::define_sql_function!(fn lower(x: Text) -> Text);
+
+/// The attributes of a user that SCIM manages.
+pub struct ScimUserFields {
+    pub username: String,
+    pub email: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub active: bool,
+}
+
+/// The address of the primary email, else of the first one.
+fn primary_email(emails: &[ScimEmail]) -> Option<&str> {
+    emails
+        .iter()
+        .find(|address| address.primary)
+        .or_else(|| emails.first())
+        .map(|address| address.value.as_str())
+}
+
+/// The address set by an email operation: a string, an email or a list of emails. We keep a
+/// single address, so every email operation replaces it.
+fn email_value(value: Option<&Value>) -> Result<String, ScimError> {
+    let emails = match value {
+        Some(Value::String(address)) => return Ok(address.clone()),
+        Some(Value::Array(values)) => values.clone(),
+        Some(single @ Value::Object(_)) => vec![single.clone()],
+        _ => return Err(ScimError::invalid_value("emails must hold an address")),
+    };
+    let emails: Vec<ScimEmail> = serde_json::from_value(Value::Array(emails))
+        .map_err(|e| ScimError::invalid_value(format!("Invalid emails: {}", e)))?;
+
+    primary_email(&emails)
+        .map(str::to_string)
+        .ok_or_else(|| ScimError::invalid_value("A user needs an email address"))
+}
+
+/// `None` for missing and blank names.
+fn name_part(value: Option<String>) -> Option<String> {
+    value
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+}
+
+impl ScimUserFields {
+    /// Fields of a created or replaced user. Without an email address, a `userName` that is
+    /// one is used.
+    pub fn from_input(input: ScimUserInput) -> Result<Self, ScimError> {
+        let email = primary_email(&input.emails)
+            .or_else(|| input.user_name.contains('@').then_some(input.user_name.as_str()))
+            .ok_or_else(|| ScimError::invalid_value("A user needs an email address"))?
+            .to_string();
+        let name = input.name.unwrap_or_default();
+
+        let fields = Self {
+            username: input.user_name,
+            email,
+            first_name: name.given_name,
+            last_name: name.family_name,
+            active: input.active.unwrap_or(true),
+        };
+        fields.normalized()
+    }
+
+    pub fn from_user(user: &User) -> Self {
+        Self {
+            username: user.username.clone(),
+            email: user.email.clone(),
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            active: user.is_active,
+        }
+    }
+
+    /// Applies the operations of a PATCH request.
+    pub fn apply(mut self, operations: &[ScimPatchOperation]) -> Result<Self, ScimError> {
+        for operation in operations {
+            let op = PatchOp::parse(&operation.op)?;
+            match &operation.path {
+                Some(path) => self.set(op, path, operation.value.as_ref())?,
+                // Without a path, the value holds the attributes to add or replace.
+                None => match &operation.value {
+                    Some(Value::Object(attributes)) if op != PatchOp::Remove => {
+                        for (path, value) in attributes {
+                            self.set(op, path, Some(value))?;
+                        }
+                    }
+                    _ => return Err(ScimError::invalid_path("A path is required")),
+                },
+            }
+        }
+        self.normalized()
+    }
+
+    fn set(&mut self, op: PatchOp, path: &str, value: Option<&Value>) -> Result<(), ScimError> {
+        let target = parse_path(path)?;
+
+        match (target.attribute.as_str(), target.sub_attribute.as_deref()) {
+            ("name", None) => match value {
+                Some(Value::Object(parts)) if op != PatchOp::Remove => {
+                    for (part, value) in parts {
+                        self.set(op, &format!("name.{}", part), Some(value))?;
+                    }
+                }
+                _ if op == PatchOp::Remove => {
+                    self.first_name = None;
+                    self.last_name = None;
+                }
+                _ => return Err(ScimError::invalid_value("name must be an object")),
+            },
+            ("name", Some("givenname")) if op == PatchOp::Remove => self.first_name = None,
+            ("name", Some("familyname")) if op == PatchOp::Remove => self.last_name = None,
+            ("name", Some("givenname")) => {
+                self.first_name = Some(text_value(value, "name.givenName")?)
+            }
+            ("name", Some("familyname")) => {
+                self.last_name = Some(text_value(value, "name.familyName")?)
+            }
+            ("username" | "emails" | "active", _) if op == PatchOp::Remove => {
+                return Err(ScimError::mutability(format!("{} cannot be removed", path)));
+            }
+            ("username", None) => self.username = text_value(value, "userName")?,
+            ("active", None) => self.active = boolean_value(value, "active")?,
+            // We keep a single address, so every email operation sets it.
+            ("emails", None | Some("value")) => self.email = email_value(value)?,
+            // Attributes we do not store, such as `externalId` or `title`, are ignored.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn normalized(mut self) -> Result<Self, ScimError> {
+        self.username = self.username.trim().to_string();
+        self.email = self.email.trim().to_lowercase();
+        self.first_name = name_part(self.first_name);
+        self.last_name = name_part(self.last_name);
+
+        if self.username.is_empty() || self.username.chars().count() > MAX_USERNAME_LENGTH {
+            return Err(ScimError::invalid_value("Invalid userName"));
+        }
+        if !self.email.contains('@') || self.email.chars().count() > MAX_EMAIL_LENGTH {
+            return Err(ScimError::invalid_value("Invalid email address"));
+        }
+        let too_long = |part: &Option<String>| {
+            part.as_ref().is_some_and(|part| part.chars().count() > MAX_NAME_LENGTH)
+        };
+        if too_long(&self.first_name) || too_long(&self.last_name) {
+            return Err(ScimError::invalid_value("Names are limited to 100 characters"));
+        }
+
+        Ok(self)
+    }
+}
+
+/// Permissions `user` holds through their roles and individual grants.
+pub fn granted_permissions(user: &User, roles: &[Role]) -> i64 {
+    roles
+        .iter()
+        .filter(|role| user.roles & role_bit(role) != 0)
+        .fold(user.permissions, |bits, role| bits | role.permission)
+}
+
+pub fn to_view(user: User, roles: &[Role], base_url: &str) -> ScimUserView {
+    ScimUserView {
+        schemas: [USER_SCHEMA],
+        id: user.temp_id,
+        name: ScimName {
+            given_name: user.first_name,
+            family_name: user.last_name,
+        },
+        emails: vec![ScimEmail {
+            value: user.email,
+            kind: Some("work".into()),
+            primary: true,
+        }],
+        active: user.is_active,
+        groups: roles
+            .iter()
+            .filter(|role| user.roles & role_bit(role) != 0)
+            .map(|role| ScimMember {
+                value: role.id.to_string(),
+                display: Some(role.name.clone()),
+            })
+            .collect(),
+        meta: ScimMeta {
+            resource_type: "User",
+            created: user.created_at,
+            last_modified: user.updated_at.or(user.created_at),
+            location: format!("{}/Users/{}", base_url, user.temp_id),
+        },
+        user_name: user.username,
+    }
+}
+
+fn filtered_users(filter: Option<&Filter>) -> Result<users::BoxedQuery<'_, Pg>, ScimError> {
+    let query = users::table
+        .filter(users::is_service_account.eq(false))
+        .into_boxed();
+    let Some(filter) = filter else {
+        return Ok(query);
+    };
+
+    Ok(match filter.attribute.as_str() {
+        "username" => query.filter(lower(users::username).eq(filter.value.text()?.to_lowercase())),
+        "emails" | "emails.value" => {
+            query.filter(users::email.eq(filter.value.text()?.to_lowercase()))
+        }
+        "active" => query.filter(users::is_active.eq(filter.value.boolean()?)),
+        // Unknown ids match nothing.
+        "id" => {
+            let temp_id = Uuid::parse_str(filter.value.text()?).ok();
+            query.filter(users::temp_id.nullable().eq(temp_id))
+        }
+        _ => {
+            return Err(ScimError::invalid_filter(format!(
+                "Filtering by '{}' is not supported",
+                filter.attribute
+            )));
+        }
+    })
+}
+
+/// The users matching `filter` in `page`, with the number of all matching users.
+pub fn list_users(
+    conn: &mut PgConnection,
+    filter: Option<&Filter>,
+    page: Page,
+) -> Result<(i64, Vec<User>), ScimError> {
+    let total = filtered_users(filter)?
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    let found = filtered_users(filter)?
+        .order(users::id.asc())
+        .offset(page.offset())
+        .limit(page.count)
+        .load::<User>(conn)
+        .map_err(|e| internal_error("DB load error", e))?;
+
+    Ok((total, found))
+}
+
+/// Loads the user with the SCIM id `user_id`; service accounts are reported as not found.
+pub fn find_user(conn: &mut PgConnection, user_id: &str) -> Result<User, ScimError> {
+    let not_found = || ScimError::not_found(format!("User {} not found", user_id));
+    let temp_id = Uuid::parse_str(user_id).map_err(|_| not_found())?;
+
+    users::table
+        .filter(users::temp_id.eq(temp_id))
+        .filter(users::is_service_account.eq(false))
+        .first::<User>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?
+        .ok_or_else(not_found)
+}
+
+fn taken(e: Error) -> ScimError {
+    match e {
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            (StatusCode::CONFLICT, "userName or email is already taken".to_string()).into()
+        }
+        e => internal_error("DB write error", e).into(),
+    }
+}
+
+/// Creates a user without a password; they log in through the identity provider.
+pub fn insert_user(conn: &mut PgConnection, fields: ScimUserFields) -> Result<User, ScimError> {
+    diesel::insert_into(users::table)
+        .values(&NewScimUser {
+            email: fields.email,
+            username: fields.username,
+            first_name: fields.first_name,
+            last_name: fields.last_name,
+            is_active: fields.active,
+            // The provider manages the addresses of its users.
+            email_verified_at: Some(Utc::now()),
+        })
+        .get_result::<User>(conn)
+        .map_err(taken)
+}
+
+/// Writes `fields` to `user`. Deactivating a user ends their sessions; activating them lifts
+/// their suspension.
+pub fn update_user(
+    conn: &mut PgConnection,
+    user: &User,
+    fields: ScimUserFields,
+) -> Result<User, ScimError> {
+    let now = Utc::now();
+    let email_verified_at = if fields.email == user.email {
+        user.email_verified_at
+    } else {
+        Some(now)
+    };
+
+    let updated = conn
+        .transaction(|conn| {
+            let updated = diesel::update(users::table.find(user.id))
+                .set((
+                    users::username.eq(fields.username),
+                    users::email.eq(fields.email),
+                    users::first_name.eq(fields.first_name),
+                    users::last_name.eq(fields.last_name),
+                    users::is_active.eq(fields.active),
+                    users::email_verified_at.eq(email_verified_at),
+                    users::updated_at.eq(now),
+                ))
+                .get_result::<User>(conn)?;
+
+            if !user.is_active && updated.is_active {
+                lift_open_suspension(conn, user.id)?;
+            }
+            Ok(updated)
+        })
+        .map_err(taken)?;
+
+    if user.is_active && !updated.is_active {
+        revoke_user_sessions(conn, user.id, None)
+            .map_err(|e| internal_error("Session revocation failed", e))?;
+    }
+
+    Ok(updated)
+}
+
+pub fn delete_user(conn: &mut PgConnection, user: &User) -> Result<(), ScimError> {
+    diesel::delete(users::table.find(user.id))
+        .execute(conn)
+        .map_err(|e| internal_error("DB delete error", e))?;
+
+    Ok(())
+}
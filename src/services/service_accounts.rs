@@ -0,0 +1,102 @@
+use crate::models::{NewServiceAccount, User};
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+use std::collections::HashMap;
+
+/// Role bitmask and combined role permissions of the roles called `names`.
+pub fn roles_by_name(
+    conn: &mut PgConnection,
+    names: &[String],
+) -> Result<(i16, i64), (StatusCode, String)> {
+    use crate::schema::roles::dsl::*;
+
+    let found = roles
+        .filter(name.eq_any(names))
+        .select((id, name, permission))
+        .load::<(i32, String, i64)>(conn)
+        .map_err(|e| internal_error("DB query error", e))?;
+
+    if let Some(unknown) = names
+        .iter()
+        .find(|n| !found.iter().any(|(_, found_name, _)| found_name == *n))
+    {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown role: {}", unknown)));
+    }
+
+    Ok(found
+        .iter()
+        .fold((0, 0), |(role_bits, permission_bits), (role_id, _, role_permission)| {
+            (role_bits | (1 << (role_id - 1)), permission_bits | role_permission)
+        }))
+}
+
+/// Names of the roles in the bitmask `role_bits`.
+pub fn role_names(role_map: &HashMap<i32, String>, role_bits: i16) -> Vec<String> {
+    let mut names: Vec<(i32, String)> = role_map
+        .iter()
+        .filter(|(role_id, _)| **role_id >= 1 && (role_bits as i32) & (1 << (**role_id - 1)) != 0)
+        .map(|(role_id, role_name)| (*role_id, role_name.clone()))
+        .collect();
+    names.sort();
+
+    names.into_iter().map(|(_, role_name)| role_name).collect()
+}
+
+pub fn load_role_map(
+    conn: &mut PgConnection,
+) -> Result<HashMap<i32, String>, (StatusCode, String)> {
+    use crate::schema::roles::dsl::*;
+
+    Ok(roles
+        .select((id, name))
+        .load::<(i32, String)>(conn)
+        .map_err(|e| internal_error("DB load roles error", e))?
+        .into_iter()
+        .collect())
+}
+
+pub fn insert_service_account(
+    conn: &mut PgConnection,
+    account: &NewServiceAccount,
+) -> Result<User, (StatusCode, String)> {
+    use crate::schema::users::dsl::users;
+
+    diesel::insert_into(users)
+        .values(account)
+        .get_result::<User>(conn)
+        .map_err(|e| match e {
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => (
+                StatusCode::CONFLICT,
+                "Username or email is already taken".into(),
+            ),
+            e => internal_error("DB insert error", e),
+        })
+}
+
+/// Loads the service account `account_id`; other users are reported as not found.
+pub fn find_service_account(
+    conn: &mut PgConnection,
+    account_id: i32,
+) -> Result<User, (StatusCode, String)> {
+    use crate::schema::users::dsl::*;
+
+    users
+        .find(account_id)
+        .filter(is_service_account.eq(true))
+        .first::<User>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))?
+        .ok_or((StatusCode::NOT_FOUND, "Service account not found".into()))
+}
+
+pub fn list_service_accounts(conn: &mut PgConnection) -> Result<Vec<User>, (StatusCode, String)> {
+    use crate::schema::users::dsl::*;
+
+    users
+        .filter(is_service_account.eq(true))
+        .order(id.asc())
+        .load::<User>(conn)
+        .map_err(|e| internal_error("DB load error", e))
+}
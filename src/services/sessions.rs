@@ -0,0 +1,134 @@
+use crate::models::{NewUserSession, User};
+use crate::services::jwt::{create_jwt, Claims, TokenPurpose};
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+const SESSION_LIFETIME_HOURS: i64 = 24;
+const RESTRICTED_SESSION_LIFETIME_MINUTES: i64 = 15;
+
+/// Records a new session for `user` and returns the JWT bound to it.
+///
+/// Sessions started with a `purpose` are short-lived and only accepted by the endpoint
+/// that completes that step.
+pub fn start_session(
+    conn: &mut PgConnection,
+    user: &User,
+    jwt_secret: &str,
+    purpose: Option<TokenPurpose>,
+) -> Result<String, (StatusCode, String)> {
+    use crate::schema::user_sessions::dsl::user_sessions;
+
+    let lifetime = match purpose {
+        Some(_) => Duration::minutes(RESTRICTED_SESSION_LIFETIME_MINUTES),
+        None => Duration::hours(SESSION_LIFETIME_HOURS),
+    };
+
+    let session = NewUserSession {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        expires_at: Utc::now() + lifetime,
+        oauth_client_id: None,
+        auth_time: None,
+    };
+
+    diesel::insert_into(user_sessions)
+        .values(&session)
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    create_jwt(user, session.id, session.expires_at, purpose, None, jwt_secret)
+        .map_err(|e| internal_error("JWT generation failed", e))
+}
+
+/// Rejects tokens whose session was revoked or no longer exists.
+pub fn ensure_session_active(
+    conn: &mut PgConnection,
+    claims: &Claims,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::user_sessions::dsl::*;
+
+    let session_id = Uuid::parse_str(&claims.sid)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+
+    let active = user_sessions
+        .filter(id.eq(session_id))
+        .filter(revoked_at.is_null())
+        .filter(expires_at.gt(Utc::now()))
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| internal_error("Session query failed", e))?;
+
+    if active == 0 {
+        return Err((StatusCode::UNAUTHORIZED, "Session has been revoked".into()));
+    }
+
+    Ok(())
+}
+
+/// When the session of a login token started, i.e. when the user last authenticated.
+pub fn session_started_at(
+    conn: &mut PgConnection,
+    claims: &Claims,
+) -> Result<DateTime<Utc>, (StatusCode, String)> {
+    use crate::schema::user_sessions::dsl::*;
+
+    let session_id = Uuid::parse_str(&claims.sid)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+
+    user_sessions
+        .find(session_id)
+        .select(created_at)
+        .first(conn)
+        .map_err(|e| internal_error("Session query failed", e))
+}
+
+/// Revokes every active session of the user, optionally keeping the one in `keep`.
+pub fn revoke_user_sessions(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+    keep: Option<Uuid>,
+) -> QueryResult<usize> {
+    use crate::schema::user_sessions::dsl::*;
+
+    let active_sessions = user_sessions
+        .filter(user_id.eq(target_user_id))
+        .filter(revoked_at.is_null());
+
+    match keep {
+        Some(keep_id) => diesel::update(active_sessions.filter(id.ne(keep_id)))
+            .set(revoked_at.eq(Utc::now()))
+            .execute(conn),
+        None => diesel::update(active_sessions)
+            .set(revoked_at.eq(Utc::now()))
+            .execute(conn),
+    }
+}
+
+/// Revokes the session the token in `claims` belongs to.
+pub fn revoke_session(
+    conn: &mut PgConnection,
+    claims: &Claims,
+) -> Result<(), (StatusCode, String)> {
+    let session_id = Uuid::parse_str(&claims.sid)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+
+    revoke_session_by_id(conn, session_id)
+}
+
+/// Revokes the session `session_id`, ending every token issued for it.
+pub fn revoke_session_by_id(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::user_sessions::dsl::*;
+
+    diesel::update(user_sessions.find(session_id))
+        .set(revoked_at.eq(Utc::now()))
+        .execute(conn)
+        .map_err(|e| internal_error("DB update error", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Suspensions deactivate users for a reason, until an administrator reactivates them or
+//! their end time passes.
+
+use crate::db::Pool;
+use crate::models::{NewUserSuspension, User, UserSuspension};
+use crate::services::audit::record_event;
+use crate::services::sessions::revoke_user_sessions;
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_REASON_LENGTH: usize = 500;
+/// How often suspensions that ended are lifted.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The suspension in force for the user with id `target_user_id`.
+pub fn open_suspension(
+    conn: &mut PgConnection,
+    target_user_id: i32,
+) -> Result<Option<UserSuspension>, (StatusCode, String)> {
+    use crate::schema::user_suspensions::dsl::*;
+
+    user_suspensions
+        .filter(user_id.eq(target_user_id))
+        .filter(lifted_at.is_null())
+        .first::<UserSuspension>(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+/// Deactivates `user` and ends their sessions. Without `until`, the suspension lasts until the
+/// user is reactivated.
+pub fn suspend_user(
+    conn: &mut PgConnection,
+    user: &User,
+    reason: &str,
+    until: Option<DateTime<Utc>>,
+    suspended_by: &str,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::user_suspensions::dsl::user_suspensions;
+    use crate::schema::users::dsl::{is_active, updated_at, users};
+
+    let reason = reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Reason must be between 1 and 500 characters".into(),
+        ));
+    }
+    if until.is_some_and(|until| until <= Utc::now()) {
+        return Err((StatusCode::BAD_REQUEST, "ends_at must be in the future".into()));
+    }
+    if !user.is_active {
+        return Err((StatusCode::CONFLICT, "User is already inactive".into()));
+    }
+
+    conn.transaction(|conn| {
+        // Left open if the user was activated without lifting it, e.g. through SCIM.
+        lift_open_suspension(conn, user.id)?;
+
+        diesel::insert_into(user_suspensions)
+            .values(&NewUserSuspension {
+                user_id: user.id,
+                reason: reason.to_string(),
+                suspended_by: suspended_by.to_string(),
+                ends_at: until,
+            })
+            .execute(conn)?;
+
+        diesel::update(users.find(user.id))
+            .set((is_active.eq(false), updated_at.eq(Utc::now())))
+            .execute(conn)?;
+
+        revoke_user_sessions(conn, user.id, None)
+    })
+    .map_err(|e| match e {
+        // Another request suspended the user at the same time.
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            (StatusCode::CONFLICT, "User is already suspended".to_string())
+        }
+        e => internal_error("DB update error", e),
+    })?;
+
+    let until = until.map_or_else(|| "indefinitely".to_string(), |until| until.to_rfc3339());
+    record_event(
+        conn,
+        Some(user.id),
+        "user_suspended",
+        Some(format!("by {} until {}: {}", suspended_by, until, reason)),
+    )?;
+
+    Ok(())
+}
+
+/// Lifts the suspension in force for the user with id `target_user_id`, if any. Users that
+/// are activated by other means than `reactivate_user` need this too.
+pub fn lift_open_suspension(conn: &mut PgConnection, target_user_id: i32) -> QueryResult<()> {
+    use crate::schema::user_suspensions::dsl::*;
+
+    diesel::update(
+        user_suspensions
+            .filter(user_id.eq(target_user_id))
+            .filter(lifted_at.is_null()),
+    )
+    .set(lifted_at.eq(Utc::now()))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Activates the user and lifts their suspension, if any. Fails with a unique violation if
+/// another active user has taken their email address or username meanwhile.
+fn activate(conn: &mut PgConnection, target_user_id: i32) -> QueryResult<()> {
+    use crate::schema::users::dsl::{is_active, updated_at, users};
+
+    conn.transaction(|conn| {
+        diesel::update(users.find(target_user_id))
+            .set((is_active.eq(true), updated_at.eq(Utc::now())))
+            .execute(conn)?;
+
+        lift_open_suspension(conn, target_user_id)
+    })
+}
+
+/// Activates `user` again, whether they were suspended or deactivated otherwise.
+pub fn reactivate_user(
+    conn: &mut PgConnection,
+    user: &User,
+    reactivated_by: &str,
+) -> Result<(), (StatusCode, String)> {
+    if user.is_active {
+        return Err((StatusCode::CONFLICT, "User is already active".into()));
+    }
+
+    activate(conn, user.id).map_err(|e| match e {
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => (
+            StatusCode::CONFLICT,
+            "Another active user has the same email address or username".to_string(),
+        ),
+        e => internal_error("DB update error", e),
+    })?;
+
+    record_event(
+        conn,
+        Some(user.id),
+        "user_reactivated",
+        Some(format!("by {}", reactivated_by)),
+    )?;
+
+    Ok(())
+}
+
+/// Reactivates the users whose suspension has ended and returns how many were reactivated.
+///
+/// Users whose email address or username was taken by another active user meanwhile stay
+/// inactive; their suspension is lifted anyway, so an administrator can resolve the conflict
+/// and reactivate them.
+pub fn lift_expired_suspensions(conn: &mut PgConnection) -> Result<usize, (StatusCode, String)> {
+    use crate::schema::user_suspensions::dsl::*;
+
+    let ended = user_suspensions
+        .filter(lifted_at.is_null())
+        .filter(ends_at.le(Utc::now()))
+        .load::<UserSuspension>(conn)
+        .map_err(|e| internal_error("DB load error", e))?;
+
+    let mut reactivated = 0;
+    for suspension in ended {
+        match activate(conn, suspension.user_id) {
+            Ok(()) => {
+                reactivated += 1;
+                record_event(
+                    conn,
+                    Some(suspension.user_id),
+                    "user_reactivated",
+                    Some("suspension ended".into()),
+                )?;
+            }
+            Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                diesel::update(user_suspensions.find(suspension.id))
+                    .set(lifted_at.eq(Utc::now()))
+                    .execute(conn)
+                    .map_err(|e| internal_error("DB update error", e))?;
+                record_event(
+                    conn,
+                    Some(suspension.user_id),
+                    "suspension_ended",
+                    Some("user stays inactive: email address or username is taken".into()),
+                )?;
+            }
+            Err(e) => return Err(internal_error("DB update error", e)),
+        }
+    }
+
+    Ok(reactivated)
+}
+
+/// Lifts suspensions that ended every `SWEEP_INTERVAL`, for as long as the server runs.
+pub async fn lift_expired_suspensions_periodically(pool: Arc<Pool>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let lifted = pool
+            .get()
+            .map_err(|e| internal_error("DB Pool error", e))
+            .and_then(|mut conn| lift_expired_suspensions(&mut conn));
+        match lifted {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Reactivated {} users whose suspension ended", count),
+            Err((_, e)) => tracing::error!("Lifting ended suspensions failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,901 @@
+//! Minimal WebAuthn relying party: challenge handling, attestation parsing and assertion
+//! verification for ES256 and RS256 credentials.
+//!
+//! Registrations request `none` attestation, so the attestation statement is not verified;
+//! the credential public key is trusted on first use like any other passkey.
+
+use crate::config::AppConfig;
+use crate::models::{NewWebauthnChallenge, NewWebauthnCredential, WebauthnCredential};
+use crate::utils::error::internal_error;
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use ciborium::Value;
+use diesel::prelude::*;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use uuid::Uuid;
+
+const CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+const CEREMONY_TIMEOUT_MS: u64 = 300_000;
+
+const COSE_ALG_ES256: i64 = -7;
+const COSE_ALG_RS256: i64 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+pub const CEREMONY_REGISTRATION: &str = "registration";
+pub const CEREMONY_AUTHENTICATION: &str = "authentication";
+
+#[derive(Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub alg: i64,
+}
+
+#[derive(Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transports: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub user_verification: &'static str,
+}
+
+/// `PublicKeyCredentialCreationOptions`, with binary fields base64url encoded.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameter>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: &'static str,
+}
+
+/// `PublicKeyCredentialRequestOptions`, with binary fields base64url encoded.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u64,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CeremonyStart<T> {
+    /// Must be sent back with the result of the ceremony.
+    pub challenge_id: Uuid,
+    pub public_key: T,
+}
+
+#[derive(Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationFinish {
+    pub challenge_id: Uuid,
+    /// Label for the passkey, e.g. "Work laptop".
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationFinish {
+    pub challenge_id: Uuid,
+    pub credential: AssertionCredential,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// A credential extracted from a verified registration.
+pub struct RegisteredCredential {
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+}
+
+/// Result of a verified assertion.
+pub struct VerifiedAssertion {
+    pub sign_count: i64,
+    pub user_verified: bool,
+}
+
+fn bad_request(message: &str) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message.to_string())
+}
+
+fn decode_b64(value: &str, field: &str) -> Result<Vec<u8>, (StatusCode, String)> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| bad_request(&format!("{} is not valid base64url", field)))
+}
+
+pub fn encode_b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Stores a new challenge for a ceremony and returns its id and value.
+pub fn create_challenge(
+    conn: &mut PgConnection,
+    user_id: Option<i32>,
+    ceremony: &str,
+) -> Result<(Uuid, String), (StatusCode, String)> {
+    use crate::schema::webauthn_challenges::dsl::webauthn_challenges;
+
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+
+    let challenge = NewWebauthnChallenge {
+        id: Uuid::new_v4(),
+        user_id,
+        ceremony: ceremony.to_string(),
+        challenge: encode_b64(&bytes),
+        expires_at: Utc::now() + Duration::minutes(CHALLENGE_LIFETIME_MINUTES),
+    };
+
+    diesel::insert_into(webauthn_challenges)
+        .values(&challenge)
+        .execute(conn)
+        .map_err(|e| internal_error("DB insert error", e))?;
+
+    Ok((challenge.id, challenge.challenge))
+}
+
+/// Deletes and returns an unexpired challenge, so each one is used at most once.
+///
+/// Returns the challenge value and the user it was issued for.
+pub fn take_challenge(
+    conn: &mut PgConnection,
+    challenge_id: Uuid,
+    expected_ceremony: &str,
+) -> Result<(String, Option<i32>), (StatusCode, String)> {
+    use crate::schema::webauthn_challenges::dsl::*;
+
+    diesel::delete(
+        webauthn_challenges
+            .filter(id.eq(challenge_id))
+            .filter(ceremony.eq(expected_ceremony))
+            .filter(expires_at.gt(Utc::now())),
+    )
+    .returning((challenge, user_id))
+    .get_result::<(String, Option<i32>)>(conn)
+    .optional()
+    .map_err(|e| internal_error("DB delete error", e))?
+    .ok_or_else(|| bad_request("Unknown or expired challenge"))
+}
+
+pub fn creation_options(
+    config: &AppConfig,
+    challenge: String,
+    user_handle: &Uuid,
+    user_name: &str,
+    display_name: &str,
+    existing: &[WebauthnCredential],
+) -> CreationOptions {
+    CreationOptions {
+        challenge,
+        rp: RelyingParty {
+            id: config.webauthn_rp_id.clone(),
+            name: config.webauthn_rp_name.clone(),
+        },
+        user: UserEntity {
+            id: encode_b64(user_handle.as_bytes()),
+            name: user_name.to_string(),
+            display_name: display_name.to_string(),
+        },
+        pub_key_cred_params: vec![
+            CredentialParameter {
+                kind: "public-key",
+                alg: COSE_ALG_ES256,
+            },
+            CredentialParameter {
+                kind: "public-key",
+                alg: COSE_ALG_RS256,
+            },
+        ],
+        timeout: CEREMONY_TIMEOUT_MS,
+        exclude_credentials: descriptors(existing),
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "preferred",
+            user_verification: "preferred",
+        },
+        attestation: "none",
+    }
+}
+
+pub fn request_options(
+    config: &AppConfig,
+    challenge: String,
+    allowed: &[WebauthnCredential],
+) -> RequestOptions {
+    RequestOptions {
+        challenge,
+        rp_id: config.webauthn_rp_id.clone(),
+        timeout: CEREMONY_TIMEOUT_MS,
+        allow_credentials: descriptors(allowed),
+        user_verification: "preferred",
+    }
+}
+
+fn descriptors(credentials: &[WebauthnCredential]) -> Vec<CredentialDescriptor> {
+    credentials
+        .iter()
+        .map(|credential| CredentialDescriptor {
+            kind: "public-key",
+            id: credential.credential_id.clone(),
+            transports: credential.transports.clone(),
+        })
+        .collect()
+}
+
+/// Checks `clientDataJSON` and returns its SHA-256 hash.
+fn verify_client_data(
+    config: &AppConfig,
+    client_data_json: &str,
+    expected_type: &str,
+    expected_challenge: &str,
+) -> Result<[u8; 32], (StatusCode, String)> {
+    let raw = decode_b64(client_data_json, "clientDataJSON")?;
+    let client_data: ClientData =
+        serde_json::from_slice(&raw).map_err(|_| bad_request("Malformed clientDataJSON"))?;
+
+    if client_data.kind != expected_type {
+        return Err(bad_request("Unexpected ceremony type"));
+    }
+    if client_data.challenge.trim_end_matches('=') != expected_challenge {
+        return Err(bad_request("Challenge mismatch"));
+    }
+    if client_data.origin != config.webauthn_origin {
+        return Err(bad_request("Origin mismatch"));
+    }
+
+    Ok(Sha256::digest(&raw).into())
+}
+
+/// Checks the rpIdHash and flags of authenticator data; returns `(flags, sign_count)`.
+fn verify_authenticator_data(
+    config: &AppConfig,
+    auth_data: &[u8],
+) -> Result<(u8, i64), (StatusCode, String)> {
+    if auth_data.len() < 37 {
+        return Err(bad_request("Authenticator data too short"));
+    }
+
+    let rp_id_hash: [u8; 32] = Sha256::digest(config.webauthn_rp_id.as_bytes()).into();
+    if auth_data[..32] != rp_id_hash {
+        return Err(bad_request("Relying party id mismatch"));
+    }
+
+    let flags = auth_data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(bad_request("User presence is required"));
+    }
+
+    let sign_count =
+        u32::from_be_bytes([auth_data[33], auth_data[34], auth_data[35], auth_data[36]]);
+
+    Ok((flags, sign_count as i64))
+}
+
+/// Verifies a registration response and extracts the new credential.
+pub fn verify_registration(
+    config: &AppConfig,
+    expected_challenge: &str,
+    credential: &RegistrationCredential,
+) -> Result<RegisteredCredential, (StatusCode, String)> {
+    verify_client_data(
+        config,
+        &credential.response.client_data_json,
+        "webauthn.create",
+        expected_challenge,
+    )?;
+
+    let attestation_object =
+        decode_b64(&credential.response.attestation_object, "attestationObject")?;
+    let attestation: Value = ciborium::from_reader(attestation_object.as_slice())
+        .map_err(|_| bad_request("Malformed attestationObject"))?;
+
+    let auth_data = map_get_text(&attestation, "authData")
+        .and_then(|value| value.as_bytes().cloned())
+        .ok_or_else(|| bad_request("attestationObject has no authData"))?;
+
+    let (flags, sign_count) = verify_authenticator_data(config, &auth_data)?;
+    if flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+        return Err(bad_request("No attested credential data"));
+    }
+
+    // aaguid (16 bytes) and credential id length (2 bytes) follow the 37 byte header.
+    let rest = &auth_data[37..];
+    if rest.len() < 18 {
+        return Err(bad_request("Attested credential data too short"));
+    }
+    let id_length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+    let rest = &rest[18..];
+    if rest.len() < id_length {
+        return Err(bad_request("Attested credential data too short"));
+    }
+    let (credential_id, key_bytes) = rest.split_at(id_length);
+
+    if encode_b64(credential_id) != credential.id.trim_end_matches('=') {
+        return Err(bad_request("Credential id mismatch"));
+    }
+
+    // The COSE key is followed by optional extensions; keep exactly the bytes of the key.
+    let mut cursor = Cursor::new(key_bytes);
+    let cose_key: Value =
+        ciborium::from_reader(&mut cursor).map_err(|_| bad_request("Malformed public key"))?;
+    let public_key = key_bytes[..cursor.position() as usize].to_vec();
+
+    match map_get_int(&cose_key, 3).and_then(value_as_i64) {
+        Some(COSE_ALG_ES256) | Some(COSE_ALG_RS256) => {}
+        _ => return Err(bad_request("Unsupported public key algorithm")),
+    }
+
+    Ok(RegisteredCredential {
+        credential_id: encode_b64(credential_id),
+        public_key,
+        sign_count,
+    })
+}
+
+/// Verifies an assertion against a stored credential.
+pub fn verify_assertion(
+    config: &AppConfig,
+    expected_challenge: &str,
+    stored: &WebauthnCredential,
+    credential: &AssertionCredential,
+) -> Result<VerifiedAssertion, (StatusCode, String)> {
+    let client_data_hash = verify_client_data(
+        config,
+        &credential.response.client_data_json,
+        "webauthn.get",
+        expected_challenge,
+    )?;
+
+    let auth_data = decode_b64(&credential.response.authenticator_data, "authenticatorData")?;
+    let (flags, sign_count) = verify_authenticator_data(config, &auth_data)?;
+
+    let signature = decode_b64(&credential.response.signature, "signature")?;
+
+    let mut signed_data = auth_data.clone();
+    signed_data.extend_from_slice(&client_data_hash);
+
+    verify_signature(&stored.public_key, &signed_data, &signature)?;
+
+    // Authenticators that keep a counter must increase it; a lower value points to a clone.
+    if (sign_count != 0 || stored.sign_count != 0) && sign_count <= stored.sign_count {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Signature counter did not increase".into(),
+        ));
+    }
+
+    Ok(VerifiedAssertion {
+        sign_count,
+        user_verified: flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+fn verify_signature(
+    cose_key_bytes: &[u8],
+    signed_data: &[u8],
+    signature: &[u8],
+) -> Result<(), (StatusCode, String)> {
+    let invalid = || (StatusCode::UNAUTHORIZED, "Invalid signature".to_string());
+
+    let cose_key: Value = ciborium::from_reader(cose_key_bytes)
+        .map_err(|e| internal_error("Stored public key is malformed", e))?;
+
+    match map_get_int(&cose_key, 3).and_then(value_as_i64) {
+        Some(COSE_ALG_ES256) => {
+            use p256::ecdsa::signature::Verifier;
+            use p256::ecdsa::{Signature, VerifyingKey};
+
+            let x = map_get_int(&cose_key, -2).and_then(|v| v.as_bytes());
+            let y = map_get_int(&cose_key, -3).and_then(|v| v.as_bytes());
+            let (Some(x), Some(y)) = (x, y) else {
+                return Err(internal_error("Stored public key is malformed", "missing x/y"));
+            };
+
+            let mut sec1 = vec![0x04];
+            sec1.extend_from_slice(x);
+            sec1.extend_from_slice(y);
+
+            let key = VerifyingKey::from_sec1_bytes(&sec1)
+                .map_err(|e| internal_error("Stored public key is malformed", e))?;
+            let signature = Signature::from_der(signature).map_err(|_| invalid())?;
+
+            key.verify(signed_data, &signature).map_err(|_| invalid())
+        }
+        Some(COSE_ALG_RS256) => {
+            use rsa::pkcs1v15::{Signature, VerifyingKey};
+            use rsa::signature::Verifier;
+            use rsa::{BigUint, RsaPublicKey};
+
+            let n = map_get_int(&cose_key, -1).and_then(|v| v.as_bytes());
+            let e = map_get_int(&cose_key, -2).and_then(|v| v.as_bytes());
+            let (Some(n), Some(e)) = (n, e) else {
+                return Err(internal_error("Stored public key is malformed", "missing n/e"));
+            };
+
+            let public_key =
+                RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+                    .map_err(|e| internal_error("Stored public key is malformed", e))?;
+            let key = VerifyingKey::<Sha256>::new(public_key);
+            let signature = Signature::try_from(signature).map_err(|_| invalid())?;
+
+            key.verify(signed_data, &signature).map_err(|_| invalid())
+        }
+        _ => Err(internal_error("Stored public key is malformed", "unsupported algorithm")),
+    }
+}
+
+fn map_get_text<'a>(map: &'a Value, key: &str) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn map_get_int(map: &Value, key: i64) -> Option<&Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| value_as_i64(k) == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn value_as_i64(value: &Value) -> Option<i64> {
+    value.as_integer().and_then(|i| i64::try_from(i).ok())
+}
+
+pub fn list_credentials(
+    conn: &mut PgConnection,
+    owner_id: i32,
+) -> Result<Vec<WebauthnCredential>, (StatusCode, String)> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    webauthn_credentials
+        .filter(user_id.eq(owner_id))
+        .order(created_at.asc())
+        .select(WebauthnCredential::as_select())
+        .load(conn)
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+pub fn find_credential(
+    conn: &mut PgConnection,
+    id_b64: &str,
+) -> Result<Option<WebauthnCredential>, (StatusCode, String)> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    webauthn_credentials
+        .filter(credential_id.eq(id_b64.trim_end_matches('=')))
+        .select(WebauthnCredential::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| internal_error("DB query error", e))
+}
+
+pub fn store_credential(
+    conn: &mut PgConnection,
+    owner_id: i32,
+    registered: RegisteredCredential,
+    transports: Vec<String>,
+    name: String,
+) -> Result<WebauthnCredential, (StatusCode, String)> {
+    use crate::schema::webauthn_credentials::dsl::webauthn_credentials;
+
+    let credential = NewWebauthnCredential {
+        user_id: owner_id,
+        credential_id: registered.credential_id,
+        public_key: registered.public_key,
+        sign_count: registered.sign_count,
+        transports,
+        name,
+    };
+
+    diesel::insert_into(webauthn_credentials)
+        .values(&credential)
+        .on_conflict_do_nothing()
+        .returning(WebauthnCredential::as_returning())
+        .get_result(conn)
+        .optional()
+        .map_err(|e| internal_error("DB insert error", e))?
+        .ok_or_else(|| (StatusCode::CONFLICT, "Passkey is already registered".into()))
+}
+
+/// Stores the new signature counter after a successful assertion.
+///
+/// The counter is only written if it is still below the new value, so of two logins racing
+/// with the same assertion only one succeeds. Authenticators without a counter always report
+/// `0`.
+pub fn record_credential_use(
+    conn: &mut PgConnection,
+    credential_pk: i32,
+    new_sign_count: i64,
+) -> Result<(), (StatusCode, String)> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    let credential = webauthn_credentials.find(credential_pk);
+    let changes = (sign_count.eq(new_sign_count), last_used_at.eq(Utc::now()));
+
+    let updated = if new_sign_count == 0 {
+        diesel::update(credential.filter(sign_count.eq(0)))
+            .set(changes)
+            .execute(conn)
+    } else {
+        diesel::update(credential.filter(sign_count.lt(new_sign_count)))
+            .set(changes)
+            .execute(conn)
+    }
+    .map_err(|e| internal_error("DB update error", e))?;
+
+    if updated == 0 {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Signature counter did not increase".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+
+    const RP_ID: &str = "example.com";
+    const ORIGIN: &str = "https://example.com";
+    const CHALLENGE: &str = "q2ymdwsKnTUkzAyTtyIAjSRR0q7mS8h6ifzX4EA9mAo";
+    const CREDENTIAL_ID: &[u8] = b"software-authenticator-credential";
+
+    fn config() -> AppConfig {
+        AppConfig {
+            webauthn_rp_id: RP_ID.into(),
+            webauthn_origin: ORIGIN.into(),
+            ..AppConfig::from_env()
+        }
+    }
+
+    /// A software authenticator with a fixed P-256 key.
+    struct Authenticator {
+        key: SigningKey,
+    }
+
+    impl Authenticator {
+        fn new(seed: u8) -> Self {
+            Self {
+                key: SigningKey::from_slice(&[seed; 32]).unwrap(),
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let int = |i: i64| Value::Integer(i.into());
+
+            let key = Value::Map(vec![
+                (int(1), int(2)),
+                (int(3), int(COSE_ALG_ES256)),
+                (int(-1), int(1)),
+                (int(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                (int(-3), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&key, &mut bytes).unwrap();
+            bytes
+        }
+
+        fn registration(&self, client_data: &str, rp_id: &str) -> RegistrationCredential {
+            let mut auth_data =
+                authenticator_data(rp_id, FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data.extend_from_slice(&(CREDENTIAL_ID.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(CREDENTIAL_ID);
+            auth_data.extend_from_slice(&self.cose_key());
+
+            let attestation = Value::Map(vec![
+                (Value::Text("fmt".into()), Value::Text("none".into())),
+                (Value::Text("attStmt".into()), Value::Map(Vec::new())),
+                (Value::Text("authData".into()), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            RegistrationCredential {
+                id: encode_b64(CREDENTIAL_ID),
+                response: AttestationResponse {
+                    client_data_json: encode_b64(client_data.as_bytes()),
+                    attestation_object: encode_b64(&attestation_object),
+                    transports: Vec::new(),
+                },
+            }
+        }
+
+        fn assertion(
+            &self,
+            client_data: &str,
+            rp_id: &str,
+            flags: u8,
+            sign_count: u32,
+        ) -> AssertionCredential {
+            let auth_data = authenticator_data(rp_id, flags, sign_count);
+
+            let mut signed_data = auth_data.clone();
+            signed_data.extend_from_slice(&Sha256::digest(client_data.as_bytes()));
+            let signature: Signature = self.key.sign(&signed_data);
+
+            AssertionCredential {
+                id: encode_b64(CREDENTIAL_ID),
+                response: AssertionResponse {
+                    client_data_json: encode_b64(client_data.as_bytes()),
+                    authenticator_data: encode_b64(&auth_data),
+                    signature: encode_b64(signature.to_der().as_bytes()),
+                },
+            }
+        }
+
+        fn stored(&self, sign_count: i64) -> WebauthnCredential {
+            WebauthnCredential {
+                id: 1,
+                user_id: 1,
+                credential_id: encode_b64(CREDENTIAL_ID),
+                public_key: self.cose_key(),
+                sign_count,
+                transports: Vec::new(),
+                name: "Passkey".into(),
+                created_at: Utc::now(),
+                last_used_at: None,
+            }
+        }
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    fn client_data(kind: &str, origin: &str) -> String {
+        serde_json::json!({ "type": kind, "challenge": CHALLENGE, "origin": origin }).to_string()
+    }
+
+    fn error_of<T>(result: Result<T, (StatusCode, String)>) -> (StatusCode, String) {
+        result.err().expect("verification should fail")
+    }
+
+    #[test]
+    fn registers_software_authenticator() {
+        let authenticator = Authenticator::new(7);
+        let credential =
+            authenticator.registration(&client_data("webauthn.create", ORIGIN), RP_ID);
+
+        let registered = verify_registration(&config(), CHALLENGE, &credential).unwrap();
+
+        assert_eq!(registered.credential_id, encode_b64(CREDENTIAL_ID));
+        assert_eq!(registered.public_key, authenticator.cose_key());
+        assert_eq!(registered.sign_count, 0);
+    }
+
+    #[test]
+    fn registration_rejects_wrong_origin_and_rp_id() {
+        let authenticator = Authenticator::new(7);
+
+        let credential = authenticator
+            .registration(&client_data("webauthn.create", "https://evil.example"), RP_ID);
+        let (_, message) = error_of(verify_registration(&config(), CHALLENGE, &credential));
+        assert_eq!(message, "Origin mismatch");
+
+        let credential =
+            authenticator.registration(&client_data("webauthn.create", ORIGIN), "evil.example");
+        let (_, message) = error_of(verify_registration(&config(), CHALLENGE, &credential));
+        assert_eq!(message, "Relying party id mismatch");
+    }
+
+    #[test]
+    fn registration_rejects_other_challenge_and_ceremony() {
+        let authenticator = Authenticator::new(7);
+
+        let credential =
+            authenticator.registration(&client_data("webauthn.create", ORIGIN), RP_ID);
+        let (_, message) = error_of(verify_registration(&config(), "other", &credential));
+        assert_eq!(message, "Challenge mismatch");
+
+        let credential = authenticator.registration(&client_data("webauthn.get", ORIGIN), RP_ID);
+        let (_, message) = error_of(verify_registration(&config(), CHALLENGE, &credential));
+        assert_eq!(message, "Unexpected ceremony type");
+    }
+
+    #[test]
+    fn verifies_assertion_with_user_verification() {
+        let authenticator = Authenticator::new(7);
+        let credential = authenticator.assertion(
+            &client_data("webauthn.get", ORIGIN),
+            RP_ID,
+            FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+            6,
+        );
+
+        let assertion =
+            verify_assertion(&config(), CHALLENGE, &authenticator.stored(5), &credential).unwrap();
+
+        assert_eq!(assertion.sign_count, 6);
+        assert!(assertion.user_verified);
+    }
+
+    #[test]
+    fn reports_missing_user_verification() {
+        let authenticator = Authenticator::new(7);
+        let credential = authenticator.assertion(
+            &client_data("webauthn.get", ORIGIN),
+            RP_ID,
+            FLAG_USER_PRESENT,
+            1,
+        );
+
+        let assertion =
+            verify_assertion(&config(), CHALLENGE, &authenticator.stored(0), &credential).unwrap();
+
+        assert!(!assertion.user_verified);
+    }
+
+    #[test]
+    fn assertion_requires_user_presence() {
+        let authenticator = Authenticator::new(7);
+        let credential = authenticator.assertion(
+            &client_data("webauthn.get", ORIGIN),
+            RP_ID,
+            FLAG_USER_VERIFIED,
+            1,
+        );
+
+        let (_, message) =
+            error_of(verify_assertion(&config(), CHALLENGE, &authenticator.stored(0), &credential));
+        assert_eq!(message, "User presence is required");
+    }
+
+    #[test]
+    fn assertion_rejects_wrong_origin_and_rp_id() {
+        let authenticator = Authenticator::new(7);
+        let stored = authenticator.stored(0);
+
+        let credential = authenticator.assertion(
+            &client_data("webauthn.get", "https://evil.example"),
+            RP_ID,
+            FLAG_USER_PRESENT,
+            1,
+        );
+        let (_, message) = error_of(verify_assertion(&config(), CHALLENGE, &stored, &credential));
+        assert_eq!(message, "Origin mismatch");
+
+        let credential = authenticator.assertion(
+            &client_data("webauthn.get", ORIGIN),
+            "evil.example",
+            FLAG_USER_PRESENT,
+            1,
+        );
+        let (_, message) = error_of(verify_assertion(&config(), CHALLENGE, &stored, &credential));
+        assert_eq!(message, "Relying party id mismatch");
+    }
+
+    #[test]
+    fn assertion_rejects_signature_of_other_key() {
+        let credential = Authenticator::new(8).assertion(
+            &client_data("webauthn.get", ORIGIN),
+            RP_ID,
+            FLAG_USER_PRESENT,
+            1,
+        );
+
+        let (status, _) = error_of(verify_assertion(
+            &config(),
+            CHALLENGE,
+            &Authenticator::new(7).stored(0),
+            &credential,
+        ));
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn assertion_rejects_counter_regression() {
+        let authenticator = Authenticator::new(7);
+        let assert_with = |sign_count| {
+            authenticator.assertion(
+                &client_data("webauthn.get", ORIGIN),
+                RP_ID,
+                FLAG_USER_PRESENT,
+                sign_count,
+            )
+        };
+
+        for (stored, presented) in [(5, 5), (5, 4), (5, 0)] {
+            let (status, message) = error_of(verify_assertion(
+                &config(),
+                CHALLENGE,
+                &authenticator.stored(stored),
+                &assert_with(presented),
+            ));
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(message, "Signature counter did not increase");
+        }
+
+        // Authenticators without a counter always report 0.
+        assert!(
+            verify_assertion(&config(), CHALLENGE, &authenticator.stored(0), &assert_with(0))
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use axum::http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Returns the IP address of the client that sent the request.
+///
+/// With `trust_proxy_headers` the last address in `X-Forwarded-For` is used when present;
+/// otherwise the peer address of the connection. The last address is the one the proxy in
+/// front of the API appended; earlier ones are sent by the client and can be forged.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, trust_proxy_headers: bool) -> IpAddr {
+    if trust_proxy_headers
+        && let Some(forwarded) = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .next_back()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|last| last.trim().parse::<IpAddr>().ok())
+    {
+        return forwarded;
+    }
+
+    peer.ip()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.2:51234".parse().unwrap()
+    }
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn uses_peer_unless_proxy_headers_are_trusted() {
+        let headers = forwarded(&["203.0.113.7"]);
+
+        assert_eq!(client_ip(&headers, peer(), false), peer().ip());
+        assert_eq!(client_ip(&HeaderMap::new(), peer(), true), peer().ip());
+    }
+
+    #[test]
+    fn uses_address_appended_by_proxy() {
+        let headers = forwarded(&["198.51.100.1, 203.0.113.7"]);
+        assert_eq!(client_ip(&headers, peer(), true), "203.0.113.7".parse::<IpAddr>().unwrap());
+
+        let headers = forwarded(&["198.51.100.1", "2001:db8::7"]);
+        assert_eq!(client_ip(&headers, peer(), true), "2001:db8::7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn ignores_forged_leading_addresses() {
+        let headers = forwarded(&["1.2.3.4, 5.6.7.8, 203.0.113.7"]);
+
+        assert_eq!(client_ip(&headers, peer(), true), "203.0.113.7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn falls_back_to_peer_for_malformed_header() {
+        let headers = forwarded(&["203.0.113.7, unknown"]);
+
+        assert_eq!(client_ip(&headers, peer(), true), peer().ip());
+    }
+}
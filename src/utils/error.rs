@@ -3,3 +3,8 @@ use axum::http::StatusCode;
 pub fn internal_error(msg: &str, e: impl std::fmt::Display) -> (StatusCode, String) {
     (StatusCode::INTERNAL_SERVER_ERROR, format!("{}: {}", msg, e))
 }
+
+/// An identity provider or directory we depend on failed or sent something unusable.
+pub fn upstream_error(msg: &str, e: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::BAD_GATEWAY, format!("{}: {}", msg, e))
+}
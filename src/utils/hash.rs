@@ -1,5 +1,6 @@
 use argon2::{password_hash::{PasswordHasher, SaltString}, Argon2, PasswordHash, PasswordVerifier};
 use rand::RngCore;
+use std::sync::OnceLock;
 
 pub fn hash_password(password: &str) -> Result<String, String> {
     let mut salt_bytes = [0u8; 16];
@@ -14,10 +15,23 @@ pub fn hash_password(password: &str) -> Result<String, String> {
         .map(|phc| phc.to_string())
 }
 
+/// Returns `Ok(false)` for a wrong password and `Err` only if the hash cannot be used.
 pub fn verify_password(password: &str, hash: &str) -> Result<bool, String> {
     let parsed_hash = PasswordHash::new(hash).map_err(|e| e.to_string())?;
-    Argon2::default()
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .map(|_| true)
-        .map_err(|e| e.to_string())
+    match Argon2::default().verify_password(password.as_bytes(), &parsed_hash) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Verifies `password` against a throwaway hash so that logins for unknown accounts take as
+/// long as logins with a wrong password.
+pub fn verify_dummy_password(password: &str) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let hash = DUMMY_HASH.get_or_init(|| {
+        hash_password("dummy password for timing").expect("Failed to hash dummy password")
+    });
+    let _ = verify_password(password, hash);
 }
@@ -1,2 +1,5 @@
 pub mod hash;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod password_policy;
+pub mod token;
+pub mod client_ip;
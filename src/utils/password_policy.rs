@@ -0,0 +1,61 @@
+use axum::http::StatusCode;
+use rand::Rng;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Checks a new password against the password policy.
+///
+/// A password must be between `MIN_PASSWORD_LENGTH` and `MAX_PASSWORD_LENGTH` characters
+/// and contain at least one letter and one digit.
+pub fn validate_password(password: &str) -> Result<(), (StatusCode, String)> {
+    let length = password.chars().count();
+
+    if length < MIN_PASSWORD_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH),
+        ));
+    }
+
+    if length > MAX_PASSWORD_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Password must be at most {} characters", MAX_PASSWORD_LENGTH),
+        ));
+    }
+
+    if !password.chars().any(char::is_alphabetic) || !password.chars().any(|c| c.is_ascii_digit()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Password must contain at least one letter and one digit".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Generates a random password that satisfies the password policy.
+pub fn generate_temporary_password() -> String {
+    const LETTERS: &[u8] = b"abcdefghjkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+    const DIGITS: &[u8] = b"23456789";
+    const LENGTH: usize = 16;
+
+    let mut rng = rand::rng();
+
+    loop {
+        let password: String = (0..LENGTH)
+            .map(|_| {
+                if rng.random_ratio(1, 4) {
+                    DIGITS[rng.random_range(0..DIGITS.len())] as char
+                } else {
+                    LETTERS[rng.random_range(0..LETTERS.len())] as char
+                }
+            })
+            .collect();
+
+        if validate_password(&password).is_ok() {
+            return password;
+        }
+    }
+}